@@ -0,0 +1,66 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{dir}/cbindgen.toml"))
+            .expect("cbindgen.toml should be valid");
+        // The checked in include/screen_capture.h is compared to this by a test in src/ffi.rs.
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{dir}/src/ffi.rs"))
+            .generate()
+            .expect("the header should be generated")
+            .write_to_file(format!("{out_dir}/screen_capture.h"));
+    }
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+        // The messages are defined in src/grpc.rs, following proto/screen_capture.proto.
+        let method = |name: &str, route: &str, input: &str, output: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("super::{input}"))
+                .output_type(format!("super::{output}"))
+                .codec_path("tonic_prost::ProstCodec")
+        };
+        let service = Service::builder()
+            .name("CaptureService")
+            .package("screen_capture")
+            .method(
+                method(
+                    "list_displays",
+                    "ListDisplays",
+                    "ListDisplaysRequest",
+                    "ListDisplaysResponse",
+                )
+                .build(),
+            )
+            .method(
+                method(
+                    "configure",
+                    "Configure",
+                    "ConfigureRequest",
+                    "ConfigureResponse",
+                )
+                .build(),
+            )
+            .method(method("get_frame", "GetFrame", "GetFrameRequest", "Frame").build())
+            .method(
+                method(
+                    "subscribe_frames",
+                    "SubscribeFrames",
+                    "SubscribeFramesRequest",
+                    "Frame",
+                )
+                .server_streaming()
+                .build(),
+            )
+            .build();
+        Builder::new().compile(&[service]);
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
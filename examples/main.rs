@@ -6,10 +6,12 @@ use screen_capture::{CaptureConfig, ThreadedCapturer};
 
 fn test_threaded() {
     println!("Starting default, that should be disabled.");
-    let capturer = ThreadedCapturer::default();
-
-    capturer.set_pre_callback(std::sync::Arc::new(|v|{print!("Frame {v} -> ")}));
-    capturer.set_post_callback(std::sync::Arc::new(|v|{println!("Captured {v:?}")}));
+    let capturer = ThreadedCapturer::builder()
+        .on_capture(|v| print!("Frame {v} -> "))
+        .on_frame(|v| println!("Captured {v:?}"))
+        .on_error(|e| println!("Capture failed: {e}"))
+        .thread_name("capture")
+        .build();
     std::thread::sleep(Duration::from_millis(1000));
     println!("latest: {:?}", capturer.latest());
 
@@ -17,6 +19,7 @@ fn test_threaded() {
     capturer.set_config(CaptureConfig {
         capture: vec![],
         rate: 5.0,
+        ..Default::default()
     });
     std::thread::sleep(Duration::from_millis(1000));
     println!("latest: {:?}", capturer.latest());
@@ -25,18 +28,21 @@ fn test_threaded() {
     capturer.set_config(CaptureConfig {
         capture: vec![],
         rate: 20.0,
+        ..Default::default()
     });
     std::thread::sleep(Duration::from_millis(500));
     println!("Switching once in 10 seconds");
     capturer.set_config(CaptureConfig {
         capture: vec![],
         rate: 0.1,
+        ..Default::default()
     });
     std::thread::sleep(Duration::from_millis(5000));
     println!("Switching back to 1 second seconds");
     capturer.set_config(CaptureConfig {
         capture: vec![],
         rate: 1.0,
+        ..Default::default()
     });
     std::thread::sleep(Duration::from_millis(5000));
 }
@@ -46,23 +52,29 @@ fn main() {
 
     use screen_capture::util::{read_ppm, WriteSupport};
 
-    let mut grabber = screen_capture::capture();
+    let mut grabber = screen_capture::capture().expect("should be able to capture");
 
-    let res = grabber.resolution();
+    let res = grabber
+        .resolution()
+        .expect("should be able to get the resolution");
 
     println!("Capture reports resolution of: {:?}", res);
     if res.width > 1920 {
         // Use my right monitor...
-        grabber.prepare_capture(0, 1920, 0, res.width - 1920, res.height);
+        grabber
+            .prepare_capture(0, 1920, 0, res.width - 1920, res.height)
+            .expect("should be able to prepare");
     } else {
         // use left monitor only.
-        grabber.prepare_capture(0, 0, 0, res.width, res.height);
+        grabber
+            .prepare_capture(0, 0, 0, res.width, res.height)
+            .expect("should be able to prepare");
     }
 
     std::thread::sleep(std::time::Duration::from_millis(1000));
 
     let start = Instant::now();
-    grabber.capture_image();
+    let _ = grabber.capture_image();
     let duration = start.elapsed();
     println!("Capture time : {:?}", duration);
 
@@ -71,11 +83,11 @@ fn main() {
     let duration = start.elapsed();
     println!("2nd capture time : {:?}", duration);
 
-    while !res {
+    while res.is_err() {
         res = grabber.capture_image();
     }
 
-    println!("Capture tried to capture image, succes? {}", res);
+    println!("Capture tried to capture image, succes? {:?}", res);
     let img = grabber.image().expect("grab image should succeed");
 
     // res = grabber.capture_image();
@@ -253,7 +265,7 @@ fn main() {
 
     for _i in 0..2 {
         let res = grabber.capture_image();
-        println!("Capture tried to capture image, succes? {}", res);
+        println!("Capture tried to capture image, succes? {:?}", res);
         let img = grabber.image().expect("should succeed");
         println!(
             "last pixel: {:#?}",
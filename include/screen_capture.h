@@ -0,0 +1,155 @@
+#ifndef SCREEN_CAPTURE_H
+#define SCREEN_CAPTURE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The major version of the api, this changes when existing functions or structs change.
+#define SCREEN_CAPTURE_API_VERSION_MAJOR 1
+
+// The minor version of the api, this increases when functions or capabilities are added.
+#define SCREEN_CAPTURE_API_VERSION_MINOR 0
+
+// The version of the api, the major version in the upper 16 bits and the minor in the lower.
+#define SCREEN_CAPTURE_API_VERSION ((SCREEN_CAPTURE_API_VERSION_MAJOR << 16) | SCREEN_CAPTURE_API_VERSION_MINOR)
+
+// Regions can be captured, through [`screen_capture_set_region`].
+#define SCREEN_CAPTURE_CAPABILITY_REGION (1 << 0)
+
+// Frames can be rgb, see [`ScreenCaptureFormat::Rgb`].
+#define SCREEN_CAPTURE_CAPABILITY_FORMAT_RGB (1 << 1)
+
+// Frames can be bgr, see [`ScreenCaptureFormat::Bgr`].
+#define SCREEN_CAPTURE_CAPABILITY_FORMAT_BGR (1 << 2)
+
+// The result of the calls, the errors correspond to the [`ScreenCaptureError`] variants.
+typedef enum ScreenCaptureStatus {
+  SCREEN_CAPTURE_STATUS_OK = 0,
+  // A null pointer or an invalid value was passed.
+  SCREEN_CAPTURE_STATUS_INVALID_ARGUMENT = 1,
+  // No frame was captured yet.
+  SCREEN_CAPTURE_STATUS_NO_FRAME = 2,
+  SCREEN_CAPTURE_STATUS_INITIALISATION = 3,
+  SCREEN_CAPTURE_STATUS_TRANSIENT = 4,
+  SCREEN_CAPTURE_STATUS_LOST_CAPTURE = 5,
+  SCREEN_CAPTURE_STATUS_PERMISSION_DENIED = 6,
+  SCREEN_CAPTURE_STATUS_TIMEOUT = 7,
+  SCREEN_CAPTURE_STATUS_UNSUPPORTED_FORMAT = 8,
+  SCREEN_CAPTURE_STATUS_BACKEND_UNAVAILABLE = 9,
+  SCREEN_CAPTURE_STATUS_DISCONNECTED = 10,
+  SCREEN_CAPTURE_STATUS_OTHER = 11,
+  // The library doesn't implement the major version of the header, see
+  // [`screen_capture_negotiate`].
+  SCREEN_CAPTURE_STATUS_INCOMPATIBLE_VERSION = 12,
+} ScreenCaptureStatus;
+
+// The pixel format of a frame, see [`OutputFormat`].
+typedef enum ScreenCaptureFormat {
+  // Four bytes per pixel, red, green, blue and an opaque alpha.
+  SCREEN_CAPTURE_FORMAT_RGBA = 0,
+  // Three bytes per pixel, red, green and blue.
+  SCREEN_CAPTURE_FORMAT_RGB = 1,
+  // Four bytes per pixel, blue, green, red and an unspecified fourth byte.
+  SCREEN_CAPTURE_FORMAT_BGR = 2,
+} ScreenCaptureFormat;
+
+// A capturer running in its own thread, created with [`screen_capture_new`].
+typedef struct ScreenCapture ScreenCapture;
+
+// A frame, the pixels are rows of `stride` bytes starting at `data`.
+//
+// Release it with [`screen_capture_frame_release`], which clears all fields.
+typedef struct ScreenCaptureFrame {
+  const uint8_t *data;
+  uint32_t width;
+  uint32_t height;
+  // The number of bytes from the start of one row to the next.
+  uint32_t stride;
+  enum ScreenCaptureFormat format;
+  // The counter of the capture, this increases for each capture.
+  uint64_t counter;
+  // The frame owning the data, not to be used directly.
+  const void *handle;
+} ScreenCaptureFrame;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of the library, see [`SCREEN_CAPTURE_API_VERSION`].
+uint32_t screen_capture_api_version(void);
+
+// The capabilities of the library, a combination of the `SCREEN_CAPTURE_CAPABILITY_` flags.
+uint64_t screen_capture_capabilities(void);
+
+// Check whether the library can be used by an application built against the header of the
+// version, pass [`SCREEN_CAPTURE_API_VERSION`]. Fails with
+// [`ScreenCaptureStatus::IncompatibleVersion`] if the major versions differ. Otherwise the
+// capabilities, if not null, are set to those of the library that the header knows about.
+//
+// # Safety
+// The capabilities must be null or writable.
+enum ScreenCaptureStatus screen_capture_negotiate(uint32_t version, uint64_t *capabilities);
+
+// Create a capturer of the entire default display at the rate in Hz, null if the rate is
+// invalid.
+//
+// Free it with [`screen_capture_free`].
+struct ScreenCapture *screen_capture_new(float rate);
+
+// Stop the capturer and free it, null is ignored.
+//
+// # Safety
+// The capture must be null or obtained from [`screen_capture_new`], and not used afterwards.
+void screen_capture_free(struct ScreenCapture *capture);
+
+// Capture the region of the display, a width or height of zero extends it to the edge of the
+// display and negative offsets are relative to the right and bottom edges.
+//
+// # Safety
+// The capture must be null or a valid capturer.
+enum ScreenCaptureStatus screen_capture_set_region(struct ScreenCapture *capture,
+                                                   uint32_t display,
+                                                   int32_t x,
+                                                   int32_t y,
+                                                   uint32_t width,
+                                                   uint32_t height);
+
+// Set the capture rate in Hz.
+//
+// # Safety
+// The capture must be null or a valid capturer.
+enum ScreenCaptureStatus screen_capture_set_rate(struct ScreenCapture *capture, float rate);
+
+// Set the pixel format of the frames that are captured from now on, one of the
+// [`ScreenCaptureFormat`] values.
+//
+// # Safety
+// The capture must be null or a valid capturer.
+enum ScreenCaptureStatus screen_capture_set_format(struct ScreenCapture *capture, uint32_t format);
+
+// Obtain the latest frame, on failure the frame is left empty and the status tells why the
+// latest capture failed.
+//
+// # Safety
+// The capture must be null or a valid capturer, the frame must be null or writable, it is
+// overwritten without releasing it.
+enum ScreenCaptureStatus screen_capture_latest(struct ScreenCapture *capture,
+                                               struct ScreenCaptureFrame *frame);
+
+// Release the frame and clear it, null and empty frames are ignored.
+//
+// # Safety
+// The frame must be null, empty or filled by [`screen_capture_latest`].
+void screen_capture_frame_release(struct ScreenCaptureFrame *frame);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SCREEN_CAPTURE_H */
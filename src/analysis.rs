@@ -0,0 +1,571 @@
+//! Analysis of the content of frames.
+use crate::capturer::CaptureInfo;
+use crate::{ImageBGR, OwnedFrame, Rect, ScreenCaptureError, BGR};
+
+/// Settings of [`find_borders`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BorderConfig {
+    /// Pixels whose red, green and blue values are all at or below this are considered black.
+    pub threshold: u8,
+    /// The number of lines that are searched on each side, evenly spread over the side.
+    pub lines: u32,
+}
+
+impl Default for BorderConfig {
+    fn default() -> Self {
+        BorderConfig {
+            threshold: 8,
+            lines: 5,
+        }
+    }
+}
+
+/// The number of black pixels from the start of a line of `len` pixels towards its centre,
+/// assuming the line is black up to the content. This is `None` if it is black up to the centre.
+fn bisect(len: u32, is_black: impl Fn(u32) -> bool) -> Option<u32> {
+    let half = len.div_ceil(2);
+    let (mut low, mut high) = (0, half);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if is_black(mid) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    (low < half).then_some(low)
+}
+
+/// Find the region of the frame within its black borders, like the letterbox of a video or the
+/// pillarbox of a game.
+///
+/// Each side is searched by bisection along a few lines from the edge towards the centre, the
+/// border is the smallest found on a side. This assumes the borders are uniformly black and that
+/// the content covers the centre. The entire frame is returned if it is black along the lines.
+pub fn find_borders(img: &dyn ImageBGR, config: &BorderConfig) -> Rect {
+    let (width, height) = (img.width(), img.height());
+    let whole = Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    if width == 0 || height == 0 {
+        return whole;
+    }
+    let black = |p: BGR| p.r.max(p.g).max(p.b) <= config.threshold;
+    let lines = config.lines.max(1);
+    let spread = |len: u32| {
+        (0..lines).map(move |i| (len as u64 * (i as u64 + 1) / (lines as u64 + 1)) as u32)
+    };
+
+    let side = |len: u32, across: u32, pixel: &dyn Fn(u32, u32) -> BGR| {
+        spread(across)
+            .filter_map(|line| bisect(len, |p| black(pixel(line, p))))
+            .min()
+    };
+    let top = side(height, width, &|x, d| img.pixel(x, d));
+    let bottom = side(height, width, &|x, d| img.pixel(x, height - 1 - d));
+    let left = side(width, height, &|y, d| img.pixel(d, y));
+    let right = side(width, height, &|y, d| img.pixel(width - 1 - d, y));
+    let (Some(top), Some(bottom), Some(left), Some(right)) = (top, bottom, left, right) else {
+        return whole;
+    };
+    Rect {
+        x: left,
+        y: top,
+        width: width - left - right,
+        height: height - top - bottom,
+    }
+}
+
+/// Split a length into `count` parts that cover it without gaps, as start and length.
+fn split(start: u32, len: u32, count: u32) -> impl DoubleEndedIterator<Item = (u32, u32)> {
+    let at = move |i: u32| start + (len as u64 * i as u64 / count as u64) as u32;
+    (0..count).map(move |i| (at(i), at(i + 1) - at(i)))
+}
+
+/// Divide the edges of the rectangle into zones, like the areas that light the leds of a strip
+/// around a display.
+///
+/// The zones are `depth` pixels deep, limited to half the size of the rectangle, and go clockwise
+/// from the top left corner: `horizontal_count` along the top edge from left to right,
+/// `vertical_count` along the right edge from top to bottom, then the bottom and left edges.
+/// The top and bottom zones span the full width, the left and right ones the height between them,
+/// such that the zones don't overlap.
+pub fn zones(rect: Rect, horizontal_count: u32, vertical_count: u32, depth: u32) -> Vec<Rect> {
+    let across = depth.min(rect.width / 2);
+    let down = depth.min(rect.height / 2);
+    let inner = rect.height - 2 * down;
+    let bottom = rect.y + rect.height - down;
+    let right = rect.x + rect.width - across;
+    let horizontal = |y| {
+        split(rect.x, rect.width, horizontal_count).map(move |(x, width)| Rect {
+            x,
+            y,
+            width,
+            height: down,
+        })
+    };
+    let vertical = |x| {
+        split(rect.y + down, inner, vertical_count).map(move |(y, height)| Rect {
+            x,
+            y,
+            width: across,
+            height,
+        })
+    };
+    horizontal(rect.y)
+        .chain(vertical(right))
+        .chain(horizontal(bottom).rev())
+        .chain(vertical(rect.x).rev())
+        .collect()
+}
+
+/// Averages the pixels of zones, like those of [`zones`], from a fixed set of points in each.
+///
+/// The points are computed once, their coordinates are stored in flat arrays with the points of
+/// each zone adjacent, such that sampling is a linear pass over them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampler {
+    xs: Vec<u32>,
+    ys: Vec<u32>,
+    /// The index of the first point of each zone, followed by the number of points.
+    starts: Vec<usize>,
+}
+
+impl Sampler {
+    /// Create a sampler for the zones, with up to `samples_per_zone` points spread evenly over
+    /// each, in a grid of about as many columns as rows. Empty zones have no points.
+    pub fn new(zones: &[Rect], samples_per_zone: u32) -> Sampler {
+        let samples = samples_per_zone.max(1);
+        let columns = (samples as f64).sqrt().ceil() as u32;
+        let rows = samples.div_ceil(columns);
+        let mut sampler = Sampler {
+            xs: vec![],
+            ys: vec![],
+            starts: vec![0],
+        };
+        // The centre of a cell of the grid over the length.
+        let centre = |start: u32, len: u32, i: u32, count: u32| {
+            start + ((2 * i as u64 + 1) * len as u64 / (2 * count as u64)) as u32
+        };
+        for zone in zones {
+            if zone.width > 0 && zone.height > 0 {
+                for i in 0..samples {
+                    let (column, row) = (i % columns, i / columns);
+                    sampler.xs.push(centre(zone.x, zone.width, column, columns));
+                    sampler.ys.push(centre(zone.y, zone.height, row, rows));
+                }
+            }
+            sampler.starts.push(sampler.xs.len());
+        }
+        sampler
+    }
+
+    /// The number of zones.
+    pub fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The average colour of the points of each zone, points outside of the image are skipped.
+    /// Zones without points in the image are black.
+    pub fn sample(&self, img: &dyn ImageBGR) -> Vec<BGR> {
+        let (width, height) = (img.width(), img.height());
+        let data = img.data();
+        self.starts
+            .windows(2)
+            .map(|range| {
+                let (xs, ys) = (&self.xs[range[0]..range[1]], &self.ys[range[0]..range[1]]);
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+                for (&x, &y) in xs.iter().zip(ys) {
+                    if x < width && y < height {
+                        let p = data[y as usize * width as usize + x as usize];
+                        sum[0] += p.b as u32;
+                        sum[1] += p.g as u32;
+                        sum[2] += p.r as u32;
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    return BGR::default();
+                }
+                BGR {
+                    b: (sum[0] / count) as u8,
+                    g: (sum[1] / count) as u8,
+                    r: (sum[2] / count) as u8,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The colours of the zones around the content of frames, for ambient lighting.
+///
+/// This finds the borders of each frame with [`find_borders`], divides the edges of the content
+/// into [`zones`] and averages them with a [`Sampler`]. The sampler is reused while the borders
+/// don't change.
+#[derive(Debug, Clone)]
+pub struct AmbientSampler {
+    border: BorderConfig,
+    horizontal_count: u32,
+    vertical_count: u32,
+    depth: u32,
+    samples_per_zone: u32,
+    cached: Option<(Rect, Sampler)>,
+}
+
+impl AmbientSampler {
+    /// Create a sampler for zones as described by [`zones`], each averaged from up to
+    /// `samples_per_zone` points.
+    pub fn new(
+        horizontal_count: u32,
+        vertical_count: u32,
+        depth: u32,
+        samples_per_zone: u32,
+    ) -> AmbientSampler {
+        AmbientSampler {
+            border: Default::default(),
+            horizontal_count,
+            vertical_count,
+            depth,
+            samples_per_zone,
+            cached: None,
+        }
+    }
+
+    /// Use these settings to find the borders.
+    pub fn with_border_config(mut self, config: BorderConfig) -> AmbientSampler {
+        self.border = config;
+        self.cached = None;
+        self
+    }
+
+    /// The borders found in the most recent frame, `None` before the first.
+    pub fn borders(&self) -> Option<Rect> {
+        self.cached.as_ref().map(|(rect, _)| *rect)
+    }
+
+    /// The colour of each zone of the image, in the order of [`zones`].
+    pub fn sample_image(&mut self, img: &dyn ImageBGR) -> Vec<BGR> {
+        let rect = find_borders(img, &self.border);
+        if self.borders() != Some(rect) {
+            let zones = zones(rect, self.horizontal_count, self.vertical_count, self.depth);
+            self.cached = Some((rect, Sampler::new(&zones, self.samples_per_zone)));
+        }
+        self.cached.as_ref().unwrap().1.sample(img)
+    }
+
+    /// The colour of each zone of a capture, the error if it failed.
+    pub fn sample(&mut self, info: &CaptureInfo) -> Result<Vec<BGR>, ScreenCaptureError> {
+        let frame = info.result.as_ref().map_err(Clone::clone)?;
+        match &**frame {
+            OwnedFrame::Bgr(img) => Ok(self.sample_image(img)),
+            #[cfg(feature = "image")]
+            other => Ok(self.sample_image(&other.to_bgr())),
+        }
+    }
+}
+
+/// Statistics of the brightness and colour of a frame, see [`frame_stats`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct FrameStats {
+    /// The mean relative luminance, from zero for black to one for white.
+    pub luminance: f32,
+    /// The standard deviation of the luminance, the rms contrast.
+    pub contrast: f32,
+    /// The estimated correlated colour temperature of the mean colour in kelvin, `None` for a
+    /// black frame.
+    pub colour_temperature: Option<f32>,
+}
+
+/// The luminance, contrast and colour temperature of the image. The pixels are taken to be srgb,
+/// the colour temperature is estimated with McCamy's approximation, which is meaningful for
+/// colours near white.
+pub fn frame_stats(img: &dyn ImageBGR) -> FrameStats {
+    let data = img.data();
+    if data.is_empty() {
+        return FrameStats::default();
+    }
+    let linear: Vec<f32> = (0..=255u8)
+        .map(|v| {
+            let v = v as f32 / 255.0;
+            if v <= 0.04045 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+    let (mut sum, mut squares) = ([0f64; 3], 0f64);
+    for p in data {
+        let (r, g, b) = (
+            linear[p.r as usize],
+            linear[p.g as usize],
+            linear[p.b as usize],
+        );
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        sum[0] += r as f64;
+        sum[1] += g as f64;
+        sum[2] += b as f64;
+        squares += (y * y) as f64;
+    }
+    let n = data.len() as f64;
+    let [r, g, b] = sum.map(|v| v / n);
+    let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let contrast = (squares / n - luminance * luminance).max(0.0).sqrt();
+
+    let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+    let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+    let total = x + luminance + z;
+    let colour_temperature = (total > 0.0).then(|| {
+        let (cx, cy) = (x / total, luminance / total);
+        let n = (cx - 0.3320) / (0.1858 - cy);
+        (449.0 * n.powi(3) + 3525.0 * n.powi(2) + 6823.3 * n + 5520.33) as f32
+    });
+    FrameStats {
+        luminance: luminance as f32,
+        contrast: contrast as f32,
+        colour_temperature,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::raster_image::RasterImageBGR;
+
+    const WHITE: BGR = BGR {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    #[test]
+    fn test_bisect() {
+        assert_eq!(bisect(10, |p| p < 3), Some(3));
+        assert_eq!(bisect(10, |_| false), Some(0));
+        assert_eq!(bisect(10, |_| true), None);
+        // The centre pixel of an odd line is searched.
+        assert_eq!(bisect(5, |p| p < 2), Some(2));
+        assert_eq!(bisect(0, |_| true), None);
+    }
+
+    #[test]
+    fn test_find_borders() {
+        let config = BorderConfig::default();
+        // Letterboxed, the content has some noise in the border and black pixels of its own.
+        let mut img = RasterImageBGR::filled(64, 36, Default::default());
+        img.fill_rectangle(0, 64, 5, 31, WHITE);
+        img.fill_rectangle(20, 30, 10, 20, Default::default());
+        img.set_pixel(3, 2, BGR { r: 6, g: 6, b: 6 });
+        assert_eq!(
+            find_borders(&img, &config),
+            Rect {
+                x: 0,
+                y: 5,
+                width: 64,
+                height: 26
+            }
+        );
+
+        // Pillarboxed with a border of a different size on each side.
+        let mut img = RasterImageBGR::filled(64, 36, Default::default());
+        img.fill_rectangle(7, 60, 0, 36, WHITE);
+        assert_eq!(
+            find_borders(&img, &config),
+            Rect {
+                x: 7,
+                y: 0,
+                width: 53,
+                height: 36
+            }
+        );
+
+        // The content is found if only one of the lines crosses it early.
+        let mut img = RasterImageBGR::filled(60, 40, Default::default());
+        img.fill_rectangle(10, 50, 10, 30, WHITE);
+        img.fill_rectangle(30, 31, 4, 10, WHITE);
+        assert_eq!(find_borders(&img, &config).y, 4);
+
+        // Entirely black or empty.
+        let img = RasterImageBGR::filled(16, 9, Default::default());
+        assert_eq!(find_borders(&img, &config).width, 16);
+        let img = RasterImageBGR::filled(0, 0, Default::default());
+        assert_eq!(find_borders(&img, &config), Rect::default());
+    }
+
+    #[test]
+    fn test_zones() {
+        let rect = Rect {
+            x: 10,
+            y: 20,
+            width: 100,
+            height: 50,
+        };
+        let edges = zones(rect, 3, 2, 5);
+        assert_eq!(edges.len(), 10);
+        let r = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            edges[..3],
+            [r(10, 20, 33, 5), r(43, 20, 33, 5), r(76, 20, 34, 5)]
+        );
+        assert_eq!(edges[3..5], [r(105, 25, 5, 20), r(105, 45, 5, 20)]);
+        assert_eq!(edges[5], r(76, 65, 34, 5));
+        assert_eq!(edges[9], r(10, 25, 5, 20));
+        // The zones cover the edges exactly once.
+        let area: u32 = edges.iter().map(|z| z.width * z.height).sum();
+        assert_eq!(area, 100 * 50 - 90 * 40);
+
+        // The depth is limited to half of the rectangle.
+        let edges = zones(r(0, 0, 8, 4), 1, 1, 10);
+        assert_eq!(edges[0], r(0, 0, 8, 2));
+        assert_eq!(edges[1], r(4, 2, 4, 0));
+        assert!(zones(rect, 0, 0, 5).is_empty());
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut img = RasterImageBGR::filled(20, 10, Default::default());
+        // The left half is red, the right half green, the bottom right quarter is blue.
+        img.fill_rectangle(0, 10, 0, 10, BGR { r: 200, g: 0, b: 0 });
+        img.fill_rectangle(10, 20, 0, 10, BGR { r: 0, g: 100, b: 0 });
+        img.fill_rectangle(15, 20, 5, 10, BGR { r: 0, g: 0, b: 40 });
+        let r = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let sampler = Sampler::new(
+            &[
+                r(0, 0, 10, 10),
+                r(10, 0, 10, 10),
+                r(5, 0, 10, 2),
+                r(0, 0, 0, 5),
+                r(30, 0, 5, 5),
+            ],
+            4,
+        );
+        assert_eq!(sampler.len(), 5);
+        assert_eq!(
+            sampler.sample(&img),
+            vec![
+                BGR { r: 200, g: 0, b: 0 },
+                // One of the four points is blue.
+                BGR { r: 0, g: 75, b: 10 },
+                BGR {
+                    r: 100,
+                    g: 50,
+                    b: 0
+                },
+                BGR::default(),
+                BGR::default(),
+            ]
+        );
+        // A single sample is the centre of the zone.
+        let sampler = Sampler::new(&[r(12, 4, 6, 6)], 1);
+        assert_eq!(sampler.sample(&img), vec![BGR { r: 0, g: 0, b: 40 }]);
+    }
+
+    #[test]
+    fn test_ambient_sampler() {
+        use crate::OutputFormat;
+        use std::sync::Arc;
+        // Letterboxed content, red at the top and blue at the bottom.
+        let mut img = RasterImageBGR::filled(40, 30, Default::default());
+        img.fill_rectangle(0, 40, 5, 15, BGR { r: 90, g: 0, b: 0 });
+        img.fill_rectangle(0, 40, 15, 25, BGR { r: 0, g: 0, b: 60 });
+        let mut ambient = AmbientSampler::new(2, 1, 3, 4);
+        let info = CaptureInfo {
+            result: Ok(Arc::new(OwnedFrame::from_image(&img, OutputFormat::Bgr))),
+            ..Default::default()
+        };
+        let colours = ambient.sample(&info).unwrap();
+        assert_eq!(
+            ambient.borders(),
+            Some(Rect {
+                x: 0,
+                y: 5,
+                width: 40,
+                height: 20
+            })
+        );
+        assert_eq!(colours.len(), 6);
+        let red = BGR { r: 90, g: 0, b: 0 };
+        let blue = BGR { r: 0, g: 0, b: 60 };
+        assert_eq!(&colours[..2], &[red, red]);
+        assert_eq!(&colours[3..5], &[blue, blue]);
+        // The sides are half red and half blue.
+        assert_eq!(colours[2], BGR { r: 45, g: 0, b: 30 });
+
+        // The same borders reuse the sampler, different ones replace it.
+        let cached = ambient.cached.clone();
+        ambient.sample_image(&img);
+        assert_eq!(ambient.cached, cached);
+        img.fill_rectangle(0, 40, 0, 5, red);
+        ambient.sample_image(&img);
+        assert_eq!(ambient.borders().unwrap().y, 0);
+
+        let failed = CaptureInfo {
+            result: Err(ScreenCaptureError::Timeout),
+            ..Default::default()
+        };
+        assert_eq!(ambient.sample(&failed), Err(ScreenCaptureError::Timeout));
+    }
+
+    #[test]
+    fn test_frame_stats() {
+        let white = RasterImageBGR::filled(4, 4, WHITE);
+        let stats = frame_stats(&white);
+        assert!((stats.luminance - 1.0).abs() < 1e-3);
+        assert!(stats.contrast < 1e-3);
+        // The white point of srgb is D65.
+        let temperature = stats.colour_temperature.unwrap();
+        assert!((temperature - 6504.0).abs() < 20.0, "{temperature}");
+
+        // Half black and half white.
+        let mut img = RasterImageBGR::filled(4, 4, Default::default());
+        img.fill_rectangle(0, 2, 0, 4, WHITE);
+        let stats = frame_stats(&img);
+        assert!((stats.luminance - 0.5).abs() < 1e-3);
+        assert!((stats.contrast - 0.5).abs() < 1e-3);
+
+        let warm = RasterImageBGR::filled(
+            2,
+            2,
+            BGR {
+                r: 255,
+                g: 200,
+                b: 150,
+            },
+        );
+        let cool = RasterImageBGR::filled(
+            2,
+            2,
+            BGR {
+                r: 200,
+                g: 220,
+                b: 255,
+            },
+        );
+        let warm = frame_stats(&warm).colour_temperature.unwrap();
+        let cool = frame_stats(&cool).colour_temperature.unwrap();
+        assert!(warm < 4000.0 && cool > 8000.0, "{warm} {cool}");
+
+        let black = RasterImageBGR::filled(2, 2, Default::default());
+        assert_eq!(frame_stats(&black).colour_temperature, None);
+        let empty = RasterImageBGR::filled(0, 0, Default::default());
+        assert_eq!(frame_stats(&empty), FrameStats::default());
+    }
+}
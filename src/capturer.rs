@@ -1,13 +1,20 @@
 //! Helpers to select a configuration based on the resolution.
 
-use crate::{Capture, ImageBGR, Resolution};
+use crate::error::{ConfigError, ThreadError};
+use crate::frame::{FrameData, ProcessedFrame};
+use crate::{
+    BackendOptions, Capture, DisplayInfo, ImageBGR, OutputFormat, OwnedFrame, Rect, Resolution,
+    ScreenCaptureError, WindowInfo,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Capture specification that conditionally applies.
 ///
 /// If `match_*` is populated and matches the resolution's value it will be
 /// considered to match and the capture will be setup according to the other fields.
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Copy, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CaptureSpecification {
     /// The resolution's width to match to.
     pub match_width: Option<u32>,
@@ -15,12 +22,69 @@ pub struct CaptureSpecification {
     /// The resolution's height to match to.
     pub match_height: Option<u32>,
 
+    /// The minimum resolution's width to match to, inclusive.
     #[serde(default)]
-    /// The x offset to apply for this specification.
-    pub x: u32,
-    /// The y offset to apply for this specification.
+    pub match_width_min: Option<u32>,
+    /// The maximum resolution's width to match to, inclusive.
     #[serde(default)]
-    pub y: u32,
+    pub match_width_max: Option<u32>,
+
+    /// The minimum resolution's height to match to, inclusive.
+    #[serde(default)]
+    pub match_height_min: Option<u32>,
+    /// The maximum resolution's height to match to, inclusive.
+    #[serde(default)]
+    pub match_height_max: Option<u32>,
+
+    /// The minimum aspect ratio (width / height) of the resolution to match to, inclusive. An
+    /// ultrawide 21:9 display can be matched with a minimum of 2.3 and maximum of 2.5, as the
+    /// common resolutions are not exactly 21:9.
+    #[serde(default)]
+    pub match_aspect_min: Option<f32>,
+    /// The maximum aspect ratio (width / height) of the resolution to match to, inclusive.
+    #[serde(default)]
+    pub match_aspect_max: Option<f32>,
+
+    /// The name of the display to match to, `*` matches any sequence of characters and `?` any
+    /// single character. If a display matches, `display` is set to its index.
+    #[serde(default)]
+    pub match_display_name: Option<String>,
+
+    /// The number of connected displays to match to, to distinguish a docked laptop from an
+    /// undocked one. Displays that span the others, like the entire X11 screen, don't count.
+    #[serde(default)]
+    pub match_display_count: Option<u32>,
+
+    /// The scale factor of the display to match to, like `1.5` for 150%. High DPI displays often
+    /// have the same resolution as regular ones, but need different regions.
+    #[serde(default)]
+    pub match_scale: Option<f32>,
+
+    /// The title of a window to capture, `*` matches any sequence of characters and `?` any single
+    /// character. If a visible window matches, `display` and the region are set to the contents
+    /// of the first such window, in pixels, and follow it as it moves. See [`Capture::windows`].
+    #[serde(default)]
+    pub match_window_title: Option<String>,
+
+    /// The class of a window to capture, like `mpv`, with the same patterns and behaviour as
+    /// `match_window_title`. If both are set the window has to match both.
+    #[serde(default)]
+    pub match_window_class: Option<String>,
+
+    /// Whether the offsets and sizes are in logical units, which are multiplied by the scale
+    /// factor of the display to obtain pixels. Merged specifications are converted with their own
+    /// setting before they are merged.
+    #[serde(default)]
+    pub logical: bool,
+
+    #[serde(default)]
+    /// The x offset to apply for this specification, a negative offset is relative to the right
+    /// edge, such that `-800` captures the rightmost 800 pixels if the width is zero.
+    pub x: i32,
+    /// The y offset to apply for this specification, a negative offset is relative to the bottom
+    /// edge.
+    #[serde(default)]
+    pub y: i32,
 
     /// The width to apply for this specification, set to the resolutions' width - x if zero.
     #[serde(default)]
@@ -29,363 +93,2915 @@ pub struct CaptureSpecification {
     #[serde(default)]
     pub height: u32,
 
+    /// The x offset as a fraction of the width, overrides `x` if set.
+    #[serde(default)]
+    pub x_frac: Option<f32>,
+    /// The y offset as a fraction of the height, overrides `y` if set.
+    #[serde(default)]
+    pub y_frac: Option<f32>,
+
+    /// The width as a fraction of the width, overrides `width` if set.
+    #[serde(default)]
+    pub width_frac: Option<f32>,
+    /// The height as a fraction of the height, overrides `height` if set.
+    #[serde(default)]
+    pub height_frac: Option<f32>,
+
     /// The display to set the capture setup to.
     #[serde(default)]
     pub display: u32,
+
+    /// The capture rate while this specification is active, overriding [`CaptureConfig::rate`].
+    /// Used only if [`ThreadedCapturer`] is used, where it can't enable capturing if the
+    /// configuration's rate disables it.
+    #[serde(default)]
+    pub rate: Option<f32>,
+
+    /// The format in which frames are delivered while this specification is active, overriding
+    /// [`CaptureConfig::output_format`]. Used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Areas to black out before frames are delivered, like notification popups that should not
+    /// end up in recordings. These are in the same coordinates as `x` and `y`, so they stay in
+    /// place if the region changes. Used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub mask: Vec<Rect>,
+
+    /// Of all matching specifications the one with the highest priority is used, the first one
+    /// if several share that priority.
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Instead of competing with the other specifications, apply the fields that are set on top
+    /// of the selected specification when this one matches. Zero offsets and sizes are
+    /// considered unset, masks are added to those of the selected specification and the
+    /// `match_*` fields and the priority are never applied. This allows a
+    /// base specification with small tweaks for particular resolutions.
+    #[serde(default)]
+    pub merge: bool,
+}
+
+/// A specification that was considered by [`CaptureSpecification::select`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecificationCandidate {
+    /// The index of the specification.
+    pub index: usize,
+    /// The priority of the specification.
+    pub priority: i32,
+    /// Why the specification didn't match, `None` if it matched.
+    pub rejection: Option<String>,
+}
+
+/// The outcome of [`CaptureSpecification::select`], the [`std::fmt::Display`] implementation
+/// explains why the specification was selected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecificationSelection {
+    /// The index of the selected specification, `None` if none matched and the entire display
+    /// is captured.
+    pub index: Option<usize>,
+    /// The selected specification with the merged ones applied, populated for the resolution.
+    pub specification: CaptureSpecification,
+    /// The indices of the matching specifications that were merged into the selected one.
+    pub merged: Vec<usize>,
+    /// All specifications, in order.
+    pub candidates: Vec<SpecificationCandidate>,
+}
+
+impl std::fmt::Display for SpecificationSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let matching: Vec<usize> = self
+            .candidates
+            .iter()
+            .filter(|c| c.rejection.is_none())
+            .map(|c| c.index)
+            .collect();
+        match self.index {
+            Some(index) => write!(
+                f,
+                "selected specification {} with priority {}, matching: {:?}",
+                index, self.candidates[index].priority, matching
+            )?,
+            None => write!(f, "no specification matched, capturing the entire display")?,
+        }
+        if !self.merged.is_empty() {
+            write!(f, ", merged: {:?}", self.merged)?;
+        }
+        for candidate in self.candidates.iter() {
+            if let Some(rejection) = candidate.rejection.as_ref() {
+                write!(f, "; {}: {}", candidate.index, rejection)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl CaptureSpecification {
     /// Iterates through the specs to find the best one, augmends the missing or 0 values and returns it.
+    /// Fractions and anchored offsets are resolved into pixels, so the returned specification has
+    /// no fractions set and non-negative offsets.
     /// See the documentation of [`CaptureSpecification`] for further information.
     pub fn get_config(
         width: u32,
         height: u32,
         specs: &[CaptureSpecification],
     ) -> CaptureSpecification {
-        for spec in specs.iter() {
-            let mut matches = true;
-            if let Some(match_width) = spec.match_width {
-                matches &= match_width == width;
-            }
-            if let Some(match_height) = spec.match_height {
-                matches &= match_height == height;
-            }
-            if !matches {
-                continue;
-            }
+        Self::get_config_for_displays(width, height, &[], specs)
+    }
 
-            // We found the best match, copy this and populate it as best we can.
-            let mut populated: CaptureSpecification = *spec;
-            populated.width = if populated.width == 0 {
-                width - populated.x
-            } else {
-                populated.width
-            };
-            populated.height = if populated.height == 0 {
-                height - populated.y
+    /// Resolve the fractions and anchored offsets into pixels and populate the missing width and
+    /// height for a display of the provided size.
+    fn populate(&self, width: u32, height: u32) -> CaptureSpecification {
+        let fraction = |v: f32, total: u32| (v.clamp(0.0, 1.0) * total as f32).round() as u32;
+        let anchored = |v: i32, total: u32| {
+            if v < 0 {
+                total.saturating_sub(v.unsigned_abs()) as i32
             } else {
-                populated.height
-            };
-            return populated;
+                v
+            }
+        };
+        let mut populated = self.clone();
+        populated.x = anchored(populated.x, width);
+        populated.y = anchored(populated.y, height);
+        if let Some(v) = populated.x_frac.take() {
+            populated.x = fraction(v, width) as i32;
+        }
+        if let Some(v) = populated.y_frac.take() {
+            populated.y = fraction(v, height) as i32;
+        }
+        if let Some(v) = populated.width_frac.take() {
+            populated.width = fraction(v, width);
+        }
+        if let Some(v) = populated.height_frac.take() {
+            populated.height = fraction(v, height);
         }
+        if populated.width == 0 {
+            populated.width = width.saturating_sub(populated.x as u32);
+        }
+        if populated.height == 0 {
+            populated.height = height.saturating_sub(populated.y as u32);
+        }
+        populated
+    }
 
-        // No capture match found... well, return some sane default then.
-        CaptureSpecification {
-            width,
-            height,
-            ..Default::default()
+    /// Apply the fields of this specification that are set on top of the provided one, with the
+    /// display this specification resolved to.
+    fn merge_onto(&self, base: &mut CaptureSpecification, display: u32) {
+        if self.x != 0 {
+            base.x = self.x;
+            base.x_frac = None;
+        }
+        if self.y != 0 {
+            base.y = self.y;
+            base.y_frac = None;
+        }
+        if self.width != 0 {
+            base.width = self.width;
+            base.width_frac = None;
         }
+        if self.height != 0 {
+            base.height = self.height;
+            base.height_frac = None;
+        }
+        base.x_frac = self.x_frac.or(base.x_frac);
+        base.y_frac = self.y_frac.or(base.y_frac);
+        base.width_frac = self.width_frac.or(base.width_frac);
+        base.height_frac = self.height_frac.or(base.height_frac);
+        if display != 0 {
+            base.display = display;
+        }
+        base.rate = self.rate.or(base.rate);
+        base.output_format = self.output_format.or(base.output_format);
+        base.mask.extend(self.mask.iter().copied());
     }
-}
 
-/// Configuration struct, specifying all the configurable properties of the displaylight struct..
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
-pub struct CaptureConfig {
-    /// A list of capture specifications, the first one to match will be used.
-    pub capture: Vec<CaptureSpecification>,
+    /// Convert the offsets and sizes from logical units into pixels if they are logical.
+    fn to_physical(&self, scale: f32) -> CaptureSpecification {
+        let mut physical = self.clone();
+        if physical.logical {
+            physical.x = (self.x as f32 * scale).round() as i32;
+            physical.y = (self.y as f32 * scale).round() as i32;
+            physical.width = (self.width as f32 * scale).round() as u32;
+            physical.height = (self.height as f32 * scale).round() as u32;
+            let scaled = |v: u32| (v as f32 * scale).round() as u32;
+            for rect in physical.mask.iter_mut() {
+                *rect = Rect {
+                    x: scaled(rect.x),
+                    y: scaled(rect.y),
+                    width: scaled(rect.width),
+                    height: scaled(rect.height),
+                };
+            }
+            physical.logical = false;
+        }
+        physical
+    }
 
-    /// A rate, used only if [`ThreadedCapturer`] is used.
-    pub rate: f32,
-}
+    /// Whether this specification captures a window, see
+    /// [`CaptureSpecification::match_window_title`].
+    pub fn matches_windows(&self) -> bool {
+        self.match_window_title.is_some() || self.match_window_class.is_some()
+    }
 
-/// Helper struct to use the capture object to grab according to configuration.
-pub struct Capturer {
-    pub config: CaptureConfig,
-    pub grabber: Box<dyn Capture>,
-    pub cached_resolution: Option<Resolution>,
-}
+    /// The first of the windows that matches the title and class patterns.
+    fn find_window<'a>(&self, windows: &'a [WindowInfo]) -> Option<&'a WindowInfo> {
+        let matches = |pattern: &Option<String>, text: &str| {
+            pattern.as_ref().is_none_or(|p| glob_match(p, text))
+        };
+        windows.iter().find(|w| {
+            matches(&self.match_window_title, &w.title)
+                && matches(&self.match_window_class, &w.class)
+        })
+    }
 
-impl Capturer {
-    /// Instantiate a new capture grabber with configuration.
-    pub fn new(config: CaptureConfig) -> Capturer {
-        Self {
-            config,
-            grabber: crate::capture(),
-            cached_resolution: None,
+    /// Whether the `match_*` fields that concern the resolution match the provided resolution.
+    pub fn matches_resolution(&self, width: u32, height: u32) -> bool {
+        self.resolution_mismatch(width, height).is_none()
+    }
+
+    /// Describe the first `match_*` field that doesn't match the resolution, if any.
+    fn resolution_mismatch(&self, width: u32, height: u32) -> Option<String> {
+        let aspect = width as f32 / height.max(1) as f32;
+        match (self.match_width, self.match_height) {
+            (Some(v), _) if v != width => return Some(format!("width {width} is not {v}")),
+            (_, Some(v)) if v != height => return Some(format!("height {height} is not {v}")),
+            _ => {}
+        }
+        match (self.match_width_min, self.match_width_max) {
+            (Some(v), _) if width < v => return Some(format!("width {width} is below {v}")),
+            (_, Some(v)) if width > v => return Some(format!("width {width} is above {v}")),
+            _ => {}
+        }
+        match (self.match_height_min, self.match_height_max) {
+            (Some(v), _) if height < v => return Some(format!("height {height} is below {v}")),
+            (_, Some(v)) if height > v => return Some(format!("height {height} is above {v}")),
+            _ => {}
+        }
+        match (self.match_aspect_min, self.match_aspect_max) {
+            (Some(v), _) if aspect < v => Some(format!("aspect ratio {aspect:.3} is below {v}")),
+            (_, Some(v)) if aspect > v => Some(format!("aspect ratio {aspect:.3} is above {v}")),
+            _ => None,
         }
     }
 
-    /// Update the capture configuration according to the latest resolution.
+    /// Like [`CaptureSpecification::get_config`], but also matches against the provided displays.
     ///
-    /// Returns true if the configuration changed.
-    pub fn update_resolution(&mut self) -> bool {
-        // First, check if the resolution of the desktop environment has changed, if so, act.
-        let current_resolution = self.grabber.resolution();
-        let old_resolution = self.cached_resolution;
+    /// Missing width and height are populated from the size of the selected display if it is
+    /// among the displays, and from the resolution otherwise.
+    pub fn get_config_for_displays(
+        width: u32,
+        height: u32,
+        displays: &[DisplayInfo],
+        specs: &[CaptureSpecification],
+    ) -> CaptureSpecification {
+        Self::select(width, height, displays, specs).specification
+    }
 
-        if self.cached_resolution.is_none()
-            || *self.cached_resolution.as_ref().unwrap() != current_resolution
-        {
-            let width = current_resolution.width;
-            let height = current_resolution.height;
+    /// Select the specification to use, reporting which specifications matched and why the
+    /// others didn't. See [`CaptureSpecification::get_config_for_displays`].
+    pub fn select(
+        width: u32,
+        height: u32,
+        displays: &[DisplayInfo],
+        specs: &[CaptureSpecification],
+    ) -> SpecificationSelection {
+        Self::select_with_windows(width, height, displays, &[], specs)
+    }
 
-            // Resolution has changed, figure out the best match in our configurations and
-            // prepare the capture accordingly.
-            let config = CaptureSpecification::get_config(width, height, &self.config.capture);
-
-            self.grabber.prepare_capture(
-                config.display,
-                config.x,
-                config.y,
-                config.width,
-                config.height,
-            );
-            // Store the current resolution.
-            self.cached_resolution = Some(current_resolution);
+    /// Like [`CaptureSpecification::select`], but also matches against the provided windows,
+    /// see [`CaptureSpecification::match_window_title`].
+    pub fn select_with_windows(
+        width: u32,
+        height: u32,
+        displays: &[DisplayInfo],
+        windows: &[WindowInfo],
+        specs: &[CaptureSpecification],
+    ) -> SpecificationSelection {
+        let mut candidates = vec![];
+        // The index of the best match and the specification with the display and window resolved.
+        let mut best: Option<(usize, CaptureSpecification)> = None;
+        // The matching specifications to merge, resolved like the best match.
+        let mut merged: Vec<(usize, CaptureSpecification)> = vec![];
+        for (index, spec) in specs.iter().enumerate() {
+            let mut rejection = spec.resolution_mismatch(width, height);
+            let mut resolved = spec.clone();
+            if let (None, Some(count)) = (&rejection, spec.match_display_count) {
+                let connected = displays.iter().filter(|d| !d.combined).count() as u32;
+                if connected != count {
+                    rejection = Some(format!("{connected} displays connected, not {count}"));
+                }
+            }
+            if let (None, Some(pattern)) = (&rejection, spec.match_display_name.as_ref()) {
+                match displays.iter().find(|d| glob_match(pattern, &d.name)) {
+                    Some(info) => resolved.display = info.index,
+                    None => rejection = Some(format!("no display named like \"{pattern}\"")),
+                }
+            }
+            if rejection.is_none() && spec.matches_windows() {
+                let region = spec.find_window(windows).map(|w| {
+                    let region = Self::for_desktop_region(w.x, w.y, w.width, w.height, displays);
+                    (w, region)
+                });
+                match region {
+                    Some((_, Some(region))) => {
+                        resolved.display = region.display;
+                        resolved.x = region.x;
+                        resolved.y = region.y;
+                        resolved.width = region.width;
+                        resolved.height = region.height;
+                        resolved.x_frac = None;
+                        resolved.y_frac = None;
+                        resolved.width_frac = None;
+                        resolved.height_frac = None;
+                        resolved.logical = false;
+                    }
+                    Some((w, None)) => {
+                        rejection =
+                            Some(format!("window \"{}\" is on none of the displays", w.title))
+                    }
+                    None => rejection = Some("no matching window".to_owned()),
+                }
+            }
+            if let (None, Some(expected)) = (&rejection, spec.match_scale) {
+                let display = resolved.display;
+                match displays
+                    .iter()
+                    .find(|d| d.index == display && d.scale > 0.0)
+                {
+                    Some(info) if (info.scale - expected).abs() < 0.01 => {}
+                    Some(info) => {
+                        rejection = Some(format!("scale {} is not {expected}", info.scale))
+                    }
+                    None => rejection = Some(format!("scale of display {display} is unknown")),
+                }
+            }
+            if rejection.is_none() && spec.merge {
+                merged.push((index, resolved));
+            } else if rejection.is_none()
+                && best
+                    .as_ref()
+                    .map(|(b, _)| spec.priority > specs[*b].priority)
+                    .unwrap_or(true)
+            {
+                best = Some((index, resolved));
+            }
+            candidates.push(SpecificationCandidate {
+                index,
+                priority: spec.priority,
+                rejection,
+            });
+        }
+
+        let base = match best.as_ref() {
+            Some((_, resolved)) => resolved.clone(),
+            None => CaptureSpecification::default(),
+        };
+        // The last merged specification with a display determines the display, each is converted
+        // into pixels with its scale before merging, as they may differ in being logical.
+        let display = merged
+            .iter()
+            .map(|(_, resolved)| resolved.display)
+            .rfind(|d| *d != 0)
+            .unwrap_or(base.display);
+        let info = displays.iter().find(|d| d.index == display);
+        let scale = info.map(|d| d.scale).filter(|v| *v > 0.0).unwrap_or(1.0);
+        let combined =
+            merged
+                .iter()
+                .fold(base.to_physical(scale), |mut combined, (_, resolved)| {
+                    resolved
+                        .to_physical(scale)
+                        .merge_onto(&mut combined, resolved.display);
+                    combined
+                });
+        let specification = if best.is_none() && merged.is_empty() {
+            // No capture match found... well, return some sane default then.
+            CaptureSpecification {
+                width,
+                height,
+                ..Default::default()
+            }
+        } else {
+            // Populate the match as best we can.
+            let (width, height) = info.map(|d| (d.width, d.height)).unwrap_or((width, height));
+            combined.populate(width, height)
+        };
+        SpecificationSelection {
+            index: best.map(|(index, _)| index),
+            specification,
+            merged: merged.into_iter().map(|(index, _)| index).collect(),
+            candidates,
         }
-        old_resolution != self.cached_resolution
     }
 
-    /// Set the configuration and re-initialise appropriately.
-    pub fn set_config(&mut self, config: CaptureConfig) {
-        self.cached_resolution = None; // force reinitialisation.
-        self.config = config;
+    /// The specification capturing a region of the desktop, on the display that contains the
+    /// centre of the region. Displays that span the others are only used if no other display
+    /// contains the centre. The region is clipped to the display, `None` if it is empty or on
+    /// none of the displays.
+    pub fn for_desktop_region(
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        displays: &[DisplayInfo],
+    ) -> Option<CaptureSpecification> {
+        let centre = (x as i64 + width as i64 / 2, y as i64 + height as i64 / 2);
+        let contains = |d: &&DisplayInfo| {
+            (d.x as i64..d.x as i64 + d.width as i64).contains(&centre.0)
+                && (d.y as i64..d.y as i64 + d.height as i64).contains(&centre.1)
+        };
+        let display = displays
+            .iter()
+            .filter(|d| !d.combined)
+            .find(contains)
+            .or_else(|| displays.iter().filter(|d| d.combined).find(contains))?;
+        let left = x.max(display.x);
+        let top = y.max(display.y);
+        let right = (x as i64 + width as i64).min(display.x as i64 + display.width as i64);
+        let bottom = (y as i64 + height as i64).min(display.y as i64 + display.height as i64);
+        if right <= left as i64 || bottom <= top as i64 {
+            return None;
+        }
+        Some(CaptureSpecification {
+            display: display.index,
+            x: left - display.x,
+            y: top - display.y,
+            width: (right - left as i64) as u32,
+            height: (bottom - top as i64) as u32,
+            ..Default::default()
+        })
     }
 
-    /// Get the current config.
-    pub fn config(&self) -> CaptureConfig {
-        self.config.clone()
+    /// The specification capturing the contents of the window, like one of the application
+    /// itself from winit or tao, `displays` are those of the backend that captures it. The
+    /// window is located once, so call this again after it moved or resized.
+    ///
+    /// This supports Win32 windows on Windows and Xlib or XCB windows on Linux.
+    #[cfg(feature = "raw-window-handle")]
+    pub fn for_window(
+        window: raw_window_handle::RawWindowHandle,
+        displays: &[DisplayInfo],
+    ) -> Result<CaptureSpecification, ScreenCaptureError> {
+        let (x, y, width, height) = crate::backend::window_region(window)?;
+        Self::for_desktop_region(x, y, width, height, displays).ok_or_else(|| {
+            ScreenCaptureError::Other(
+                format!("the window at {x},{y} of {width}x{height} is on none of the displays")
+                    .into(),
+            )
+        })
     }
+}
 
-    /// Update the resolution and capture a new image.
-    pub fn capture(&mut self) -> Result<Box<dyn ImageBGR>, ()> {
-        self.update_resolution();
+/// Match the text against a pattern in which `*` matches any sequence of characters and `?` any
+/// single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last star in the pattern and the text position it is matched up to.
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the star consume one more character and retry.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
 
-        // Now, we are ready to try and get the image:
-        let res = self.grabber.capture_image();
+/// Priority of the capture thread, relative to the other threads.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ThreadPriority {
+    Lowest,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    Highest,
+}
 
-        if !res {
-            return Err(());
-        }
+/// Settings for the capture thread of the [`ThreadedCapturer`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ThreadConfig {
+    /// The name of the thread, this is only applied when the thread is spawned. Defaults to
+    /// [`ThreadConfig::DEFAULT_NAME`].
+    #[serde(default)]
+    pub name: Option<String>,
 
-        // Then, we can grab the actual image.
-        self.grabber.image()
-    }
+    /// The priority of the thread, left unchanged if not set.
+    ///
+    /// On Linux this sets the niceness of the thread, raising the priority above normal requires
+    /// the `CAP_SYS_NICE` capability.
+    #[serde(default)]
+    pub priority: Option<ThreadPriority>,
+
+    /// The cores the thread is allowed to run on, left unchanged if not set. Cores from 1024 on
+    /// Linux and from 64 on Windows are rejected.
+    #[serde(default)]
+    pub affinity: Option<Vec<usize>>,
 }
 
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::Relaxed;
-use std::sync::mpsc::{channel, Sender};
-use std::sync::{Arc, Mutex};
+impl ThreadConfig {
+    /// The name of the capture thread if none is set.
+    pub const DEFAULT_NAME: &'static str = "screen-capture";
 
-#[derive(PartialEq, Clone)]
-pub struct CaptureInfo {
-    /// The result of the capture.
-    pub result: Result<Arc<image::RgbaImage>, ()>,
+    /// Apply the priority and affinity to the calling thread.
+    pub fn apply(&self) -> Result<(), ScreenCaptureError> {
+        if let Some(priority) = self.priority {
+            crate::backend::set_thread_priority(priority)?;
+        }
+        if let Some(affinity) = self.affinity.as_ref() {
+            crate::backend::set_thread_affinity(affinity)?;
+        }
+        Ok(())
+    }
+}
 
-    /// The time at which the capture was triggered.
-    pub time: std::time::SystemTime,
+/// Determines when the [`ThreadedCapturer`] starts each capture.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ScheduleMode {
+    /// Captures start an interval after the start of the previous capture, if a capture takes
+    /// longer than the interval the next one starts right away. This keeps the rate, but the
+    /// moments of capture drift relative to the wall clock.
+    #[default]
+    Interval,
 
-    /// The duration it took to capture and process the image combined.
-    pub duration: std::time::Duration,
+    /// Captures start an interval after the previous capture finished, such that there is always
+    /// at least the interval between captures. The effective rate is lower than the configured
+    /// rate by the time it takes to capture.
+    IntervalAfterFinish,
 
-    /// The frame identifier as a counter, this increases for each capture() invocation.
-    pub counter: usize,
+    /// Captures are aligned to multiples of the interval on the wall clock, at 5 Hz captures
+    /// happen at `:00.000`, `:00.200`, `:00.400` and so on. Slots that are missed because a
+    /// capture took too long are skipped.
+    FixedCadence,
+
+    /// Captures start as soon as the previous one finished, leaving the pacing to the backend.
+    /// The Desktop Duplication API blocks until a new frame is available, on X11 this captures
+    /// as fast as possible. The rate still needs to be positive to enable capturing.
+    FrameDriven,
 }
 
-impl std::fmt::Debug for CaptureInfo {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        fmt.debug_struct("CaptureInfo")
-            .field(
-                "result",
-                &self
-                    .result
-                    .as_ref()
-                    .map(|v| format!("Image<{}x{}>", v.width(), v.height())),
-            )
-            .field("time", &self.time)
-            .field("duration", &self.duration)
-            .field("counter", &self.counter)
-            .finish()
-    }
+/// Settings for the session lock detection of the [`ThreadedCapturer`], see
+/// [`crate::session_locked`].
+///
+/// The lock state is polled on a separate thread if pausing is enabled or a session callback is
+/// registered, see [`ThreadedCapturer::add_session_callback`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionConfig {
+    /// Pause capturing while the session is locked, the lock screen can't be captured anyway.
+    #[serde(default)]
+    pub pause_when_locked: bool,
+
+    /// Time in seconds between polls of the lock state, zero or an invalid value uses one second.
+    #[serde(default)]
+    pub poll_interval: f32,
 }
 
-impl Default for CaptureInfo {
-    fn default() -> Self {
-        Self {
-            result: Err(()),
-            time: std::time::SystemTime::now(),
-            duration: std::time::Duration::new(0, 0),
-            counter: 0,
+impl SessionConfig {
+    /// The interval at which the lock state is polled.
+    fn interval(&self) -> std::time::Duration {
+        match std::time::Duration::try_from_secs_f32(self.poll_interval) {
+            Ok(interval) if !interval.is_zero() => interval,
+            _ => std::time::Duration::from_secs(1),
         }
     }
 }
 
-pub struct ThreadedCapturer {
-    thread: Option<std::thread::JoinHandle<()>>,
-    running: Arc<AtomicBool>,
-    latest: Arc<Mutex<CaptureInfo>>,
-    sender_config: Sender<CaptureConfig>,
-    sender_pre: Sender<PreCallback>,
-    sender_post: Sender<PostCallback>,
-    /// Pointer to the current config.
-    config: Arc<Mutex<CaptureConfig>>,
+/// A change of the session's lock state, see [`ThreadedCapturer::add_session_callback`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    Locked,
+    Unlocked,
 }
-pub type PreCallback = Arc<dyn Fn(usize) -> () + Send + Sync + 'static>;
-pub type PostCallback = Arc<dyn Fn(CaptureInfo) -> () + Send + Sync + 'static>;
 
-impl Drop for ThreadedCapturer {
-    fn drop(&mut self) {
-        self.running
-            .store(false, std::sync::atomic::Ordering::Relaxed);
-        let t = self.thread.take();
-        t.unwrap().join().expect("join should succeed");
-    }
+/// Settings for the watchdog of the [`ThreadedCapturer`].
+///
+/// The watchdog restarts the capture thread with the current configuration if it panicked, for
+/// example from within a callback, or if it stalled. Each restart is reported to the error
+/// callbacks. Registered callbacks and the processor are retained, the capture backends are
+/// recreated.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WatchdogConfig {
+    /// Whether to use the watchdog, this is only applied when the capturer is created.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Time in seconds without progress after which the capture thread is considered stalled,
+    /// zero or an invalid value disables stall detection. This must be longer than any callback
+    /// or capture takes. A stalled thread can't be interrupted, it exits when it eventually
+    /// resumes.
+    #[serde(default)]
+    pub stall_timeout: f32,
 }
 
-impl Default for ThreadedCapturer {
-    fn default() -> Self {
-        ThreadedCapturer::new(Default::default())
+impl WatchdogConfig {
+    /// The stall timeout, `None` if stall detection is disabled.
+    fn stall_limit(&self) -> Option<std::time::Duration> {
+        std::time::Duration::try_from_secs_f32(self.stall_timeout)
+            .ok()
+            .filter(|limit| !limit.is_zero())
     }
 }
-impl ThreadedCapturer {
-    /// Instantiate a new capture grabber with configuration.
-    pub fn new(config: CaptureConfig) -> ThreadedCapturer {
-        let running: Arc<AtomicBool> = Arc::new(true.into());
-        let latest = Arc::new(Mutex::new(CaptureInfo::default()));
-        let running_t = Arc::clone(&running);
-        let latest_t = Arc::clone(&latest);
-        let config_initial = config.clone();
-        let config = Arc::new(Mutex::new(config));
-        let config_t = Arc::clone(&config);
-        let (sender_config, receiver_config) = channel::<CaptureConfig>();
-        let (sender_pre, receiver_pre) = channel::<PreCallback>();
-        let (sender_post, receiver_post) = channel::<PostCallback>();
-        let thread = std::thread::spawn(move || {
-            use std::time::{Duration, Instant};
-            const DEBUG_PRINT: bool = false;
-
-            let epoch = Instant::now();
-            let mut capturer = Capturer::new(config_initial);
-            let latest = latest_t;
-            let config = config_t;
-
-            let mut last_duration = std::time::Duration::new(0, 0);
-            let mut last_end = Instant::now();
-            let mut counter = 0;
-            let mut pre_callback: PreCallback = Arc::new(|_|{});
-            let mut post_callback: PostCallback = Arc::new(|_|{});
-
-            while running_t.load(Relaxed) {
-                // First, check for new configs, if so consume them.
-                for new_config in receiver_config.try_iter() {
-                    capturer.set_config(new_config.clone());
-                    {
-                        let mut locked = config.lock().unwrap();
-                        *locked = new_config;
-                    }
-                }
-                for callback in receiver_pre.try_iter() {
-                    pre_callback = callback;
-                }
-                for callback in receiver_post.try_iter() {
-                    post_callback = callback;
-                }
 
-                let rate_valid = capturer.config.rate > 0.0;
-                if !rate_valid {
-                    // Rate is negative or zero, can be used to disable, block on config updates for 100ms.
-                    if let Ok(new_config) = receiver_config.recv_timeout(Duration::from_millis(100)) {
-                        capturer.set_config(new_config.clone());
-                        {
-                            let mut locked = config.lock().unwrap();
-                            *locked = new_config;
-                        }
-                    }
-                    continue;
-                }
+/// Determines what the [`ThreadedCapturer`] does when nobody is consuming the frames.
+///
+/// The capturer is idle if no post callback is set and [`ThreadedCapturer::latest`] hasn't been
+/// called since the last frame was published. While idle the previous frame remains the latest.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum IdleBehaviour {
+    /// Keep capturing and converting frames as usual.
+    #[default]
+    Capture,
 
-                // Next, calculate the desired interval and point in time to start.
-                let interval = Duration::from_secs_f32(1.0 / capturer.config.rate);
-                let start_timepoint = last_end + interval - last_duration;
-                if DEBUG_PRINT {
-                    println!(
-                        "current:   {: >16.6?} start_timepoint: {: >12.6?}",
-                        Instant::now().duration_since(epoch),
-                        start_timepoint.duration_since(epoch)
-                    );
-                }
-                let now = Instant::now();
-                if now <= start_timepoint {
-                    // Still have to wait, limit the wait to 100ms.
-                    let to_wait = start_timepoint - now;
-                    let limited = to_wait.min(Duration::from_millis(100));
-                    if DEBUG_PRINT {
-                        println!("sleeping for: {:?}", limited);
-                    }
-                    std::thread::sleep(limited);
-                    // Quick check if we still have to wait more.
-                    if Instant::now() <= start_timepoint {
-                        continue;
-                    }
-                }
+    /// Capture from the backend, but skip the conversion, the processor and publishing. This
+    /// keeps the backend active, such that frames are available without delay when a consumer
+    /// returns.
+    SkipConversion,
 
-                counter += 1;
-                let this_counter = counter;
-                (pre_callback)(this_counter);
-                let start = Instant::now();
-                let capture_time = std::time::SystemTime::now();
-                let img = capturer.capture();
-                let img = img.map(|v| v.to_rgba());
-                let end;
-                let info = {
-                    let mut locked = latest.lock().unwrap();
-                    if DEBUG_PRINT {
-                        println!("capture at {: >16.6?} ", start.duration_since(epoch));
-                    }
-                    end = std::time::Instant::now();
-                    let info = CaptureInfo {
-                        result: img.map(|v| Arc::new(v)),
-                        time: capture_time,
-                        duration: end - start,
-                        counter: this_counter,
-                    };
-                    *locked = info.clone();
-                    info
-                };
-                (post_callback)(info);
-                // std::thread::sleep(Duration::from_millis(100) - (std::time::Instant::now() - start));
-
-                last_duration = end - start;
-                last_end = end;
-                if DEBUG_PRINT {
-                    println!(
-                        "Duration was {: >13.6?} at {: >12.6?}",
-                        last_duration.as_secs_f64(),
-                        Instant::now().duration_since(epoch)
-                    );
-                }
-            }
-            if DEBUG_PRINT {
-                println!("Broke from loop, thread closing");
-            }
-        });
-        Self {
-            config,
-            running,
-            latest,
-            sender_config,
-            sender_pre,
-            sender_post,
-            thread: Some(thread),
-        }
-    }
+    /// Skip the capture entirely.
+    SkipCapture,
+}
 
-    /// Set the configuration and re-initialise appropriately.
-    pub fn set_config(&self, config: CaptureConfig) {
-        let _ = self.sender_config.send(config);
-    }
+/// Statistics about how accurately captures start at their scheduled time.
+///
+/// The jitter of a capture is the time between the scheduled start and the actual start.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ScheduleStats {
+    /// The scheduling mode these statistics were collected for.
+    pub mode: ScheduleMode,
 
-    /// Set the callback that's invoked before the frame is captured.
-    pub fn set_pre_callback(&self, f: PreCallback) {
-        let _ = self.sender_pre.send(f);
-    }
+    /// The number of captures the statistics are based on.
+    pub samples: u64,
 
-    /// Set the callback that's invoked after the frame capture is complete, it is passed
-    /// the capture info. This will be called from the thread that captures, so keep it short
-    /// else it blocks capturing thread.
-    pub fn set_post_callback(&self, f: PostCallback) {
-        let _ = self.sender_post.send(f);
+    /// The mean jitter.
+    pub mean: std::time::Duration,
+
+    /// The largest jitter seen.
+    pub max: std::time::Duration,
+
+    /// The standard deviation of the jitter.
+    pub std_dev: std::time::Duration,
+}
+
+/// Information about the capture thread, see [`ThreadedCapturer::thread_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    /// The identifier of the thread within this process.
+    pub id: std::thread::ThreadId,
+
+    /// The identifier of the thread as known to the operating system and profilers, the tid on
+    /// Linux.
+    pub os_id: u64,
+
+    /// The name of the thread.
+    pub name: Option<String>,
+
+    /// When the thread was spawned.
+    pub spawned: std::time::SystemTime,
+
+    /// The number of times the thread was restarted by the watchdog.
+    pub restarts: usize,
+}
+
+/// Summary of the state of a [`ThreadedCapturer`] for liveness and readiness probes, see
+/// [`ThreadedCapturer::health`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// Whether the capture thread is running and made progress recently, within the
+    /// [`WatchdogConfig::stall_timeout`] or [`Health::STALL_TIMEOUT`] if that is zero.
+    pub alive: bool,
+
+    /// Time since the last successful capture of any display, `None` if there wasn't one yet.
+    /// This also grows while capturing is paused or skipped.
+    pub since_success: Option<std::time::Duration>,
+
+    /// The error of the most recent failed capture, this is kept after later successes.
+    pub last_error: Option<ScreenCaptureError>,
+
+    /// The name of the backend in use, see [`Capture::name`], `None` until it is created.
+    pub backend: Option<String>,
+}
+
+impl Health {
+    /// Time without progress after which the capture thread is no longer considered alive, if
+    /// the watchdog's stall timeout is zero.
+    pub const STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+}
+
+/// Configuration struct, specifying all the configurable properties of the displaylight struct..
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CaptureConfig {
+    /// A list of capture specifications, the first one to match with the highest priority will
+    /// be used.
+    #[serde(default)]
+    pub capture: Vec<CaptureSpecification>,
+
+    /// A rate, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub rate: f32,
+
+    /// The displays to capture simultaneously, used only if [`ThreadedCapturer`] is used.
+    ///
+    /// If empty, a single display is captured according to the specifications. Otherwise a
+    /// capture backend is created for each display listed, each using the specifications that have
+    /// a matching `display` field.
+    #[serde(default)]
+    pub displays: Vec<u32>,
+
+    /// The format in which frames are delivered, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
+    /// Settings for the capture thread, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub thread: ThreadConfig,
+
+    /// Determines when captures start, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub schedule: ScheduleMode,
+
+    /// Determines what happens if nobody consumes the frames, used only if [`ThreadedCapturer`]
+    /// is used.
+    #[serde(default)]
+    pub idle: IdleBehaviour,
+
+    /// Settings for restarting the capture thread, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+
+    /// Settings for the session lock detection, used only if [`ThreadedCapturer`] is used.
+    #[serde(default)]
+    pub session: SessionConfig,
+
+    /// Options for the platform's backend, only applied when the backend is created.
+    #[serde(default)]
+    pub backend: BackendOptions,
+
+    /// Named configurations to switch to, see [`ThreadedCapturer::activate_profile`].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, CaptureConfig>,
+}
+
+/// The file formats a [`CaptureConfig`] can be stored in, each needs the feature of its name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+/// Parses the contents of a configuration file.
+type ConfigParser = fn(&str) -> Result<CaptureConfig, ConfigError>;
+/// Serializes a configuration into the contents of a file.
+type ConfigSerializer = fn(&CaptureConfig) -> Result<String, ConfigError>;
+
+/// Track the path into the configuration, such that errors can point at the key.
+#[cfg(any(feature = "toml", feature = "json", feature = "yaml"))]
+fn parse_error<E: std::fmt::Display>(e: serde_path_to_error::Error<E>) -> ConfigError {
+    let key = e.path().to_string();
+    ConfigError::Parse {
+        key: (key != ".").then_some(key),
+        message: e.inner().to_string(),
+    }
+}
+
+impl ConfigFormat {
+    fn from_path(path: &std::path::Path) -> Result<ConfigFormat, ConfigError> {
+        let extension = path.extension().and_then(|v| v.to_str()).unwrap_or("");
+        match extension {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            _ => Err(ConfigError::UnsupportedFormat(extension.to_owned())),
+        }
+    }
+
+    /// The parser of the format, fails if its feature isn't enabled.
+    fn parser(self) -> Result<ConfigParser, ConfigError> {
+        match self {
+            #[cfg(feature = "toml")]
+            ConfigFormat::Toml => Ok(|contents: &str| {
+                let deserializer =
+                    toml::Deserializer::parse(contents).map_err(|e| ConfigError::Parse {
+                        key: None,
+                        message: e.to_string(),
+                    })?;
+                serde_path_to_error::deserialize(deserializer).map_err(parse_error)
+            }),
+            #[cfg(feature = "json")]
+            ConfigFormat::Json => Ok(|contents: &str| {
+                let mut deserializer = serde_json::Deserializer::from_str(contents);
+                serde_path_to_error::deserialize(&mut deserializer).map_err(parse_error)
+            }),
+            #[cfg(feature = "yaml")]
+            ConfigFormat::Yaml => Ok(|contents: &str| {
+                let deserializer = serde_yaml::Deserializer::from_str(contents);
+                serde_path_to_error::deserialize(deserializer).map_err(parse_error)
+            }),
+            #[cfg(not(feature = "toml"))]
+            ConfigFormat::Toml => Err(ConfigError::FormatNotEnabled("toml")),
+            #[cfg(not(feature = "json"))]
+            ConfigFormat::Json => Err(ConfigError::FormatNotEnabled("json")),
+            #[cfg(not(feature = "yaml"))]
+            ConfigFormat::Yaml => Err(ConfigError::FormatNotEnabled("yaml")),
+        }
+    }
+
+    /// The serializer of the format, fails if its feature isn't enabled.
+    fn serializer(self) -> Result<ConfigSerializer, ConfigError> {
+        match self {
+            #[cfg(feature = "toml")]
+            ConfigFormat::Toml => Ok(|config| {
+                toml::to_string_pretty(config).map_err(|e| ConfigError::Serialize(e.to_string()))
+            }),
+            #[cfg(feature = "json")]
+            ConfigFormat::Json => Ok(|config| {
+                serde_json::to_string_pretty(config)
+                    .map_err(|e| ConfigError::Serialize(e.to_string()))
+            }),
+            #[cfg(feature = "yaml")]
+            ConfigFormat::Yaml => Ok(|config| {
+                serde_yaml::to_string(config).map_err(|e| ConfigError::Serialize(e.to_string()))
+            }),
+            #[cfg(not(feature = "toml"))]
+            ConfigFormat::Toml => Err(ConfigError::FormatNotEnabled("toml")),
+            #[cfg(not(feature = "json"))]
+            ConfigFormat::Json => Err(ConfigError::FormatNotEnabled("json")),
+            #[cfg(not(feature = "yaml"))]
+            ConfigFormat::Yaml => Err(ConfigError::FormatNotEnabled("yaml")),
+        }
+    }
+}
+
+/// A problem found by [`CaptureConfig::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigIssue {
+    /// The region of the specification extends beyond the resolution.
+    OutOfBounds {
+        index: usize,
+        region: (u32, u32, u32, u32),
+        resolution: Resolution,
+    },
+    /// The region of the specification has no area.
+    ZeroSized { index: usize },
+    /// The `match_*` fields of the specification contradict each other, it never matches.
+    NeverMatches { index: usize },
+    /// The specification matches only if another specification with precedence matches too.
+    Unreachable { index: usize, shadowed_by: usize },
+    /// A rate is not a finite number, or is unreasonably high. The index is `None` for the rate
+    /// of the configuration.
+    InvalidRate { index: Option<usize>, rate: f32 },
+    /// The specification sets a rate, but capturing is disabled by the rate of the configuration.
+    IneffectiveRate { index: usize },
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigIssue::OutOfBounds {
+                index,
+                region: (x, y, width, height),
+                resolution,
+            } => write!(
+                f,
+                "specification {index}: region {width}x{height} at ({x}, {y}) exceeds the resolution {}x{}",
+                resolution.width, resolution.height
+            ),
+            ConfigIssue::ZeroSized { index } => {
+                write!(f, "specification {index}: region has no area")
+            }
+            ConfigIssue::NeverMatches { index } => {
+                write!(f, "specification {index}: match fields contradict each other")
+            }
+            ConfigIssue::Unreachable { index, shadowed_by } => write!(
+                f,
+                "specification {index}: unreachable, specification {shadowed_by} always takes precedence"
+            ),
+            ConfigIssue::InvalidRate { index: Some(index), rate } => {
+                write!(f, "specification {index}: invalid rate {rate}")
+            }
+            ConfigIssue::InvalidRate { index: None, rate } => write!(f, "invalid rate {rate}"),
+            ConfigIssue::IneffectiveRate { index } => write!(
+                f,
+                "specification {index}: rate has no effect, capturing is disabled"
+            ),
+        }
+    }
+}
+
+impl CaptureSpecification {
+    /// The inclusive range of widths this specification matches.
+    fn width_range(&self) -> (u32, u32) {
+        let min = self.match_width.max(self.match_width_min).unwrap_or(0);
+        let max = [self.match_width, self.match_width_max]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(u32::MAX);
+        (min, max)
+    }
+
+    /// The inclusive range of heights this specification matches.
+    fn height_range(&self) -> (u32, u32) {
+        let min = self.match_height.max(self.match_height_min).unwrap_or(0);
+        let max = [self.match_height, self.match_height_max]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(u32::MAX);
+        (min, max)
+    }
+
+    /// Whether there is any resolution this specification matches.
+    fn can_match(&self) -> bool {
+        let (width, height) = (self.width_range(), self.height_range());
+        let aspect_min = self.match_aspect_min.unwrap_or(0.0);
+        let aspect_max = self.match_aspect_max.unwrap_or(f32::INFINITY);
+        width.0 <= width.1 && height.0 <= height.1 && aspect_min <= aspect_max
+    }
+
+    /// Whether this specification matches whenever the other one does.
+    fn covers(&self, other: &CaptureSpecification) -> bool {
+        let contains = |a: (u32, u32), b: (u32, u32)| a.0 <= b.0 && b.1 <= a.1;
+        let aspect_min = |s: &CaptureSpecification| s.match_aspect_min.unwrap_or(0.0);
+        let aspect_max = |s: &CaptureSpecification| s.match_aspect_max.unwrap_or(f32::INFINITY);
+        contains(self.width_range(), other.width_range())
+            && contains(self.height_range(), other.height_range())
+            && aspect_min(self) <= aspect_min(other)
+            && aspect_max(other) <= aspect_max(self)
+            && (self.match_display_name.is_none()
+                || self.match_display_name == other.match_display_name)
+            && (self.match_display_count.is_none()
+                || self.match_display_count == other.match_display_count)
+            && (self.match_scale.is_none() || self.match_scale == other.match_scale)
+            && (self.match_window_title.is_none()
+                || self.match_window_title == other.match_window_title)
+            && (self.match_window_class.is_none()
+                || self.match_window_class == other.match_window_class)
+    }
+}
+
+impl CaptureConfig {
+    /// Rates above this are considered a mistake by [`CaptureConfig::validate`].
+    pub const MAX_RATE: f32 = 1000.0;
+
+    /// Check the configuration for mistakes, the regions are checked for the specifications
+    /// that match the provided resolution. Specifications that match on a display name, count or
+    /// scale are assumed to match, with the resolution as their display's size. The regions of
+    /// specifications that capture a window follow the window, so these aren't checked.
+    pub fn validate(&self, resolution: Resolution) -> Vec<ConfigIssue> {
+        let mut issues = vec![];
+        let valid_rate = |rate: f32| rate.is_finite() && rate <= Self::MAX_RATE;
+        if !valid_rate(self.rate) {
+            issues.push(ConfigIssue::InvalidRate {
+                index: None,
+                rate: self.rate,
+            });
+        }
+        for (index, spec) in self.capture.iter().enumerate() {
+            if let Some(rate) = spec.rate {
+                if !valid_rate(rate) || rate <= 0.0 {
+                    issues.push(ConfigIssue::InvalidRate {
+                        index: Some(index),
+                        rate,
+                    });
+                } else if self.rate <= 0.0 {
+                    issues.push(ConfigIssue::IneffectiveRate { index });
+                }
+            }
+
+            if !spec.can_match() {
+                issues.push(ConfigIssue::NeverMatches { index });
+                continue;
+            }
+            if spec.merge {
+                // Merged specifications don't compete and their region depends on the others.
+                continue;
+            }
+            // A specification has precedence if it has a higher priority, or the same priority
+            // and comes first.
+            let shadowed_by = self.capture.iter().enumerate().find(|(other, s)| {
+                *other != index
+                    && !s.merge
+                    && s.display == spec.display
+                    && (s.priority > spec.priority
+                        || (s.priority == spec.priority && *other < index))
+                    && s.covers(spec)
+            });
+            if let Some((shadowed_by, _)) = shadowed_by {
+                issues.push(ConfigIssue::Unreachable { index, shadowed_by });
+                continue;
+            }
+
+            if spec.matches_windows()
+                || !spec.matches_resolution(resolution.width, resolution.height)
+            {
+                continue;
+            }
+            let populated = spec.populate(resolution.width, resolution.height);
+            let (x, y) = (populated.x as u32, populated.y as u32);
+            if populated.width == 0 || populated.height == 0 {
+                issues.push(ConfigIssue::ZeroSized { index });
+            } else if x as u64 + populated.width as u64 > resolution.width as u64
+                || y as u64 + populated.height as u64 > resolution.height as u64
+            {
+                issues.push(ConfigIssue::OutOfBounds {
+                    index,
+                    region: (x, y, populated.width, populated.height),
+                    resolution,
+                });
+            }
+        }
+        issues
+    }
+
+    /// Load a configuration from a file, see [`CaptureConfig::load`].
+    pub fn from_path<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> Result<CaptureConfig, Box<dyn std::error::Error>> {
+        Ok(Self::load(path)?)
+    }
+
+    /// Load a configuration from a file, the format is determined by the extension. Supported
+    /// are `.toml`, `.json` and `.yaml` or `.yml`, each behind the feature of the same name.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<CaptureConfig, ConfigError> {
+        let path = path.as_ref();
+        let parse = ConfigFormat::from_path(path)?.parser()?;
+        let contents = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        parse(&contents)
+    }
+
+    /// Save the configuration to a file, the format is determined by the extension, see
+    /// [`CaptureConfig::load`].
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let contents = ConfigFormat::from_path(path)?.serializer()?(self)?;
+        std::fs::write(path, contents).map_err(ConfigError::Io)
+    }
+
+    /// The JSON schema of the configuration, including the documentation of the fields, for
+    /// editors and front-ends to validate and complete configuration files with.
+    #[cfg(feature = "schema")]
+    pub fn json_schema() -> String {
+        let schema = schemars::schema_for!(CaptureConfig);
+        serde_json::to_string_pretty(&schema).expect("schema is valid json")
+    }
+
+    /// Override the configuration from environment variables, to adjust a deployed application
+    /// without changing its configuration file:
+    /// - `SCREEN_CAPTURE_RATE` sets the rate.
+    /// - `SCREEN_CAPTURE_REGION=x,y,w,h` replaces the specifications with one capturing this
+    ///   region.
+    /// - `SCREEN_CAPTURE_DISPLAY` captures only this display, by setting it on all specifications.
+    pub fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        self.apply_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Apply the overrides of [`CaptureConfig::apply_env_overrides`] from the provided lookup.
+    fn apply_overrides<F: Fn(&str) -> Option<String>>(
+        &mut self,
+        lookup: F,
+    ) -> Result<(), ConfigError> {
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .trim()
+                .parse()
+                .map_err(|e: T::Err| ConfigError::Parse {
+                    key: Some(name.to_owned()),
+                    message: e.to_string(),
+                })
+        }
+
+        if let Some(value) = lookup("SCREEN_CAPTURE_RATE") {
+            self.rate = parse("SCREEN_CAPTURE_RATE", &value)?;
+        }
+        if let Some(value) = lookup("SCREEN_CAPTURE_REGION") {
+            let parts: Vec<&str> = value.split(',').collect();
+            let [x, y, width, height] = parts[..] else {
+                return Err(ConfigError::Parse {
+                    key: Some("SCREEN_CAPTURE_REGION".to_owned()),
+                    message: format!("expected x,y,w,h, got \"{value}\""),
+                });
+            };
+            self.capture = vec![CaptureSpecification {
+                x: parse("SCREEN_CAPTURE_REGION", x)?,
+                y: parse("SCREEN_CAPTURE_REGION", y)?,
+                width: parse("SCREEN_CAPTURE_REGION", width)?,
+                height: parse("SCREEN_CAPTURE_REGION", height)?,
+                ..Default::default()
+            }];
+        }
+        if let Some(value) = lookup("SCREEN_CAPTURE_DISPLAY") {
+            let display = parse("SCREEN_CAPTURE_DISPLAY", &value)?;
+            for spec in self.capture.iter_mut() {
+                spec.display = display;
+                spec.match_display_name = None;
+            }
+            if !self.displays.is_empty() {
+                self.displays = vec![display];
+            }
+            if self.capture.is_empty() {
+                self.capture.push(CaptureSpecification {
+                    display,
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The configuration of the named profile. It retains the profiles of this configuration if
+    /// it has none of its own, such that it is possible to switch between the profiles.
+    pub fn profile(&self, name: &str) -> Option<CaptureConfig> {
+        let mut profile = self.profiles.get(name)?.clone();
+        if profile.profiles.is_empty() {
+            profile.profiles = self.profiles.clone();
+        }
+        Some(profile)
+    }
+
+    /// Create the configuration used for a single display out of the [`CaptureConfig::displays`].
+    ///
+    /// Only the specifications for this display are retained, with a fallback to capture the
+    /// entire display if none of them match. Specifications with a
+    /// [`CaptureSpecification::match_display_name`] are resolved against the displays like
+    /// [`CaptureSpecification::select`] does, so they are retained if the name resolves to this
+    /// display.
+    pub fn for_display(&self, display: u32, displays: &[DisplayInfo]) -> CaptureConfig {
+        let resolves_to = |spec: &CaptureSpecification| match spec.match_display_name.as_ref() {
+            Some(pattern) => displays
+                .iter()
+                .find(|d| glob_match(pattern, &d.name))
+                .map(|d| d.index),
+            None => Some(spec.display),
+        };
+        let mut capture: Vec<CaptureSpecification> = self
+            .capture
+            .iter()
+            .filter(|spec| resolves_to(spec) == Some(display))
+            .cloned()
+            .collect();
+        capture.push(CaptureSpecification {
+            display,
+            ..Default::default()
+        });
+        CaptureConfig {
+            capture,
+            displays: vec![],
+            ..self.clone()
+        }
+    }
+}
+
+/// Helper struct to use the capture object to grab according to configuration.
+pub struct Capturer {
+    pub config: CaptureConfig,
+    pub grabber: Box<dyn Capture>,
+    pub cached_resolution: Option<Resolution>,
+    pub cached_specification: Option<CaptureSpecification>,
+    pub cached_selection: Option<SpecificationSelection>,
+    /// The change of the specification in use that wasn't taken yet.
+    specification_change: Option<SpecificationChange>,
+    /// The number of consecutive captures that failed with a recoverable error.
+    failures: u32,
+    /// The monotonic and wall clock time of the previous capture, to detect a resume.
+    last_capture: Option<(std::time::Instant, std::time::SystemTime)>,
+    /// The windows and when they were enumerated, if a specification captures a window.
+    cached_windows: Option<(std::time::Instant, Vec<WindowInfo>)>,
+}
+
+/// Why the backend of a [`Capturer`] was reinitialised.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Recovery {
+    /// The system resumed from suspend, the wall clock advanced this much more than the
+    /// monotonic clock.
+    Resume(std::time::Duration),
+    /// This many consecutive captures failed.
+    Failures(u32),
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recovery::Resume(gap) => write!(f, "resume after {:.1}s of suspend", gap.as_secs_f32()),
+            Recovery::Failures(n) => write!(f, "{n} consecutive failures"),
+        }
+    }
+}
+
+/// The suspend duration, if the wall clock advanced at least [`Capturer::RESUME_GAP`] more than
+/// the monotonic clock, which doesn't advance during suspend on Linux. On Windows it does, there
+/// only the consecutive failures trigger the recovery.
+fn resumed(
+    monotonic: std::time::Duration,
+    wall: Option<std::time::Duration>,
+) -> Option<std::time::Duration> {
+    let gap = wall?.checked_sub(monotonic)?;
+    (gap >= Capturer::RESUME_GAP).then_some(gap)
+}
+
+/// A change of the specification in use, see [`Capturer::take_specification_change`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecificationChange {
+    /// The specification that was in use before, `None` for the first specification.
+    pub previous: Option<CaptureSpecification>,
+    /// The specification now in use.
+    pub current: CaptureSpecification,
+}
+
+impl Capturer {
+    /// Instantiate a new capture grabber with configuration.
+    pub fn new(config: CaptureConfig) -> Result<Capturer, ScreenCaptureError> {
+        Ok(Self::with_backend(
+            crate::capture_with_options(&config.backend)?,
+            config,
+        ))
+    }
+
+    /// Instantiate a capturer using the provided backend instead of the platform's backend.
+    pub fn with_backend(grabber: Box<dyn Capture>, config: CaptureConfig) -> Capturer {
+        Self {
+            config,
+            grabber,
+            cached_resolution: None,
+            cached_specification: None,
+            cached_selection: None,
+            specification_change: None,
+            failures: 0,
+            last_capture: None,
+            cached_windows: None,
+        }
+    }
+
+    /// The number of consecutive recoverable failures after which the backend is reinitialised.
+    pub const RECOVERY_FAILURES: u32 = 10;
+
+    /// The minimum suspend duration after which the backend is reinitialised.
+    pub const RESUME_GAP: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// The interval at which the windows are enumerated to follow a captured window, see
+    /// [`CaptureSpecification::match_window_title`].
+    pub const WINDOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    /// Enumerate the windows again if a specification captures a window and the interval
+    /// passed, returns whether they changed since the last enumeration.
+    fn poll_windows(&mut self) -> bool {
+        if !self.config.capture.iter().any(|s| s.matches_windows()) {
+            self.cached_windows = None;
+            return false;
+        }
+        if let Some((polled, _)) = self.cached_windows.as_ref() {
+            if polled.elapsed() < Self::WINDOW_POLL_INTERVAL {
+                return false;
+            }
+        }
+        let windows = self.grabber.windows();
+        let changed = self.cached_windows.as_ref().map(|(_, w)| w) != Some(&windows);
+        self.cached_windows = Some((std::time::Instant::now(), windows));
+        changed
+    }
+
+    /// Reinitialise the backend and prepare the capture again, see [`Capture::reset`].
+    fn recover(&mut self, reason: Recovery) -> Result<(), ScreenCaptureError> {
+        let result = self.grabber.reset();
+        self.failures = 0;
+        self.cached_resolution = None;
+        match &result {
+            Ok(()) => log::info!("Recovered the capture backend, reason: {reason}"),
+            Err(e) => {
+                log::warn!("Recovering the capture backend failed, reason: {reason}, error: {e}")
+            }
+        }
+        result
+    }
+
+    /// Update the capture configuration according to the latest resolution.
+    ///
+    /// Returns true if the configuration changed.
+    pub fn update_resolution(&mut self) -> Result<bool, ScreenCaptureError> {
+        // First, check if the resolution of the desktop environment has changed, if so, act.
+        let current_resolution = self.grabber.resolution()?;
+        let old_resolution = self.cached_resolution;
+        let windows_changed = self.poll_windows();
+        let resolution_changed = self.cached_resolution != Some(current_resolution);
+
+        if resolution_changed || windows_changed {
+            let width = current_resolution.width;
+            let height = current_resolution.height;
+
+            // Resolution has changed, figure out the best match in our configurations and
+            // prepare the capture accordingly.
+            let displays = self.grabber.displays();
+            let windows = self
+                .cached_windows
+                .as_ref()
+                .map(|(_, w)| &w[..])
+                .unwrap_or(&[]);
+            let selection = CaptureSpecification::select_with_windows(
+                width,
+                height,
+                &displays,
+                windows,
+                &self.config.capture,
+            );
+            let config = selection.specification.clone();
+
+            // Windows change often, like a clock in a title bar, only prepare again if the region
+            // moved as well.
+            let unchanged = self.cached_specification.as_ref() == Some(&config);
+            if resolution_changed || !unchanged {
+                if let Err(e) = self.grabber.prepare_capture(
+                    config.display,
+                    config.x as u32,
+                    config.y as u32,
+                    config.width,
+                    config.height,
+                ) {
+                    log::warn!("Preparing the capture failed: {}", e);
+                }
+            }
+            // Store the current resolution and the specification in use.
+            self.cached_resolution = Some(current_resolution);
+            if self.cached_specification.as_ref() != Some(&config) {
+                let previous = self
+                    .specification_change
+                    .take()
+                    .map(|c| c.previous)
+                    .unwrap_or_else(|| self.cached_specification.clone());
+                // A change that wasn't taken yet may be undone by this one.
+                self.specification_change =
+                    (previous.as_ref() != Some(&config)).then(|| SpecificationChange {
+                        previous,
+                        current: config.clone(),
+                    });
+            }
+            self.cached_specification = Some(config);
+            self.cached_selection = Some(selection);
+        }
+        Ok(old_resolution != self.cached_resolution)
+    }
+
+    /// Take the change of the specification in use since the last call, if it changed. The
+    /// resolved specifications are compared, so a change of resolution that results in the same
+    /// region is not a change.
+    pub fn take_specification_change(&mut self) -> Option<SpecificationChange> {
+        self.specification_change.take()
+    }
+
+    /// Which specification is in use and why, available after the first capture.
+    pub fn selection(&self) -> Option<&SpecificationSelection> {
+        self.cached_selection.as_ref()
+    }
+
+    /// The capture rate, from the specification in use if it has one, see
+    /// [`CaptureSpecification::rate`].
+    pub fn rate(&self) -> f32 {
+        self.cached_specification
+            .as_ref()
+            .and_then(|v| v.rate)
+            .unwrap_or(self.config.rate)
+    }
+
+    /// The format to deliver frames in, from the specification in use if it has one, see
+    /// [`CaptureSpecification::output_format`].
+    pub fn output_format(&self) -> OutputFormat {
+        self.cached_specification
+            .as_ref()
+            .and_then(|v| v.output_format)
+            .unwrap_or(self.config.output_format)
+    }
+
+    /// Black out the masked areas of the specification in use, see
+    /// [`CaptureSpecification::mask`].
+    fn apply_mask(&self, frame: &mut OwnedFrame) {
+        let Some(spec) = self.cached_specification.as_ref() else {
+            return;
+        };
+        let region = Rect {
+            x: spec.x as u32,
+            y: spec.y as u32,
+            width: spec.width,
+            height: spec.height,
+        };
+        for masked in spec.mask.iter().filter_map(|m| m.intersection(&region)) {
+            frame.fill_black(Rect {
+                x: masked.x - region.x,
+                y: masked.y - region.y,
+                ..masked
+            });
+        }
+    }
+
+    /// Set the configuration and re-initialise appropriately.
+    pub fn set_config(&mut self, config: CaptureConfig) {
+        self.cached_resolution = None; // force reinitialisation.
+        self.cached_windows = None;
+        self.config = config;
+    }
+
+    /// Get the current config.
+    pub fn config(&self) -> CaptureConfig {
+        self.config.clone()
+    }
+
+    /// Update the resolution and capture a new image.
+    ///
+    /// The backend is reinitialised after a resume from suspend, or after
+    /// [`Capturer::RECOVERY_FAILURES`] consecutive transient failures, as the shared resources
+    /// are frequently stale in these cases. Timeouts just mean the screen didn't change, so these
+    /// don't count as failures.
+    pub fn capture(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        let now = (std::time::Instant::now(), std::time::SystemTime::now());
+        if let Some((instant, time)) = self.last_capture.replace(now) {
+            if let Some(gap) = resumed(now.0 - instant, now.1.duration_since(time).ok()) {
+                // A failed reset is logged by recover, the capture below reports whether the
+                // backend is still usable.
+                let _ = self.recover(Recovery::Resume(gap));
+            }
+        }
+        let result = self.capture_once();
+        let recoverable = matches!(
+            result,
+            Err(ScreenCaptureError::Transient(_)
+                | ScreenCaptureError::LostCapture(_)
+                | ScreenCaptureError::Disconnected)
+        );
+        if !recoverable {
+            self.failures = 0;
+            return result;
+        }
+        self.failures += 1;
+        if self.failures < Self::RECOVERY_FAILURES {
+            return result;
+        }
+        self.recover(Recovery::Failures(self.failures))?;
+        self.capture_once()
+    }
+
+    fn capture_once(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        self.update_resolution()?;
+
+        // Now, we are ready to try and get the image:
+        self.grabber.capture_image()?;
+
+        // Then, we can grab the actual image.
+        self.grabber.image()
+    }
+}
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// The capture result of a single display.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisplayCapture {
+    /// The display this result belongs to.
+    pub display: u32,
+
+    /// The result of the capture.
+    pub result: Result<Arc<OwnedFrame>, ScreenCaptureError>,
+
+    /// The data attached by the frame processor, if any.
+    pub data: Option<FrameData>,
+
+    /// Whether the display was captured for this frame. If the display has a lower rate than
+    /// other displays, see [`CaptureSpecification::rate`], it is only captured when due and its
+    /// previous result is repeated otherwise.
+    pub fresh: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaptureInfo {
+    /// The result of the capture, for multiple displays this is the result of the first display.
+    pub result: Result<Arc<OwnedFrame>, ScreenCaptureError>,
+
+    /// The data attached by the frame processor to the result, if any.
+    pub data: Option<FrameData>,
+
+    /// The results for each display captured, these share the time and counter.
+    pub displays: Vec<DisplayCapture>,
+
+    /// The time at which the capture was triggered.
+    pub time: std::time::SystemTime,
+
+    /// The duration it took to capture and process the image combined.
+    pub duration: std::time::Duration,
+
+    /// The frame identifier as a counter, this increases for each capture() invocation.
+    pub counter: usize,
+}
+
+impl CaptureInfo {
+    /// The metadata of this capture, without the frames.
+    pub fn metadata(&self) -> CaptureMetadata {
+        let resolution = |frame: &OwnedFrame| Resolution {
+            width: frame.width(),
+            height: frame.height(),
+        };
+        CaptureMetadata {
+            resolution: self.result.as_deref().ok().map(resolution),
+            error: self.result.as_ref().err().cloned(),
+            displays: self
+                .displays
+                .iter()
+                .filter(|d| d.fresh)
+                .map(|d| d.display)
+                .collect(),
+            time: self.time,
+            duration: self.duration,
+            counter: self.counter,
+        }
+    }
+}
+
+/// The serializable part of a [`CaptureInfo`], to log or transmit what was captured.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CaptureMetadata {
+    /// The resolution of the frame, `None` if the capture failed.
+    pub resolution: Option<Resolution>,
+
+    /// The error if the capture failed.
+    pub error: Option<ScreenCaptureError>,
+
+    /// The displays that were captured for this frame.
+    pub displays: Vec<u32>,
+
+    /// The time at which the capture was triggered.
+    pub time: std::time::SystemTime,
+
+    /// The duration it took to capture and process the image combined.
+    pub duration: std::time::Duration,
+
+    /// The frame identifier as a counter.
+    pub counter: usize,
+}
+
+impl Default for CaptureInfo {
+    fn default() -> Self {
+        Self {
+            result: Err(ScreenCaptureError::Initialisation(
+                "no capture performed yet".into(),
+            )),
+            data: None,
+            displays: vec![],
+            time: std::time::SystemTime::now(),
+            duration: std::time::Duration::new(0, 0),
+            counter: 0,
+        }
+    }
+}
+
+/// The capturers used by the [`ThreadedCapturer`], one for each display.
+struct DisplayCapturers {
+    config: CaptureConfig,
+    backend: BackendFactory,
+    /// The capturer for each display, `None` if the backend still has to be created.
+    capturers: Vec<Option<Capturer>>,
+    /// The previous capture of each display with its time, to repeat it if it isn't due.
+    previous: Vec<Option<(std::time::Instant, DisplayCapture)>>,
+}
+
+impl DisplayCapturers {
+    fn new(config: CaptureConfig, backend: BackendFactory) -> Self {
+        let mut r = DisplayCapturers {
+            config: Default::default(),
+            backend,
+            capturers: vec![],
+            previous: vec![],
+        };
+        r.set_config(config);
+        r
+    }
+
+    /// The configuration for the capturer at the provided index, with the displays of its
+    /// backend.
+    fn display_config(&self, index: usize, displays: &[DisplayInfo]) -> CaptureConfig {
+        match self.config.displays.get(index) {
+            Some(display) => self.config.for_display(*display, displays),
+            None => self.config.clone(),
+        }
+    }
+
+    /// Set the configuration, backends are only recreated if the displays changed.
+    fn set_config(&mut self, config: CaptureConfig) {
+        let count = config.displays.len().max(1);
+        let displays_changed = self.config.displays != config.displays;
+        self.config = config;
+        if displays_changed || self.capturers.len() != count {
+            self.capturers = (0..count).map(|_| None).collect();
+        }
+        // Capture all displays right away, as their rates may have changed.
+        self.previous = (0..count).map(|_| None).collect();
+        for index in 0..count {
+            let Some(displays) = self.capturers[index].as_mut().map(|c| c.grabber.displays())
+            else {
+                continue;
+            };
+            let display_config = self.display_config(index, &displays);
+            if let Some(capturer) = self.capturers[index].as_mut() {
+                capturer.set_config(display_config);
+            }
+        }
+    }
+
+    /// Obtain the capturer for the display at the index, creating the backend if necessary.
+    fn capturer(&mut self, index: usize) -> Result<&mut Capturer, ScreenCaptureError> {
+        if self.capturers[index].is_none() {
+            let mut grabber = (self.backend)()?;
+            let config = self.display_config(index, &grabber.displays());
+            self.capturers[index] = Some(Capturer::with_backend(grabber, config));
+        }
+        Ok(self.capturers[index].as_mut().unwrap())
+    }
+
+    /// The rate at which to capture, the highest rate of the displays.
+    fn rate(&self) -> f32 {
+        self.capturers
+            .iter()
+            .flatten()
+            .map(|c| c.rate())
+            .reduce(f32::max)
+            .unwrap_or(self.config.rate)
+    }
+
+    /// Record the specifications in use and, if not known yet, the backend in the state.
+    fn update_state(&mut self, state: &mut WorkerState) {
+        state.specifications = self
+            .capturers
+            .iter()
+            .map(|c| c.as_ref().and_then(|c| c.cached_specification.clone()))
+            .collect();
+        if state.backend.is_none() {
+            if let Some(capturer) = self.capturers.iter_mut().flatten().next() {
+                state.backend = Some(capturer.grabber.name().to_owned());
+                state.displays = capturer.grabber.displays();
+            }
+        }
+    }
+
+    /// Take the changes of the specifications in use of all displays.
+    fn take_specification_changes(&mut self) -> Vec<SpecificationChange> {
+        self.capturers
+            .iter_mut()
+            .flatten()
+            .filter_map(|c| c.take_specification_change())
+            .collect()
+    }
+
+    /// Capture all displays without converting the images, returns the errors that occurred.
+    fn capture_only(&mut self) -> Vec<ScreenCaptureError> {
+        (0..self.capturers.len())
+            .filter_map(|index| self.capturer(index).and_then(|c| c.capture()).err())
+            .collect()
+    }
+
+    /// Capture all displays, convert the images and run them through the processor.
+    ///
+    /// Backends that haven't been created yet, or failed to be created, are created here, such
+    /// that a failure to create them is reported as the result of the capture.
+    ///
+    /// Displays with a lower rate than the highest rate are only captured when they are due.
+    fn capture(&mut self, processor: Option<&FrameProcessor>) -> Vec<DisplayCapture> {
+        let now = std::time::Instant::now();
+        // Allow displays to be captured slightly early, up to half of the shortest interval.
+        let slack = std::time::Duration::from_secs_f32(0.5 / self.rate().max(f32::EPSILON));
+        (0..self.capturers.len())
+            .map(|index| {
+                let rate = self.capturers[index].as_ref().map(|c| c.rate());
+                if let (Some(rate), Some((time, previous))) = (rate, &self.previous[index]) {
+                    let interval = std::time::Duration::from_secs_f32(1.0 / rate.max(f32::EPSILON));
+                    if now.duration_since(*time) + slack < interval {
+                        return DisplayCapture {
+                            fresh: false,
+                            ..previous.clone()
+                        };
+                    }
+                }
+                let result = self.capture_display(index, processor);
+                self.previous[index] = Some((now, result.clone()));
+                result
+            })
+            .collect()
+    }
+
+    /// Capture the display at the index, convert the image and run it through the processor.
+    fn capture_display(
+        &mut self,
+        index: usize,
+        processor: Option<&FrameProcessor>,
+    ) -> DisplayCapture {
+        let _span = crate::telemetry::display_span(index);
+        let display = self.config.displays.get(index).copied();
+        let capturer = match self.capturer(index) {
+            Ok(capturer) => capturer,
+            Err(e) => {
+                return DisplayCapture {
+                    display: display.unwrap_or_default(),
+                    result: Err(e),
+                    data: None,
+                    fresh: true,
+                }
+            }
+        };
+        let start = std::time::Instant::now();
+        let captured = capturer.capture();
+        let capture_duration = start.elapsed();
+        let processed = captured.map(|v| {
+            // The specification is selected by the capture, so the format is known only now.
+            let mut frame = OwnedFrame::from_image(v.as_ref(), capturer.output_format());
+            capturer.apply_mask(&mut frame);
+            match processor {
+                Some(processor) => (processor)(frame),
+                None => frame.into(),
+            }
+        });
+        let conversion_duration = start.elapsed() - capture_duration;
+        let display = capturer
+            .cached_specification
+            .as_ref()
+            .map(|v| v.display)
+            .or(display)
+            .unwrap_or_default();
+        match &processed {
+            Ok(_) => {
+                crate::telemetry::frame_captured(display, capture_duration, conversion_duration)
+            }
+            Err(e) => crate::telemetry::capture_failed(display, e),
+        }
+        match processed {
+            Ok(processed) => DisplayCapture {
+                display,
+                result: Ok(Arc::new(processed.frame)),
+                data: processed.data,
+                fresh: true,
+            },
+            Err(e) => DisplayCapture {
+                display,
+                result: Err(e),
+                data: None,
+                fresh: true,
+            },
+        }
+    }
+}
+
+pub struct ThreadedCapturer {
+    thread: Option<std::thread::JoinHandle<()>>,
+    running: Arc<AtomicBool>,
+    latest: Arc<Mutex<CaptureInfo>>,
+    sender_config: Sender<CaptureConfig>,
+    sender_pre: Sender<CallbackUpdate<PreCallback>>,
+    sender_post: Sender<CallbackUpdate<PostCallback>>,
+    sender_error: Sender<CallbackUpdate<ErrorCallback>>,
+    sender_specification: Sender<CallbackUpdate<SpecificationCallback>>,
+    sender_session: Sender<CallbackUpdate<SessionCallback>>,
+    /// Source of the identifiers of callback handles.
+    next_handle: AtomicU64,
+    sender_watch: Sender<Option<PathBuf>>,
+    sender_processor: Sender<Option<FrameProcessor>>,
+    schedule_stats: Arc<Mutex<ScheduleStats>>,
+    /// Counter of the latest frame that was read through [`ThreadedCapturer::latest`].
+    last_read: Arc<AtomicUsize>,
+    /// Pointer to the current config.
+    config: Arc<Mutex<CaptureConfig>>,
+    thread_info: Arc<Mutex<Option<ThreadInfo>>>,
+    state: Arc<Mutex<WorkerState>>,
+    epoch: std::time::Instant,
+    heartbeat: Arc<AtomicU64>,
+}
+pub type PreCallback = Arc<dyn Fn(usize) + Send + Sync + 'static>;
+pub type PostCallback = Arc<dyn Fn(CaptureInfo) + Send + Sync + 'static>;
+pub type ErrorCallback = Arc<dyn Fn(ScreenCaptureError) + Send + Sync + 'static>;
+pub type SpecificationCallback = Arc<dyn Fn(SpecificationChange) + Send + Sync + 'static>;
+pub type SessionCallback = Arc<dyn Fn(SessionEvent) + Send + Sync + 'static>;
+pub type FrameProcessor = Arc<dyn Fn(OwnedFrame) -> ProcessedFrame + Send + Sync + 'static>;
+
+/// Identifies a callback registered with [`ThreadedCapturer`], used to remove it again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CallbackHandle(u64);
+
+/// Changes to a list of callbacks, sent to the capture thread.
+enum CallbackUpdate<T> {
+    /// Replace all callbacks with this one.
+    Set(T),
+    /// Append a callback.
+    Add(CallbackHandle, T),
+    /// Remove the callback with this handle, if present.
+    Remove(CallbackHandle),
+}
+
+/// The callbacks of one kind, invoked in the order they were registered.
+#[derive(Clone)]
+struct Callbacks<T> {
+    entries: Vec<(CallbackHandle, T)>,
+}
+
+impl<T> Callbacks<T> {
+    /// The handle used for callbacks that are set instead of added, it can't be removed.
+    const SET_HANDLE: CallbackHandle = CallbackHandle(0);
+
+    fn new(initial: Option<T>) -> Self {
+        Callbacks {
+            entries: initial.map(|f| (Self::SET_HANDLE, f)).into_iter().collect(),
+        }
+    }
+
+    fn update(&mut self, update: CallbackUpdate<T>) {
+        match update {
+            CallbackUpdate::Set(f) => self.entries = vec![(Self::SET_HANDLE, f)],
+            CallbackUpdate::Add(handle, f) => self.entries.push((handle, f)),
+            CallbackUpdate::Remove(handle) => self.entries.retain(|(h, _)| *h != handle),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<A: Clone> Callbacks<Arc<dyn Fn(A) + Send + Sync + 'static>> {
+    fn call(&self, arg: A) {
+        for (_, f) in self.entries.iter() {
+            (f)(arg.clone());
+        }
+    }
+}
+
+/// Determines whether the session is locked, `None` if unknown, see [`crate::session_locked`].
+pub type SessionProbe = Arc<dyn Fn() -> Option<bool> + Send + Sync + 'static>;
+
+/// Polls the [`SessionProbe`] on its own thread, as the probe may block, like `loginctl` does on
+/// Linux. The capture thread only reads the last result, the thread exits once this is dropped.
+struct SessionMonitor {
+    /// The last lock state, zero if unknown, one if unlocked and two if locked.
+    state: Arc<AtomicU8>,
+    /// Time between polls in microseconds, zero stops the thread.
+    interval: Arc<AtomicU64>,
+}
+
+impl SessionMonitor {
+    fn spawn(probe: SessionProbe, interval: std::time::Duration) -> Self {
+        let monitor = SessionMonitor {
+            state: Default::default(),
+            interval: Arc::new(AtomicU64::new(interval.as_micros().max(1) as u64)),
+        };
+        let state = monitor.state.clone();
+        let interval = monitor.interval.clone();
+        let spawned = std::thread::Builder::new()
+            .name("capture session monitor".to_owned())
+            .spawn(move || loop {
+                let locked = match probe() {
+                    None => 0,
+                    Some(false) => 1,
+                    Some(true) => 2,
+                };
+                state.store(locked, Relaxed);
+                match interval.load(Relaxed) {
+                    0 => break,
+                    micros => std::thread::sleep(std::time::Duration::from_micros(micros)),
+                }
+                if interval.load(Relaxed) == 0 {
+                    break;
+                }
+            });
+        if let Err(e) = spawned {
+            log::warn!("Failed to spawn the session monitor thread: {e}");
+        }
+        monitor
+    }
+
+    fn set_interval(&self, interval: std::time::Duration) {
+        self.interval
+            .store(interval.as_micros().max(1) as u64, Relaxed);
+    }
+
+    /// The last polled lock state, `None` if unknown or not polled yet.
+    fn locked(&self) -> Option<bool> {
+        match self.state.load(Relaxed) {
+            1 => Some(false),
+            2 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for SessionMonitor {
+    fn drop(&mut self) {
+        self.interval.store(0, Relaxed);
+    }
+}
+
+/// Creates a capture backend, this is called from the capture thread.
+pub type BackendFactory =
+    Arc<dyn Fn() -> Result<Box<dyn Capture>, ScreenCaptureError> + Send + Sync + 'static>;
+
+impl Drop for ThreadedCapturer {
+    fn drop(&mut self) {
+        let res = self.shutdown();
+        if !std::thread::panicking() {
+            res.expect("capture thread should not have panicked");
+        }
+    }
+}
+
+/// State of the config file being watched by the capture thread.
+struct ConfigWatch {
+    path: PathBuf,
+    modified: Option<std::time::SystemTime>,
+    last_check: Option<std::time::Instant>,
+}
+
+impl ConfigWatch {
+    /// Interval at which the modification time of the file is checked.
+    const INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+    fn new(path: PathBuf) -> Self {
+        ConfigWatch {
+            path,
+            modified: None,
+            last_check: None,
+        }
+    }
+
+    /// Returns the result of loading the file if it changed since the last time it was loaded.
+    fn poll(&mut self) -> Option<Result<CaptureConfig, ScreenCaptureError>> {
+        let now = std::time::Instant::now();
+        if self
+            .last_check
+            .map(|v| now.duration_since(v) < Self::INTERVAL)
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        self.last_check = Some(now);
+
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|v| v.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        // Store the modification time even if loading fails, to avoid retrying a broken file.
+        self.modified = modified;
+        Some(CaptureConfig::load(&self.path).map_err(|e| {
+            ScreenCaptureError::Initialisation(
+                format!("failed to load config {}: {}", self.path.display(), e).into(),
+            )
+        }))
+    }
+}
+
+/// Tracks the scheduled start of captures and the jitter statistics.
+struct Schedule {
+    stats: ScheduleStats,
+    /// Running sum of squared differences from the mean, in seconds squared.
+    m2: f64,
+    /// The wall clock aligned start used for the fixed cadence, with the interval it is for.
+    cadence_target: Option<(std::time::Instant, std::time::Duration)>,
+}
+
+impl Schedule {
+    fn new() -> Self {
+        Schedule {
+            stats: Default::default(),
+            m2: 0.0,
+            cadence_target: None,
+        }
+    }
+
+    /// Determine the start of the next capture, given the end and duration of the previous one.
+    fn next_start(
+        &mut self,
+        mode: ScheduleMode,
+        interval: std::time::Duration,
+        last_end: std::time::Instant,
+        last_duration: std::time::Duration,
+    ) -> std::time::Instant {
+        match mode {
+            ScheduleMode::Interval => last_end + interval - last_duration,
+            ScheduleMode::IntervalAfterFinish => last_end + interval,
+            ScheduleMode::FixedCadence => match self.cadence_target {
+                Some((target, target_interval)) if target_interval == interval => target,
+                _ => {
+                    // Determine the next multiple of the interval on the wall clock.
+                    let now = std::time::Instant::now();
+                    let wall = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    let interval_ns = interval.as_nanos().max(1);
+                    let slot = (wall.as_nanos() / interval_ns + 1) * interval_ns;
+                    let target =
+                        now + std::time::Duration::from_nanos((slot - wall.as_nanos()) as u64);
+                    self.cadence_target = Some((target, interval));
+                    target
+                }
+            },
+            ScheduleMode::FrameDriven => last_end,
+        }
+    }
+
+    /// Record that the scheduled capture was skipped, it is not included in the statistics.
+    fn skip(&mut self) {
+        self.cadence_target = None;
+    }
+
+    /// Record that a capture scheduled for `scheduled` started at `start`.
+    fn record(
+        &mut self,
+        mode: ScheduleMode,
+        scheduled: std::time::Instant,
+        start: std::time::Instant,
+    ) {
+        self.cadence_target = None;
+        if mode != self.stats.mode {
+            *self = Schedule::new();
+            self.stats.mode = mode;
+        }
+        // Welford's online algorithm for the mean and variance.
+        let jitter = start.saturating_duration_since(scheduled);
+        let value = jitter.as_secs_f64();
+        let stats = &mut self.stats;
+        stats.samples += 1;
+        let mean = stats.mean.as_secs_f64();
+        let delta = value - mean;
+        let mean = mean + delta / stats.samples as f64;
+        self.m2 += delta * (value - mean);
+        stats.mean = std::time::Duration::from_secs_f64(mean);
+        stats.max = stats.max.max(jitter);
+        stats.std_dev = std::time::Duration::from_secs_f64((self.m2 / stats.samples as f64).sqrt());
+    }
+}
+
+/// The callbacks and processor used by the capture thread.
+#[derive(Clone)]
+struct Handlers {
+    pre: Callbacks<PreCallback>,
+    post: Callbacks<PostCallback>,
+    error: Callbacks<ErrorCallback>,
+    specification: Callbacks<SpecificationCallback>,
+    session: Callbacks<SessionCallback>,
+    processor: Option<FrameProcessor>,
+}
+
+/// The inputs of the capture thread, these are shared such that they survive a restart of the
+/// capture thread by the watchdog.
+struct WorkerInputs {
+    receiver_config: Receiver<CaptureConfig>,
+    receiver_pre: Receiver<CallbackUpdate<PreCallback>>,
+    receiver_post: Receiver<CallbackUpdate<PostCallback>>,
+    receiver_error: Receiver<CallbackUpdate<ErrorCallback>>,
+    receiver_specification: Receiver<CallbackUpdate<SpecificationCallback>>,
+    receiver_session: Receiver<CallbackUpdate<SessionCallback>>,
+    receiver_watch: Receiver<Option<PathBuf>>,
+    receiver_processor: Receiver<Option<FrameProcessor>>,
+    watch: Option<ConfigWatch>,
+    handlers: Handlers,
+}
+
+impl WorkerInputs {
+    /// Consume all pending updates, returns the configs to apply and the errors to report.
+    fn poll(&mut self) -> (Vec<CaptureConfig>, Vec<ScreenCaptureError>) {
+        let mut configs: Vec<CaptureConfig> = self.receiver_config.try_iter().collect();
+        let mut errors = vec![];
+        for update in self.receiver_pre.try_iter() {
+            self.handlers.pre.update(update);
+        }
+        for update in self.receiver_post.try_iter() {
+            self.handlers.post.update(update);
+        }
+        for update in self.receiver_error.try_iter() {
+            self.handlers.error.update(update);
+        }
+        for update in self.receiver_specification.try_iter() {
+            self.handlers.specification.update(update);
+        }
+        for update in self.receiver_session.try_iter() {
+            self.handlers.session.update(update);
+        }
+        for new_processor in self.receiver_processor.try_iter() {
+            self.handlers.processor = new_processor;
+        }
+        for path in self.receiver_watch.try_iter() {
+            self.watch = path.map(ConfigWatch::new);
+        }
+        match self.watch.as_mut().and_then(|w| w.poll()) {
+            Some(Ok(new_config)) => configs.push(new_config),
+            Some(Err(e)) => errors.push(e),
+            None => {}
+        }
+        (configs, errors)
+    }
+}
+
+/// The state of the capture thread, recorded for [`ThreadedCapturer::health`] and
+/// [`ThreadedCapturer::debug_dump`].
+#[derive(Debug, Default)]
+struct WorkerState {
+    last_success: Option<std::time::Instant>,
+    last_error: Option<ScreenCaptureError>,
+    backend: Option<String>,
+    /// The displays reported by the backend when it was created.
+    displays: Vec<DisplayInfo>,
+    /// The specification in use for each display.
+    specifications: Vec<Option<CaptureSpecification>>,
+}
+
+impl WorkerState {
+    /// Record the outcomes of captures, `None` for a success.
+    fn record<'a>(
+        &mut self,
+        time: std::time::Instant,
+        outcomes: impl Iterator<Item = Option<&'a ScreenCaptureError>>,
+    ) {
+        for outcome in outcomes {
+            match outcome {
+                None => self.last_success = Some(time),
+                Some(e) => self.last_error = Some(e.clone()),
+            }
+        }
+    }
+}
+
+/// Time since the heartbeat, which holds the milliseconds since the epoch of the last progress.
+fn since_heartbeat(epoch: std::time::Instant, heartbeat: &AtomicU64) -> std::time::Duration {
+    let elapsed = epoch.elapsed().as_millis() as u64;
+    std::time::Duration::from_millis(elapsed.saturating_sub(heartbeat.load(Relaxed)))
+}
+
+/// The interval between captures at the rate, `None` if the rate disables capturing or isn't a
+/// valid number.
+fn rate_interval(rate: f32) -> Option<std::time::Duration> {
+    if rate > 0.0 {
+        std::time::Duration::try_from_secs_f32(1.0 / rate).ok()
+    } else {
+        None
+    }
+}
+
+/// Everything the capture thread and the watchdog share with the [`ThreadedCapturer`].
+#[derive(Clone)]
+struct WorkerContext {
+    running: Arc<AtomicBool>,
+    /// The generation of the capture thread that should be running, older ones exit.
+    generation: Arc<AtomicUsize>,
+    latest: Arc<Mutex<CaptureInfo>>,
+    config: Arc<Mutex<CaptureConfig>>,
+    schedule_stats: Arc<Mutex<ScheduleStats>>,
+    last_read: Arc<AtomicUsize>,
+    /// Counter of the last capture that was started.
+    counter: Arc<AtomicUsize>,
+    epoch: std::time::Instant,
+    /// Milliseconds since the epoch at which the capture thread last made progress.
+    heartbeat: Arc<AtomicU64>,
+    backend: BackendFactory,
+    session_probe: SessionProbe,
+    inputs: Arc<Mutex<WorkerInputs>>,
+    /// Information about the running capture thread, set by the thread itself.
+    thread_info: Arc<Mutex<Option<ThreadInfo>>>,
+    state: Arc<Mutex<WorkerState>>,
+}
+
+impl WorkerContext {
+    /// Interval at which the watchdog checks the capture thread.
+    const WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+    fn inputs(&self) -> std::sync::MutexGuard<'_, WorkerInputs> {
+        // Callbacks are never invoked while holding the lock, but don't fail on poisoning anyway.
+        self.inputs
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn beat(&self) {
+        let elapsed = self.epoch.elapsed().as_millis() as u64;
+        self.heartbeat.store(elapsed, Relaxed);
+    }
+
+    fn since_heartbeat(&self) -> std::time::Duration {
+        since_heartbeat(self.epoch, &self.heartbeat)
+    }
+
+    fn spawn_worker(&self, generation: usize) -> std::thread::JoinHandle<()> {
+        let config = self.config.lock().unwrap().clone();
+        let name = config
+            .thread
+            .name
+            .clone()
+            .unwrap_or_else(|| ThreadConfig::DEFAULT_NAME.to_owned());
+        let thread_builder = std::thread::Builder::new().name(name);
+        self.beat();
+        let context = self.clone();
+        thread_builder
+            .spawn(move || {
+                let thread = std::thread::current();
+                *context.thread_info.lock().unwrap() = Some(ThreadInfo {
+                    id: thread.id(),
+                    os_id: crate::backend::current_thread_id(),
+                    name: thread.name().map(str::to_owned),
+                    spawned: std::time::SystemTime::now(),
+                    restarts: generation,
+                });
+                context.run(generation, config)
+            })
+            .expect("should be able to spawn the capture thread")
+    }
+
+    /// Monitor the capture thread, restarting it if it died or stalled, until stopped.
+    fn supervise(&self, mut worker: std::thread::JoinHandle<()>) {
+        while self.running.load(Relaxed) {
+            std::thread::sleep(Self::WATCHDOG_INTERVAL);
+            let stall_limit = self.config.lock().unwrap().watchdog.stall_limit();
+            let reason = if worker.is_finished() {
+                if !self.running.load(Relaxed) {
+                    break;
+                }
+                match std::mem::replace(&mut worker, self.spawn_worker_next()).join() {
+                    Err(panic) => ThreadError::from_panic(panic).to_string(),
+                    Ok(()) => "capture thread exited".to_owned(),
+                }
+            } else if let Some(limit) = stall_limit.filter(|l| self.since_heartbeat() > *l) {
+                // The stalled thread can't be stopped, it exits once it notices it was replaced.
+                worker = self.spawn_worker_next();
+                format!(
+                    "capture thread stalled for more than {}s",
+                    limit.as_secs_f32()
+                )
+            } else {
+                continue;
+            };
+            let error_callbacks = self.inputs().handlers.error.clone();
+            error_callbacks.call(ScreenCaptureError::Other(
+                format!("{reason}, restarted it").into(),
+            ));
+        }
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    fn spawn_worker_next(&self) -> std::thread::JoinHandle<()> {
+        let generation = self.generation.fetch_add(1, Relaxed) + 1;
+        self.spawn_worker(generation)
+    }
+
+    /// The capture loop, runs until stopped or replaced by a newer generation.
+    fn run(&self, generation: usize, config_initial: CaptureConfig) {
+        use std::time::{Duration, Instant};
+
+        let epoch = Instant::now();
+        let mut handlers = self.inputs().handlers.clone();
+        if let Err(e) = config_initial.thread.apply() {
+            handlers.error.call(e);
+        }
+        let mut capturers = DisplayCapturers::new(config_initial, self.backend.clone());
+        let latest = &self.latest;
+        let config = &self.config;
+
+        let mut last_duration = std::time::Duration::new(0, 0);
+        let mut last_end = Instant::now();
+        // Counter of the last published frame, may be from a previous capture thread.
+        let mut published = latest.lock().unwrap().counter;
+        let mut schedule = Schedule::new();
+        let mut locked = false;
+        let mut session_monitor: Option<SessionMonitor> = None;
+
+        let apply_config = |capturers: &mut DisplayCapturers, new_config: CaptureConfig| {
+            let thread_changed = capturers.config.thread != new_config.thread;
+            capturers.set_config(new_config.clone());
+            let mut locked = config.lock().unwrap();
+            *locked = new_config;
+            if thread_changed {
+                locked.thread.apply()
+            } else {
+                Ok(())
+            }
+        };
+
+        while self.running.load(Relaxed) && self.generation.load(Relaxed) == generation {
+            self.beat();
+            // First, check for new configs and callbacks, if so consume them.
+            let (new_configs, mut errors) = {
+                let mut inputs = self.inputs();
+                let updates = inputs.poll();
+                handlers = inputs.handlers.clone();
+                updates
+            };
+            for new_config in new_configs {
+                if let Err(e) = apply_config(&mut capturers, new_config) {
+                    errors.push(e);
+                }
+            }
+            for e in errors {
+                handlers.error.call(e);
+            }
+
+            let session = capturers.config.session;
+            if session.pause_when_locked || !handlers.session.is_empty() {
+                let monitor = session_monitor.get_or_insert_with(|| {
+                    SessionMonitor::spawn(self.session_probe.clone(), session.interval())
+                });
+                monitor.set_interval(session.interval());
+                if let Some(now_locked) = monitor.locked() {
+                    if now_locked != locked {
+                        locked = now_locked;
+                        log::debug!("Session locked: {}", locked);
+                        handlers.session.call(if locked {
+                            SessionEvent::Locked
+                        } else {
+                            SessionEvent::Unlocked
+                        });
+                    }
+                }
+                if locked && session.pause_when_locked {
+                    std::thread::sleep(session.interval().min(Duration::from_millis(100)));
+                    continue;
+                }
+            } else {
+                session_monitor = None;
+            }
+
+            let rate_valid = capturers.config.rate > 0.0;
+            if !rate_valid {
+                // Rate is negative or zero, can be used to disable, block on config updates for 100ms.
+                let received = self
+                    .inputs()
+                    .receiver_config
+                    .recv_timeout(Duration::from_millis(100));
+                if let Ok(new_config) = received {
+                    if let Err(e) = apply_config(&mut capturers, new_config) {
+                        handlers.error.call(e);
+                    }
+                }
+                continue;
+            }
+
+            // Next, calculate the desired interval and point in time to start.
+            let rate = capturers.rate();
+            let Some(interval) = rate_interval(rate) else {
+                // The active specifications disable capturing.
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            };
+            let mode = capturers.config.schedule;
+            let start_timepoint = schedule.next_start(mode, interval, last_end, last_duration);
+            log::trace!(
+                "current:   {: >16.6?} start_timepoint: {: >12.6?}",
+                Instant::now().duration_since(epoch),
+                start_timepoint.duration_since(epoch)
+            );
+            let now = Instant::now();
+            if now <= start_timepoint {
+                // Still have to wait, limit the wait to 100ms.
+                let to_wait = start_timepoint - now;
+                let limited = to_wait.min(Duration::from_millis(100));
+                log::trace!("sleeping for: {:?}", limited);
+                std::thread::sleep(limited);
+                // Quick check if we still have to wait more.
+                if Instant::now() <= start_timepoint {
+                    continue;
+                }
+            }
+
+            let idle = handlers.post.is_empty() && self.last_read.load(Relaxed) < published;
+            let idle_behaviour = capturers.config.idle;
+            if idle && idle_behaviour != IdleBehaviour::Capture {
+                schedule.skip();
+                let start = Instant::now();
+                if idle_behaviour == IdleBehaviour::SkipConversion {
+                    let errors = capturers.capture_only();
+                    let outcomes: Vec<_> = if errors.is_empty() {
+                        vec![None]
+                    } else {
+                        errors.iter().map(Some).collect()
+                    };
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .record(start, outcomes.into_iter());
+                    for e in errors {
+                        handlers.error.call(e);
+                    }
+                    for change in capturers.take_specification_changes() {
+                        handlers.specification.call(change);
+                    }
+                }
+                last_end = Instant::now();
+                last_duration = last_end - start;
+                continue;
+            }
+
+            let this_counter = self.counter.fetch_add(1, Relaxed) + 1;
+            let _span = crate::telemetry::frame_span(this_counter);
+            handlers.pre.call(this_counter);
+            let start = Instant::now();
+            schedule.record(mode, start_timepoint, start);
+            *self.schedule_stats.lock().unwrap() = schedule.stats;
+            let capture_time = std::time::SystemTime::now();
+            let displays = capturers.capture(handlers.processor.as_ref());
+            let changes = capturers.take_specification_changes();
+            let changed = !changes.is_empty();
+            for change in changes {
+                handlers.specification.call(change);
+            }
+            let end;
+            let info = {
+                let mut locked = latest.lock().unwrap();
+                log::trace!("capture at {: >16.6?} ", start.duration_since(epoch));
+                end = std::time::Instant::now();
+                let info = CaptureInfo {
+                    data: displays.first().and_then(|v| v.data.clone()),
+                    result: displays.first().map(|v| v.result.clone()).unwrap_or(Err(
+                        ScreenCaptureError::Initialisation("no displays to capture".into()),
+                    )),
+                    displays,
+                    time: capture_time,
+                    duration: end - start,
+                    counter: this_counter,
+                };
+                *locked = info.clone();
+                info
+            };
+            published = this_counter;
+            {
+                let mut state = self.state.lock().unwrap();
+                let fresh = info.displays.iter().filter(|d| d.fresh);
+                state.record(end, fresh.map(|d| d.result.as_ref().err()));
+                if changed || state.backend.is_none() {
+                    capturers.update_state(&mut state);
+                }
+            }
+            for display in info.displays.iter().filter(|d| d.fresh) {
+                if let Err(e) = &display.result {
+                    handlers.error.call(e.clone());
+                }
+            }
+            handlers.post.call(info);
+            // std::thread::sleep(Duration::from_millis(100) - (std::time::Instant::now() - start));
+
+            last_duration = end - start;
+            last_end = end;
+            log::trace!(
+                "Duration was {: >13.6?} at {: >12.6?}",
+                last_duration.as_secs_f64(),
+                Instant::now().duration_since(epoch)
+            );
+        }
+        log::debug!("Broke from loop, thread closing");
+    }
+}
+
+/// Builder to setup a [`ThreadedCapturer`] in one go, see [`ThreadedCapturer::builder`].
+///
+/// The callbacks are in place before the capture thread starts, so no frames are missed.
+#[derive(Default)]
+pub struct ThreadedCapturerBuilder {
+    config: CaptureConfig,
+    pre_callback: Option<PreCallback>,
+    post_callback: Option<PostCallback>,
+    error_callback: Option<ErrorCallback>,
+    specification_callback: Option<SpecificationCallback>,
+    session_callback: Option<SessionCallback>,
+    processor: Option<FrameProcessor>,
+    backend: Option<BackendFactory>,
+    session_probe: Option<SessionProbe>,
+    watch: Option<PathBuf>,
+}
+
+impl ThreadedCapturerBuilder {
+    /// Replace the entire configuration.
+    pub fn config(mut self, config: CaptureConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the capture rate in Hz.
+    pub fn rate(mut self, rate: f32) -> Self {
+        self.config.rate = rate;
+        self
+    }
+
+    /// Add a region to capture, used if none of the earlier specifications match. Negative offsets
+    /// are relative to the right and bottom edges.
+    pub fn region(self, x: i32, y: i32, width: u32, height: u32) -> Self {
+        self.specification(CaptureSpecification {
+            x,
+            y,
+            width,
+            height,
+            ..Default::default()
+        })
+    }
+
+    /// Add a capture specification, these are matched in the order they are added.
+    pub fn specification(mut self, spec: CaptureSpecification) -> Self {
+        self.config.capture.push(spec);
+        self
+    }
+
+    /// Set the displays to capture simultaneously, see [`CaptureConfig::displays`].
+    pub fn displays(mut self, displays: &[u32]) -> Self {
+        self.config.displays = displays.to_vec();
+        self
+    }
+
+    /// Set when captures start.
+    pub fn schedule(mut self, mode: ScheduleMode) -> Self {
+        self.config.schedule = mode;
+        self
+    }
+
+    /// Set the format in which frames are delivered.
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.config.output_format = format;
+        self
+    }
+
+    /// Set what happens when nobody consumes the frames.
+    pub fn idle(mut self, idle: IdleBehaviour) -> Self {
+        self.config.idle = idle;
+        self
+    }
+
+    /// Set the session lock detection settings, see [`SessionConfig`].
+    pub fn session(mut self, session: SessionConfig) -> Self {
+        self.config.session = session;
+        self
+    }
+
+    /// Set the watchdog settings, see [`WatchdogConfig`].
+    pub fn watchdog(mut self, watchdog: WatchdogConfig) -> Self {
+        self.config.watchdog = watchdog;
+        self
+    }
+
+    /// Set the callback that's invoked before each frame is captured.
+    pub fn on_capture<F: Fn(usize) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.pre_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the callback that's invoked with the capture info after each frame.
+    pub fn on_frame<F: Fn(CaptureInfo) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.post_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the callback that's invoked for each error that occurs in the capture thread.
+    pub fn on_error<F: Fn(ScreenCaptureError) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.error_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the callback that's invoked when the specification in use changes, see
+    /// [`ThreadedCapturer::add_specification_callback`].
+    pub fn on_specification_change<F: Fn(SpecificationChange) + Send + Sync + 'static>(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.specification_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the callback that's invoked when the session is locked or unlocked, see
+    /// [`ThreadedCapturer::add_session_callback`].
+    pub fn on_session_change<F: Fn(SessionEvent) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.session_callback = Some(Arc::new(f));
+        self
+    }
+
+    /// Use the provided function to determine whether the session is locked, instead of
+    /// [`crate::session_locked`]. It is called from a separate thread, so it may block.
+    pub fn session_probe<F: Fn() -> Option<bool> + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.session_probe = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the frame processor, see [`ThreadedCapturer::set_processor`].
+    pub fn processor<F: Fn(OwnedFrame) -> ProcessedFrame + Send + Sync + 'static>(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.processor = Some(Arc::new(f));
+        self
+    }
+
+    /// Use the provided backend instead of the platform's backend, see
+    /// [`ThreadedCapturer::with_backend`].
+    pub fn backend<
+        F: Fn() -> Result<Box<dyn Capture>, ScreenCaptureError> + Send + Sync + 'static,
+    >(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.backend = Some(Arc::new(f));
+        self
+    }
+
+    /// Watch a configuration file, see [`ThreadedCapturer::watch_config`].
+    pub fn watch_config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.watch = Some(path.into());
+        self
+    }
+
+    /// Set the name of the capture thread.
+    pub fn thread_name(mut self, name: &str) -> Self {
+        self.config.thread.name = Some(name.to_owned());
+        self
+    }
+
+    /// Set the priority of the capture thread.
+    pub fn thread_priority(mut self, priority: ThreadPriority) -> Self {
+        self.config.thread.priority = Some(priority);
+        self
+    }
+
+    /// Set the cores the capture thread is allowed to run on.
+    pub fn thread_affinity(mut self, cores: &[usize]) -> Self {
+        self.config.thread.affinity = Some(cores.to_vec());
+        self
+    }
+
+    /// Create the [`ThreadedCapturer`] and start its capture thread.
+    pub fn build(self) -> ThreadedCapturer {
+        ThreadedCapturer::spawn(self)
+    }
+}
+
+impl Default for ThreadedCapturer {
+    fn default() -> Self {
+        ThreadedCapturer::new(Default::default())
+    }
+}
+impl ThreadedCapturer {
+    /// Instantiate a new capture grabber with configuration.
+    pub fn new(config: CaptureConfig) -> ThreadedCapturer {
+        Self::builder().config(config).build()
+    }
+
+    /// Instantiate a capture grabber that uses the provided backend instead of the platform's.
+    ///
+    /// The backends are not required to be [`Send`], so instead of a backend this takes a function
+    /// that creates one. It is called from the capture thread, once for each display that is
+    /// captured and again if a backend has to be recreated.
+    pub fn with_backend(backend: BackendFactory, config: CaptureConfig) -> ThreadedCapturer {
+        let mut builder = Self::builder().config(config);
+        builder.backend = Some(backend);
+        builder.build()
+    }
+
+    /// Create a builder to configure the capturer and its callbacks before it starts.
+    pub fn builder() -> ThreadedCapturerBuilder {
+        Default::default()
+    }
+
+    fn spawn(builder: ThreadedCapturerBuilder) -> ThreadedCapturer {
+        let (sender_config, receiver_config) = channel::<CaptureConfig>();
+        let (sender_pre, receiver_pre) = channel::<CallbackUpdate<PreCallback>>();
+        let (sender_post, receiver_post) = channel::<CallbackUpdate<PostCallback>>();
+        let (sender_error, receiver_error) = channel::<CallbackUpdate<ErrorCallback>>();
+        let (sender_specification, receiver_specification) =
+            channel::<CallbackUpdate<SpecificationCallback>>();
+        let (sender_session, receiver_session) = channel::<CallbackUpdate<SessionCallback>>();
+        let (sender_watch, receiver_watch) = channel::<Option<PathBuf>>();
+        let (sender_processor, receiver_processor) = channel::<Option<FrameProcessor>>();
+        let inputs = WorkerInputs {
+            receiver_config,
+            receiver_pre,
+            receiver_post,
+            receiver_error,
+            receiver_specification,
+            receiver_session,
+            receiver_watch,
+            receiver_processor,
+            watch: builder.watch.map(ConfigWatch::new),
+            handlers: Handlers {
+                pre: Callbacks::new(builder.pre_callback),
+                post: Callbacks::new(builder.post_callback),
+                error: Callbacks::new(builder.error_callback),
+                specification: Callbacks::new(builder.specification_callback),
+                session: Callbacks::new(builder.session_callback),
+                processor: builder.processor,
+            },
+        };
+        let watchdog = builder.config.watchdog.enabled;
+        let options = builder.config.backend.clone();
+        let context = WorkerContext {
+            running: Arc::new(true.into()),
+            generation: Default::default(),
+            latest: Arc::new(Mutex::new(CaptureInfo::default())),
+            config: Arc::new(Mutex::new(builder.config)),
+            schedule_stats: Default::default(),
+            last_read: Default::default(),
+            counter: Default::default(),
+            epoch: std::time::Instant::now(),
+            heartbeat: Default::default(),
+            backend: builder
+                .backend
+                .unwrap_or(Arc::new(move || Ok(crate::capture_with_options(&options)?))),
+            session_probe: builder
+                .session_probe
+                .unwrap_or(Arc::new(crate::session_locked)),
+            inputs: Arc::new(Mutex::new(inputs)),
+            thread_info: Default::default(),
+            state: Default::default(),
+        };
+
+        let worker = context.spawn_worker(0);
+        let thread = if watchdog {
+            let context = context.clone();
+            std::thread::Builder::new()
+                .name("capture watchdog".to_owned())
+                .spawn(move || context.supervise(worker))
+                .expect("should be able to spawn the watchdog thread")
+        } else {
+            worker
+        };
+        Self {
+            config: context.config,
+            running: context.running,
+            latest: context.latest,
+            sender_config,
+            sender_pre,
+            sender_post,
+            sender_error,
+            sender_specification,
+            sender_session,
+            next_handle: AtomicU64::new(1),
+            sender_watch,
+            sender_processor,
+            schedule_stats: context.schedule_stats,
+            last_read: context.last_read,
+            thread_info: context.thread_info,
+            state: context.state,
+            epoch: context.epoch,
+            heartbeat: context.heartbeat,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop the capture thread and wait for it to finish.
+    ///
+    /// Returns an error if the capture thread panicked, for example from within a callback. With
+    /// the watchdog enabled such a panic is only returned if it happened while stopping.
+    /// Dropping the capturer also stops the thread, but propagates such a panic instead.
+    pub fn stop(mut self) -> Result<(), ThreadError> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<(), ThreadError> {
+        self.running.store(false, Relaxed);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(ThreadError::from_panic),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the configuration and re-initialise appropriately.
+    pub fn set_config(&self, config: CaptureConfig) {
+        let _ = self.sender_config.send(config);
+    }
+
+    /// Switch to the named profile of the current configuration, see [`CaptureConfig::profile`].
+    pub fn activate_profile(&self, name: &str) -> Result<(), ConfigError> {
+        let config = self
+            .config()
+            .profile(name)
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_owned()))?;
+        self.set_config(config);
+        Ok(())
+    }
+
+    /// Watch the configuration file at the provided path, it is loaded immediately and reloaded
+    /// whenever its modification time changes. See [`CaptureConfig::load`] for the supported
+    /// formats. A file that fails to load is reported to the error callback and the current
+    /// configuration is retained.
+    pub fn watch_config<P: Into<PathBuf>>(&self, path: P) {
+        let _ = self.sender_watch.send(Some(path.into()));
+    }
+
+    /// Stop watching the configuration file, the current configuration is retained.
+    pub fn unwatch_config(&self) {
+        let _ = self.sender_watch.send(None);
+    }
+
+    /// Set the callback that's invoked before the frame is captured, this replaces all pre
+    /// callbacks, including those that were added.
+    pub fn set_pre_callback(&self, f: PreCallback) {
+        let _ = self.sender_pre.send(CallbackUpdate::Set(f));
+    }
+
+    /// Set the callback that's invoked after the frame capture is complete, it is passed
+    /// the capture info. This will be called from the thread that captures, so keep it short
+    /// else it blocks capturing thread. This replaces all post callbacks, including those that
+    /// were added.
+    pub fn set_post_callback(&self, f: PostCallback) {
+        let _ = self.sender_post.send(CallbackUpdate::Set(f));
+    }
+
+    /// Set the callback that's invoked for each error that occurs in the capture thread, like
+    /// the post callback this is called from the thread that captures. This replaces all error
+    /// callbacks, including those that were added.
+    pub fn set_error_callback(&self, f: ErrorCallback) {
+        let _ = self.sender_error.send(CallbackUpdate::Set(f));
+    }
+
+    /// Add a callback that's invoked before the frame is captured, in addition to the existing
+    /// ones. Callbacks are invoked in the order they were registered.
+    pub fn add_pre_callback(&self, f: PreCallback) -> CallbackHandle {
+        let handle = self.new_handle();
+        let _ = self.sender_pre.send(CallbackUpdate::Add(handle, f));
+        handle
+    }
+
+    /// Add a callback that's invoked after the frame capture is complete, in addition to the
+    /// existing ones. Callbacks are invoked in the order they were registered.
+    pub fn add_post_callback(&self, f: PostCallback) -> CallbackHandle {
+        let handle = self.new_handle();
+        let _ = self.sender_post.send(CallbackUpdate::Add(handle, f));
+        handle
+    }
+
+    /// Add a callback that's invoked for each error, in addition to the existing ones. Callbacks
+    /// are invoked in the order they were registered.
+    pub fn add_error_callback(&self, f: ErrorCallback) -> CallbackHandle {
+        let handle = self.new_handle();
+        let _ = self.sender_error.send(CallbackUpdate::Add(handle, f));
+        handle
+    }
+
+    /// Add a callback that's invoked when the specification in use for a display changes, for
+    /// example because the resolution changed or a new configuration was set. It is invoked from
+    /// the capture thread before the post callbacks of the first frame captured with the new
+    /// specification, such that layouts depending on the region can be rebuilt in time.
+    pub fn add_specification_callback(&self, f: SpecificationCallback) -> CallbackHandle {
+        let handle = self.new_handle();
+        let _ = self
+            .sender_specification
+            .send(CallbackUpdate::Add(handle, f));
+        handle
+    }
+
+    /// Add a callback that's invoked from the capture thread when the session is locked or
+    /// unlocked. Registering it enables polling the lock state, see [`SessionConfig`].
+    pub fn add_session_callback(&self, f: SessionCallback) -> CallbackHandle {
+        let handle = self.new_handle();
+        let _ = self.sender_session.send(CallbackUpdate::Add(handle, f));
+        handle
+    }
+
+    /// Remove a callback that was added, it is not invoked for frames captured after the
+    /// capture thread processed the removal. Removing it twice has no effect.
+    pub fn remove_callback(&self, handle: CallbackHandle) {
+        let _ = self.sender_pre.send(CallbackUpdate::Remove(handle));
+        let _ = self.sender_post.send(CallbackUpdate::Remove(handle));
+        let _ = self.sender_error.send(CallbackUpdate::Remove(handle));
+        let _ = self
+            .sender_specification
+            .send(CallbackUpdate::Remove(handle));
+        let _ = self.sender_session.send(CallbackUpdate::Remove(handle));
+    }
+
+    fn new_handle(&self) -> CallbackHandle {
+        CallbackHandle(self.next_handle.fetch_add(1, Relaxed))
+    }
+
+    /// Set the processor that's invoked on the capture thread for each frame, after the
+    /// conversion to the output format and before the capture info is published.
+    ///
+    /// This allows per-frame work like masking, downscaling or analysis to happen without
+    /// copying the frame or needing another thread. The processor takes ownership of the frame
+    /// and returns the frame to be published, optionally with data attached to it. The time it
+    /// takes is included in the capture duration and thus reduces the achievable rate.
+    pub fn set_processor(&self, f: FrameProcessor) {
+        let _ = self.sender_processor.send(Some(f));
+    }
+
+    /// Remove the frame processor, frames are published as converted.
+    pub fn clear_processor(&self) {
+        let _ = self.sender_processor.send(None);
     }
 
     /// Get the current config.
@@ -394,9 +3010,1698 @@ impl ThreadedCapturer {
         locked.clone()
     }
 
+    /// Get the jitter statistics for the current scheduling mode, these are reset when the mode
+    /// changes.
+    pub fn schedule_stats(&self) -> ScheduleStats {
+        *self.schedule_stats.lock().unwrap()
+    }
+
+    /// Get the identifiers, name and spawn time of the capture thread, `None` until it started.
+    /// After a restart by the watchdog this describes the new thread.
+    pub fn thread_info(&self) -> Option<ThreadInfo> {
+        self.thread_info.lock().unwrap().clone()
+    }
+
+    /// The displays as enumerated by the backends when the capture was last set up, empty before
+    /// that or if the backends can't enumerate them.
+    pub fn displays(&self) -> Vec<DisplayInfo> {
+        self.state.lock().unwrap().displays.clone()
+    }
+
+    /// Summarise whether the capture thread is alive, when it last captured successfully, the
+    /// last error and the backend in use, for liveness and readiness probes.
+    pub fn health(&self) -> Health {
+        let limit = self
+            .config
+            .lock()
+            .unwrap()
+            .watchdog
+            .stall_limit()
+            .unwrap_or(Health::STALL_TIMEOUT);
+        let alive = self.running.load(Relaxed)
+            && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+            && since_heartbeat(self.epoch, &self.heartbeat) <= limit;
+        let state = self.state.lock().unwrap();
+        Health {
+            alive,
+            since_success: state.last_success.map(|t| t.elapsed()),
+            last_error: state.last_error.clone(),
+            backend: state.backend.clone(),
+        }
+    }
+
+    /// Write the state of the capturer to the directory, creating it if needed, for bug reports.
+    ///
+    /// This writes `state.txt` with the config, the specification in use for each display, the
+    /// schedule statistics, the health, the thread and the displays reported by the backend.
+    /// With the `toml` feature the config is also written to `config.toml`. The most recent frame
+    /// of each display is written as `frame_<display>.ppm`. This does not mark the frame as
+    /// consumed.
+    pub fn debug_dump<P: AsRef<std::path::Path>>(&self, dir: P) -> std::io::Result<()> {
+        use std::io::Write;
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let config = self.config();
+        let latest = self.latest.lock().unwrap().clone();
+        let (specifications, displays) = {
+            let state = self.state.lock().unwrap();
+            (state.specifications.clone(), state.displays.clone())
+        };
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(dir.join("state.txt"))?);
+        writeln!(file, "version: {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(file, "time: {:?}", std::time::SystemTime::now())?;
+        writeln!(file, "config: {config:#?}")?;
+        writeln!(file, "specifications: {specifications:#?}")?;
+        writeln!(file, "schedule_stats: {:#?}", self.schedule_stats())?;
+        writeln!(file, "health: {:#?}", self.health())?;
+        writeln!(file, "thread_info: {:#?}", self.thread_info())?;
+        writeln!(file, "displays: {displays:#?}")?;
+        writeln!(file, "latest: {:#?}", latest.metadata())?;
+        file.flush()?;
+
+        #[cfg(feature = "toml")]
+        config
+            .save(dir.join("config.toml"))
+            .map_err(std::io::Error::other)?;
+
+        for display in latest.displays.iter() {
+            if let Ok(frame) = &display.result {
+                let path = dir.join(format!("frame_{}.ppm", display.display));
+                let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+                crate::util::write_ppm_binary_to(&frame.to_bgr(), &mut file)?;
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+
     /// Obtain the latest image and its capture time.
+    ///
+    /// This marks the frame as consumed, see [`IdleBehaviour`].
     pub fn latest(&self) -> CaptureInfo {
         let lock = self.latest.lock().unwrap();
+        self.last_read.fetch_max(lock.counter, Relaxed);
         lock.clone()
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::synthetic::tests::test_pattern;
+    use crate::tests::TestDir;
+    use crate::BGR;
+    use std::sync::mpsc::Receiver;
+    use std::time::{Duration, Instant};
+
+    /// How long the tests wait for the capture thread before failing.
+    pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Receive the counters of the frames the capturer delivers from now on.
+    pub fn frames(capturer: &ThreadedCapturer) -> Receiver<usize> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        capturer.add_post_callback(Arc::new(move |info| {
+            let _ = sender.lock().unwrap().send(info.counter);
+        }));
+        receiver
+    }
+
+    /// Wait for the next frame, panics if none arrives within the timeout.
+    pub fn next_frame(frames: &Receiver<usize>) -> usize {
+        frames
+            .recv_timeout(TIMEOUT)
+            .expect("timed out waiting for a frame")
+    }
+
+    /// Wait until the condition holds, panics if it doesn't within the timeout.
+    pub fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for a condition"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_config_from_path() {
+        let dir = TestDir::new("config_from_path");
+        let toml_path = dir.join("config.toml");
+        std::fs::write(
+            &toml_path,
+            "rate = 5.0\n[[capture]]\nmatch_width = 1920\nx = 100\n",
+        )
+        .unwrap();
+        let config = CaptureConfig::from_path(&toml_path).expect("should parse toml");
+        assert_eq!(config.rate, 5.0);
+        assert_eq!(config.capture.len(), 1);
+        assert_eq!(config.capture[0].match_width, Some(1920));
+        assert_eq!(config.capture[0].x, 100);
+
+        std::fs::write(&toml_path, "rate = 5.0\n[[capture]]\nx = \"left\"\n").unwrap();
+        let e = CaptureConfig::load(&toml_path).expect_err("should fail on the string");
+        assert!(
+            matches!(e, ConfigError::Parse { key: Some(ref key), .. } if key == "capture[0].x")
+        );
+
+        let unknown_path = dir.join("config.ini");
+        std::fs::write(&unknown_path, "rate = 5.0").unwrap();
+        assert!(matches!(
+            CaptureConfig::load(&unknown_path),
+            Err(ConfigError::UnsupportedFormat(_))
+        ));
+
+        #[cfg(not(feature = "yaml"))]
+        {
+            let yaml_path = dir.join("config.yaml");
+            assert!(matches!(
+                CaptureConfig::load(&yaml_path),
+                Err(ConfigError::FormatNotEnabled("yaml"))
+            ));
+            assert!(matches!(
+                CaptureConfig::default().save(&yaml_path),
+                Err(ConfigError::FormatNotEnabled("yaml"))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_config_save_load() {
+        let config = CaptureConfig {
+            rate: 5.0,
+            capture: vec![CaptureSpecification {
+                match_width: Some(1920),
+                x: -100,
+                rate: Some(2.0),
+                ..Default::default()
+            }],
+            displays: vec![0, 1],
+            backend: BackendOptions {
+                debug_layer: true,
+                strict_images: true,
+            },
+            profiles: HashMap::from([(
+                "fast".to_owned(),
+                CaptureConfig {
+                    rate: 30.0,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let extensions: &[&str] = &[
+            #[cfg(feature = "toml")]
+            "toml",
+            #[cfg(feature = "json")]
+            "json",
+            #[cfg(feature = "yaml")]
+            "yaml",
+        ];
+        let dir = TestDir::new("config_save_load");
+        for extension in extensions {
+            let path = dir.join(format!("config.{extension}"));
+            config.save(&path).expect("should save");
+            let loaded = CaptureConfig::load(&path).expect("should load");
+            assert_eq!(config, loaded, "{extension}");
+        }
+    }
+
+    #[test]
+    fn test_config_for_display() {
+        let config = CaptureConfig {
+            capture: vec![
+                CaptureSpecification {
+                    display: 1,
+                    x: 10,
+                    ..Default::default()
+                },
+                CaptureSpecification {
+                    display: 2,
+                    ..Default::default()
+                },
+            ],
+            rate: 10.0,
+            displays: vec![1, 2],
+            ..Default::default()
+        };
+        let display_1 = config.for_display(1, &[]);
+        assert_eq!(display_1.rate, 10.0);
+        assert!(display_1.displays.is_empty());
+        assert_eq!(display_1.capture.len(), 2);
+        assert_eq!(display_1.capture[0].x, 10);
+
+        // Falls back to the whole display if nothing matches.
+        let display_3 = config.for_display(3, &[]);
+        let spec = CaptureSpecification::get_config(1920, 1080, &display_3.capture);
+        assert_eq!(spec.display, 3);
+        assert_eq!(spec.width, 1920);
+        assert_eq!(spec.height, 1080);
+
+        // Names are resolved against the displays, like the selection of the specification.
+        let displays = vec![
+            DisplayInfo {
+                index: 1,
+                name: "eDP-1".to_owned(),
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 2,
+                name: "DP-2".to_owned(),
+                ..Default::default()
+            },
+        ];
+        let config = CaptureConfig {
+            capture: vec![CaptureSpecification {
+                match_display_name: Some("DP-*".to_owned()),
+                x: 20,
+                ..Default::default()
+            }],
+            displays: vec![1, 2],
+            ..Default::default()
+        };
+        assert_eq!(config.for_display(1, &displays).capture.len(), 1);
+        let display_2 = config.for_display(2, &displays);
+        assert_eq!(display_2.capture.len(), 2);
+        let spec = CaptureSpecification::get_config_for_displays(
+            1920,
+            1080,
+            &displays,
+            &display_2.capture,
+        );
+        assert_eq!((spec.display, spec.x), (2, 20));
+        // Without the displays the name can't be resolved.
+        assert_eq!(config.for_display(2, &[]).capture.len(), 1);
+    }
+
+    #[test]
+    fn test_fractional_region() {
+        let right = CaptureSpecification {
+            x_frac: Some(0.6),
+            width_frac: Some(0.4),
+            y: 10,
+            ..Default::default()
+        };
+        let specs = vec![right];
+        let spec = CaptureSpecification::get_config(1920, 1080, &specs);
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (1152, 10, 768, 1070)
+        );
+        assert_eq!(spec.x_frac, None);
+        let spec = CaptureSpecification::get_config(2560, 1440, &specs);
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (1536, 10, 1024, 1430)
+        );
+    }
+
+    #[test]
+    fn test_anchored_offsets() {
+        let specs = vec![CaptureSpecification {
+            x: -800,
+            y: -100,
+            height: 50,
+            ..Default::default()
+        }];
+        let spec = CaptureSpecification::get_config(1920, 1080, &specs);
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (1120, 980, 800, 50)
+        );
+        let spec = CaptureSpecification::get_config(640, 80, &specs);
+        assert_eq!((spec.x, spec.y, spec.width, spec.height), (0, 0, 640, 50));
+    }
+
+    #[test]
+    fn test_select_priority() {
+        let specs = vec![
+            CaptureSpecification {
+                match_width: Some(1920),
+                x: 1,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_width: Some(2560),
+                priority: 10,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                x: 2,
+                priority: 5,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                x: 3,
+                priority: 5,
+                ..Default::default()
+            },
+        ];
+        let selection = CaptureSpecification::select(1920, 1080, &[], &specs);
+        assert_eq!(selection.index, Some(2));
+        assert_eq!(selection.specification.x, 2);
+        assert_eq!(
+            selection.candidates[1].rejection,
+            Some("width 1920 is not 2560".to_owned())
+        );
+        assert_eq!(
+            selection.to_string(),
+            "selected specification 2 with priority 5, matching: [0, 2, 3]; 1: width 1920 is not 2560"
+        );
+
+        let selection = CaptureSpecification::select(1920, 1080, &[], &specs[..2]);
+        assert_eq!(selection.index, Some(0));
+        let selection = CaptureSpecification::select(1280, 720, &[], &specs[..2]);
+        assert_eq!(selection.index, None);
+        assert_eq!(selection.specification.width, 1280);
+    }
+
+    #[test]
+    fn test_select_merge() {
+        let specs = vec![
+            CaptureSpecification {
+                x: 100,
+                y: 50,
+                width_frac: Some(0.5),
+                rate: Some(10.0),
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_width: Some(2560),
+                y: 80,
+                merge: true,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_height: Some(1440),
+                width: 600,
+                merge: true,
+                ..Default::default()
+            },
+        ];
+        let selection = CaptureSpecification::select(1920, 1080, &[], &specs);
+        assert_eq!(selection.index, Some(0));
+        assert!(selection.merged.is_empty());
+        assert_eq!(
+            (selection.specification.y, selection.specification.width),
+            (50, 960)
+        );
+
+        let selection = CaptureSpecification::select(2560, 1440, &[], &specs);
+        assert_eq!(selection.index, Some(0));
+        assert_eq!(selection.merged, vec![1, 2]);
+        let spec = &selection.specification;
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (100, 80, 600, 1360)
+        );
+        assert_eq!(spec.rate, Some(10.0));
+        assert_eq!(
+            selection.to_string(),
+            "selected specification 0 with priority 0, matching: [0, 1, 2], merged: [1, 2]"
+        );
+
+        // Without a base specification, the merged ones apply to the entire display.
+        let selection = CaptureSpecification::select(2560, 1440, &[], &specs[1..]);
+        assert_eq!(selection.index, None);
+        let spec = &selection.specification;
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (0, 80, 600, 1360)
+        );
+
+        let config = CaptureConfig {
+            capture: specs,
+            rate: 5.0,
+            ..Default::default()
+        };
+        let resolution = Resolution {
+            width: 2560,
+            height: 1440,
+        };
+        assert_eq!(config.validate(resolution), vec![]);
+    }
+
+    #[test]
+    fn test_for_desktop_region() {
+        let displays = vec![
+            DisplayInfo {
+                index: 0,
+                width: 3840,
+                height: 1080,
+                combined: true,
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 1,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 2,
+                x: 1920,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+        ];
+        // Mostly on the second monitor, clipped to it.
+        let spec = CaptureSpecification::for_desktop_region(1800, 100, 800, 600, &displays);
+        let spec = spec.unwrap();
+        assert_eq!(
+            (spec.display, spec.x, spec.y, spec.width, spec.height),
+            (2, 0, 100, 680, 600)
+        );
+        // The combined display is used if no monitor contains the centre.
+        let spec = CaptureSpecification::for_desktop_region(10, 20, 30, 40, &displays[..1]);
+        assert_eq!(
+            spec.map(|s| (s.display, s.x, s.y, s.width, s.height)),
+            Some((0, 10, 20, 30, 40))
+        );
+        // Partially left of the primary monitor.
+        let spec = CaptureSpecification::for_desktop_region(-100, -50, 400, 300, &displays);
+        assert_eq!(
+            spec.map(|s| (s.display, s.x, s.y, s.width, s.height)),
+            Some((1, 0, 0, 300, 250))
+        );
+        assert!(CaptureSpecification::for_desktop_region(-900, 0, 800, 600, &displays).is_none());
+        assert!(CaptureSpecification::for_desktop_region(0, 0, 0, 0, &displays).is_none());
+    }
+
+    #[cfg(feature = "raw-window-handle")]
+    #[test]
+    fn test_for_window_unsupported() {
+        let window = raw_window_handle::WebWindowHandle::new(1).into();
+        assert!(matches!(
+            CaptureSpecification::for_window(window, &[]),
+            Err(ScreenCaptureError::Other(_))
+        ));
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_json_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&CaptureConfig::json_schema()).unwrap();
+        assert_eq!(schema["title"], "CaptureConfig");
+        let spec = &schema["$defs"]["CaptureSpecification"]["properties"];
+        assert_eq!(spec["merge"]["type"], "boolean");
+        assert!(spec["x"]["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("The x offset"));
+    }
+
+    #[test]
+    fn test_config_overrides() {
+        let mut config = CaptureConfig {
+            rate: 1.0,
+            capture: vec![CaptureSpecification {
+                match_display_name: Some("HDMI-*".to_owned()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let env = |name: &str| match name {
+            "SCREEN_CAPTURE_RATE" => Some("30".to_owned()),
+            "SCREEN_CAPTURE_DISPLAY" => Some("2".to_owned()),
+            _ => None,
+        };
+        config.apply_overrides(env).unwrap();
+        assert_eq!(config.rate, 30.0);
+        assert_eq!(config.capture[0].display, 2);
+        assert_eq!(config.capture[0].match_display_name, None);
+
+        let env = |name: &str| (name == "SCREEN_CAPTURE_REGION").then(|| "-200, 10,200,100".into());
+        config.apply_overrides(env).unwrap();
+        assert_eq!(
+            config.capture,
+            vec![CaptureSpecification {
+                x: -200,
+                y: 10,
+                width: 200,
+                height: 100,
+                ..Default::default()
+            }]
+        );
+
+        let env = |name: &str| (name == "SCREEN_CAPTURE_REGION").then(|| "1,2,3".into());
+        let e = config.apply_overrides(env).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid value for `SCREEN_CAPTURE_REGION`: expected x,y,w,h, got \"1,2,3\""
+        );
+        let env = |name: &str| (name == "SCREEN_CAPTURE_RATE").then(|| "fast".into());
+        assert!(config.apply_overrides(env).is_err());
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let resolution = Resolution {
+            width: 1920,
+            height: 1080,
+        };
+        let config = CaptureConfig {
+            rate: 5.0,
+            capture: vec![
+                CaptureSpecification {
+                    match_width: Some(1920),
+                    x: 1800,
+                    width: 200,
+                    ..Default::default()
+                },
+                CaptureSpecification {
+                    match_width: Some(1920),
+                    match_height: Some(1080),
+                    ..Default::default()
+                },
+                CaptureSpecification {
+                    match_width: Some(1920),
+                    match_height: Some(1080),
+                    priority: 1,
+                    x: 1920,
+                    rate: Some(f32::NAN),
+                    ..Default::default()
+                },
+                CaptureSpecification {
+                    match_width_min: Some(2000),
+                    match_width_max: Some(1000),
+                    ..Default::default()
+                },
+                CaptureSpecification {
+                    match_aspect_min: Some(2.3),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let issues = config.validate(resolution);
+        assert_eq!(issues.len(), 5);
+        assert_eq!(
+            issues[0],
+            ConfigIssue::OutOfBounds {
+                index: 0,
+                region: (1800, 0, 200, 1080),
+                resolution
+            }
+        );
+        assert_eq!(
+            issues[1],
+            ConfigIssue::Unreachable {
+                index: 1,
+                shadowed_by: 0
+            }
+        );
+        assert!(matches!(
+            issues[2],
+            ConfigIssue::InvalidRate { index: Some(2), .. }
+        ));
+        assert_eq!(issues[3], ConfigIssue::ZeroSized { index: 2 });
+        assert_eq!(issues[4], ConfigIssue::NeverMatches { index: 3 });
+        assert_eq!(
+            issues[1].to_string(),
+            "specification 1: unreachable, specification 0 always takes precedence"
+        );
+
+        let config = CaptureConfig {
+            rate: f32::INFINITY,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(resolution),
+            vec![ConfigIssue::InvalidRate {
+                index: None,
+                rate: f32::INFINITY
+            }]
+        );
+    }
+
+    #[test]
+    fn test_matches_resolution() {
+        let ultrawide = CaptureSpecification {
+            match_aspect_min: Some(2.3),
+            match_aspect_max: Some(2.5),
+            ..Default::default()
+        };
+        assert!(ultrawide.matches_resolution(3440, 1440));
+        assert!(ultrawide.matches_resolution(2560, 1080));
+        assert!(!ultrawide.matches_resolution(1920, 1080));
+
+        let range = CaptureSpecification {
+            match_width_min: Some(1900),
+            match_width_max: Some(1920),
+            match_height_min: Some(1000),
+            ..Default::default()
+        };
+        assert!(range.matches_resolution(1920, 1080));
+        assert!(range.matches_resolution(1900, 1000));
+        assert!(!range.matches_resolution(1921, 1080));
+        assert!(!range.matches_resolution(1920, 999));
+        assert!(CaptureSpecification::default().matches_resolution(1, 1));
+    }
+
+    #[test]
+    fn test_match_display_name() {
+        assert!(glob_match("DP-2", "DP-2"));
+        assert!(!glob_match("DP-2", "DP-21"));
+        assert!(glob_match("DP-?", "DP-1"));
+        assert!(glob_match("DELL*", "DELL U2720Q"));
+        assert!(glob_match("*U27*Q", "DELL U2720Q"));
+        assert!(!glob_match("*U27*X", "DELL U2720Q"));
+
+        let displays = vec![
+            DisplayInfo {
+                index: 1,
+                name: "HDMI-1".to_owned(),
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 2,
+                name: "DP-2".to_owned(),
+                x: 1920,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+        ];
+        let specs = vec![
+            CaptureSpecification {
+                match_display_name: Some("eDP*".to_owned()),
+                x: 5,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_display_name: Some("DP-*".to_owned()),
+                y: 80,
+                ..Default::default()
+            },
+        ];
+        let spec = CaptureSpecification::get_config_for_displays(3840, 1080, &displays, &specs);
+        assert_eq!(spec.display, 2);
+
+        let by_count = vec![
+            CaptureSpecification {
+                match_display_count: Some(1),
+                x: 1,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_display_count: Some(2),
+                x: 2,
+                ..Default::default()
+            },
+        ];
+        let docked =
+            CaptureSpecification::get_config_for_displays(3840, 1080, &displays, &by_count);
+        assert_eq!(docked.x, 2);
+        let single =
+            CaptureSpecification::get_config_for_displays(1920, 1080, &displays[..1], &by_count);
+        assert_eq!(single.x, 1);
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (0, 80, 1920, 1000)
+        );
+
+        // Without display information the names can't match.
+        let spec = CaptureSpecification::get_config(3840, 1080, &specs);
+        assert_eq!(spec.display, 0);
+        assert_eq!((spec.width, spec.height), (3840, 1080));
+    }
+
+    #[test]
+    fn test_match_scale() {
+        let displays = vec![
+            DisplayInfo {
+                index: 1,
+                name: "eDP-1".to_owned(),
+                width: 2880,
+                height: 1800,
+                scale: 2.0,
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 2,
+                name: "DP-2".to_owned(),
+                width: 2560,
+                height: 1440,
+                scale: 1.0,
+                ..Default::default()
+            },
+        ];
+        let specs = vec![
+            CaptureSpecification {
+                match_scale: Some(2.0),
+                display: 2,
+                x: 1,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                display: 1,
+                x: 100,
+                y: -50,
+                width: 200,
+                logical: true,
+                ..Default::default()
+            },
+        ];
+        let selection = CaptureSpecification::select(2880, 1800, &displays, &specs);
+        assert_eq!(
+            selection.candidates[0].rejection,
+            Some("scale 1 is not 2".to_owned())
+        );
+        let spec = selection.specification;
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (200, 1700, 400, 100)
+        );
+        assert!(!spec.logical);
+
+        // Merged specifications are converted with their own setting.
+        let merged = [
+            specs[1].clone(),
+            CaptureSpecification {
+                y: 10,
+                merge: true,
+                ..Default::default()
+            },
+            CaptureSpecification {
+                height: 30,
+                logical: true,
+                merge: true,
+                ..Default::default()
+            },
+        ];
+        let spec = CaptureSpecification::select(2880, 1800, &displays, &merged).specification;
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (200, 10, 400, 60)
+        );
+
+        let spec = CaptureSpecification::get_config(2880, 1800, &specs[..1]);
+        assert_eq!(spec.x, 0);
+        let mut high_dpi = specs[0].clone();
+        high_dpi.display = 1;
+        let spec =
+            CaptureSpecification::get_config_for_displays(2880, 1800, &displays, &[high_dpi]);
+        assert_eq!(spec.x, 1);
+    }
+
+    #[test]
+    fn test_match_window() {
+        use crate::synthetic::TestPatternCapture;
+        let displays = vec![
+            DisplayInfo {
+                index: 1,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+            DisplayInfo {
+                index: 2,
+                x: 1920,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+        ];
+        let windows = vec![
+            WindowInfo {
+                title: "notes.txt - editor".to_owned(),
+                class: "editor".to_owned(),
+                x: 10,
+                y: 10,
+                width: 400,
+                height: 300,
+            },
+            WindowInfo {
+                title: "video.mkv - mpv".to_owned(),
+                class: "mpv".to_owned(),
+                x: 2020,
+                y: 50,
+                width: 1280,
+                height: 720,
+            },
+        ];
+        let specs = vec![
+            CaptureSpecification {
+                match_window_title: Some("*.mkv - mpv".to_owned()),
+                match_window_class: Some("mpv".to_owned()),
+                x_frac: Some(0.5),
+                rate: Some(10.0),
+                ..Default::default()
+            },
+            CaptureSpecification {
+                match_window_class: Some("browser".to_owned()),
+                priority: 1,
+                ..Default::default()
+            },
+        ];
+        let selection =
+            CaptureSpecification::select_with_windows(3840, 1080, &displays, &windows, &specs);
+        assert_eq!(selection.index, Some(0));
+        assert_eq!(
+            selection.candidates[1].rejection,
+            Some("no matching window".to_owned())
+        );
+        let spec = selection.specification;
+        assert_eq!(
+            (spec.display, spec.x, spec.y, spec.width, spec.height),
+            (2, 100, 50, 1280, 720)
+        );
+        assert_eq!(spec.rate, Some(10.0));
+        // Without windows the specification doesn't match.
+        let selection = CaptureSpecification::select(3840, 1080, &displays, &specs);
+        assert_eq!(selection.index, None);
+
+        // The capturer enumerates the windows of the backend.
+        struct WindowedCapture(
+            TestPatternCapture,
+            Arc<Mutex<Vec<WindowInfo>>>,
+            Arc<AtomicUsize>,
+        );
+        impl Capture for WindowedCapture {
+            fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+                self.0.capture_image()
+            }
+            fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+                self.0.image()
+            }
+            fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+                self.0.resolution()
+            }
+            fn prepare_capture(
+                &mut self,
+                display: u32,
+                x: u32,
+                y: u32,
+                width: u32,
+                height: u32,
+            ) -> Result<(), ScreenCaptureError> {
+                self.2.fetch_add(1, Relaxed);
+                self.0.prepare_capture(display, x, y, width, height)
+            }
+            fn displays(&mut self) -> Vec<DisplayInfo> {
+                vec![DisplayInfo {
+                    width: 64,
+                    height: 64,
+                    ..Default::default()
+                }]
+            }
+            fn windows(&mut self) -> Vec<WindowInfo> {
+                self.1.lock().unwrap().clone()
+            }
+        }
+        let window = WindowInfo {
+            title: "video.mkv - mpv".to_owned(),
+            class: "mpv".to_owned(),
+            x: 8,
+            y: 16,
+            width: 32,
+            height: 16,
+        };
+        let windows = Arc::new(Mutex::new(vec![window]));
+        let prepares = Arc::new(AtomicUsize::new(0));
+        let backend = WindowedCapture(
+            TestPatternCapture::new(Resolution {
+                width: 64,
+                height: 64,
+            }),
+            windows.clone(),
+            prepares.clone(),
+        );
+        let config = CaptureConfig {
+            capture: vec![specs[0].clone()],
+            ..Default::default()
+        };
+        let mut capturer = Capturer::with_backend(Box::new(backend), config);
+        let frame = capturer.capture().unwrap();
+        assert_eq!((frame.width(), frame.height()), (32, 16));
+        assert_eq!(
+            capturer.cached_specification.as_ref().map(|s| (s.x, s.y)),
+            Some((8, 16))
+        );
+        assert_eq!(prepares.load(Relaxed), 1);
+
+        // A new title doesn't prepare the same region again, moving the window does.
+        windows.lock().unwrap()[0].title = "other.mkv - mpv".to_owned();
+        capturer.cached_windows = None;
+        assert!(capturer.capture().is_ok());
+        assert_eq!(prepares.load(Relaxed), 1);
+        windows.lock().unwrap()[0].x = 12;
+        capturer.cached_windows = None;
+        assert!(capturer.capture().is_ok());
+        assert_eq!(prepares.load(Relaxed), 2);
+        assert_eq!(
+            capturer.cached_specification.as_ref().map(|s| (s.x, s.y)),
+            Some((12, 16))
+        );
+    }
+
+    #[test]
+    fn test_builder_config() {
+        let builder = ThreadedCapturer::builder()
+            .rate(30.0)
+            .region(10, 20, 300, 200)
+            .output_format(OutputFormat::Bgr)
+            .thread_name("capture");
+        assert_eq!(builder.config.rate, 30.0);
+        assert_eq!(builder.config.output_format, OutputFormat::Bgr);
+        assert_eq!(builder.config.capture.len(), 1);
+        let spec = CaptureSpecification::get_config(1920, 1080, &builder.config.capture);
+        assert_eq!(
+            (spec.x, spec.y, spec.width, spec.height),
+            (10, 20, 300, 200)
+        );
+        assert_eq!(builder.config.thread.name.as_deref(), Some("capture"));
+    }
+
+    #[test]
+    fn test_thread_info() {
+        let before = std::time::SystemTime::now();
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .build();
+        next_frame(&frames(&capturer));
+        let info = capturer.thread_info().expect("thread should have started");
+        assert_eq!(info.name.as_deref(), Some(ThreadConfig::DEFAULT_NAME));
+        assert_ne!(info.id, std::thread::current().id());
+        assert_ne!(info.os_id, crate::backend::current_thread_id());
+        assert!(info.spawned >= before);
+        assert_eq!(info.restarts, 0);
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_health() {
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .build();
+        next_frame(&frames(&capturer));
+        let health = capturer.health();
+        assert!(health.alive);
+        assert!(health.since_success.unwrap() < std::time::Duration::from_secs(1));
+        assert_eq!(health.last_error, None);
+        assert_eq!(health.backend.as_deref(), Some("test pattern"));
+        assert_eq!(capturer.stop(), Ok(()));
+
+        let capturer = ThreadedCapturer::builder()
+            .backend(|| Err(ScreenCaptureError::Disconnected))
+            .rate(100.0)
+            .build();
+        wait_until(|| capturer.health().last_error.is_some());
+        let health = capturer.health();
+        assert!(health.alive);
+        assert_eq!(health.since_success, None);
+        assert_eq!(health.last_error, Some(ScreenCaptureError::Disconnected));
+        assert_eq!(health.backend, None);
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .build();
+        next_frame(&frames(&capturer));
+        let dir = TestDir::new("debug_dump");
+        capturer
+            .debug_dump(dir.path())
+            .expect("should write the dump");
+        let state = std::fs::read_to_string(dir.join("state.txt")).unwrap();
+        assert!(state.contains("test pattern"));
+        assert!(state.contains("specifications: [\n    Some("));
+        let frame = crate::util::read_ppm(dir.join("frame_0.ppm").to_str().unwrap()).unwrap();
+        assert_eq!((frame.width(), frame.height()), (4, 4));
+        #[cfg(feature = "toml")]
+        assert_eq!(
+            CaptureConfig::load(dir.join("config.toml")).unwrap(),
+            capturer.config()
+        );
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_thread_config_apply() {
+        // Lowering the priority is always permitted, do it on a separate thread.
+        std::thread::spawn(|| {
+            let config = ThreadConfig {
+                priority: Some(ThreadPriority::Lowest),
+                ..Default::default()
+            };
+            config.apply().expect("lowering priority should succeed");
+            let nice = unsafe {
+                let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+                libc::getpriority(libc::PRIO_PROCESS, tid)
+            };
+            assert!(nice >= 10);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_stop_propagates_panic() {
+        let capturer = ThreadedCapturer::builder()
+            .rate(100.0)
+            .on_capture(|_| panic!("callback failed"))
+            .build();
+        wait_until(|| !capturer.health().alive);
+        assert_eq!(
+            capturer.stop(),
+            Err(ThreadError::Panicked("callback failed".to_string()))
+        );
+
+        let capturer = ThreadedCapturer::default();
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_watchdog_restart() {
+        use std::sync::mpsc::channel;
+        let (sender, receiver) = channel::<ScreenCaptureError>();
+        let sender = Mutex::new(sender);
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .watchdog(WatchdogConfig {
+                enabled: true,
+                stall_timeout: 0.2,
+            })
+            .on_capture(|counter| match counter {
+                2 => panic!("callback failed"),
+                4 => std::thread::sleep(std::time::Duration::from_millis(500)),
+                _ => {}
+            })
+            .on_error(move |e| {
+                let _ = sender.lock().unwrap().send(e);
+            })
+            .build();
+        let timeout = std::time::Duration::from_secs(2);
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Ok(ScreenCaptureError::Other(
+                "capture thread panicked: callback failed, restarted it".into()
+            ))
+        );
+        assert_eq!(
+            receiver.recv_timeout(timeout),
+            Ok(ScreenCaptureError::Other(
+                "capture thread stalled for more than 0.2s, restarted it".into()
+            ))
+        );
+        wait_until(|| capturer.latest().counter > 4);
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_capturer_with_backend() {
+        use crate::synthetic::TestPatternCapture;
+        let backend = TestPatternCapture::new(Resolution {
+            width: 64,
+            height: 32,
+        });
+        let config = CaptureConfig {
+            capture: vec![CaptureSpecification {
+                x: 10,
+                y: 5,
+                width: 20,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut capturer = Capturer::with_backend(Box::new(backend), config);
+        let img = capturer.capture().expect("capture should succeed");
+        assert_eq!((img.width(), img.height()), (20, 27));
+        assert_eq!(img.pixel(0, 0), TestPatternCapture::pattern(10, 5, 1));
+        assert_eq!(img.pixel(19, 26), TestPatternCapture::pattern(29, 31, 1));
+        let img = capturer.capture().expect("capture should succeed");
+        assert_eq!(img.pixel(0, 0), TestPatternCapture::pattern(10, 5, 2));
+    }
+
+    #[test]
+    fn test_threaded_with_backend() {
+        let backend: BackendFactory = Arc::new(test_pattern(16, 8));
+        let config = CaptureConfig {
+            rate: 100.0,
+            output_format: OutputFormat::Bgr,
+            ..Default::default()
+        };
+        let capturer = ThreadedCapturer::with_backend(backend, config);
+        wait_until(|| capturer.latest().counter > 0);
+        let latest = capturer.latest();
+        let metadata = latest.metadata();
+        assert_eq!(metadata.counter, latest.counter);
+        assert_eq!(metadata.error, None);
+        assert_eq!(metadata.displays, vec![0]);
+        #[cfg(feature = "json")]
+        {
+            let json = serde_json::to_string(&metadata).unwrap();
+            let restored: CaptureMetadata = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored, metadata);
+            assert!(json.contains(r#""resolution":{"width":16,"height":8}"#));
+        }
+        let frame = latest.result.expect("capture should succeed");
+        assert_eq!(frame.format(), OutputFormat::Bgr);
+        assert_eq!((frame.width(), frame.height()), (16, 8));
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_activate_profile() {
+        let gaming = CaptureConfig {
+            rate: 60.0,
+            output_format: OutputFormat::Rgb,
+            ..Default::default()
+        };
+        let config = CaptureConfig {
+            rate: 100.0,
+            profiles: HashMap::from([("gaming".to_owned(), gaming)]),
+            ..Default::default()
+        };
+        let profile = config.profile("gaming").unwrap();
+        assert_eq!(profile.rate, 60.0);
+        assert_eq!(profile.profiles, config.profiles);
+        assert_eq!(config.profile("office"), None);
+
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .config(config)
+            .build();
+        assert!(matches!(
+            capturer.activate_profile("office"),
+            Err(ConfigError::UnknownProfile(_))
+        ));
+        capturer.activate_profile("gaming").unwrap();
+        wait_until(|| capturer.config().rate == 60.0);
+        assert_eq!(capturer.config().output_format, OutputFormat::Rgb);
+        // The profiles are retained, such that another profile can be activated.
+        capturer.activate_profile("gaming").unwrap();
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_specification_change() {
+        use std::sync::mpsc::channel;
+        let (sender, receiver) = channel::<SpecificationChange>();
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(8, 8))
+            .rate(100.0)
+            .region(2, 2, 4, 4)
+            .on_specification_change(move |change| {
+                let _ = sender.send(change);
+            })
+            .build();
+        let timeout = std::time::Duration::from_secs(1);
+        let first = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(first.previous, None);
+        assert_eq!((first.current.x, first.current.width), (2, 4));
+
+        // The same region doesn't result in a change, a different one does.
+        capturer.set_config(capturer.config());
+        capturer.set_config(CaptureConfig {
+            rate: 100.0,
+            ..Default::default()
+        });
+        let second = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(second.previous, Some(first.current));
+        assert_eq!((second.current.x, second.current.width), (0, 8));
+        assert_eq!(capturer.stop(), Ok(()));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_multiple_callbacks() {
+        use std::sync::mpsc::channel;
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .build();
+        let (sender, receiver) = channel::<(&'static str, usize)>();
+        let first = sender.clone();
+        let first = capturer.add_post_callback(Arc::new(move |info| {
+            let _ = first.send(("first", info.counter));
+        }));
+        let second = sender.clone();
+        capturer.add_post_callback(Arc::new(move |info| {
+            let _ = second.send(("second", info.counter));
+        }));
+        capturer.set_config(CaptureConfig {
+            rate: 100.0,
+            ..Default::default()
+        });
+
+        let timeout = std::time::Duration::from_secs(1);
+        let a = receiver.recv_timeout(timeout).unwrap();
+        let b = receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(a.0, "first");
+        assert_eq!(b, ("second", a.1));
+
+        capturer.remove_callback(first);
+        // Callbacks for frames that were in flight may still arrive, skip those.
+        let removed_at = capturer.latest().counter;
+        let after = loop {
+            let v = receiver.recv_timeout(timeout).unwrap();
+            if v.1 > removed_at + 1 {
+                break v;
+            }
+        };
+        assert_eq!(after.0, "second");
+        assert_eq!(receiver.recv_timeout(timeout).unwrap().0, "second");
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_spec_rate() {
+        let fresh = Arc::new(Mutex::new(vec![0usize; 2]));
+        let fresh_t = Arc::clone(&fresh);
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .displays(&[0, 1])
+            .specification(CaptureSpecification {
+                display: 1,
+                rate: Some(10.0),
+                ..Default::default()
+            })
+            .on_frame(move |info| {
+                let mut fresh = fresh_t.lock().unwrap();
+                for (i, display) in info.displays.iter().enumerate() {
+                    fresh[i] += display.fresh as usize;
+                }
+            })
+            .build();
+        // Half a second of frames at the rate of the first display.
+        let frames = frames(&capturer);
+        while next_frame(&frames) < 50 {}
+        assert_eq!(capturer.stop(), Ok(()));
+        let fresh = fresh.lock().unwrap();
+        assert!(fresh[1] >= 2, "{fresh:?}");
+        assert!(fresh[0] > 3 * fresh[1], "{fresh:?}");
+    }
+
+    #[test]
+    fn test_invalid_durations() {
+        use std::time::Duration;
+        // Invalid numbers disable capturing or fall back to the defaults instead of panicking.
+        assert_eq!(rate_interval(4.0), Some(Duration::from_millis(250)));
+        for rate in [0.0, -1.0, f32::NAN, 1e-40] {
+            assert_eq!(rate_interval(rate), None, "{rate}");
+        }
+        for value in [0.0, -1.0, f32::NAN, f32::INFINITY, 1e30] {
+            let session = SessionConfig {
+                poll_interval: value,
+                ..Default::default()
+            };
+            assert_eq!(session.interval(), Duration::from_secs(1), "{value}");
+            let watchdog = WatchdogConfig {
+                stall_timeout: value,
+                ..Default::default()
+            };
+            assert_eq!(watchdog.stall_limit(), None, "{value}");
+        }
+        let watchdog = WatchdogConfig {
+            stall_timeout: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(watchdog.stall_limit(), Some(Duration::from_millis(500)));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_spec_output_format() {
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .displays(&[0, 1])
+            .output_format(OutputFormat::Rgba)
+            .specification(CaptureSpecification {
+                display: 1,
+                output_format: Some(OutputFormat::Bgr),
+                ..Default::default()
+            })
+            .build();
+        next_frame(&frames(&capturer));
+        let formats: Vec<_> = capturer
+            .latest()
+            .displays
+            .iter()
+            .map(|d| d.result.as_ref().ok().map(|f| f.format()))
+            .collect();
+        assert_eq!(
+            formats,
+            vec![Some(OutputFormat::Rgba), Some(OutputFormat::Bgr)]
+        );
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_mask() {
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(16, 16))
+            .rate(100.0)
+            .output_format(OutputFormat::Bgr)
+            .specification(CaptureSpecification {
+                x: 4,
+                y: 4,
+                mask: vec![Rect {
+                    x: 0,
+                    y: 0,
+                    width: 6,
+                    height: 5,
+                }],
+                ..Default::default()
+            })
+            .build();
+        next_frame(&frames(&capturer));
+        let latest = capturer.latest();
+        assert_eq!(capturer.stop(), Ok(()));
+        let frame = latest.result.expect("capture should succeed");
+        let img = frame.to_bgr();
+        assert_eq!(img.pixel(1, 0), BGR::default());
+        assert_ne!(img.pixel(2, 0), BGR::default());
+        assert_ne!(img.pixel(1, 1), BGR::default());
+    }
+
+    #[test]
+    fn test_idle_skip_capture() {
+        let (sender, frames) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .idle(IdleBehaviour::SkipCapture)
+            .on_capture(move |counter| {
+                let _ = sender.lock().unwrap().send(counter);
+            })
+            .build();
+        // A post callback would make the capturer busy, wait on the captures instead.
+        assert_eq!(next_frame(&frames), 1);
+        // Nobody read the first frame, so no further frames are captured.
+        assert!(frames.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(capturer.latest().counter, 1);
+        // Reading it allows exactly one more frame to be captured.
+        assert_eq!(next_frame(&frames), 2);
+        wait_until(|| capturer.latest().counter == 2);
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "fault-injection")]
+    fn test_fault_injection() {
+        use crate::synthetic::{FaultHandle, TestPatternCapture};
+        let pattern = || {
+            Box::new(TestPatternCapture::new(Resolution {
+                width: 4,
+                height: 4,
+            }))
+        };
+        let faults = FaultHandle::new();
+        let mut capturer =
+            Capturer::with_backend(Box::new(faults.wrap(pattern())), Default::default());
+        let failures = Capturer::RECOVERY_FAILURES as u64;
+        // A non recoverable error interrupts the consecutive failures.
+        faults.inject_range(1..failures, ScreenCaptureError::LostCapture("lost".into()));
+        faults.inject(
+            failures,
+            ScreenCaptureError::PermissionDenied("denied".into()),
+        );
+        let transient = ScreenCaptureError::Transient("transient".into());
+        faults.inject_range(failures + 1..2 * failures + 2, transient.clone());
+        for _ in 1..failures {
+            assert!(matches!(
+                capturer.capture(),
+                Err(ScreenCaptureError::LostCapture(_))
+            ));
+        }
+        assert!(matches!(
+            capturer.capture(),
+            Err(ScreenCaptureError::PermissionDenied(_))
+        ));
+        for _ in 1..failures {
+            assert_eq!(capturer.capture().err(), Some(transient.clone()));
+        }
+        assert_eq!(faults.resets(), 0);
+        // The tenth failure in a row resets, the retry fails again as scheduled.
+        assert_eq!(capturer.capture().err(), Some(transient.clone()));
+        assert_eq!(faults.resets(), 1);
+        assert!(capturer.capture().is_ok());
+        assert_eq!(faults.captures(), 2 * failures + 2);
+
+        // Timeouts only mean the screen didn't change, these never reset the backend.
+        faults.inject_range(
+            2 * failures + 3..3 * failures + 4,
+            ScreenCaptureError::Timeout,
+        );
+        for _ in 0..=failures {
+            assert_eq!(capturer.capture().err(), Some(ScreenCaptureError::Timeout));
+        }
+        assert_eq!(faults.resets(), 1);
+        assert!(capturer.capture().is_ok());
+
+        // The threaded capturer reports the injected errors and keeps capturing.
+        let faults = FaultHandle::new();
+        faults.inject(3, ScreenCaptureError::Timeout);
+        let errors = Arc::new(Mutex::new(vec![]));
+        let capturer = {
+            let faults = faults.clone();
+            let errors = errors.clone();
+            ThreadedCapturer::builder()
+                .backend(move || Ok(Box::new(faults.wrap(pattern()))))
+                .on_error(move |e| errors.lock().unwrap().push(e))
+                .rate(100.0)
+                .build()
+        };
+        wait_until(|| faults.captures() > 3);
+        assert_eq!(*errors.lock().unwrap(), vec![ScreenCaptureError::Timeout]);
+        assert!(faults.captures() > 3);
+        assert_eq!(
+            capturer.health().last_error,
+            Some(ScreenCaptureError::Timeout)
+        );
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_capturer_recovery() {
+        use crate::synthetic::TestPatternCapture;
+        use std::time::Duration;
+        /// Fails until it is reset.
+        struct StaleCapture {
+            inner: TestPatternCapture,
+            stale: bool,
+            broken: bool,
+            resets: Arc<AtomicUsize>,
+        }
+        impl Capture for StaleCapture {
+            fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+                if self.stale {
+                    return Err(ScreenCaptureError::Transient("stale".into()));
+                }
+                self.inner.capture_image()
+            }
+            fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+                self.inner.image()
+            }
+            fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+                self.inner.resolution()
+            }
+            fn prepare_capture(
+                &mut self,
+                display: u32,
+                x: u32,
+                y: u32,
+                width: u32,
+                height: u32,
+            ) -> Result<(), ScreenCaptureError> {
+                self.inner.prepare_capture(display, x, y, width, height)
+            }
+            fn reset(&mut self) -> Result<(), ScreenCaptureError> {
+                self.resets.fetch_add(1, Relaxed);
+                if self.broken {
+                    return Err(ScreenCaptureError::Other("broken".into()));
+                }
+                self.stale = false;
+                Ok(())
+            }
+        }
+        let resets = Arc::new(AtomicUsize::new(0));
+        let backend = StaleCapture {
+            inner: TestPatternCapture::new(Resolution {
+                width: 4,
+                height: 4,
+            }),
+            stale: true,
+            broken: false,
+            resets: resets.clone(),
+        };
+        let mut capturer = Capturer::with_backend(Box::new(backend), Default::default());
+        for _ in 1..Capturer::RECOVERY_FAILURES {
+            assert!(capturer.capture().is_err());
+        }
+        assert_eq!(resets.load(Relaxed), 0);
+        // The last failure in a row reinitialises the backend and captures again.
+        assert!(capturer.capture().is_ok());
+        assert_eq!(resets.load(Relaxed), 1);
+
+        // A failed reset after a resume still attempts the capture.
+        let resets = Arc::new(AtomicUsize::new(0));
+        let backend = StaleCapture {
+            inner: TestPatternCapture::new(Resolution {
+                width: 4,
+                height: 4,
+            }),
+            stale: false,
+            broken: true,
+            resets: resets.clone(),
+        };
+        let mut capturer = Capturer::with_backend(Box::new(backend), Default::default());
+        assert!(capturer.capture().is_ok());
+        let suspended = std::time::SystemTime::now() - Duration::from_secs(60);
+        capturer.last_capture = Some((std::time::Instant::now(), suspended));
+        assert!(capturer.capture().is_ok());
+        assert_eq!(resets.load(Relaxed), 1);
+
+        let second = Duration::from_secs(1);
+        assert_eq!(resumed(second, Some(second * 2)), None);
+        assert_eq!(resumed(second, Some(second * 7)), Some(second * 6));
+        assert_eq!(resumed(second * 7, Some(second)), None);
+        assert_eq!(resumed(second, None), None);
+    }
+
+    #[test]
+    fn test_session_lock() {
+        let locked = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(Mutex::new(vec![]));
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .session(SessionConfig {
+                pause_when_locked: true,
+                poll_interval: 0.01,
+            })
+            .session_probe({
+                let locked = locked.clone();
+                move || Some(locked.load(Relaxed))
+            })
+            .on_session_change({
+                let events = events.clone();
+                move |e| events.lock().unwrap().push(e)
+            })
+            .build();
+        let frames = frames(&capturer);
+        next_frame(&frames);
+        locked.store(true, Relaxed);
+        wait_until(|| !events.lock().unwrap().is_empty());
+        let paused = capturer.latest().counter;
+        while let Ok(counter) = frames.recv_timeout(Duration::from_millis(100)) {
+            assert!(counter <= paused);
+        }
+        assert_eq!(capturer.latest().counter, paused);
+        assert_eq!(*events.lock().unwrap(), vec![SessionEvent::Locked]);
+
+        locked.store(false, Relaxed);
+        assert!(next_frame(&frames) > paused);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![SessionEvent::Locked, SessionEvent::Unlocked]
+        );
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_session_probe_blocking() {
+        // A slow probe doesn't hold up the captures.
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(4, 4))
+            .rate(100.0)
+            .session(SessionConfig {
+                pause_when_locked: true,
+                poll_interval: 0.01,
+            })
+            .session_probe(|| {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                Some(false)
+            })
+            .build();
+        let start = Instant::now();
+        let frames = frames(&capturer);
+        while next_frame(&frames) <= 5 {}
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(capturer.stop(), Ok(()));
+    }
+
+    #[test]
+    fn test_schedule() {
+        use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+        let mut schedule = Schedule::new();
+        let interval = Duration::from_millis(200);
+        let last_end = Instant::now();
+        let last_duration = Duration::from_millis(50);
+        assert_eq!(
+            schedule.next_start(ScheduleMode::Interval, interval, last_end, last_duration),
+            last_end + Duration::from_millis(150)
+        );
+        assert_eq!(
+            schedule.next_start(
+                ScheduleMode::IntervalAfterFinish,
+                interval,
+                last_end,
+                last_duration
+            ),
+            last_end + interval
+        );
+        assert_eq!(
+            schedule.next_start(ScheduleMode::FrameDriven, interval, last_end, last_duration),
+            last_end
+        );
+
+        // The fixed cadence lands on a multiple of the interval on the wall clock.
+        let now = Instant::now();
+        let wall = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let target = schedule.next_start(
+            ScheduleMode::FixedCadence,
+            interval,
+            last_end,
+            last_duration,
+        );
+        let target_wall = wall + (target - now);
+        let offset = target_wall.as_millis() % interval.as_millis();
+        assert!(!(5..=195).contains(&offset), "offset was {offset}");
+        // And stays the same until a capture is recorded.
+        assert_eq!(
+            schedule.next_start(
+                ScheduleMode::FixedCadence,
+                interval,
+                last_end,
+                last_duration
+            ),
+            target
+        );
+
+        // Jitter statistics.
+        let mut schedule = Schedule::new();
+        schedule.record(ScheduleMode::Interval, now, now + Duration::from_millis(1));
+        schedule.record(ScheduleMode::Interval, now, now + Duration::from_millis(3));
+        let stats = schedule.stats;
+        assert_eq!(stats.samples, 2);
+        assert_eq!(stats.max, Duration::from_millis(3));
+        assert!((stats.mean.as_secs_f64() - 0.002).abs() < 1e-9);
+        assert!((stats.std_dev.as_secs_f64() - 0.001).abs() < 1e-9);
+
+        // Changing the mode resets the statistics.
+        schedule.record(ScheduleMode::FrameDriven, now, now);
+        assert_eq!(schedule.stats.samples, 1);
+        assert_eq!(schedule.stats.mode, ScheduleMode::FrameDriven);
+    }
+}
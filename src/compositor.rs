@@ -0,0 +1,250 @@
+//! Composing regions of several captured displays into a single frame, like a simple scene.
+//!
+//! A [`Layout`] places [`Layer`]s on the output, each draws a region of the frame of one display,
+//! scaled and positioned, on top of the layers below it. The displays are those captured by a
+//! [`ThreadedCapturer`](crate::ThreadedCapturer) configured with
+//! [`CaptureConfig::displays`](crate::CaptureConfig::displays).
+use crate::capturer::CaptureInfo;
+use crate::{OwnedFrame, Rect};
+use serde::{Deserialize, Serialize};
+
+fn one() -> f32 {
+    1.0
+}
+
+/// A region of a display drawn on the output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Layer {
+    /// The display whose frame is drawn, see
+    /// [`DisplayCapture::display`](crate::capturer::DisplayCapture::display).
+    pub display: u32,
+
+    /// The region of the frame to draw, the entire frame if not set. It is clipped to the frame.
+    #[serde(default)]
+    pub source: Option<Rect>,
+
+    /// The horizontal position of the region on the output, it may be partially outside of it.
+    #[serde(default)]
+    pub x: i32,
+    /// The vertical position of the region on the output.
+    #[serde(default)]
+    pub y: i32,
+
+    /// The factor by which the region is scaled.
+    #[serde(default = "one")]
+    pub scale: f32,
+
+    /// Layers with a higher z are drawn on top of those with a lower one, layers with the same z
+    /// are drawn in order.
+    #[serde(default)]
+    pub z: i32,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Layer {
+            display: 0,
+            source: None,
+            x: 0,
+            y: 0,
+            scale: 1.0,
+            z: 0,
+        }
+    }
+}
+
+/// The size of the output and the layers drawn on it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+
+    /// The colour of the output where no layer is drawn, as red, green and blue.
+    #[serde(default)]
+    pub background: [u8; 3],
+
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+}
+
+/// Draws the layers of a layout, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct Compositor {
+    layout: Layout,
+    /// The indices of the layers, in drawing order.
+    order: Vec<usize>,
+}
+
+impl Compositor {
+    /// Create a compositor drawing the layout.
+    pub fn new(layout: Layout) -> Compositor {
+        let mut order: Vec<usize> = (0..layout.layers.len()).collect();
+        order.sort_by_key(|i| layout.layers[*i].z);
+        Compositor { layout, order }
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Compose the frames of the displays, layers of displays without a frame are skipped.
+    pub fn compose(&self, frames: &[(u32, &OwnedFrame)]) -> image::RgbaImage {
+        let [r, g, b] = self.layout.background;
+        let mut output = image::RgbaImage::from_pixel(
+            self.layout.width,
+            self.layout.height,
+            image::Rgba([r, g, b, 255]),
+        );
+        for layer in self.order.iter().map(|i| &self.layout.layers[*i]) {
+            let Some((_, frame)) = frames.iter().find(|(d, _)| *d == layer.display) else {
+                continue;
+            };
+            let bounds = Rect {
+                x: 0,
+                y: 0,
+                width: frame.width(),
+                height: frame.height(),
+            };
+            let Some(source) = layer.source.unwrap_or(bounds).intersection(&bounds) else {
+                continue;
+            };
+            let region = match frame.as_rgba() {
+                Some(rgba) => {
+                    image::imageops::crop_imm(rgba, source.x, source.y, source.width, source.height)
+                        .to_image()
+                }
+                None => image::imageops::crop_imm(
+                    &frame.to_rgba(),
+                    source.x,
+                    source.y,
+                    source.width,
+                    source.height,
+                )
+                .to_image(),
+            };
+            let width = (source.width as f32 * layer.scale).round() as u32;
+            let height = (source.height as f32 * layer.scale).round() as u32;
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let region = if (width, height) == (source.width, source.height) {
+                region
+            } else {
+                image::imageops::resize(
+                    &region,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                )
+            };
+            image::imageops::replace(&mut output, &region, layer.x as i64, layer.y as i64);
+        }
+        output
+    }
+
+    /// Compose the displays of a capture that succeeded, see [`CaptureInfo::displays`].
+    pub fn compose_capture(&self, info: &CaptureInfo) -> OwnedFrame {
+        let frames: Vec<(u32, &OwnedFrame)> = info
+            .displays
+            .iter()
+            .filter_map(|d| Some((d.display, &**d.result.as_ref().ok()?)))
+            .collect();
+        OwnedFrame::Rgba(self.compose(&frames))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::capturer::DisplayCapture;
+    use crate::raster_image::RasterImageBGR;
+    use crate::{OutputFormat, BGR};
+    use std::sync::Arc;
+
+    fn filled(width: u32, height: u32, r: u8) -> OwnedFrame {
+        let img = RasterImageBGR::filled(width, height, BGR { r, g: 0, b: 0 });
+        OwnedFrame::from_image(&img, OutputFormat::Bgr)
+    }
+
+    #[test]
+    fn test_compose() {
+        let layout = Layout {
+            width: 8,
+            height: 4,
+            background: [0, 0, 9],
+            layers: vec![
+                // Drawn on top of display 1, though it is listed first.
+                Layer {
+                    display: 2,
+                    source: Some(Rect {
+                        x: 0,
+                        y: 0,
+                        width: 1,
+                        height: 1,
+                    }),
+                    x: 1,
+                    y: 1,
+                    scale: 2.0,
+                    z: 1,
+                },
+                Layer {
+                    display: 1,
+                    x: -2,
+                    ..Default::default()
+                },
+                // Not captured.
+                Layer {
+                    display: 3,
+                    z: 2,
+                    ..Default::default()
+                },
+            ],
+        };
+        let compositor = Compositor::new(layout);
+        let info = CaptureInfo {
+            displays: [(1, filled(4, 4, 10)), (2, filled(3, 3, 20))]
+                .into_iter()
+                .map(|(display, frame)| DisplayCapture {
+                    display,
+                    result: Ok(Arc::new(frame)),
+                    data: None,
+                    fresh: true,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let OwnedFrame::Rgba(output) = compositor.compose_capture(&info) else {
+            panic!("the output should be rgba");
+        };
+        let red = |x, y| output.get_pixel(x, y).0[0];
+        assert_eq!((output.width(), output.height()), (8, 4));
+        assert_eq!((red(0, 0), red(1, 0), red(2, 0)), (10, 10, 0));
+        assert_eq!(output.get_pixel(2, 0).0, [0, 0, 9, 255]);
+        assert_eq!(
+            (red(1, 1), red(2, 2), red(3, 3), red(0, 3)),
+            (20, 20, 0, 10)
+        );
+
+        let output = compositor.compose(&[]);
+        assert!(output.pixels().all(|p| p.0 == [0, 0, 9, 255]));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_layout_defaults() {
+        let layout: Layout = serde_json::from_str(
+            r#"{"width": 1920, "height": 1080, "layers": [{"display": 1, "x": 960}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            layout.layers,
+            vec![Layer {
+                display: 1,
+                x: 960,
+                ..Default::default()
+            }]
+        );
+    }
+}
@@ -0,0 +1,561 @@
+//! A capture service that is driven by other processes, such that a single capture can be shared
+//! by scripts that query its status, change its configuration, take screenshots and record.
+//!
+//! The [`Daemon`] owns a [`ThreadedCapturer`] and restarts it when its thread is no longer alive.
+//! It listens on a unix socket, or on Windows on a named pipe like `\\.\pipe\screen_capture`.
+//! Each line sent to it is a json [`Request`], each is answered with a json [`Response`] line:
+//!
+//! ```text
+//! $ echo '{"command": "screenshot", "path": "/tmp/shot.bmp"}' | socat - UNIX-CONNECT:/tmp/capture.sock
+//! {"ok":true,"path":"/tmp/shot.bmp"}
+//! ```
+use crate::capturer::{BackendFactory, CaptureConfig, PostCallback};
+use crate::recording::{FfmpegConfig, FfmpegRecorder};
+use crate::{ScreenCaptureError, ThreadedCapturer};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+
+/// A command sent to the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Report the [`Status`].
+    Status,
+    /// Replace the configuration of the capturer.
+    SetConfig { config: CaptureConfig },
+    /// Write the most recent frame to the file, the format follows from its extension: `bmp`,
+    /// `ppm`, `qoi` and with the `png` feature `png`.
+    Screenshot { path: PathBuf },
+    /// Record to the video file with ffmpeg, stopping the current recording if any. Fails if no
+    /// frame was captured yet or ffmpeg can't be started.
+    StartRecording { path: PathBuf },
+    /// Stop recording and wait for the file to be finished.
+    StopRecording,
+}
+
+/// The state of the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// Whether the capture thread is running, see [`Health::alive`](crate::capturer::Health).
+    pub alive: bool,
+    /// The name of the backend in use.
+    pub backend: Option<String>,
+    /// The counter of the most recent capture.
+    pub counter: usize,
+    /// Seconds since the last successful capture.
+    pub since_success: Option<f32>,
+    /// The error of the most recent failed capture.
+    pub last_error: Option<ScreenCaptureError>,
+    /// The file being recorded to.
+    pub recording: Option<PathBuf>,
+    /// The number of times the capturer was restarted.
+    pub restarts: u64,
+    pub config: CaptureConfig,
+}
+
+/// The answer to a [`Request`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    /// The file written or recorded to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl Response {
+    fn failed(error: impl std::fmt::Display) -> Response {
+        Response {
+            ok: false,
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// A supervised capturer and recorder handling [`Request`]s, see the [module](self) documentation.
+pub struct Daemon {
+    capturer: Mutex<ThreadedCapturer>,
+    backend: Option<BackendFactory>,
+    recorder: Arc<Mutex<FfmpegRecorder>>,
+    restarts: AtomicU64,
+}
+
+impl Daemon {
+    /// Create a daemon capturing with the backend of this platform.
+    pub fn new(config: CaptureConfig) -> Daemon {
+        Self::create(None, config)
+    }
+
+    /// Create a daemon capturing with the provided backend.
+    pub fn with_backend(backend: BackendFactory, config: CaptureConfig) -> Daemon {
+        Self::create(Some(backend), config)
+    }
+
+    fn create(backend: Option<BackendFactory>, config: CaptureConfig) -> Daemon {
+        let recorder = Arc::new(Mutex::new(FfmpegRecorder::new(Default::default())));
+        let capturer = Self::start(&backend, config, &recorder);
+        Daemon {
+            capturer: Mutex::new(capturer),
+            backend,
+            recorder,
+            restarts: AtomicU64::new(0),
+        }
+    }
+
+    /// Set the ffmpeg settings used by recordings started after this.
+    pub fn with_recorder(self, config: FfmpegConfig) -> Daemon {
+        *self.recorder.lock().unwrap() = FfmpegRecorder::new(config);
+        self
+    }
+
+    fn start(
+        backend: &Option<BackendFactory>,
+        config: CaptureConfig,
+        recorder: &Arc<Mutex<FfmpegRecorder>>,
+    ) -> ThreadedCapturer {
+        let capturer = match backend {
+            Some(backend) => ThreadedCapturer::with_backend(backend.clone(), config),
+            None => ThreadedCapturer::new(config),
+        };
+        capturer.add_post_callback(Self::record_callback(recorder.clone()));
+        capturer
+    }
+
+    fn record_callback(recorder: Arc<Mutex<FfmpegRecorder>>) -> PostCallback {
+        Arc::new(move |info| {
+            let Ok(frame) = info.result else {
+                return;
+            };
+            let mut recorder = recorder.lock().unwrap();
+            if let Err(e) = recorder.write_frame(&frame, info.time) {
+                // Stopped, such that the next frames don't fail the same way.
+                log::warn!("Recording the frame failed, stopping the recording: {e}");
+                if let Err(e) = recorder.stop() {
+                    log::warn!("Stopping the recording failed: {e}");
+                }
+            }
+        })
+    }
+
+    /// Restart the capturer with its current configuration if its thread is no longer alive,
+    /// returns whether it was restarted.
+    pub fn supervise(&self) -> bool {
+        let mut capturer = self.capturer.lock().unwrap();
+        if capturer.health().alive {
+            return false;
+        }
+        log::warn!("The capturer is no longer alive, restarting it");
+        let replacement = Self::start(&self.backend, capturer.config(), &self.recorder);
+        let stopped = std::mem::replace(&mut *capturer, replacement);
+        if let Err(e) = stopped.stop() {
+            log::warn!("Stopping the capturer failed: {e:?}");
+        }
+        self.restarts.fetch_add(1, Relaxed);
+        true
+    }
+
+    pub fn status(&self) -> Status {
+        let capturer = self.capturer.lock().unwrap();
+        let health = capturer.health();
+        Status {
+            alive: health.alive,
+            backend: health.backend,
+            counter: capturer.latest().counter,
+            since_success: health.since_success.map(|d| d.as_secs_f32()),
+            last_error: health.last_error,
+            recording: self.recorder.lock().unwrap().path().map(Path::to_owned),
+            restarts: self.restarts.load(Relaxed),
+            config: capturer.config(),
+        }
+    }
+
+    /// Handle a request, failures are reported in the response.
+    pub fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Status => Ok(Response {
+                status: Some(self.status()),
+                ..Default::default()
+            }),
+            Request::SetConfig { config } => {
+                self.capturer.lock().unwrap().set_config(config);
+                Ok(Default::default())
+            }
+            Request::Screenshot { path } => self.screenshot(&path).map(|_| Response {
+                path: Some(path),
+                ..Default::default()
+            }),
+            Request::StartRecording { path } => self.start_recording(&path).map(|_| Response {
+                path: Some(path),
+                ..Default::default()
+            }),
+            Request::StopRecording => {
+                let mut recorder = self.recorder.lock().unwrap();
+                let path = recorder.path().map(Path::to_owned);
+                recorder.stop().map(|_| Response {
+                    path,
+                    ..Default::default()
+                })
+            }
+        };
+        match result {
+            Ok(response) => Response {
+                ok: true,
+                ..response
+            },
+            Err(e) => Response::failed(e),
+        }
+    }
+
+    /// Start recording with the most recent frame, such that failing to run ffmpeg is reported.
+    fn start_recording(&self, path: &Path) -> std::io::Result<()> {
+        let info = self.capturer.lock().unwrap().latest();
+        let frame = info.result.map_err(std::io::Error::other)?;
+        let mut recorder = self.recorder.lock().unwrap();
+        recorder.start(path)?;
+        if let Err(e) = recorder.write_frame(&frame, info.time) {
+            let _ = recorder.stop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn screenshot(&self, path: &Path) -> std::io::Result<()> {
+        let info = self.capturer.lock().unwrap().latest();
+        let frame = info.result.map_err(std::io::Error::other)?;
+        let img = frame.to_bgr();
+        let filename = path.to_string_lossy();
+        match path.extension().and_then(|v| v.to_str()).unwrap_or("") {
+            "bmp" => crate::util::write_bmp(&img, &filename),
+            "ppm" => crate::util::write_ppm_binary(&img, &filename),
+            "qoi" => crate::util::write_qoi(&img, &filename),
+            #[cfg(feature = "png")]
+            "png" => crate::util::write_png(&img, &filename),
+            extension => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("unsupported screenshot format {extension:?}"),
+            )),
+        }
+    }
+
+    /// Answer the requests of a connection until it is closed.
+    fn serve_connection<S: std::io::Read + Write>(&self, stream: S) -> std::io::Result<()> {
+        let mut reader = std::io::BufReader::new(stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if !line.trim().is_empty() {
+                let response = match serde_json::from_str(&line) {
+                    Ok(request) => self.handle(request),
+                    Err(e) => Response::failed(format!("invalid request: {e}")),
+                };
+                let mut encoded = serde_json::to_vec(&response)?;
+                encoded.push(b'\n');
+                reader.get_mut().write_all(&encoded)?;
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Listen for connections on the unix socket or named pipe, each is served from its own
+    /// thread. The capturer is supervised once a second while the server runs.
+    pub fn serve<P: AsRef<Path>>(self, path: P) -> std::io::Result<DaemonServer> {
+        let path = path.as_ref().to_owned();
+        let listener = Listener::bind(&path)?;
+        let running = Arc::new(AtomicBool::new(true));
+        let daemon = Arc::new(self);
+
+        let supervisor = {
+            let (daemon, running) = (daemon.clone(), running.clone());
+            std::thread::Builder::new()
+                .name("daemon supervisor".to_owned())
+                .spawn(move || {
+                    while running.load(Relaxed) {
+                        std::thread::sleep(std::time::Duration::from_millis(100));
+                        daemon.supervise();
+                        std::thread::sleep(std::time::Duration::from_millis(900));
+                    }
+                })?
+        };
+        let thread = {
+            let running = running.clone();
+            std::thread::Builder::new()
+                .name("daemon server".to_owned())
+                .spawn(move || {
+                    while running.load(Relaxed) {
+                        let stream = match listener.accept() {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                log::warn!("Accepting a connection failed: {e}");
+                                continue;
+                            }
+                        };
+                        if !running.load(Relaxed) {
+                            break;
+                        }
+                        let daemon = daemon.clone();
+                        std::thread::spawn(move || {
+                            // Clients disconnecting is expected, nothing to report.
+                            let _ = daemon.serve_connection(stream);
+                        });
+                    }
+                })?
+        };
+        Ok(DaemonServer {
+            path,
+            running,
+            threads: vec![thread, supervisor],
+        })
+    }
+}
+
+/// A running server started by [`Daemon::serve`].
+pub struct DaemonServer {
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl DaemonServer {
+    /// The socket or pipe the server listens on.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop accepting connections, connected clients are served until they disconnect.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if !self.running.swap(false, Relaxed) {
+            return;
+        }
+        // Wake up the listener, it is blocked on accepting a connection.
+        let _ = connect(&self.path);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for DaemonServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(unix)]
+type Connection = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type Connection = std::fs::File;
+
+#[cfg(unix)]
+struct Listener(std::os::unix::net::UnixListener);
+
+#[cfg(unix)]
+impl Listener {
+    fn bind(path: &Path) -> std::io::Result<Listener> {
+        // A socket left behind by a daemon that didn't stop cleanly can be replaced.
+        if path.exists() && connect(path).is_err() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Listener(std::os::unix::net::UnixListener::bind(path)?))
+    }
+
+    fn accept(&self) -> std::io::Result<Connection> {
+        Ok(self.0.accept()?.0)
+    }
+}
+
+#[cfg(windows)]
+struct Listener(String);
+
+#[cfg(windows)]
+impl Listener {
+    fn bind(path: &Path) -> std::io::Result<Listener> {
+        Ok(Listener(path.to_string_lossy().into_owned()))
+    }
+
+    fn accept(&self) -> std::io::Result<Connection> {
+        crate::backend::accept_pipe(&self.0)
+    }
+}
+
+fn connect(path: &Path) -> std::io::Result<Connection> {
+    #[cfg(unix)]
+    return std::os::unix::net::UnixStream::connect(path);
+    #[cfg(windows)]
+    return std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path);
+}
+
+/// Send a request to the daemon listening on the socket or pipe and wait for the response.
+pub fn send<P: AsRef<Path>>(path: P, request: &Request) -> std::io::Result<Response> {
+    let mut stream = connect(path.as_ref())?;
+    let mut encoded = serde_json::to_vec(request)?;
+    encoded.push(b'\n');
+    stream.write_all(&encoded)?;
+    let mut line = String::new();
+    std::io::BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::capturer::tests::{next_frame, wait_until};
+    use crate::synthetic::tests::test_pattern;
+
+    fn test_daemon() -> Daemon {
+        let backend: BackendFactory = Arc::new(test_pattern(4, 3));
+        let config = CaptureConfig {
+            rate: 100.0,
+            ..Default::default()
+        };
+        Daemon::with_backend(backend, config)
+    }
+
+    /// Receive the counters of the frames the daemon captures from now on.
+    fn frames(daemon: &Daemon) -> std::sync::mpsc::Receiver<usize> {
+        crate::capturer::tests::frames(&daemon.capturer.lock().unwrap())
+    }
+
+    #[test]
+    fn test_request_format() {
+        let request: Request =
+            serde_json::from_str(r#"{"command": "screenshot", "path": "a.bmp"}"#).unwrap();
+        assert_eq!(
+            request,
+            Request::Screenshot {
+                path: "a.bmp".into()
+            }
+        );
+        let response = serde_json::to_string(&Response::failed("no")).unwrap();
+        assert_eq!(response, r#"{"ok":false,"error":"no"}"#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_daemon_serve() {
+        use crate::ImageBGR;
+        let dir = crate::tests::TestDir::new("daemon_serve");
+        let socket = dir.join("daemon.sock");
+        let daemon = test_daemon();
+        let frames = frames(&daemon);
+        let server = daemon.serve(&socket).unwrap();
+        next_frame(&frames);
+
+        let response = send(&socket, &Request::Status).unwrap();
+        assert!(response.ok);
+        let status = response.status.unwrap();
+        assert!(status.alive);
+        assert!(status.counter > 0);
+        assert_eq!(status.backend.as_deref(), Some("test pattern"));
+        assert_eq!((status.recording, status.restarts), (None, 0));
+
+        let screenshot = dir.join("shot.bmp");
+        let response = send(
+            &socket,
+            &Request::Screenshot {
+                path: screenshot.clone(),
+            },
+        )
+        .unwrap();
+        assert_eq!(response.path.as_ref(), Some(&screenshot));
+        let img = crate::util::read_bmp(&screenshot.to_string_lossy()).unwrap();
+        assert_eq!((img.width(), img.height()), (4, 3));
+        let response = send(
+            &socket,
+            &Request::Screenshot {
+                path: dir.join("shot.gif"),
+            },
+        )
+        .unwrap();
+        assert!(!response.ok);
+
+        let config = CaptureConfig {
+            rate: 50.0,
+            ..Default::default()
+        };
+        assert!(send(&socket, &Request::SetConfig { config }).unwrap().ok);
+        wait_until(|| {
+            let status = send(&socket, &Request::Status).unwrap().status.unwrap();
+            status.config.rate == 50.0
+        });
+
+        // Several requests over a single connection, and one that isn't valid.
+        let mut stream = connect(&socket).unwrap();
+        stream
+            .write_all(b"{\"command\": \"stop_recording\"}\n{\"command\": \"jump\"}\n")
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let lines: Vec<Response> = std::io::BufReader::new(stream)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ok);
+        assert!(lines[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("invalid request"));
+
+        server.stop();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_daemon_supervise() {
+        let daemon = test_daemon();
+        next_frame(&frames(&daemon));
+        assert!(!daemon.supervise());
+        // The capture thread ends when a callback panics.
+        daemon
+            .capturer
+            .lock()
+            .unwrap()
+            .add_post_callback(Arc::new(|_| panic!("callback failed")));
+        wait_until(|| !daemon.status().alive);
+        assert!(daemon.supervise());
+        let status = daemon.status();
+        assert_eq!(status.restarts, 1);
+        next_frame(&frames(&daemon));
+        assert!(daemon.status().alive);
+    }
+
+    #[test]
+    fn test_daemon_recording() {
+        let daemon = test_daemon().with_recorder(FfmpegConfig {
+            binary: "screen_capture_missing_ffmpeg".into(),
+            ..Default::default()
+        });
+        let path = PathBuf::from("recording.mp4");
+        next_frame(&frames(&daemon));
+        // The reply reports that ffmpeg couldn't be started.
+        let response = daemon.handle(Request::StartRecording { path: path.clone() });
+        assert!(!response.ok);
+        assert_eq!(daemon.status().recording, None);
+
+        // Recordings that fail while running are stopped.
+        let recorder = Arc::new(Mutex::new(FfmpegRecorder::new(FfmpegConfig {
+            binary: "screen_capture_missing_ffmpeg".into(),
+            ..Default::default()
+        })));
+        recorder.lock().unwrap().start(&path).unwrap();
+        let info = daemon.capturer.lock().unwrap().latest();
+        Daemon::record_callback(recorder.clone())(info);
+        assert!(!recorder.lock().unwrap().is_recording());
+    }
+}
@@ -0,0 +1,246 @@
+//! The error type used throughout the crate.
+use serde::{Deserialize, Serialize};
+
+/// The error code reported by the operating system or display server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OsError {
+    /// A Windows `HRESULT`, like `DXGI_ERROR_ACCESS_LOST`.
+    Hresult(i32),
+    /// The error code of an X11 error event, like `BadMatch`.
+    X11(u8),
+    /// An `errno` value.
+    Errno(i32),
+}
+
+impl std::fmt::Display for OsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OsError::Hresult(v) => write!(f, "HRESULT {:#010x}", *v as u32),
+            OsError::X11(v) => write!(f, "X11 error {v}"),
+            OsError::Errno(v) => write!(f, "errno {v}"),
+        }
+    }
+}
+
+/// The description of a [`ScreenCaptureError`], with the code of the operating system if it
+/// originated there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub message: String,
+    pub os_error: Option<OsError>,
+}
+
+impl ErrorDetail {
+    /// A detail with the code that caused it.
+    pub fn with_os_error<S: Into<String>>(message: S, os_error: OsError) -> Self {
+        ErrorDetail {
+            message: message.into(),
+            os_error: Some(os_error),
+        }
+    }
+}
+
+impl From<String> for ErrorDetail {
+    fn from(message: String) -> Self {
+        ErrorDetail {
+            message,
+            os_error: None,
+        }
+    }
+}
+
+impl From<&str> for ErrorDetail {
+    fn from(message: &str) -> Self {
+        message.to_owned().into()
+    }
+}
+
+impl std::fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.os_error {
+            Some(code) => write!(f, "{} ({code})", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Errors that can occur while capturing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScreenCaptureError {
+    /// Setting up the capture failed, the backend or configuration is not usable as is.
+    Initialisation(ErrorDetail),
+
+    /// A temporary failure, retrying the capture may succeed.
+    Transient(ErrorDetail),
+
+    /// The capture was lost, for example because the resolution changed, the backend needs to
+    /// be reinitialised before capturing can continue.
+    LostCapture(ErrorDetail),
+
+    /// The operating system denied access to the screen contents.
+    PermissionDenied(ErrorDetail),
+
+    /// No frame became available in time, capturing again may succeed.
+    Timeout,
+
+    /// The captured data is in a pixel format the crate can't convert, like a 16 bit display or
+    /// an HDR desktop.
+    UnsupportedFormat { got: String },
+
+    /// The backend can't be used on this system, like X11 without the shared memory extension.
+    BackendUnavailable { backend: String },
+
+    /// The connection to the display server or graphics device was lost, the backend needs to be
+    /// recreated or reset, see [`Capture::reset`](crate::Capture::reset).
+    Disconnected,
+
+    /// Any error that doesn't fit the other categories.
+    Other(ErrorDetail),
+}
+
+impl ScreenCaptureError {
+    /// The code of the operating system that caused this error, if known.
+    pub fn os_error(&self) -> Option<OsError> {
+        match self {
+            ScreenCaptureError::Initialisation(v)
+            | ScreenCaptureError::Transient(v)
+            | ScreenCaptureError::LostCapture(v)
+            | ScreenCaptureError::PermissionDenied(v)
+            | ScreenCaptureError::Other(v) => v.os_error,
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ScreenCaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenCaptureError::Initialisation(v) => write!(f, "initialisation failed: {v}"),
+            ScreenCaptureError::Transient(v) => write!(f, "transient failure: {v}"),
+            ScreenCaptureError::LostCapture(v) => write!(f, "capture lost: {v}"),
+            ScreenCaptureError::PermissionDenied(v) => write!(f, "permission denied: {v}"),
+            ScreenCaptureError::Timeout => write!(f, "timed out waiting for a frame"),
+            ScreenCaptureError::UnsupportedFormat { got } => {
+                write!(f, "unsupported pixel format: {got}")
+            }
+            ScreenCaptureError::BackendUnavailable { backend } => {
+                write!(f, "the {backend} backend is not available")
+            }
+            ScreenCaptureError::Disconnected => write!(f, "disconnected from the display"),
+            ScreenCaptureError::Other(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+impl std::error::Error for ScreenCaptureError {}
+
+/// Errors of the capture thread of the [`ThreadedCapturer`](crate::ThreadedCapturer).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadError {
+    /// The capture thread panicked, holds the panic message.
+    Panicked(String),
+}
+
+impl ThreadError {
+    /// Create the error from the payload of a panic, as returned by joining a thread.
+    pub fn from_panic(payload: Box<dyn std::any::Any + Send + 'static>) -> ThreadError {
+        let message = if let Some(v) = payload.downcast_ref::<&str>() {
+            v.to_string()
+        } else if let Some(v) = payload.downcast_ref::<String>() {
+            v.clone()
+        } else {
+            "unknown panic payload".to_string()
+        };
+        ThreadError::Panicked(message)
+    }
+}
+
+impl std::fmt::Display for ThreadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThreadError::Panicked(v) => write!(f, "capture thread panicked: {v}"),
+        }
+    }
+}
+
+impl std::error::Error for ThreadError {}
+
+/// Errors of loading or saving a [`CaptureConfig`](crate::CaptureConfig).
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Reading or writing the file failed.
+    Io(std::io::Error),
+
+    /// The extension of the file is not a known format, holds the extension.
+    UnsupportedFormat(String),
+
+    /// The format of the file is known but its feature isn't enabled, holds the feature.
+    FormatNotEnabled(&'static str),
+
+    /// The contents could not be parsed, the key is the path to the offending value if known.
+    Parse {
+        key: Option<String>,
+        message: String,
+    },
+
+    /// The configuration could not be serialized.
+    Serialize(String),
+
+    /// The configuration has no profile with this name.
+    UnknownProfile(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{e}"),
+            ConfigError::UnsupportedFormat(v) => {
+                write!(f, "unsupported config format \"{v}\"")
+            }
+            ConfigError::FormatNotEnabled(v) => {
+                write!(
+                    f,
+                    "the {v} config format is not enabled, enable the `{v}` feature"
+                )
+            }
+            ConfigError::Parse {
+                key: Some(key),
+                message,
+            } => write!(f, "invalid value for `{key}`: {message}"),
+            ConfigError::Parse { key: None, message } => write!(f, "{message}"),
+            ConfigError::Serialize(v) => write!(f, "serializing failed: {v}"),
+            ConfigError::UnknownProfile(v) => write!(f, "no profile named \"{v}\""),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_os_error() {
+        let lost = ScreenCaptureError::LostCapture(ErrorDetail::with_os_error(
+            "access lost",
+            OsError::Hresult(0x887A0026u32 as i32),
+        ));
+        assert_eq!(lost.os_error(), Some(OsError::Hresult(-2005270490)));
+        assert_eq!(
+            lost.to_string(),
+            "capture lost: access lost (HRESULT 0x887a0026)"
+        );
+        let plain = ScreenCaptureError::Transient("no frame".into());
+        assert_eq!(plain.os_error(), None);
+        assert_eq!(plain.to_string(), "transient failure: no frame");
+        assert_eq!(ScreenCaptureError::Timeout.os_error(), None);
+    }
+}
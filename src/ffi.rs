@@ -0,0 +1,496 @@
+//! A C api to the [`ThreadedCapturer`], for applications in other languages.
+//!
+//! The header `include/screen_capture.h` is generated from this module by cbindgen when the
+//! crate is built with the `ffi` feature, a test checks that the copy in the repository is up to
+//! date. Update it with `SCREEN_CAPTURE_UPDATE_HEADER=1 cargo test --features ffi test_header`.
+//! All functions accept null handles and report
+//! [`ScreenCaptureStatus::InvalidArgument`] for them, frames obtained from
+//! [`screen_capture_latest`] remain valid until they are released, also after the capturer
+//! is freed.
+//!
+//! Applications check the library they are loaded with through [`screen_capture_negotiate`], with
+//! the [`SCREEN_CAPTURE_API_VERSION`] of the header they were built against. Within a major
+//! version functions, formats and capabilities are only added, the existing functions and structs
+//! don't change. Additions are reported by the [capabilities](screen_capture_capabilities), such
+//! that applications can fall back when the library is older than their header.
+use crate::{CaptureConfig, CaptureSpecification, OutputFormat, OwnedFrame, ThreadedCapturer};
+use crate::{ImageBGR, ScreenCaptureError};
+use std::sync::{Arc, Mutex};
+
+/// The major version of the api, this changes when existing functions or structs change.
+pub const SCREEN_CAPTURE_API_VERSION_MAJOR: u32 = 1;
+/// The minor version of the api, this increases when functions or capabilities are added.
+pub const SCREEN_CAPTURE_API_VERSION_MINOR: u32 = 0;
+/// The version of the api, the major version in the upper 16 bits and the minor in the lower.
+pub const SCREEN_CAPTURE_API_VERSION: u32 =
+    SCREEN_CAPTURE_API_VERSION_MAJOR << 16 | SCREEN_CAPTURE_API_VERSION_MINOR;
+
+/// Regions can be captured, through [`screen_capture_set_region`].
+pub const SCREEN_CAPTURE_CAPABILITY_REGION: u64 = 1 << 0;
+/// Frames can be rgb, see [`ScreenCaptureFormat::Rgb`].
+pub const SCREEN_CAPTURE_CAPABILITY_FORMAT_RGB: u64 = 1 << 1;
+/// Frames can be bgr, see [`ScreenCaptureFormat::Bgr`].
+pub const SCREEN_CAPTURE_CAPABILITY_FORMAT_BGR: u64 = 1 << 2;
+
+/// The capabilities of this library and the minor version that introduced each.
+const CAPABILITIES: [(u64, u32); 3] = [
+    (SCREEN_CAPTURE_CAPABILITY_REGION, 0),
+    (SCREEN_CAPTURE_CAPABILITY_FORMAT_RGB, 0),
+    (SCREEN_CAPTURE_CAPABILITY_FORMAT_BGR, 0),
+];
+
+/// A capturer running in its own thread, created with [`screen_capture_new`].
+pub struct ScreenCapture {
+    capturer: ThreadedCapturer,
+    /// The configuration as set through this api, the capturer applies it asynchronously.
+    config: Mutex<CaptureConfig>,
+}
+
+/// The result of the calls, the errors correspond to the [`ScreenCaptureError`] variants.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScreenCaptureStatus {
+    Ok = 0,
+    /// A null pointer or an invalid value was passed.
+    InvalidArgument = 1,
+    /// No frame was captured yet.
+    NoFrame = 2,
+    Initialisation = 3,
+    Transient = 4,
+    LostCapture = 5,
+    PermissionDenied = 6,
+    Timeout = 7,
+    UnsupportedFormat = 8,
+    BackendUnavailable = 9,
+    Disconnected = 10,
+    Other = 11,
+    /// The library doesn't implement the major version of the header, see
+    /// [`screen_capture_negotiate`].
+    IncompatibleVersion = 12,
+}
+
+impl From<&ScreenCaptureError> for ScreenCaptureStatus {
+    fn from(error: &ScreenCaptureError) -> Self {
+        match error {
+            ScreenCaptureError::Initialisation(_) => ScreenCaptureStatus::Initialisation,
+            ScreenCaptureError::Transient(_) => ScreenCaptureStatus::Transient,
+            ScreenCaptureError::LostCapture(_) => ScreenCaptureStatus::LostCapture,
+            ScreenCaptureError::PermissionDenied(_) => ScreenCaptureStatus::PermissionDenied,
+            ScreenCaptureError::Timeout => ScreenCaptureStatus::Timeout,
+            ScreenCaptureError::UnsupportedFormat { .. } => ScreenCaptureStatus::UnsupportedFormat,
+            ScreenCaptureError::BackendUnavailable { .. } => {
+                ScreenCaptureStatus::BackendUnavailable
+            }
+            ScreenCaptureError::Disconnected => ScreenCaptureStatus::Disconnected,
+            ScreenCaptureError::Other(_) => ScreenCaptureStatus::Other,
+        }
+    }
+}
+
+/// The pixel format of a frame, see [`OutputFormat`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScreenCaptureFormat {
+    /// Four bytes per pixel, red, green, blue and an opaque alpha.
+    Rgba = 0,
+    /// Three bytes per pixel, red, green and blue.
+    Rgb = 1,
+    /// Four bytes per pixel, blue, green, red and an unspecified fourth byte.
+    Bgr = 2,
+}
+
+impl TryFrom<u32> for ScreenCaptureFormat {
+    type Error = ();
+    fn try_from(value: u32) -> Result<Self, ()> {
+        match value {
+            0 => Ok(ScreenCaptureFormat::Rgba),
+            1 => Ok(ScreenCaptureFormat::Rgb),
+            2 => Ok(ScreenCaptureFormat::Bgr),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<ScreenCaptureFormat> for OutputFormat {
+    fn from(format: ScreenCaptureFormat) -> Self {
+        match format {
+            ScreenCaptureFormat::Rgba => OutputFormat::Rgba,
+            ScreenCaptureFormat::Rgb => OutputFormat::Rgb,
+            ScreenCaptureFormat::Bgr => OutputFormat::Bgr,
+        }
+    }
+}
+
+impl From<OutputFormat> for ScreenCaptureFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Rgba => ScreenCaptureFormat::Rgba,
+            OutputFormat::Rgb => ScreenCaptureFormat::Rgb,
+            OutputFormat::Bgr => ScreenCaptureFormat::Bgr,
+        }
+    }
+}
+
+/// A frame, the pixels are rows of `stride` bytes starting at `data`.
+///
+/// Release it with [`screen_capture_frame_release`], which clears all fields.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ScreenCaptureFrame {
+    pub data: *const u8,
+    pub width: u32,
+    pub height: u32,
+    /// The number of bytes from the start of one row to the next.
+    pub stride: u32,
+    pub format: ScreenCaptureFormat,
+    /// The counter of the capture, this increases for each capture.
+    pub counter: u64,
+    /// The frame owning the data, not to be used directly.
+    pub handle: *const std::ffi::c_void,
+}
+
+impl ScreenCaptureFrame {
+    fn empty() -> Self {
+        ScreenCaptureFrame {
+            data: std::ptr::null(),
+            width: 0,
+            height: 0,
+            stride: 0,
+            format: ScreenCaptureFormat::Rgba,
+            counter: 0,
+            handle: std::ptr::null(),
+        }
+    }
+
+    fn new(frame: Arc<OwnedFrame>, counter: usize) -> Self {
+        let (data, pixel_size) = match &*frame {
+            OwnedFrame::Rgba(v) => (v.as_raw().as_ptr(), 4),
+            OwnedFrame::Rgb(v) => (v.as_raw().as_ptr(), 3),
+            OwnedFrame::Bgr(v) => (v.data().as_ptr() as *const u8, 4),
+        };
+        ScreenCaptureFrame {
+            data,
+            width: frame.width(),
+            height: frame.height(),
+            stride: frame.width() * pixel_size,
+            format: frame.format().into(),
+            counter: counter as u64,
+            handle: Arc::into_raw(frame) as *const std::ffi::c_void,
+        }
+    }
+}
+
+impl ScreenCapture {
+    /// Box the capturer into a handle for C.
+    fn into_raw(capturer: ThreadedCapturer) -> *mut ScreenCapture {
+        let config = Mutex::new(capturer.config());
+        Box::into_raw(Box::new(ScreenCapture { capturer, config }))
+    }
+
+    fn update_config<F: FnOnce(&mut CaptureConfig)>(&self, f: F) {
+        let mut config = self.config.lock().unwrap();
+        f(&mut config);
+        self.capturer.set_config(config.clone());
+    }
+}
+
+/// The version of the library, see [`SCREEN_CAPTURE_API_VERSION`].
+#[no_mangle]
+pub extern "C" fn screen_capture_api_version() -> u32 {
+    SCREEN_CAPTURE_API_VERSION
+}
+
+/// The capabilities of the library, a combination of the `SCREEN_CAPTURE_CAPABILITY_` flags.
+#[no_mangle]
+pub extern "C" fn screen_capture_capabilities() -> u64 {
+    CAPABILITIES.iter().fold(0, |all, (flag, _)| all | flag)
+}
+
+/// Check whether the library can be used by an application built against the header of the
+/// version, pass [`SCREEN_CAPTURE_API_VERSION`]. Fails with
+/// [`ScreenCaptureStatus::IncompatibleVersion`] if the major versions differ. Otherwise the
+/// capabilities, if not null, are set to those of the library that the header knows about.
+///
+/// # Safety
+/// The capabilities must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_negotiate(
+    version: u32,
+    capabilities: *mut u64,
+) -> ScreenCaptureStatus {
+    if version >> 16 != SCREEN_CAPTURE_API_VERSION_MAJOR {
+        return ScreenCaptureStatus::IncompatibleVersion;
+    }
+    let minor = version & 0xffff;
+    if let Some(capabilities) = capabilities.as_mut() {
+        *capabilities = CAPABILITIES
+            .iter()
+            .filter(|(_, introduced)| *introduced <= minor)
+            .fold(0, |all, (flag, _)| all | flag);
+    }
+    ScreenCaptureStatus::Ok
+}
+
+/// Whether the rate in Hz can be used, it must be finite and not negative.
+fn valid_rate(rate: f32) -> bool {
+    rate.is_finite() && rate >= 0.0
+}
+
+/// Create a capturer of the entire default display at the rate in Hz, null if the rate is
+/// invalid.
+///
+/// Free it with [`screen_capture_free`].
+#[no_mangle]
+pub extern "C" fn screen_capture_new(rate: f32) -> *mut ScreenCapture {
+    if !valid_rate(rate) {
+        return std::ptr::null_mut();
+    }
+    ScreenCapture::into_raw(ThreadedCapturer::builder().rate(rate).build())
+}
+
+/// Stop the capturer and free it, null is ignored.
+///
+/// # Safety
+/// The capture must be null or obtained from [`screen_capture_new`], and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_free(capture: *mut ScreenCapture) {
+    if !capture.is_null() {
+        let capture = Box::from_raw(capture);
+        if let Err(e) = capture.capturer.stop() {
+            log::warn!("capture thread failed: {e:?}");
+        }
+    }
+}
+
+/// Capture the region of the display, a width or height of zero extends it to the edge of the
+/// display and negative offsets are relative to the right and bottom edges.
+///
+/// # Safety
+/// The capture must be null or a valid capturer.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_set_region(
+    capture: *mut ScreenCapture,
+    display: u32,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> ScreenCaptureStatus {
+    let Some(capture) = capture.as_ref() else {
+        return ScreenCaptureStatus::InvalidArgument;
+    };
+    capture.update_config(|config| {
+        config.capture = vec![CaptureSpecification {
+            display,
+            x,
+            y,
+            width,
+            height,
+            ..Default::default()
+        }];
+    });
+    ScreenCaptureStatus::Ok
+}
+
+/// Set the capture rate in Hz.
+///
+/// # Safety
+/// The capture must be null or a valid capturer.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_set_rate(
+    capture: *mut ScreenCapture,
+    rate: f32,
+) -> ScreenCaptureStatus {
+    let Some(capture) = capture.as_ref() else {
+        return ScreenCaptureStatus::InvalidArgument;
+    };
+    if !valid_rate(rate) {
+        return ScreenCaptureStatus::InvalidArgument;
+    }
+    capture.update_config(|config| config.rate = rate);
+    ScreenCaptureStatus::Ok
+}
+
+/// Set the pixel format of the frames that are captured from now on, one of the
+/// [`ScreenCaptureFormat`] values.
+///
+/// # Safety
+/// The capture must be null or a valid capturer.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_set_format(
+    capture: *mut ScreenCapture,
+    format: u32,
+) -> ScreenCaptureStatus {
+    let Some(capture) = capture.as_ref() else {
+        return ScreenCaptureStatus::InvalidArgument;
+    };
+    // Taken as integer, C may pass any value, which would be undefined as enum.
+    let Ok(format) = ScreenCaptureFormat::try_from(format) else {
+        return ScreenCaptureStatus::InvalidArgument;
+    };
+    capture.update_config(|config| config.output_format = format.into());
+    ScreenCaptureStatus::Ok
+}
+
+/// Obtain the latest frame, on failure the frame is left empty and the status tells why the
+/// latest capture failed.
+///
+/// # Safety
+/// The capture must be null or a valid capturer, the frame must be null or writable, it is
+/// overwritten without releasing it.
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_latest(
+    capture: *mut ScreenCapture,
+    frame: *mut ScreenCaptureFrame,
+) -> ScreenCaptureStatus {
+    if frame.is_null() {
+        return ScreenCaptureStatus::InvalidArgument;
+    }
+    frame.write(ScreenCaptureFrame::empty());
+    let Some(capture) = capture.as_ref() else {
+        return ScreenCaptureStatus::InvalidArgument;
+    };
+    let latest = capture.capturer.latest();
+    match latest.result {
+        Ok(v) => {
+            frame.write(ScreenCaptureFrame::new(v, latest.counter));
+            ScreenCaptureStatus::Ok
+        }
+        Err(ScreenCaptureError::Initialisation(_)) if latest.counter == 0 => {
+            ScreenCaptureStatus::NoFrame
+        }
+        Err(e) => (&e).into(),
+    }
+}
+
+/// Release the frame and clear it, null and empty frames are ignored.
+///
+/// # Safety
+/// The frame must be null, empty or filled by [`screen_capture_latest`].
+#[no_mangle]
+pub unsafe extern "C" fn screen_capture_frame_release(frame: *mut ScreenCaptureFrame) {
+    let Some(frame) = frame.as_mut() else {
+        return;
+    };
+    if !frame.handle.is_null() {
+        drop(Arc::from_raw(frame.handle as *const OwnedFrame));
+    }
+    *frame = ScreenCaptureFrame::empty();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::capturer::tests::wait_until;
+    use crate::synthetic::tests::test_pattern;
+
+    #[test]
+    fn test_ffi() {
+        let backend = Arc::new(test_pattern(8, 8));
+        let config = CaptureConfig {
+            rate: 200.0,
+            ..Default::default()
+        };
+        let capture = ScreenCapture::into_raw(ThreadedCapturer::with_backend(backend, config));
+        unsafe {
+            assert_eq!(
+                screen_capture_set_region(capture, 0, 2, 3, 4, 2),
+                ScreenCaptureStatus::Ok
+            );
+            assert_eq!(
+                screen_capture_set_format(capture, ScreenCaptureFormat::Bgr as u32),
+                ScreenCaptureStatus::Ok
+            );
+            let mut frame = ScreenCaptureFrame::empty();
+            wait_until(|| {
+                screen_capture_frame_release(&mut frame);
+                screen_capture_latest(capture, &mut frame) == ScreenCaptureStatus::Ok
+                    && frame.format == ScreenCaptureFormat::Bgr
+                    && frame.width == 4
+            });
+            assert_eq!((frame.width, frame.height, frame.stride), (4, 2, 16));
+
+            // The frame outlives the capturer.
+            screen_capture_free(capture);
+            let data = std::slice::from_raw_parts(frame.data, 32);
+            // The second pixel of the second row, red is x and green is y.
+            assert_eq!((data[16 + 6], data[16 + 5]), (3, 4));
+            screen_capture_frame_release(&mut frame);
+            assert!(frame.data.is_null());
+            screen_capture_frame_release(&mut frame);
+        }
+    }
+
+    #[test]
+    fn test_ffi_negotiate() {
+        assert_eq!(screen_capture_api_version(), SCREEN_CAPTURE_API_VERSION);
+        unsafe {
+            let mut capabilities = 0;
+            assert_eq!(
+                screen_capture_negotiate(SCREEN_CAPTURE_API_VERSION, &mut capabilities),
+                ScreenCaptureStatus::Ok
+            );
+            assert_eq!(capabilities, screen_capture_capabilities());
+            assert_ne!(capabilities & SCREEN_CAPTURE_CAPABILITY_FORMAT_BGR, 0);
+
+            // A newer header of the same major version only learns about the capabilities.
+            let newer = SCREEN_CAPTURE_API_VERSION + 1;
+            assert_eq!(
+                screen_capture_negotiate(newer, std::ptr::null_mut()),
+                ScreenCaptureStatus::Ok
+            );
+            let next_major = (SCREEN_CAPTURE_API_VERSION_MAJOR + 1) << 16;
+            assert_eq!(
+                screen_capture_negotiate(next_major, &mut capabilities),
+                ScreenCaptureStatus::IncompatibleVersion
+            );
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/screen_capture.h"));
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/screen_capture.h");
+        if std::env::var_os("SCREEN_CAPTURE_UPDATE_HEADER").is_some() {
+            std::fs::write(path, generated).unwrap();
+        }
+        let checked_in = std::fs::read_to_string(path).unwrap();
+        assert!(
+            checked_in == generated,
+            "{path} is outdated, update it with SCREEN_CAPTURE_UPDATE_HEADER=1"
+        );
+    }
+
+    #[test]
+    fn test_ffi_invalid() {
+        unsafe {
+            let mut frame = ScreenCaptureFrame::empty();
+            assert_eq!(
+                screen_capture_latest(std::ptr::null_mut(), &mut frame),
+                ScreenCaptureStatus::InvalidArgument
+            );
+            assert_eq!(
+                screen_capture_set_rate(std::ptr::null_mut(), 1.0),
+                ScreenCaptureStatus::InvalidArgument
+            );
+            screen_capture_free(std::ptr::null_mut());
+            screen_capture_frame_release(std::ptr::null_mut());
+            assert!(screen_capture_new(f32::NAN).is_null());
+            assert!(screen_capture_new(-1.0).is_null());
+        }
+        let backend = Arc::new(test_pattern(2, 2));
+        let capture =
+            ScreenCapture::into_raw(ThreadedCapturer::with_backend(backend, Default::default()));
+        unsafe {
+            assert_eq!(
+                screen_capture_set_format(capture, 3),
+                ScreenCaptureStatus::InvalidArgument
+            );
+            assert_eq!(
+                screen_capture_set_rate(capture, f32::INFINITY),
+                ScreenCaptureStatus::InvalidArgument
+            );
+            screen_capture_free(capture);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Owned frames, as delivered by the [`ThreadedCapturer`](crate::ThreadedCapturer).
+use crate::raster_image::RasterImageBGR;
+use crate::{ImageBGR, Rect, BGR};
+use serde::{Deserialize, Serialize};
+
+/// The pixel format in which frames are delivered.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum OutputFormat {
+    /// Opaque RGBA, converted with [`ImageBGR::to_rgba`].
+    #[cfg(feature = "image")]
+    #[default]
+    Rgba,
+    /// RGB, converted with [`ImageBGR::to_rgb`].
+    #[cfg(feature = "image")]
+    Rgb,
+    /// The BGR data as captured, this only copies and doesn't convert. This is the default
+    /// without the `image` feature.
+    #[cfg_attr(not(feature = "image"), default)]
+    Bgr,
+}
+
+/// A frame that owns its data, in one of the [`OutputFormat`]s.
+#[derive(Clone, PartialEq)]
+pub enum OwnedFrame {
+    #[cfg(feature = "image")]
+    Rgba(image::RgbaImage),
+    #[cfg(feature = "image")]
+    Rgb(image::RgbImage),
+    Bgr(RasterImageBGR),
+}
+
+impl OwnedFrame {
+    /// Create an owned frame from an image, converting it to the requested format.
+    pub fn from_image(img: &dyn ImageBGR, format: OutputFormat) -> OwnedFrame {
+        match format {
+            #[cfg(feature = "image")]
+            OutputFormat::Rgba => OwnedFrame::Rgba(img.to_rgba()),
+            #[cfg(feature = "image")]
+            OutputFormat::Rgb => OwnedFrame::Rgb(img.to_rgb()),
+            OutputFormat::Bgr => OwnedFrame::Bgr(RasterImageBGR::new(img)),
+        }
+    }
+
+    /// The format of this frame.
+    pub fn format(&self) -> OutputFormat {
+        match self {
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgba(_) => OutputFormat::Rgba,
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgb(_) => OutputFormat::Rgb,
+            OwnedFrame::Bgr(_) => OutputFormat::Bgr,
+        }
+    }
+
+    /// Returns the width of the frame.
+    pub fn width(&self) -> u32 {
+        match self {
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgba(v) => v.width(),
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgb(v) => v.width(),
+            OwnedFrame::Bgr(v) => ImageBGR::width(v),
+        }
+    }
+
+    /// Returns the height of the frame.
+    pub fn height(&self) -> u32 {
+        match self {
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgba(v) => v.height(),
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgb(v) => v.height(),
+            OwnedFrame::Bgr(v) => ImageBGR::height(v),
+        }
+    }
+
+    /// Fill the rectangle with black, the part outside of the frame is ignored.
+    pub fn fill_black(&mut self, rect: Rect) {
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            width: self.width(),
+            height: self.height(),
+        };
+        let Some(r) = rect.intersection(&frame) else {
+            return;
+        };
+        let (x_max, y_max) = (r.x + r.width, r.y + r.height);
+        for y in r.y..y_max {
+            for x in r.x..x_max {
+                match self {
+                    #[cfg(feature = "image")]
+                    OwnedFrame::Rgba(v) => v.put_pixel(x, y, image::Rgba([0, 0, 0, 255])),
+                    #[cfg(feature = "image")]
+                    OwnedFrame::Rgb(v) => v.put_pixel(x, y, image::Rgb([0, 0, 0])),
+                    OwnedFrame::Bgr(v) => v.set_pixel(x, y, BGR::default()),
+                }
+            }
+        }
+    }
+
+    /// Returns the rgba image if this frame is in the [`OutputFormat::Rgba`] format.
+    #[cfg(feature = "image")]
+    pub fn as_rgba(&self) -> Option<&image::RgbaImage> {
+        match self {
+            OwnedFrame::Rgba(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Convert the frame to an rgba image, this copies if the frame is already rgba.
+    #[cfg(feature = "image")]
+    pub fn to_rgba(&self) -> image::RgbaImage {
+        match self {
+            OwnedFrame::Rgba(v) => v.clone(),
+            OwnedFrame::Rgb(v) => image::DynamicImage::ImageRgb8(v.clone()).to_rgba8(),
+            OwnedFrame::Bgr(v) => v.to_rgba(),
+        }
+    }
+
+    /// Convert the frame to a bgr image, this copies if the frame is already bgr.
+    pub fn to_bgr(&self) -> RasterImageBGR {
+        #[cfg(feature = "image")]
+        let pixels = |raw: &[u8], step: usize| {
+            raw.chunks_exact(step)
+                .map(|p| BGR {
+                    r: p[0],
+                    g: p[1],
+                    b: p[2],
+                })
+                .collect()
+        };
+        match self {
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgba(v) => RasterImageBGR::from_data(v.width(), v.height(), pixels(v, 4)),
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgb(v) => RasterImageBGR::from_data(v.width(), v.height(), pixels(v, 3)),
+            OwnedFrame::Bgr(v) => v.clone(),
+        }
+    }
+
+    /// The bounding rectangle of the pixels that differ from the previous frame, `None` if the
+    /// frames are equal. This is the entire frame if the size or the format differs.
+    pub fn damage(&self, previous: &OwnedFrame) -> Option<Rect> {
+        let (width, height) = (self.width(), self.height());
+        let whole = (width > 0 && height > 0).then_some(Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        if (previous.width(), previous.height()) != (width, height) {
+            return whole;
+        }
+        match (self, previous) {
+            #[cfg(feature = "image")]
+            (OwnedFrame::Rgba(a), OwnedFrame::Rgba(b)) => damage(a, b, width, 4),
+            #[cfg(feature = "image")]
+            (OwnedFrame::Rgb(a), OwnedFrame::Rgb(b)) => damage(a, b, width, 3),
+            (OwnedFrame::Bgr(a), OwnedFrame::Bgr(b)) => damage(a.data(), b.data(), width, 1),
+            #[cfg(feature = "image")]
+            _ => whole,
+        }
+    }
+}
+
+/// The bounding rectangle of the differing pixels of two images `width` pixels wide, with `step`
+/// values per pixel.
+fn damage<T: PartialEq>(a: &[T], b: &[T], width: u32, step: usize) -> Option<Rect> {
+    let row = width as usize * step;
+    if row == 0 {
+        return None;
+    }
+    let mut rows = a
+        .chunks_exact(row)
+        .zip(b.chunks_exact(row))
+        .enumerate()
+        .filter(|(_, (a, b))| a != b);
+    let first = rows.next()?;
+    let (top, mut bottom, mut left, mut right) = (first.0, first.0, usize::MAX, 0);
+    for (y, (a, b)) in std::iter::once(first).chain(rows) {
+        let differs = |(a, b): (&T, &T)| a != b;
+        bottom = y;
+        left = left.min(a.iter().zip(b).position(differs).unwrap_or(0) / step);
+        right = right.max(a.iter().zip(b).rposition(differs).unwrap_or(0) / step);
+    }
+    Some(Rect {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left + 1) as u32,
+        height: (bottom - top + 1) as u32,
+    })
+}
+
+impl std::fmt::Debug for OwnedFrame {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            fmt,
+            "{:?}<{}x{}>",
+            self.format(),
+            self.width(),
+            self.height()
+        )
+    }
+}
+
+/// Arbitrary data attached to a frame by a processor, see [`ProcessedFrame`].
+///
+/// Comparison is by identity, two values are equal only if they refer to the same data.
+#[derive(Clone)]
+pub struct FrameData(pub std::sync::Arc<dyn std::any::Any + Send + Sync>);
+
+impl FrameData {
+    /// Wrap a value as frame data.
+    pub fn new<T: std::any::Any + Send + Sync>(value: T) -> Self {
+        FrameData(std::sync::Arc::new(value))
+    }
+
+    /// Returns the data if it is of type `T`.
+    pub fn downcast_ref<T: std::any::Any>(&self) -> Option<&T> {
+        self.0.downcast_ref::<T>()
+    }
+}
+
+impl PartialEq for FrameData {
+    fn eq(&self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for FrameData {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "FrameData")
+    }
+}
+
+/// The result of a frame processor, the frame to publish and optionally data derived from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessedFrame {
+    /// The frame to publish, this may be the frame passed to the processor, or a modified one.
+    pub frame: OwnedFrame,
+
+    /// Data the processor derived from the frame, for example color statistics.
+    pub data: Option<FrameData>,
+}
+
+impl ProcessedFrame {
+    /// Create a processed frame with data attached to it.
+    pub fn with_data<T: std::any::Any + Send + Sync>(frame: OwnedFrame, data: T) -> Self {
+        ProcessedFrame {
+            frame,
+            data: Some(FrameData::new(data)),
+        }
+    }
+}
+
+impl From<OwnedFrame> for ProcessedFrame {
+    fn from(frame: OwnedFrame) -> Self {
+        ProcessedFrame { frame, data: None }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::BGR;
+
+    #[test]
+    fn test_processed_frame_data() {
+        let img = RasterImageBGR::filled(4, 2, BGR { r: 1, g: 2, b: 3 });
+        let frame = OwnedFrame::from_image(&img, OutputFormat::Bgr);
+        assert_eq!(frame.format(), OutputFormat::Bgr);
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+
+        let processed = ProcessedFrame::with_data(frame, 42u32);
+        let data = processed.data.clone().expect("should have data");
+        assert_eq!(data.downcast_ref::<u32>(), Some(&42));
+        assert_eq!(data.downcast_ref::<i32>(), None);
+
+        // Equality is by identity, not value.
+        assert_eq!(processed.data, Some(data));
+        assert_ne!(processed.data, Some(FrameData::new(42u32)));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_conversion_golden() {
+        let golden = crate::util::test_asset("gradient_4x3.ppm");
+        let img = crate::util::read_image(&golden).unwrap();
+        for format in [OutputFormat::Rgba, OutputFormat::Rgb, OutputFormat::Bgr] {
+            let frame = OwnedFrame::from_image(&img, format);
+            crate::util::compare_to_file(&frame.to_bgr(), &golden, 0).unwrap();
+        }
+
+        // Masking blacks out the rectangle and leaves the rest as is.
+        let mut frame = OwnedFrame::from_image(&img, OutputFormat::Rgb);
+        frame.fill_black(Rect {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        });
+        let comparison = crate::util::compare_to_file(&frame.to_bgr(), &golden, 255).unwrap();
+        assert_eq!(comparison.differing, 2);
+        assert_eq!(frame.to_bgr().pixel(2, 1), BGR::default());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_damage() {
+        let img = RasterImageBGR::filled(6, 4, BGR { r: 1, g: 2, b: 3 });
+        for format in [OutputFormat::Rgba, OutputFormat::Rgb, OutputFormat::Bgr] {
+            let previous = OwnedFrame::from_image(&img, format);
+            let mut frame = previous.clone();
+            assert_eq!(frame.damage(&previous), None);
+            frame.fill_black(Rect {
+                x: 4,
+                y: 1,
+                width: 1,
+                height: 1,
+            });
+            frame.fill_black(Rect {
+                x: 1,
+                y: 2,
+                width: 2,
+                height: 1,
+            });
+            let expected = Rect {
+                x: 1,
+                y: 1,
+                width: 4,
+                height: 2,
+            };
+            assert_eq!(frame.damage(&previous), Some(expected));
+        }
+        // Frames of another size or format are damaged entirely.
+        let rgb = OwnedFrame::from_image(&img, OutputFormat::Rgb);
+        let rgba = OwnedFrame::from_image(&img, OutputFormat::Rgba);
+        let smaller = RasterImageBGR::filled(2, 2, Default::default());
+        let smaller = OwnedFrame::from_image(&smaller, OutputFormat::Rgb);
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width: 6,
+            height: 4,
+        };
+        assert_eq!(rgb.damage(&rgba), Some(whole));
+        assert_eq!(rgb.damage(&smaller), Some(whole));
+    }
+}
@@ -0,0 +1,413 @@
+//! A gRPC service around a [`ThreadedCapturer`], for consumers in other processes, containers or
+//! on other machines. The service is described by `proto/screen_capture.proto`, the messages
+//! here follow it.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use screen_capture::grpc::CaptureService;
+//! let service = CaptureService::new(screen_capture::ThreadedCapturer::default());
+//! service.serve("127.0.0.1:50051".parse()?).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::capturer::CaptureInfo;
+use crate::{CaptureConfig, CaptureSpecification, DisplayInfo, ImageBGR};
+use crate::{OutputFormat, OwnedFrame, ScreenCaptureError, ThreadedCapturer};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+include!(concat!(
+    env!("OUT_DIR"),
+    "/screen_capture.CaptureService.rs"
+));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Format {
+    Rgba = 0,
+    Rgb = 1,
+    Bgr = 2,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Rgba => OutputFormat::Rgba,
+            Format::Rgb => OutputFormat::Rgb,
+            Format::Bgr => OutputFormat::Bgr,
+        }
+    }
+}
+
+impl From<OutputFormat> for Format {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Rgba => Format::Rgba,
+            OutputFormat::Rgb => Format::Rgb,
+            OutputFormat::Bgr => Format::Bgr,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Display {
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(int32, tag = "3")]
+    pub x: i32,
+    #[prost(int32, tag = "4")]
+    pub y: i32,
+    #[prost(uint32, tag = "5")]
+    pub width: u32,
+    #[prost(uint32, tag = "6")]
+    pub height: u32,
+    #[prost(float, tag = "7")]
+    pub scale: f32,
+    #[prost(bool, tag = "8")]
+    pub primary: bool,
+    #[prost(bool, tag = "9")]
+    pub combined: bool,
+}
+
+impl From<DisplayInfo> for Display {
+    fn from(v: DisplayInfo) -> Self {
+        Display {
+            index: v.index,
+            name: v.name,
+            x: v.x,
+            y: v.y,
+            width: v.width,
+            height: v.height,
+            scale: v.scale,
+            primary: v.primary,
+            combined: v.combined,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDisplaysRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDisplaysResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub displays: Vec<Display>,
+}
+
+/// The region of a display to capture, see [`CaptureSpecification`].
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Region {
+    #[prost(uint32, tag = "1")]
+    pub display: u32,
+    #[prost(int32, tag = "2")]
+    pub x: i32,
+    #[prost(int32, tag = "3")]
+    pub y: i32,
+    #[prost(uint32, tag = "4")]
+    pub width: u32,
+    #[prost(uint32, tag = "5")]
+    pub height: u32,
+}
+
+/// Fields that are not set are unchanged.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConfigureRequest {
+    #[prost(float, optional, tag = "1")]
+    pub rate: Option<f32>,
+    #[prost(message, optional, tag = "2")]
+    pub region: Option<Region>,
+    #[prost(enumeration = "Format", optional, tag = "3")]
+    pub format: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConfigureResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetFrameRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeFramesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Frame {
+    #[prost(uint64, tag = "1")]
+    pub counter: u64,
+    /// The capture time in nanoseconds since the unix epoch.
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    #[prost(uint32, tag = "3")]
+    pub width: u32,
+    #[prost(uint32, tag = "4")]
+    pub height: u32,
+    /// The number of bytes from the start of one row to the next.
+    #[prost(uint32, tag = "5")]
+    pub stride: u32,
+    #[prost(enumeration = "Format", tag = "6")]
+    pub format: i32,
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// The frame of the capture, the bgr pixels are padded to four bytes.
+    fn new(frame: &OwnedFrame, info: &CaptureInfo) -> Frame {
+        let (data, pixel_size) = match frame {
+            OwnedFrame::Rgba(v) => (v.as_raw().clone(), 4),
+            OwnedFrame::Rgb(v) => (v.as_raw().clone(), 3),
+            OwnedFrame::Bgr(v) => (
+                v.data().iter().flat_map(|p| [p.b, p.g, p.r, 0]).collect(),
+                4,
+            ),
+        };
+        let timestamp = info
+            .time
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Frame {
+            counter: info.counter as u64,
+            timestamp,
+            width: frame.width(),
+            height: frame.height(),
+            stride: frame.width() * pixel_size,
+            format: Format::from(frame.format()) as i32,
+            data,
+        }
+    }
+}
+
+/// The status of a failed capture.
+fn status(error: &ScreenCaptureError) -> Status {
+    let message = error.to_string();
+    match error {
+        ScreenCaptureError::Initialisation(_) => Status::failed_precondition(message),
+        ScreenCaptureError::PermissionDenied(_) => Status::permission_denied(message),
+        ScreenCaptureError::UnsupportedFormat { .. } => Status::unimplemented(message),
+        ScreenCaptureError::Other(_) => Status::internal(message),
+        ScreenCaptureError::Transient(_)
+        | ScreenCaptureError::LostCapture(_)
+        | ScreenCaptureError::Timeout
+        | ScreenCaptureError::BackendUnavailable { .. }
+        | ScreenCaptureError::Disconnected => Status::unavailable(message),
+    }
+}
+
+/// Serves the frames of a [`ThreadedCapturer`], see [`capture_service_server::CaptureService`].
+pub struct CaptureService {
+    capturer: ThreadedCapturer,
+    /// The configuration as changed through the service, the capturer applies it asynchronously.
+    config: Mutex<CaptureConfig>,
+    frames: tokio::sync::broadcast::Sender<CaptureInfo>,
+}
+
+impl CaptureService {
+    /// The number of frames buffered for each subscriber, older frames are dropped for slow
+    /// subscribers.
+    pub const BUFFERED_FRAMES: usize = 4;
+
+    /// Serve the capturer, this adds a post callback to it.
+    pub fn new(capturer: ThreadedCapturer) -> CaptureService {
+        let (frames, _) = tokio::sync::broadcast::channel(Self::BUFFERED_FRAMES);
+        let sender = frames.clone();
+        capturer.add_post_callback(Arc::new(move |info| {
+            let _ = sender.send(info);
+        }));
+        let config = Mutex::new(capturer.config());
+        CaptureService {
+            capturer,
+            config,
+            frames,
+        }
+    }
+
+    /// Wrap the service for a tonic server.
+    pub fn into_server(self) -> capture_service_server::CaptureServiceServer<CaptureService> {
+        capture_service_server::CaptureServiceServer::new(self)
+    }
+
+    /// Serve on the address until the server fails.
+    pub async fn serve(self, addr: std::net::SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_server())
+            .serve(addr)
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl capture_service_server::CaptureService for CaptureService {
+    async fn list_displays(
+        &self,
+        _: Request<ListDisplaysRequest>,
+    ) -> Result<Response<ListDisplaysResponse>, Status> {
+        let displays = self
+            .capturer
+            .displays()
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        Ok(Response::new(ListDisplaysResponse { displays }))
+    }
+
+    async fn configure(
+        &self,
+        request: Request<ConfigureRequest>,
+    ) -> Result<Response<ConfigureResponse>, Status> {
+        let request = request.into_inner();
+        let format = request
+            .format
+            .map(|v| Format::try_from(v).map_err(|_| Status::invalid_argument("unknown format")))
+            .transpose()?;
+        if matches!(request.rate, Some(rate) if !rate.is_finite() || rate < 0.0) {
+            return Err(Status::invalid_argument(
+                "the rate must be zero or positive",
+            ));
+        }
+        let mut config = self.config.lock().unwrap();
+        if let Some(rate) = request.rate {
+            config.rate = rate;
+        }
+        if let Some(region) = request.region {
+            config.capture = vec![CaptureSpecification {
+                display: region.display,
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+                ..Default::default()
+            }];
+        }
+        if let Some(format) = format {
+            config.output_format = format.into();
+        }
+        self.capturer.set_config(config.clone());
+        Ok(Response::new(ConfigureResponse {}))
+    }
+
+    async fn get_frame(&self, _: Request<GetFrameRequest>) -> Result<Response<Frame>, Status> {
+        let info = self.capturer.latest();
+        match &info.result {
+            Ok(frame) => Ok(Response::new(Frame::new(frame, &info))),
+            Err(e) => Err(status(e)),
+        }
+    }
+
+    type SubscribeFramesStream = Pin<Box<dyn Stream<Item = Result<Frame, Status>> + Send>>;
+
+    async fn subscribe_frames(
+        &self,
+        _: Request<SubscribeFramesRequest>,
+    ) -> Result<Response<Self::SubscribeFramesStream>, Status> {
+        let frames = tokio_stream::wrappers::BroadcastStream::new(self.frames.subscribe())
+            .filter_map(|info| {
+                let info = info.ok()?;
+                let frame = info.result.as_ref().ok()?;
+                Some(Ok(Frame::new(frame, &info)))
+            });
+        Ok(Response::new(Box::pin(frames)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::synthetic::tests::test_pattern;
+    use crate::synthetic::TestPatternCapture;
+    use crate::{Capture, Resolution};
+    use capture_service_client::CaptureServiceClient;
+
+    #[test]
+    fn test_capture_service() {
+        let backend = Arc::new(test_pattern(8, 8));
+        let config = CaptureConfig {
+            rate: 100.0,
+            ..Default::default()
+        };
+        let service = CaptureService::new(ThreadedCapturer::with_backend(backend, config));
+
+        // Reserve a free port for the server.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.spawn(service.serve(addr));
+        runtime.block_on(async {
+            let mut client = loop {
+                match CaptureServiceClient::connect(format!("http://{addr}")).await {
+                    Ok(client) => break client,
+                    Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                }
+            };
+            client
+                .configure(ConfigureRequest {
+                    region: Some(Region {
+                        display: 0,
+                        x: 1,
+                        y: 2,
+                        width: 3,
+                        height: 4,
+                    }),
+                    format: Some(Format::Bgr as i32),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            let invalid = client
+                .configure(ConfigureRequest {
+                    rate: Some(-1.0),
+                    ..Default::default()
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+
+            let mut stream = client
+                .subscribe_frames(SubscribeFramesRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            let mut previous = 0;
+            let frame = loop {
+                let frame = stream.next().await.unwrap().unwrap();
+                assert!(frame.counter > previous);
+                previous = frame.counter;
+                if frame.width == 3 {
+                    break frame;
+                }
+            };
+            assert_eq!(frame.format, Format::Bgr as i32);
+            assert_eq!((frame.height, frame.stride), (4, 12));
+            // The second pixel of the second row, red is x and green is y.
+            assert_eq!((frame.data[12 + 4 + 2], frame.data[12 + 4 + 1]), (2, 3));
+
+            let latest = client
+                .get_frame(GetFrameRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(latest.counter >= frame.counter);
+            let displays = client
+                .list_displays(ListDisplaysRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                displays.displays,
+                TestPatternCapture::new(Resolution {
+                    width: 8,
+                    height: 8
+                })
+                .displays()
+                .into_iter()
+                .map(Display::from)
+                .collect::<Vec<_>>()
+            );
+        });
+    }
+}
@@ -0,0 +1,284 @@
+//! Running commands on events of the capture and the recorders, for automation with shell scripts.
+//!
+//! The details of the event are passed to the command in three ways. The placeholders `{event}`,
+//! `{path}`, `{time}` and `{error}` in its arguments are replaced, the environment variables
+//! `SCREEN_CAPTURE_EVENT`, `SCREEN_CAPTURE_PATH`, `SCREEN_CAPTURE_TIME` and `SCREEN_CAPTURE_ERROR`
+//! are set, and with [`Hook::stdin`] they are written as `key=value` lines to its stdin. The time
+//! is in milliseconds since the unix epoch, values that don't apply to the event are empty.
+//!
+//! Commands run in the background, the capture doesn't wait for them.
+use crate::capturer::ErrorCallback;
+use crate::ScreenCaptureError;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The events on which commands can run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    /// A frame was written to a file by the [`SequenceRecorder`](crate::recording::SequenceRecorder),
+    /// the path is that of the file.
+    FrameSaved,
+    /// The capture was lost or the connection to the display disconnected, reported through the
+    /// error callback of a [`ThreadedCapturer`](crate::ThreadedCapturer), see
+    /// [`Hooks::error_callback`].
+    CaptureLost,
+    /// A segment directory of the [`SequenceRecorder`](crate::recording::SequenceRecorder) or a
+    /// file of the [`FfmpegRecorder`](crate::recording::FfmpegRecorder) is complete, the path is
+    /// that of the segment.
+    SegmentCompleted,
+}
+
+impl HookEvent {
+    /// The name of the event as used in the configuration, like `frame_saved`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::FrameSaved => "frame_saved",
+            HookEvent::CaptureLost => "capture_lost",
+            HookEvent::SegmentCompleted => "segment_completed",
+        }
+    }
+}
+
+/// A command to run on an event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Hook {
+    /// The event on which the command runs.
+    pub event: HookEvent,
+
+    /// The program followed by its arguments, see the [module](self) documentation for the
+    /// placeholders.
+    pub command: Vec<String>,
+
+    /// Write the details of the event to the stdin of the command.
+    #[serde(default)]
+    pub stdin: bool,
+}
+
+/// The details of an event, passed to the commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookContext {
+    pub event: HookEvent,
+    pub time: SystemTime,
+    /// The file or directory the event is about.
+    pub path: Option<PathBuf>,
+    /// The error that caused the event.
+    pub error: Option<String>,
+}
+
+impl HookContext {
+    /// The details of an event without a path or error.
+    pub fn new(event: HookEvent, time: SystemTime) -> Self {
+        HookContext {
+            event,
+            time,
+            path: None,
+            error: None,
+        }
+    }
+
+    /// Set the path the event is about.
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// The names and values of the details.
+    fn values(&self) -> [(&'static str, String); 4] {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        [
+            ("event", self.event.name().to_owned()),
+            (
+                "path",
+                self.path
+                    .as_ref()
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            ("time", time.as_millis().to_string()),
+            // A single line, such that it fits the stdin format.
+            (
+                "error",
+                self.error.clone().unwrap_or_default().replace('\n', " "),
+            ),
+        ]
+    }
+}
+
+/// The arguments of the command with the placeholders replaced.
+fn arguments(command: &[String], context: &HookContext) -> Vec<String> {
+    let values = context.values();
+    command
+        .iter()
+        .map(|arg| {
+            values.iter().fold(arg.clone(), |arg, (name, value)| {
+                arg.replace(&format!("{{{name}}}"), value)
+            })
+        })
+        .collect()
+}
+
+/// Runs the commands of the hooks, see the [module](self) documentation.
+#[derive(Debug, Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    running: Mutex<Vec<std::process::Child>>,
+}
+
+impl Hooks {
+    pub fn new(hooks: Vec<Hook>) -> Self {
+        Hooks {
+            hooks,
+            running: Default::default(),
+        }
+    }
+
+    pub fn hooks(&self) -> &[Hook] {
+        &self.hooks
+    }
+
+    /// Start the commands for the event, returns the first failure to start one, the others are
+    /// started regardless. Commands that finished since the previous call are reaped, those that
+    /// failed are logged.
+    pub fn run(&self, context: &HookContext) -> std::io::Result<()> {
+        let mut running = self.running.lock().unwrap();
+        running.retain_mut(|child| match child.try_wait() {
+            Ok(Some(status)) => {
+                if !status.success() {
+                    log::warn!("Hook command failed: {status}");
+                }
+                false
+            }
+            _ => true,
+        });
+        let mut result = Ok(());
+        for hook in self.hooks.iter().filter(|h| h.event == context.event) {
+            match Self::spawn(hook, context) {
+                Ok(child) => running.push(child),
+                Err(e) => {
+                    log::warn!("Running the hook {:?} failed: {e}", hook.command);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn spawn(hook: &Hook, context: &HookContext) -> std::io::Result<std::process::Child> {
+        let arguments = arguments(&hook.command, context);
+        let Some((program, arguments)) = arguments.split_first() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the hook has no command",
+            ));
+        };
+        let mut command = std::process::Command::new(program);
+        command.args(arguments);
+        for (name, value) in context.values() {
+            command.env(format!("SCREEN_CAPTURE_{}", name.to_uppercase()), value);
+        }
+        command.stdin(if hook.stdin {
+            std::process::Stdio::piped()
+        } else {
+            std::process::Stdio::null()
+        });
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            for (name, value) in context.values() {
+                // The command may exit without reading its stdin.
+                if writeln!(stdin, "{name}={value}").is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(child)
+    }
+
+    /// Wait for the commands that are still running.
+    pub fn wait(&self) {
+        for mut child in self.running.lock().unwrap().drain(..) {
+            match child.wait() {
+                Ok(status) if !status.success() => log::warn!("Hook command failed: {status}"),
+                Err(e) => log::warn!("Waiting for a hook command failed: {e}"),
+                _ => {}
+            }
+        }
+    }
+
+    /// An error callback for a [`ThreadedCapturer`](crate::ThreadedCapturer) that runs the
+    /// [`HookEvent::CaptureLost`] commands when the capture is lost or disconnected.
+    pub fn error_callback(hooks: Arc<Hooks>) -> ErrorCallback {
+        Arc::new(move |error: ScreenCaptureError| {
+            if matches!(
+                error,
+                ScreenCaptureError::LostCapture(_) | ScreenCaptureError::Disconnected
+            ) {
+                let mut context = HookContext::new(HookEvent::CaptureLost, SystemTime::now());
+                context.error = Some(error.to_string());
+                let _ = hooks.run(&context);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_arguments() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let context = HookContext::new(HookEvent::FrameSaved, time).with_path("/tmp/a.bmp");
+        let command = ["notify", "{event}: {path}", "{time}{error}"].map(String::from);
+        assert_eq!(
+            arguments(&command, &context),
+            vec!["notify", "frame_saved: /tmp/a.bmp", "1700000000123"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_run() {
+        let dir = crate::tests::TestDir::new("hooks_run");
+        let output = dir.join("hook.txt");
+        let hooks = Arc::new(Hooks::new(vec![Hook {
+            event: HookEvent::CaptureLost,
+            command: vec![
+                "sh".into(),
+                "-c".into(),
+                "cat > \"$0\"; echo \"$SCREEN_CAPTURE_EVENT\" >> \"$0\"".into(),
+                output.to_string_lossy().into_owned(),
+            ],
+            stdin: true,
+        }]));
+        let callback = Hooks::error_callback(hooks.clone());
+        callback(ScreenCaptureError::Timeout);
+        hooks.wait();
+        assert!(!output.exists());
+
+        callback(ScreenCaptureError::Disconnected);
+        hooks.wait();
+        let written = std::fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "event=capture_lost");
+        assert_eq!(lines[1], "path=");
+        assert_eq!(lines[3], "error=disconnected from the display");
+        assert_eq!(lines[4], "capture_lost");
+
+        let empty = Hooks::new(vec![Hook {
+            event: HookEvent::FrameSaved,
+            command: vec![],
+            stdin: false,
+        }]);
+        let context = HookContext::new(HookEvent::FrameSaved, SystemTime::now());
+        assert!(empty.run(&context).is_err());
+    }
+}
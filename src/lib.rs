@@ -7,31 +7,104 @@
 //!  - Using Windows' [Desktop Duplication API](https://docs.microsoft.com/en-us/windows/win32/direct3ddxgi/desktop-dup-api) for efficient retrieval on Windows.
 //!
 //! On X11, calling [`Capture::capture_image`] while the image still exists modifies the data underneath the previously handed out image. To ensure this
-//! doesn't happen, the previous images get 'poisoned' after a new call to [`Capture::capture_image`] is performed. Interacting with the old images in any way
-//! results in a panic. When in doubt call [`ImageBGR::to_rgba`] immediately after [`Capture::capture_image`] and immediately
+//! doesn't happen, the previous images get 'poisoned' after a new call to [`Capture::capture_image`] is performed. Old images are then
+//! [stale](ImageBGR::is_stale), they report an empty size and no data, or panic on any interaction if [`BackendOptions::strict_images`] is set. When in doubt call [`ImageBGR::to_rgba`] immediately after [`Capture::capture_image`] and immediately
 //! drop the image, keeping only the owned [`image::RgbaImage`] which one can keep around indefinitely as it owns the full content.
+//! Without the `image` feature the rgba and rgb conversions are not available, [`RasterImageBGR::new`](raster_image::RasterImageBGR::new)
+//! makes an owned copy instead.
 //!
 //! On Windows, a copied image is returned, so it can be kept around indefinitely, it also means that the capture time is longer as the copy happens.
 //!
 //! Todo: An improvement would perhaps be to make [`Capture::capture_image`] return a reference to an image. And just panic if two calls to the capture happen.
+pub mod analysis;
 pub mod capturer;
+#[cfg(feature = "image")]
+pub mod compositor;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frame;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+#[cfg(feature = "ndi")]
+pub mod ndi;
+mod pixels;
+#[cfg(any(feature = "egui", feature = "iced"))]
+pub mod preview;
+#[cfg(feature = "python")]
+pub mod python;
 pub mod raster_image;
+pub mod recording;
+pub mod scrap;
+#[cfg(feature = "shm")]
+pub mod shm;
+pub mod synthetic;
+mod telemetry;
+#[cfg(feature = "wgpu")]
+pub mod texture;
 pub mod util;
+#[cfg(all(feature = "v4l2", target_os = "linux"))]
+pub mod webcam;
 
-pub use capturer::{CaptureConfig, CaptureSpecification, Capturer, ThreadedCapturer};
+pub use capturer::{
+    CallbackHandle, CaptureConfig, CaptureMetadata, CaptureSpecification, Capturer, ConfigIssue,
+    Health, IdleBehaviour, ScheduleMode, ScheduleStats, SessionConfig, SessionEvent,
+    SpecificationCandidate, SpecificationChange, SpecificationSelection, ThreadConfig, ThreadInfo,
+    ThreadPriority, ThreadedCapturer, ThreadedCapturerBuilder, WatchdogConfig,
+};
+pub use error::{ConfigError, ErrorDetail, OsError, ScreenCaptureError, ThreadError};
+pub use frame::{OutputFormat, OwnedFrame, ProcessedFrame};
 
 #[cfg_attr(target_os = "linux", path = "./linux/linux.rs")]
 #[cfg_attr(target_os = "windows", path = "./windows/windows.rs")]
 mod backend;
 
 /// Get a new instance of the screen grabber for this platform.
-pub fn capture() -> Box<dyn Capture> {
-    backend::capture()
+///
+/// The grabber may be moved to another thread, but it must only be used from one thread at a
+/// time. Images are not [`Send`], convert them before moving the grabber, on X11 they become
+/// [stale](ImageBGR::is_stale) with the next capture.
+pub fn capture() -> Result<Box<dyn Capture + Send>, ScreenCaptureError> {
+    capture_with_options(&BackendOptions::default())
+}
+
+/// Whether the session is locked, `None` if this can't be determined.
+///
+/// On Linux this queries logind through `loginctl`, on Windows the session is considered locked
+/// if the input desktop can't be opened, which is the case while the secure desktop is shown.
+pub fn session_locked() -> Option<bool> {
+    backend::session_locked()
+}
+
+/// Options that are applied when the platform's backend is created.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BackendOptions {
+    /// Enable the D3D11 debug layer on Windows, see [`Capture::debug_messages`]. This requires
+    /// the graphics tools to be installed. Ignored on other platforms.
+    #[serde(default)]
+    pub debug_layer: bool,
+
+    /// Panic when a stale image is accessed, instead of it reporting an empty size and no data.
+    /// Only applies to X11, where images are invalidated by the next capture.
+    #[serde(default)]
+    pub strict_images: bool,
+}
+
+/// Get a new instance of the screen grabber for this platform, using the provided options.
+pub fn capture_with_options(
+    options: &BackendOptions,
+) -> Result<Box<dyn Capture + Send>, ScreenCaptureError> {
+    backend::capture(options)
 }
 
 use crate::raster_image::RasterImageBGR;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[repr(C)]
 #[repr(align(4))]
 /// Struct to represent a single pixel in BGR(A)
@@ -52,13 +125,124 @@ impl BGR {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 /// Struct to represent the resolution.
 pub struct Resolution {
     pub width: u32,
     pub height: u32,
 }
 
+/// A rectangle in pixels.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// The area covered by both rectangles, `None` if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x as u64 + self.width as u64).min(other.x as u64 + other.width as u64);
+        let bottom = (self.y as u64 + self.height as u64).min(other.y as u64 + other.height as u64);
+        (right > x as u64 && bottom > y as u64).then(|| Rect {
+            x,
+            y,
+            width: (right - x as u64) as u32,
+            height: (bottom - y as u64) as u32,
+        })
+    }
+
+    /// Clamp a requested region to bounds of the provided size, see [`Capture::prepare_capture`].
+    ///
+    /// A width or height of zero extends to the edge of the bounds, a region that extends past
+    /// the edge is clamped to it. A region that starts outside of the bounds is an error.
+    pub fn clamped(&self, bounds: Resolution) -> Result<Rect, ScreenCaptureError> {
+        if self.x >= bounds.width || self.y >= bounds.height {
+            return Err(ScreenCaptureError::Initialisation(
+                format!(
+                    "region at ({}, {}) starts outside of the {}x{} bounds",
+                    self.x, self.y, bounds.width, bounds.height
+                )
+                .into(),
+            ));
+        }
+        let max_width = bounds.width - self.x;
+        let max_height = bounds.height - self.y;
+        let clamp = |v: u32, max: u32| if v == 0 { max } else { v.min(max) };
+        Ok(Rect {
+            x: self.x,
+            y: self.y,
+            width: clamp(self.width, max_width),
+            height: clamp(self.height, max_height),
+        })
+    }
+}
+
+/// Information about a display that can be captured, see [`Capture::displays`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    /// The index to pass as display to [`Capture::prepare_capture`].
+    pub index: u32,
+    /// The name of the display, like `DP-2` on X11 or `\\.\DISPLAY1` on Windows.
+    pub name: String,
+    /// The horizontal position of the display on the desktop.
+    pub x: i32,
+    /// The vertical position of the display on the desktop.
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// The scale factor of the display, like `1.5` for 150%, zero if unknown.
+    pub scale: f32,
+    /// Whether this is the primary display.
+    pub primary: bool,
+    /// Whether this display spans the other displays, like the entire screen on X11.
+    pub combined: bool,
+}
+
+/// A top-level window on the desktop, see [`Capture::windows`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowInfo {
+    /// The title of the window.
+    pub title: String,
+    /// The class of the window, like `mpv`. The class name of `WM_CLASS` on X11 and the window
+    /// class on Windows.
+    pub class: String,
+    /// The horizontal position of the contents of the window on the desktop.
+    pub x: i32,
+    /// The vertical position of the contents of the window on the desktop.
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A texture on the graphics device that is shared with other processes, see
+/// [`Capture::shared_texture`].
+///
+/// On Windows this is an NT handle to a D3D11 texture with a keyed mutex, open it with
+/// `ID3D11Device1::OpenSharedResource1` or `ID3D12Device::OpenSharedHandle`. Both the backend and
+/// the consumers acquire and release the mutex with key [`SharedTexture::KEY`], and should hold
+/// it only briefly, as the backend waits for it before each copy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SharedTexture {
+    /// The handle, owned by the backend. It stays the same until the size or format of the frames
+    /// changes or the backend is reset or dropped.
+    pub handle: isize,
+    pub width: u32,
+    pub height: u32,
+    /// The pixel format, a `DXGI_FORMAT` on Windows, like `87` for `DXGI_FORMAT_B8G8R8A8_UNORM`.
+    pub format: u32,
+}
+
+impl SharedTexture {
+    /// The key to acquire and release the keyed mutex with.
+    pub const KEY: u64 = 0;
+}
+
 /// Trait for something that represents an BGR image.
 ///
 /// Both windows and linux use BGR(A), using 4 bytes per pixel, A is zero
@@ -80,7 +264,23 @@ pub trait ImageBGR {
     /// Returns the raw data buffer behind this image.
     fn data(&self) -> &[BGR];
 
+    /// Whether the backend reused the buffer behind this image, see [`BackendOptions::strict_images`].
+    fn is_stale(&self) -> bool {
+        false
+    }
+
+    /// Returns the raw data buffer, or a transient error if the image is stale.
+    fn try_data(&self) -> Result<&[BGR], ScreenCaptureError> {
+        if self.is_stale() {
+            return Err(ScreenCaptureError::Transient(
+                "image is stale, it was replaced by a later capture".into(),
+            ));
+        }
+        Ok(self.data())
+    }
+
     /// False color RGBA conversion, this results in blue and red swapped, and full translucency.
+    #[cfg(feature = "image")]
     fn to_rgba_false(&self) -> image::RgbaImage {
         let data = self.data();
         let data_u8 = unsafe {
@@ -97,20 +297,16 @@ pub trait ImageBGR {
     }
 
     /// Convert the the image to rgba using a for loop.
+    #[cfg(feature = "image")]
     fn to_rgba_simple(&self) -> image::RgbaImage {
         let data = self.data();
         let total_len = (self.width() * self.height() * 4) as usize;
-        let mut new_data = Vec::with_capacity(total_len);
-        // This minor application of unsafe to create an uninitialised vector
-        // speeds things up tremendously.
-        unsafe {
-            new_data.set_len(total_len);
-        };
-        for i in 0..(self.width() * self.height()) as usize {
+        let mut new_data = vec![0u8; total_len];
+        for (i, pixel) in data.iter().enumerate() {
             let out_pos = i * 4;
-            new_data[out_pos + 0] = data[i].r;
-            new_data[out_pos + 1] = data[i].g;
-            new_data[out_pos + 2] = data[i].b;
+            new_data[out_pos] = pixel.r;
+            new_data[out_pos + 1] = pixel.g;
+            new_data[out_pos + 2] = pixel.b;
             new_data[out_pos + 3] = 255;
         }
         image::RgbaImage::from_raw(self.width(), self.height(), new_data)
@@ -118,47 +314,198 @@ pub trait ImageBGR {
     }
 
     /// Convert the image to opaque rgba, using the most efficient conversion function available.
+    #[cfg(feature = "image")]
     fn to_rgba(&self) -> image::RgbaImage {
-        #[cfg(all(any(target_arch = "x86_64"), target_feature = "avx2"))]
+        #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
         {
             self.to_rgba_avx2()
         }
 
-        #[cfg(not(all(any(target_arch = "x86_64"), target_feature = "avx2")))]
+        #[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
         {
             self.to_rgba_simple()
         }
     }
 
     /// An AVX2 SIMD implementation of swapping the color space in 32 byte blocks.
-    #[cfg(any(doc, all(any(target_arch = "x86_64"), target_feature = "avx2")))]
+    #[cfg(all(
+        feature = "image",
+        any(doc, all(target_arch = "x86_64", target_feature = "avx2"))
+    ))]
     fn to_rgba_avx2(&self) -> image::RgbaImage {
-        return avx2_simd_bgr_to_rgba(self.width(), self.height(), self.data());
+        avx2_simd_bgr_to_rgba(self.width(), self.height(), self.data())
     }
 
     /// Convert the image to rgb.
+    #[cfg(feature = "image")]
     fn to_rgb(&self) -> image::RgbImage {
         let data = self.data();
         let total_len = (self.width() * self.height() * 3) as usize;
-        let mut new_data = Vec::with_capacity(total_len);
-        // This minor application of unsafe to create an uninitialised vector
-        // speeds things up tremendously.
-        unsafe {
-            new_data.set_len(total_len);
-        };
-        for i in 0..(self.width() * self.height()) as usize {
+        let mut new_data = vec![0u8; total_len];
+        for (i, pixel) in data.iter().enumerate() {
             let out_pos = i * 3;
-            new_data[out_pos + 0] = data[i].r;
-            new_data[out_pos + 1] = data[i].g;
-            new_data[out_pos + 2] = data[i].b;
+            new_data[out_pos] = pixel.r;
+            new_data[out_pos + 1] = pixel.g;
+            new_data[out_pos + 2] = pixel.b;
         }
         image::RgbImage::from_raw(self.width(), self.height(), new_data)
             .expect("must have correct dimensions")
     }
+
+    /// Convert the image to planar I420, the Y plane followed by the U and V planes at half the
+    /// resolution, rounded up. This uses the limited range BT.601 coefficients, chroma is the
+    /// average of each 2x2 block.
+    fn to_i420(&self) -> Vec<u8> {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let data = self.data();
+        let mut out = vec![0u8; width * height + 2 * chroma_width * chroma_height];
+        let (luma, chroma) = out.split_at_mut(width * height);
+        let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
+        for (i, p) in data.iter().enumerate() {
+            let (r, g, b) = (p.r as i32, p.g as i32, p.b as i32);
+            luma[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        }
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                let (mut r, mut g, mut b, mut n) = (0i32, 0i32, 0i32, 0i32);
+                for y in (cy * 2)..(cy * 2 + 2).min(height) {
+                    for x in (cx * 2)..(cx * 2 + 2).min(width) {
+                        let p = data[y * width + x];
+                        (r, g, b, n) = (r + p.r as i32, g + p.g as i32, b + p.b as i32, n + 1);
+                    }
+                }
+                let (r, g, b) = (r / n, g / n, b / n);
+                u_plane[cy * chroma_width + cx] =
+                    (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+                v_plane[cy * chroma_width + cx] =
+                    (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+            }
+        }
+        out
+    }
+
+    /// Convert the image to NV12, like [`ImageBGR::to_i420`] but with the U and V planes
+    /// interleaved into a single plane.
+    fn to_nv12(&self) -> Vec<u8> {
+        let mut out = self.to_i420();
+        let luma = self.width() as usize * self.height() as usize;
+        let chroma = out.split_off(luma);
+        let (u_plane, v_plane) = chroma.split_at(chroma.len() / 2);
+        out.extend(u_plane.iter().zip(v_plane).flat_map(|(u, v)| [*u, *v]));
+        out
+    }
+
+    /// Convert the image to packed YUYV, each pair of pixels as luma, U, luma, V, with the
+    /// coefficients of [`ImageBGR::to_i420`]. Chroma is the average of each pair, the last pixel
+    /// of an odd width is repeated, so rows are `width.div_ceil(2) * 4` bytes.
+    fn to_yuyv(&self) -> Vec<u8> {
+        let width = self.width() as usize;
+        let mut out = Vec::with_capacity(width.div_ceil(2) * 4 * self.height() as usize);
+        if width == 0 {
+            return out;
+        }
+        let luma =
+            |p: &BGR| ((66 * p.r as i32 + 129 * p.g as i32 + 25 * p.b as i32 + 128) >> 8) + 16;
+        for row in self.data().chunks_exact(width) {
+            for pair in row.chunks(2) {
+                let (first, second) = (&pair[0], pair.last().unwrap());
+                let r = (first.r as i32 + second.r as i32) / 2;
+                let g = (first.g as i32 + second.g as i32) / 2;
+                let b = (first.b as i32 + second.b as i32) / 2;
+                out.extend([
+                    luma(first) as u8,
+                    (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+                    luma(second) as u8,
+                    (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+                ]);
+            }
+        }
+        out
+    }
+
+    /// Copy the image into an array of height by width by three, with the channels in the order
+    /// red, green and blue, as most vision models expect.
+    #[cfg(feature = "ndarray")]
+    fn to_ndarray(&self) -> ndarray::Array3<u8> {
+        let shape = (self.height() as usize, self.width() as usize, 3);
+        let data = self.data().iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+        ndarray::Array3::from_shape_vec(shape, data).expect("must have correct dimensions")
+    }
+
+    /// View the image as an array of height by width by three without copying, with the channels
+    /// in the order blue, green and red. Pixels are four bytes apart, the view skips the fourth.
+    #[cfg(feature = "ndarray")]
+    fn as_ndarray(&self) -> ndarray::ArrayView3<'_, u8> {
+        use ndarray::ShapeBuilder;
+        let data = self.data();
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        assert_eq!(data.len(), width * height, "must have correct dimensions");
+        let pixel = std::mem::size_of::<BGR>();
+        let shape = (height, width, 3).strides((width * pixel, pixel, 1));
+        // The fields of BGR are the first three bytes of each pixel in the order b, g, r, the
+        // uninitialised padding after them isn't covered by the view.
+        unsafe { ndarray::ArrayView3::from_shape_ptr(shape, data.as_ptr() as *const u8) }
+    }
+
+    /// Copy the image into an OpenCV matrix of type `CV_8UC3`, in the BGR order OpenCV expects.
+    /// The pixels are copied as a matrix can't skip the padding byte of each pixel.
+    #[cfg(feature = "opencv")]
+    fn to_mat(&self) -> opencv::Result<opencv::core::Mat> {
+        use opencv::prelude::MatTraitConst;
+        let pixels: Vec<opencv::core::Vec3b> =
+            self.data().iter().map(|p| [p.b, p.g, p.r].into()).collect();
+        opencv::core::Mat::new_rows_cols_with_data(
+            self.height() as i32,
+            self.width() as i32,
+            &pixels,
+        )?
+        .try_clone()
+    }
+
+    /// Encode the image as png in a `data:image/png;base64,` uri, to embed it in html.
+    #[cfg(feature = "png")]
+    fn to_data_uri(&self) -> String {
+        let mut png = vec![];
+        util::write_rgb_png_to(&self.to_rgb(), &mut png).expect("writing to memory can't fail");
+        format!("data:image/png;base64,{}", util::base64(&png))
+    }
+
+    /// A fast non-cryptographic hash of the size and the pixels, to detect duplicate frames. The
+    /// unused fourth byte of the pixels is ignored.
+    fn hash(&self) -> u64 {
+        const K: u64 = 0x517c_c1b7_2722_0a95;
+        let mix = |h: u64, v: u64| (h.rotate_left(5) ^ v).wrapping_mul(K);
+        let mut h = mix(0, (self.width() as u64) << 32 | self.height() as u64);
+        let word = |p: &BGR| p.b as u64 | (p.g as u64) << 8 | (p.r as u64) << 16;
+        let mut pairs = self.data().chunks_exact(2);
+        for pair in &mut pairs {
+            h = mix(h, word(&pair[0]) | word(&pair[1]) << 32);
+        }
+        for p in pairs.remainder() {
+            h = mix(h, word(p));
+        }
+        h
+    }
+
+    /// The SHA-256 digest of the width and height as little endian u32, followed by the blue,
+    /// green and red bytes of each pixel, for integrity checks.
+    fn sha256(&self) -> [u8; 32] {
+        let mut digest = util::Sha256::new();
+        digest.update(&self.width().to_le_bytes());
+        digest.update(&self.height().to_le_bytes());
+        for row in self.data().chunks(1024) {
+            let bytes: Vec<u8> = row.iter().flat_map(|p| [p.b, p.g, p.r]).collect();
+            digest.update(&bytes);
+        }
+        digest.finish()
+    }
 }
 
+#[cfg(feature = "image")]
 use image::{GenericImageView, Pixel, Rgba};
 
+#[cfg(feature = "image")]
 impl GenericImageView for Box<dyn ImageBGR> {
     type Pixel = Rgba<u8>;
     fn dimensions(&self) -> (u32, u32) {
@@ -174,32 +521,102 @@ impl GenericImageView for Box<dyn ImageBGR> {
 // Implementation for cloning a boxed image, this always makes a true copy to a raster image.
 impl Clone for Box<dyn ImageBGR> {
     fn clone(&self) -> Self {
-        return Box::new(RasterImageBGR::new(self.as_ref()));
+        Box::new(RasterImageBGR::new(self.as_ref()))
     }
 }
 
 /// Trait to which the desktop frame grabbers adhere.
+///
+/// Grabbers are not required to be [`Send`], the platform's backends are and may be moved to
+/// another thread, see [`capture`]. None of them may be used from multiple threads at once.
 pub trait Capture {
     /// Capture the frame into an internal buffer, creating a 'snapshot'
-    fn capture_image(&mut self) -> bool;
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError>;
 
     /// Retrieve the image for access. By default this may be backed by the internal buffer
     /// created by capture_image.
-    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ()>;
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError>;
 
     /// Retrieve the current full desktop resolution.
-    fn resolution(&mut self) -> Resolution;
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError>;
+
+    /// Retrieve the current full desktop resolution, zero by zero if that fails.
+    #[deprecated(note = "use resolution, this can't distinguish failure from an empty desktop")]
+    fn resolution_or_default(&mut self) -> Resolution {
+        self.resolution().unwrap_or_default()
+    }
 
     /// Attempt to prepare capture for a subsection of the entire desktop.
     /// This is implementation defined and not guaranteed to do anything. It MUST be called before
     /// trying to capture an image, as setup may happen here.
-    fn prepare_capture(&mut self, display: u32, x: u32, y: u32, width: u32, height: u32) -> bool {
+    ///
+    /// The region is validated as described by [`Rect::clamped`], so it is clamped if it extends
+    /// past the edge and an [`ScreenCaptureError::Initialisation`] error if it starts outside.
+    /// On Windows the entire display is captured, the region is only validated.
+    fn prepare_capture(
+        &mut self,
+        display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
         let _ = (display, x, y, width, height);
-        false
+        Ok(())
+    }
+
+    /// Enumerate the displays that can be captured, empty if not supported by the backend.
+    fn displays(&mut self) -> Vec<DisplayInfo> {
+        vec![]
+    }
+
+    /// Enumerate the visible top-level windows, empty if not supported by the backend.
+    fn windows(&mut self) -> Vec<WindowInfo> {
+        vec![]
+    }
+
+    /// Short human readable name of the backend, like `X11`.
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    /// Reconnect to the display server or graphics device, like after a
+    /// [`ScreenCaptureError::Disconnected`] error, and prepare the last prepared region again.
+    /// Previously retrieved images may become stale.
+    fn reset(&mut self) -> Result<(), ScreenCaptureError> {
+        Ok(())
+    }
+
+    /// Retrieve and clear the diagnostic messages collected by the graphics driver, empty if the
+    /// backend doesn't collect these. On Windows this requires [`BackendOptions::debug_layer`].
+    fn debug_messages(&mut self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Copy the captured image into a texture shared with other processes, without copying it
+    /// to the cpu. Only the Desktop Duplication backend supports this.
+    fn shared_texture(&mut self) -> Result<SharedTexture, ScreenCaptureError> {
+        Err(ScreenCaptureError::Other(
+            format!("the {} backend can't share textures", self.name()).into(),
+        ))
+    }
+
+    /// Upload the captured image to a new texture on the device, see [`texture`]. Backends that
+    /// hold the frame on the graphics device may share it instead.
+    #[cfg(feature = "wgpu")]
+    fn frame_as_wgpu_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<wgpu::Texture, ScreenCaptureError> {
+        texture::image_to_texture(&*self.image()?, device, queue)
     }
 }
 
-#[cfg(any(doc, all(any(target_arch = "x86_64"), target_feature = "avx2")))]
+#[cfg(all(
+    feature = "image",
+    any(doc, all(target_arch = "x86_64", target_feature = "avx2"))
+))]
 fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaImage {
     use std::arch::x86_64::*;
     const DO_PRINTS: bool = false;
@@ -219,19 +636,14 @@ fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaIm
     unsafe fn pl(input: &__m256i) -> String {
         let v: [u8; 32] = [0; 32];
         _mm256_storeu_si256(v.as_ptr() as *mut _, *input);
-        format!(
-            "{} | {}",
-            format!("{:02X?}", &v[0..16]),
-            format!("{:02X?}", &v[16..])
-        )
+        format!("{:02X?} | {:02X?}", &v[0..16], &v[16..])
     }
 
     let new_data = unsafe {
         let data_ptr = std::mem::transmute::<*const BGR, *const u8>(data.as_ptr());
         let pixels = (width * height) as usize;
         let total_len = pixels * 4;
-        let mut output: Vec<u8> = Vec::with_capacity(total_len);
-        output.set_len(total_len);
+        let mut output: Vec<u8> = vec![0; total_len];
         let output_ptr = output.as_mut_ptr();
         // 256  / 8 = 32 bytes, 32 / 4 = 8 blocks of BGRA fit into a vector.
         const STEP_SIZE: usize = 256 / 8;
@@ -242,18 +654,18 @@ fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaIm
         trace!(" {}", pl(&alpha_mask));
         // Okay, now we need a shuffle to swap the color channels.
         let mask = _mm256_set_epi64x(
-            i64::from_ne_bytes(0x00_0c_0d_0e__00_08_09_0a_u64.to_ne_bytes()),
-            i64::from_ne_bytes(0x00_04_05_06__00_00_01_02_u64.to_ne_bytes()),
-            i64::from_ne_bytes(0x00_0c_0d_0e__00_08_09_0a_u64.to_ne_bytes()),
-            i64::from_ne_bytes(0x00_04_05_06__00_00_01_02_u64.to_ne_bytes()),
+            i64::from_ne_bytes(0x000c_0d0e_0008_090a_u64.to_ne_bytes()),
+            i64::from_ne_bytes(0x0004_0506_0000_0102_u64.to_ne_bytes()),
+            i64::from_ne_bytes(0x000c_0d0e_0008_090a_u64.to_ne_bytes()),
+            i64::from_ne_bytes(0x0004_0506_0000_0102_u64.to_ne_bytes()),
         );
         // Handle the full chunks.
         for step in 0..chunks {
             let pos = STEP_SIZE * step;
             trace!("step: {step}, pos {pos}");
             // Load the data
-            let v = _mm256_loadu_si256(std::mem::transmute::<_, *const __m256i>(
-                data_ptr.offset(pos as isize),
+            let v = _mm256_loadu_si256(std::mem::transmute::<*const u8, *const __m256i>(
+                data_ptr.add(pos),
             ));
             trace!(" {}", pl(&v));
 
@@ -267,7 +679,7 @@ fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaIm
 
             // Write back the finished data.
             _mm256_storeu_si256(
-                std::mem::transmute::<_, *mut __m256i>(output_ptr.offset(pos as isize)),
+                std::mem::transmute::<*mut u8, *mut __m256i>(output_ptr.add(pos)),
                 combined,
             );
         }
@@ -291,6 +703,171 @@ fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaIm
 pub mod tests {
     use super::*;
 
+    /// A directory unique to the process and test, removed with its contents when dropped.
+    pub struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        pub fn new(test: &str) -> TestDir {
+            let path =
+                std::env::temp_dir().join(format!("screen_capture_{test}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).expect("test directory must be writable");
+            TestDir(path)
+        }
+
+        pub fn path(&self) -> &std::path::Path {
+            &self.0
+        }
+
+        pub fn join<P: AsRef<std::path::Path>>(&self, path: P) -> std::path::PathBuf {
+            self.0.join(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_rect_clamped() {
+        let bounds = Resolution {
+            width: 100,
+            height: 50,
+        };
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            rect(0, 0, 0, 0).clamped(bounds).unwrap(),
+            rect(0, 0, 100, 50)
+        );
+        assert_eq!(
+            rect(10, 20, 30, 10).clamped(bounds).unwrap(),
+            rect(10, 20, 30, 10)
+        );
+        assert_eq!(
+            rect(90, 40, 30, 30).clamped(bounds).unwrap(),
+            rect(90, 40, 10, 10)
+        );
+        assert!(matches!(
+            rect(100, 0, 10, 10).clamped(bounds),
+            Err(ScreenCaptureError::Initialisation(_))
+        ));
+        assert!(rect(0, 50, 0, 0).clamped(bounds).is_err());
+        assert!(rect(0, 0, 0, 0).clamped(Resolution::default()).is_err());
+
+        let mut capture = synthetic::TestPatternCapture::new(bounds);
+        assert!(capture.prepare_capture(0, 120, 0, 10, 10).is_err());
+        assert!(capture.prepare_capture(0, 90, 0, 20, 10).is_ok());
+        capture.capture_image().unwrap();
+        assert_eq!(capture.image().unwrap().width(), 10);
+    }
+
+    #[test]
+    fn test_capture_send() {
+        fn assert_send<T: Send + ?Sized>() {}
+        assert_send::<Box<dyn Capture + Send>>();
+        assert_send::<synthetic::TestPatternCapture>();
+    }
+
+    #[test]
+    fn test_shared_texture_unsupported() {
+        let mut capture = synthetic::TestPatternCapture::new(Resolution {
+            width: 4,
+            height: 4,
+        });
+        capture.prepare_capture(0, 0, 0, 4, 4).unwrap();
+        capture.capture_image().unwrap();
+        assert!(matches!(
+            capture.shared_texture(),
+            Err(ScreenCaptureError::Other(e)) if e.to_string().contains("test pattern")
+        ));
+    }
+
+    #[test]
+    fn test_hash() {
+        let a = raster_image::RasterImageBGR::filled(3, 3, BGR { r: 1, g: 2, b: 3 });
+        let mut b = a.clone();
+        assert_eq!(a.hash(), b.hash());
+        assert_eq!(a.sha256(), b.sha256());
+        b.set_pixel(2, 2, BGR { r: 1, g: 2, b: 4 });
+        assert_ne!(a.hash(), b.hash());
+        assert_ne!(a.sha256(), b.sha256());
+        // The same pixels in a different shape differ.
+        let c = raster_image::RasterImageBGR::filled(1, 9, BGR { r: 1, g: 2, b: 3 });
+        assert_ne!(a.hash(), c.hash());
+        assert_ne!(a.sha256(), c.sha256());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_data_uri() {
+        let img = raster_image::RasterImageBGR::filled(2, 2, BGR { r: 1, g: 2, b: 3 });
+        let uri = img.to_data_uri();
+        // The png signature, encoded.
+        assert!(uri.starts_with("data:image/png;base64,iVBORw0KGgo"));
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_ndarray() {
+        let mut img = raster_image::RasterImageBGR::filled(3, 2, BGR::default());
+        img.set_pixel(2, 1, BGR { r: 1, g: 2, b: 3 });
+        let rgb = img.to_ndarray();
+        assert_eq!(rgb.shape(), &[2, 3, 3]);
+        assert_eq!(rgb.slice(ndarray::s![1, 2, ..]).to_vec(), vec![1, 2, 3]);
+        let bgr = img.as_ndarray();
+        assert_eq!(bgr.shape(), &[2, 3, 3]);
+        assert_eq!(bgr.slice(ndarray::s![1, 2, ..]).to_vec(), vec![3, 2, 1]);
+        assert_eq!(bgr.slice(ndarray::s![1, .., 0]).to_vec(), vec![0, 0, 3]);
+    }
+
+    #[cfg(feature = "opencv")]
+    #[test]
+    fn test_mat() {
+        use opencv::prelude::*;
+        let mut img = raster_image::RasterImageBGR::filled(3, 2, BGR::default());
+        img.set_pixel(2, 1, BGR { r: 1, g: 2, b: 3 });
+        let copy = img.to_mat().unwrap();
+        assert_eq!((copy.rows(), copy.cols(), copy.channels()), (2, 3, 3));
+        assert_eq!(
+            copy.at_2d::<opencv::core::Vec3b>(1, 2).unwrap().0,
+            [3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_yuv_layouts() {
+        // A white and a black column, three pixels wide such that the last pixel is repeated.
+        let mut img = raster_image::RasterImageBGR::filled(3, 2, BGR::default());
+        for y in 0..2 {
+            img.set_pixel(
+                0,
+                y,
+                BGR {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+            );
+        }
+        let i420 = img.to_i420();
+        let nv12 = img.to_nv12();
+        assert_eq!(nv12.len(), i420.len());
+        assert_eq!(&nv12[..6], &i420[..6]);
+        assert_eq!(&nv12[6..], &[i420[6], i420[8], i420[7], i420[9]]);
+
+        let yuyv = img.to_yuyv();
+        assert_eq!(yuyv.len(), 2 * 2 * 4);
+        assert_eq!(&yuyv[..8], &[235, 128, 16, 128, 16, 128, 16, 128]);
+        assert_eq!(&yuyv[..8], &yuyv[8..]);
+    }
+
     #[test]
     fn test_rgb_order() {
         // Both X11 and Windows use the following to convert from the bytes behind the pointer to
@@ -328,8 +905,102 @@ pub mod tests {
         assert_eq!(std::mem::size_of::<BGR>(), std::mem::size_of::<u32>());
     }
 
+    /// An image over a raw buffer with rows that are `stride` bytes apart, like the X11 and
+    /// Desktop Duplication images. Padded rows are packed for [`ImageBGR::data`], like the
+    /// backends do.
+    #[cfg(feature = "image")]
+    #[derive(Debug, Clone)]
+    struct StridedImage {
+        memory: Vec<u32>,
+        width: u32,
+        height: u32,
+        stride: usize,
+        packed: Option<Vec<BGR>>,
+    }
+
+    #[cfg(feature = "image")]
+    impl StridedImage {
+        fn new(memory: Vec<u32>, width: u32, height: u32, stride: usize) -> Self {
+            let mut image = StridedImage {
+                memory,
+                width,
+                height,
+                stride,
+                packed: None,
+            };
+            let pixels = image.pixels();
+            let packed = pixels
+                .contiguous()
+                .is_none()
+                .then(|| (0..height).flat_map(|y| pixels.row(y)).copied().collect());
+            image.packed = packed;
+            image
+        }
+
+        fn pixels(&self) -> crate::pixels::RawPixels<'_> {
+            let data = self.memory.as_ptr() as *const u8;
+            unsafe {
+                crate::pixels::RawPixels::from_raw(data, self.width, self.height, self.stride)
+            }
+            .unwrap()
+        }
+    }
+
+    #[cfg(feature = "image")]
+    impl ImageBGR for StridedImage {
+        fn width(&self) -> u32 {
+            self.width
+        }
+        fn height(&self) -> u32 {
+            self.height
+        }
+        fn pixel(&self, x: u32, y: u32) -> BGR {
+            self.pixels().pixel(x, y)
+        }
+        fn data(&self) -> &[BGR] {
+            match &self.packed {
+                Some(packed) => packed,
+                None => self.pixels().contiguous().unwrap(),
+            }
+        }
+    }
+
+    /// An image of random pixels in a raw buffer, with rows padded by up to 8 pixels of random
+    /// bytes. The unused fourth byte of each pixel is random as well.
+    #[cfg(feature = "image")]
+    fn padded_image() -> impl proptest::strategy::Strategy<Value = StridedImage> {
+        use proptest::prelude::*;
+        (0u32..70, 0u32..12, 0u32..8).prop_flat_map(|(width, height, padding)| {
+            let stride = (width + padding) as usize * 4;
+            let count = (width + padding) as usize * height as usize;
+            proptest::collection::vec(any::<u32>(), count)
+                .prop_map(move |memory| StridedImage::new(memory, width, height, stride))
+        })
+    }
+
+    proptest::proptest! {
+        #[cfg(feature = "image")]
+        #[test]
+        fn test_conversions_match(img in padded_image()) {
+            // Converting must agree with the pixels, whichever conversion is used.
+            let expected = image::RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+                let p = img.pixel(x, y);
+                image::Rgba([p.r, p.g, p.b, 255])
+            });
+            proptest::prop_assert_eq!(&img.to_rgba(), &expected);
+            proptest::prop_assert_eq!(&img.to_rgba_simple(), &expected);
+            #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+            proptest::prop_assert_eq!(&img.to_rgba_avx2(), &expected);
+            let rgb: Vec<u8> = expected.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+            proptest::prop_assert_eq!(img.to_rgb().into_raw(), rgb);
+        }
+    }
+
     #[test]
-    #[cfg(any(doc, all(any(target_arch = "x86_64"), target_feature = "avx2")))]
+    #[cfg(all(
+        feature = "image",
+        any(doc, all(target_arch = "x86_64", target_feature = "avx2"))
+    ))]
     fn test_rgb_simd() {
         // fn avx2_simd_bgr_to_rgba(width: u32, height: u32, data: &[BGR]) -> image::RgbaImage {
         use crate::util::WriteSupport;
@@ -53,6 +53,7 @@ type XID = u64;
 pub type Window = XID;
 pub type Drawable = XID;
 pub type Colormap = XID;
+pub type Atom = XID;
 
 type Bool = i32; // Wow!?
 
@@ -199,12 +200,12 @@ impl Default for XShmSegmentInfo {
 #[derive(Debug)]
 pub struct XErrorEvent {
     type_: i32,
-    display: *mut Display, /* Display the event was read from */
-    serial: u64,           /* serial number of failed request */
-    error_code: u8,        /* error code of failed request */
-    request_code: u8,      /* Major op-code of failed request */
-    minor_code: u8,        /* Minor op-code of failed request */
-    resourceid: XID,       /* resource id */
+    pub display: *mut Display, /* Display the event was read from */
+    pub serial: u64,           /* serial number of failed request */
+    pub error_code: u8,        /* error code of failed request */
+    pub request_code: u8,      /* Major op-code of failed request */
+    pub minor_code: u8,        /* Minor op-code of failed request */
+    resourceid: XID,           /* resource id */
 }
 
 /*
@@ -217,12 +218,120 @@ pub struct XErrorEvent {
 */
 
 type XErrorHandler = unsafe extern "C" fn(*mut Display, *mut XErrorEvent) -> i32;
+type XIOErrorHandler = unsafe extern "C" fn(*mut Display) -> i32;
+
+// Since libX11 1.7, loaded at runtime such that older versions still work.
+pub type XIOErrorExitHandler = unsafe extern "C" fn(*mut Display, *mut libc::c_void);
+pub type XSetIOErrorExitHandler =
+    unsafe extern "C" fn(*mut Display, XIOErrorExitHandler, *mut libc::c_void);
 
 pub const AllPlanes: u64 = 0xFFFFFFFFFFFFFFFF;
 
+// From X11/X.h and X11/extensions/shmproto.h, the latter is relative to the extension's first error.
+pub const BadAccess: u8 = 10;
+pub const BadShmSeg: u8 = 0;
+
+// From X11/X.h, X11/Xatom.h and X11/Xlib.h, for owning the clipboard selection.
+pub const CurrentTime: u64 = 0;
+pub const PropModeReplace: i32 = 0;
+pub const XA_ATOM: Atom = 4;
+pub const SelectionClear: i32 = 29;
+pub const SelectionRequest: i32 = 30;
+pub const SelectionNotify: i32 = 31;
+// For transferring large selections incrementally, with the INCR protocol.
+pub const PropertyNotify: i32 = 28;
+pub const PropertyDelete: i32 = 1;
+pub const NoEventMask: i64 = 0;
+pub const PropertyChangeMask: i64 = 1 << 22;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct XSelectionRequestEvent {
+    pub type_: i32,
+    pub serial: u64,
+    pub send_event: Bool,
+    pub display: *mut Display,
+    pub owner: Window,
+    pub requestor: Window,
+    pub selection: Atom,
+    pub target: Atom,
+    pub property: Atom,
+    pub time: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct XSelectionEvent {
+    pub type_: i32,
+    pub serial: u64,
+    pub send_event: Bool,
+    pub display: *mut Display,
+    pub requestor: Window,
+    pub selection: Atom,
+    pub target: Atom,
+    pub property: Atom,
+    pub time: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct XPropertyEvent {
+    pub type_: i32,
+    pub serial: u64,
+    pub send_event: Bool,
+    pub display: *mut Display,
+    pub window: Window,
+    pub atom: Atom,
+    pub time: u64,
+    pub state: i32,
+}
+
+/// Only the events that are handled, padded to the size of the union in Xlib.h.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union XEvent {
+    pub type_: i32,
+    pub selection_request: XSelectionRequestEvent,
+    pub selection: XSelectionEvent,
+    pub property: XPropertyEvent,
+    pad: [i64; 24],
+}
+
+impl Default for XEvent {
+    fn default() -> Self {
+        XEvent { pad: [0; 24] }
+    }
+}
+
+// From X11/extensions/Xrandr.h, this library is loaded at runtime.
+#[repr(C)]
+#[derive(Debug)]
+pub struct XRRMonitorInfo {
+    pub name: Atom,
+    pub primary: Bool,
+    pub automatic: Bool,
+    pub noutput: i32,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub mwidth: i32,
+    pub mheight: i32,
+    pub outputs: *mut XID,
+}
+
+pub type XRRGetMonitors = unsafe extern "C" fn(
+    display: *mut Display,
+    window: Window,
+    get_active: Bool,
+    nmonitors: *mut i32,
+) -> *mut XRRMonitorInfo;
+pub type XRRFreeMonitors = unsafe extern "C" fn(monitors: *mut XRRMonitorInfo);
+
 #[link(name = "X11")]
 extern "C" {
     pub fn XOpenDisplay(text: *const libc::c_char) -> *mut Display;
+    pub fn XCloseDisplay(display: *mut Display) -> i32;
 
     pub fn XRootWindow(display: *mut Display, screen_number: i32) -> Window;
     pub fn XDefaultScreen(display: *mut Display) -> i32;
@@ -235,9 +344,21 @@ extern "C" {
 
     pub fn XDestroyImage(ximage: *mut XImage) -> i32;
 
+    pub fn XGetAtomName(display: *mut Display, atom: Atom) -> *mut libc::c_char;
+    pub fn XFree(data: *mut libc::c_void) -> i32;
+
     pub fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+    pub fn XSetIOErrorHandler(handler: XIOErrorHandler) -> XIOErrorHandler;
+    pub fn XConnectionNumber(display: *mut Display) -> i32;
 
     pub fn XSync(display: *mut Display, discard: Bool);
+    pub fn XQueryExtension(
+        display: *mut Display,
+        name: *const libc::c_char,
+        major_opcode_return: *mut i32,
+        first_event_return: *mut i32,
+        first_error_return: *mut i32,
+    ) -> Bool;
     pub fn XFlush(display: *mut Display);
 
     pub fn XGetGeometry(
@@ -251,6 +372,79 @@ extern "C" {
         border_width_return: *mut u32,
         depth_return: *mut u32,
     ) -> Status;
+
+    pub fn XInternAtom(
+        display: *mut Display,
+        atom_name: *const libc::c_char,
+        only_if_exists: Bool,
+    ) -> Atom;
+
+    pub fn XCreateSimpleWindow(
+        display: *mut Display,
+        parent: Window,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        border_width: u32,
+        border: u64,
+        background: u64,
+    ) -> Window;
+    pub fn XDestroyWindow(display: *mut Display, window: Window) -> i32;
+
+    pub fn XSetSelectionOwner(
+        display: *mut Display,
+        selection: Atom,
+        owner: Window,
+        time: u64,
+    ) -> i32;
+    pub fn XGetSelectionOwner(display: *mut Display, selection: Atom) -> Window;
+    pub fn XChangeProperty(
+        display: *mut Display,
+        window: Window,
+        property: Atom,
+        type_: Atom,
+        format: i32,
+        mode: i32,
+        data: *const u8,
+        nelements: i32,
+    ) -> i32;
+    pub fn XSendEvent(
+        display: *mut Display,
+        window: Window,
+        propagate: Bool,
+        event_mask: i64,
+        event: *mut XEvent,
+    ) -> Status;
+    pub fn XNextEvent(display: *mut Display, event: *mut XEvent) -> i32;
+    pub fn XSelectInput(display: *mut Display, window: Window, event_mask: i64) -> i32;
+    pub fn XMaxRequestSize(display: *mut Display) -> i64;
+
+    pub fn XTranslateCoordinates(
+        display: *mut Display,
+        src_w: Window,
+        dest_w: Window,
+        src_x: i32,
+        src_y: i32,
+        dest_x_return: *mut i32,
+        dest_y_return: *mut i32,
+        child_return: *mut Window,
+    ) -> Bool;
+
+    pub fn XGetWindowProperty(
+        display: *mut Display,
+        window: Window,
+        property: Atom,
+        long_offset: i64,
+        long_length: i64,
+        delete: Bool,
+        req_type: Atom,
+        actual_type_return: *mut Atom,
+        actual_format_return: *mut i32,
+        nitems_return: *mut u64,
+        bytes_after_return: *mut u64,
+        prop_return: *mut *mut u8,
+    ) -> i32;
 }
 
 #[link(name = "Xext")]
@@ -269,6 +463,7 @@ extern "C" {
     ) -> *mut XImage;
 
     pub fn XShmAttach(display: *mut Display, shminfo: *const XShmSegmentInfo) -> Bool;
+    pub fn XShmDetach(display: *mut Display, shminfo: *const XShmSegmentInfo) -> Bool;
     pub fn XShmGetImage(
         display: *mut Display,
         d: Drawable,
@@ -3,71 +3,120 @@ mod X11;
 use X11::*;
 
 mod shm;
+#[cfg(feature = "v4l2")]
+pub mod v4l2;
 
-use std::rc::Rc;
+use crate::pixels::RawPixels;
 use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+
+/// Non-owning reference to an XImage, with safe accessors.
+#[derive(Debug, Copy, Clone)]
+struct XImageRef {
+    image: *mut XImage,
+}
+
+impl XImageRef {
+    /// Wrap the image.
+    ///
+    /// # Safety
+    /// The image must be a valid XImage for as long as this reference and its copies are used,
+    /// and its data must not be written to while pixels obtained from it are in use.
+    unsafe fn new(image: *mut XImage) -> XImageRef {
+        XImageRef { image }
+    }
+
+    fn as_ptr(&self) -> *mut XImage {
+        self.image
+    }
+
+    fn width(&self) -> u32 {
+        unsafe { (*self.image).width as u32 }
+    }
+
+    fn height(&self) -> u32 {
+        unsafe { (*self.image).height as u32 }
+    }
+
+    fn bits_per_pixel(&self) -> u32 {
+        unsafe { (*self.image).bits_per_pixel as u32 }
+    }
+
+    /// The number of bytes from the start of one row to the next.
+    fn stride(&self) -> usize {
+        unsafe { (*self.image).bytes_per_line as usize }
+    }
+
+    /// The pixels of the image, which must have 32 bits per pixel.
+    fn pixels(&self) -> Result<RawPixels<'_>, ScreenCaptureError> {
+        if self.bits_per_pixel() != 32 {
+            return Err(ScreenCaptureError::UnsupportedFormat {
+                got: format!("{} bits per pixel", self.bits_per_pixel()),
+            });
+        }
+        unsafe {
+            let data = (*self.image).data as *const u8;
+            RawPixels::from_raw(data, self.width(), self.height(), self.stride())
+        }
+    }
+}
 
 /// Image wrapper around XImage.
 struct ImageX11 {
-    image: *mut XImage,
-    poisoned: Rc<AtomicBool>,
+    image: XImageRef,
+    poisoned: Arc<AtomicBool>,
+    strict: bool,
 }
 
 impl ImageX11 {
-    fn check_poisoned(&self) {
-        if self.poisoned.load(Relaxed) {
+    /// Whether the image is stale, this panics instead if in strict mode.
+    fn check_poisoned(&self) -> bool {
+        let poisoned = self.poisoned.load(Relaxed);
+        if poisoned && self.strict {
             panic!("interacting with stale image, call capture_image prior")
         }
+        poisoned
     }
 }
 
 impl ImageBGR for ImageX11 {
     fn width(&self) -> u32 {
-        self.check_poisoned();
-        unsafe { (*self.image).width as u32 }
+        if self.check_poisoned() {
+            return 0;
+        }
+        self.image.width()
     }
     fn height(&self) -> u32 {
-        self.check_poisoned();
-        unsafe { (*self.image).height as u32 }
+        if self.check_poisoned() {
+            return 0;
+        }
+        self.image.height()
     }
 
     fn pixel(&self, x: u32, y: u32) -> BGR {
-        self.check_poisoned();
-        let width = self.width();
-        let height = self.height();
-        if x > width || y > height {
-            panic!("Retrieved out of bounds ({}, {})", x, y);
+        if self.check_poisoned() {
+            return BGR::default();
         }
+        // Capturing fails for images that aren't 32 bits per pixel, so there are no others.
+        self.image
+            .pixels()
+            .expect("image must have 32 bits per pixel")
+            .pixel(x, y)
+    }
 
-        unsafe {
-            let image = &(*self.image);
-            // println!("Image: {:?}", self.image.unwrap());
-            // Do some pointer magic and reach into the data, do a few casts and we're golden.
-            let data = std::mem::transmute::<*const libc::c_char, *const u8>(image.data);
-            let stride = (image.bits_per_pixel / 8) as u32;
-            let as_integer = *std::mem::transmute::<*const u8, *const u32>(
-                data.offset((y * width * stride + x * stride).try_into().unwrap()),
-            );
-            let masked = as_integer & 0x00FFFFFF;
-            BGR {
-                r: ((masked >> 16) & 0xFF) as u8,
-                g: ((masked >> 8) & 0xFF) as u8,
-                b: (masked & 0xFF) as u8,
-            }
-        }
+    fn is_stale(&self) -> bool {
+        self.poisoned.load(Relaxed)
     }
 
     fn data(&self) -> &[BGR] {
-        self.check_poisoned();
-        unsafe {
-            let image = &(*self.image);
-            let width = image.width as usize;
-            let height = image.height as usize;
-            assert!(image.bits_per_pixel / 8 == 4);
-            let data = std::mem::transmute::<*const libc::c_char, *const BGR>(image.data);
-            let len = width * height;
-            std::slice::from_raw_parts(data, len)
+        if self.check_poisoned() {
+            return &[];
         }
+        self.image
+            .pixels()
+            .expect("image must have 32 bits per pixel")
+            .contiguous()
+            .expect("rows of a shared memory image are not padded")
     }
 }
 
@@ -75,149 +124,285 @@ impl ImageBGR for ImageX11 {
 struct CaptureX11 {
     display: *mut Display,
     window: Window,
-    image: Option<*mut XImage>,
-    image_poison: Rc<AtomicBool>,
+    image: Option<XImageRef>,
+    image_poison: Arc<AtomicBool>,
     shminfo: XShmSegmentInfo,
     pos_x: u32,
     pos_y: u32,
+    /// The first error code of the shared memory extension, to recognise its errors.
+    shm_error_base: u8,
+    /// Whether stale images panic, see [`BackendOptions::strict_images`].
+    strict_images: bool,
+    /// The display and region of the last [`Capture::prepare_capture`], used by [`Capture::reset`].
+    prepared: (u32, Rect),
+    /// Whether the connection was lost, Xlib must not be used on it anymore.
+    lost: bool,
 }
 
+// The connection, image and shared memory are exclusively owned by this struct and only used
+// through it, so it can be moved between threads. Images share only the atomic poison flag.
+unsafe impl Send for CaptureX11 {}
+
 impl Drop for CaptureX11 {
     fn drop(&mut self) {
+        // Images that outlive the capture must not read the released memory.
+        self.poison_image();
         // Clean up the memory correctly.
-        unsafe {
-            XDestroyImage(self.image.unwrap());
+        self.release_image();
+        // Closing a lost connection invokes the io error handler again, so it is leaked.
+        if !self.lost {
+            unsafe { XCloseDisplay(self.display) };
         }
+        take_x11_errors(self.display);
+        X11_LOST
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|d| *d != self.display as usize);
     }
 }
 
 impl CaptureX11 {
-    pub fn new() -> CaptureX11 {
+    pub fn new() -> Result<CaptureX11, ScreenCaptureError> {
         unsafe {
             let display = XOpenDisplay(std::ptr::null::<libc::c_char>());
+            if display.is_null() {
+                return Err(ScreenCaptureError::BackendUnavailable {
+                    backend: "X11".into(),
+                });
+            }
             if XShmQueryExtension(display) == 0 {
-                panic!("We really need the xshared memory extension. Bailing out.");
+                XCloseDisplay(display);
+                return Err(ScreenCaptureError::BackendUnavailable {
+                    backend: "X11 shared memory".into(),
+                });
+            }
+            // Without this handler Xlib exits the process when the connection is lost.
+            let set_exit_handler =
+                libc::dlsym(libc::RTLD_DEFAULT, c"XSetIOErrorExitHandler".as_ptr());
+            if !set_exit_handler.is_null() {
+                let set_exit_handler = std::mem::transmute::<
+                    *mut libc::c_void,
+                    XSetIOErrorExitHandler,
+                >(set_exit_handler);
+                set_exit_handler(display, io_error_exit_handler, std::ptr::null_mut());
             }
             let window = XRootWindow(display, XDefaultScreen(display));
-            CaptureX11 {
+            let (mut opcode, mut event_base, mut error_base) = (0, 0, 0);
+            XQueryExtension(
+                display,
+                c"MIT-SHM".as_ptr(),
+                &mut opcode,
+                &mut event_base,
+                &mut error_base,
+            );
+            Ok(CaptureX11 {
                 display,
                 window,
                 image: None,
                 shminfo: Default::default(),
                 pos_x: 0,
                 pos_y: 0,
-                image_poison: Rc::new(false.into()),
+                image_poison: Arc::new(false.into()),
+                shm_error_base: error_base as u8,
+                strict_images: false,
+                prepared: Default::default(),
+                lost: false,
+            })
+        }
+    }
+
+    /// Fail with [`ScreenCaptureError::Disconnected`] if the connection to the X server was lost.
+    fn check_connection(&mut self) -> Result<(), ScreenCaptureError> {
+        if !self.lost {
+            let lost = X11_LOST
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&(self.display as usize));
+            self.lost = lost || connection_closed(unsafe { XConnectionNumber(self.display) });
+            if self.lost {
+                log::warn!("Lost the connection to the X server");
             }
         }
+        if self.lost {
+            Err(ScreenCaptureError::Disconnected)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Take the errors that occurred on this connection, returning the first as capture error.
+    fn take_error(&mut self) -> Option<ScreenCaptureError> {
+        let mut errors = take_x11_errors(self.display).into_iter();
+        let first = errors.next()?;
+        for ignored in errors {
+            log::debug!("Dropping subsequent X11 error: {:?}", ignored);
+        }
+        Some(first.to_capture_error(self.shm_error_base))
     }
 
     pub fn poison_image(&mut self) {
         self.image_poison.store(true, Relaxed);
-        self.image_poison = Rc::new(false.into());
+        self.image_poison = Arc::new(false.into());
     }
 
-    pub fn prepare(&mut self, x: u32, y: u32, width: u32, height: u32) -> bool {
+    pub fn prepare(&mut self, region: Rect) -> Result<(), ScreenCaptureError> {
         self.poison_image();
+        self.check_connection()?;
         let mut attributes = XWindowAttributes::default();
         let status = unsafe { XGetWindowAttributes(self.display, self.window, &mut attributes) };
         if status != 1 {
-            panic!("Retrieving the window attributes failed.");
+            return Err(ScreenCaptureError::Initialisation(
+                "retrieving the window attributes failed".into(),
+            ));
         }
 
-        let width = std::cmp::min(
-            if width != 0 {
-                width as i32
-            } else {
-                attributes.width
-            },
-            attributes.width,
-        );
-        let height = std::cmp::min(
-            if height != 0 {
-                height as i32
-            } else {
-                attributes.height
-            },
-            attributes.height,
-        );
-
-        let x = std::cmp::min(x as i32, attributes.width);
-        let y = std::cmp::min(y as i32, attributes.height);
-        self.pos_x = x as u32;
-        self.pos_y = y as u32;
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = region.clamped(Resolution {
+            width: attributes.width as u32,
+            height: attributes.height as u32,
+        })?;
+        self.pos_x = x;
+        self.pos_y = y;
 
-        let width = std::cmp::min(width, attributes.width - x as i32);
-        let height = std::cmp::min(height, attributes.height - y as i32);
-
-        self.image = Some(unsafe {
-            XShmCreateImage(
+        self.release_image();
+        unsafe {
+            let ximage = XShmCreateImage(
                 self.display,
                 attributes.visual,
                 attributes.depth as u32,
                 ZPixmap,
                 std::ptr::null_mut::<libc::c_char>(),
                 &mut self.shminfo,
-                width as u32,
-                height as u32,
-            )
-        });
-
-        let ximage = self.image.unwrap();
-        // Next, create the shared memory information.
-        unsafe {
-            self.shminfo.shmid = shm::shmget(
-                shm::IPC_PRIVATE,
-                ((*ximage).bytes_per_line * (*ximage).height) as u64,
-                shm::IPC_CREAT | 0x180,
+                width,
+                height,
             );
+            if ximage.is_null() {
+                return Err(ScreenCaptureError::Initialisation(
+                    "creating the shared memory image failed".into(),
+                ));
+            }
 
-            (*ximage).data = std::mem::transmute::<*mut libc::c_void, *mut libc::c_char>(
-                shm::shmat(self.shminfo.shmid, std::ptr::null_mut::<libc::c_void>(), 0),
-            );
+            // Next, create the shared memory information.
+            let size = (*ximage).bytes_per_line as u64 * (*ximage).height as u64;
+            let shmid = shm::shmget(shm::IPC_PRIVATE, size, shm::IPC_CREAT | 0x180);
+            if shmid == -1 {
+                let e = std::io::Error::last_os_error();
+                XDestroyImage(ximage);
+                return Err(ScreenCaptureError::Initialisation(errno_detail(
+                    format!("creating the shared memory segment of {size} bytes failed: {e}"),
+                    &e,
+                )));
+            }
+            let shmaddr = shm::shmat(shmid, std::ptr::null_mut::<libc::c_void>(), 0);
+            if shmaddr as isize == -1 {
+                let e = std::io::Error::last_os_error();
+                shm::shmctl(shmid, shm::IPC_RMID, std::ptr::null_mut());
+                XDestroyImage(ximage);
+                return Err(ScreenCaptureError::Initialisation(errno_detail(
+                    format!("attaching the shared memory segment failed: {e}"),
+                    &e,
+                )));
+            }
+            (*ximage).data = shmaddr as *mut libc::c_char;
+            self.shminfo.shmid = shmid;
             self.shminfo.shmaddr = (*ximage).data;
             self.shminfo.readOnly = 0;
 
             // And now, we just have to attach the shared memory.
             if XShmAttach(self.display, &self.shminfo) == 0 {
-                panic!("Couldn't attach shared memory");
+                shm::shmdt(shmaddr);
+                shm::shmctl(shmid, shm::IPC_RMID, std::ptr::null_mut());
+                XDestroyImage(ximage);
+                self.shminfo = Default::default();
+                return Err(ScreenCaptureError::Initialisation(
+                    "couldn't attach shared memory".into(),
+                ));
+            }
+            // Once the server attached it, the segment can be marked for removal. It is removed
+            // when both detached, also if the process exits without releasing it.
+            XSync(self.display, 0);
+            shm::shmctl(shmid, shm::IPC_RMID, std::ptr::null_mut());
+            self.image = Some(XImageRef::new(ximage));
+        }
+        Ok(())
+    }
+
+    /// Detach and destroy the image and its shared memory segment, if any.
+    fn release_image(&mut self) {
+        let Some(image) = self.image.take() else {
+            return;
+        };
+        unsafe {
+            if !self.lost {
+                XShmDetach(self.display, &self.shminfo);
+                XSync(self.display, 0);
             }
+            // The shared memory image only frees the struct, not the segment.
+            XDestroyImage(image.as_ptr());
+            shm::shmdt(self.shminfo.shmaddr as *const libc::c_void);
         }
-        true
+        self.shminfo = Default::default();
     }
 }
 
 impl Capture for CaptureX11 {
-    fn capture_image(&mut self) -> bool {
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
         self.poison_image();
-        if self.image.is_none() {
-            return false;
+        self.check_connection()?;
+        // Errors from earlier requests are reported before capturing anew.
+        if let Some(e) = self.take_error() {
+            return Err(e);
         }
-        unsafe {
+        let image = self.image.ok_or_else(|| {
+            ScreenCaptureError::Initialisation("no image prepared, call prepare_capture".into())
+        })?;
+        let success = unsafe {
             XShmGetImage(
                 self.display,
                 self.window,
-                self.image.unwrap(),
+                image.as_ptr(),
                 self.pos_x as i32,
                 self.pos_y as i32,
                 AllPlanes,
             )
+        };
+        let bits_per_pixel = image.bits_per_pixel();
+        if success && bits_per_pixel != 32 {
+            Err(ScreenCaptureError::UnsupportedFormat {
+                got: format!("{bits_per_pixel} bits per pixel"),
+            })
+        } else if success {
+            Ok(())
+        } else {
+            self.check_connection()?;
+            Err(self
+                .take_error()
+                .unwrap_or(ScreenCaptureError::Transient("XShmGetImage failed".into())))
         }
     }
-    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ()> {
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
         self.poison_image();
-        if self.image.is_some() {
-            let new_bool = Rc::new(false.into());
-            self.image_poison = Rc::clone(&new_bool);
+        if let Some(image) = self.image {
+            let new_bool = Arc::new(false.into());
+            self.image_poison = Arc::clone(&new_bool);
             Ok(Box::<ImageX11>::new(ImageX11 {
-                image: self.image.unwrap(),
+                image,
                 poisoned: new_bool,
+                strict: self.strict_images,
             }))
         } else {
-            Err(())
+            Err(ScreenCaptureError::Initialisation(
+                "no image prepared, call prepare_capture".into(),
+            ))
         }
     }
 
-    fn resolution(&mut self) -> Resolution {
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+        self.check_connection()?;
         let mut x: i32 = 0;
         let mut y: i32 = 0;
         let mut width: u32 = 0;
@@ -225,7 +410,7 @@ impl Capture for CaptureX11 {
         let mut border_width: u32 = 0;
         let mut depth: u32 = 0;
         let mut window: Window = Default::default();
-        unsafe {
+        let status = unsafe {
             XGetGeometry(
                 self.display,
                 self.window,
@@ -236,27 +421,989 @@ impl Capture for CaptureX11 {
                 &mut height,
                 &mut border_width,
                 &mut depth,
+            )
+        };
+        if status == 0 {
+            return Err(self
+                .take_error()
+                .unwrap_or(ScreenCaptureError::Transient("XGetGeometry failed".into())));
+        }
+        Ok(Resolution { width, height })
+    }
+
+    /// Display 0 is the entire screen, the monitors start at 1 and the region is relative to
+    /// the monitor.
+    fn prepare_capture(
+        &mut self,
+        display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
+        let region = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        self.prepared = (display, region);
+        if display == 0 {
+            return CaptureX11::prepare(self, region);
+        }
+        let Some(monitor) = self.displays().into_iter().find(|d| d.index == display) else {
+            log::warn!("Display {display} not found, capturing relative to the screen.");
+            return CaptureX11::prepare(self, region);
+        };
+        let region = region.clamped(Resolution {
+            width: monitor.width,
+            height: monitor.height,
+        })?;
+        CaptureX11::prepare(
+            self,
+            Rect {
+                x: monitor.x.max(0) as u32 + region.x,
+                y: monitor.y.max(0) as u32 + region.y,
+                ..region
+            },
+        )
+    }
+
+    /// Reconnect to the X server and prepare the last prepared region again.
+    fn reset(&mut self) -> Result<(), ScreenCaptureError> {
+        let mut fresh = CaptureX11::new()?;
+        fresh.strict_images = self.strict_images;
+        let (display, region) = self.prepared;
+        self.poison_image();
+        // This drops the previous connection.
+        *self = fresh;
+        self.prepare_capture(display, region.x, region.y, region.width, region.height)
+    }
+
+    /// The entire screen as display 0, followed by the monitors reported by XRandR.
+    fn name(&self) -> &str {
+        "X11"
+    }
+
+    fn displays(&mut self) -> Vec<DisplayInfo> {
+        let Ok(resolution) = self.resolution() else {
+            return vec![];
+        };
+        let mut displays = vec![DisplayInfo {
+            index: 0,
+            name: "screen".to_owned(),
+            width: resolution.width,
+            height: resolution.height,
+            primary: false,
+            combined: true,
+            ..Default::default()
+        }];
+        displays.extend(unsafe { monitors(self.display, self.window) });
+        // X11 has no scale for the entire screen, use the one of the primary monitor.
+        displays[0].scale = displays
+            .iter()
+            .find(|d| d.primary)
+            .or(displays.get(1))
+            .map(|d| d.scale)
+            .unwrap_or_default();
+        displays
+    }
+
+    /// The visible windows managed by the window manager, through `_NET_CLIENT_LIST`.
+    fn windows(&mut self) -> Vec<WindowInfo> {
+        if self.check_connection().is_err() {
+            return vec![];
+        }
+        let windows = unsafe { client_windows(self.display, self.window) };
+        // Windows may disappear while they are enumerated, those are skipped.
+        take_x11_errors(self.display);
+        windows
+    }
+}
+
+/// Read a property of the window, returning its format and data. Xlib widens the items of
+/// format 32 to longs.
+unsafe fn window_property(
+    display: *mut Display,
+    window: Window,
+    property: Atom,
+) -> Option<(i32, Vec<u8>)> {
+    let (mut actual_type, mut format, mut items, mut remaining) = (0, 0, 0, 0);
+    let mut data: *mut u8 = std::ptr::null_mut();
+    let status = XGetWindowProperty(
+        display,
+        window,
+        property,
+        0,
+        i32::MAX as i64,
+        0,
+        0, // AnyPropertyType
+        &mut actual_type,
+        &mut format,
+        &mut items,
+        &mut remaining,
+        &mut data,
+    );
+    if status != 0 || data.is_null() {
+        return None;
+    }
+    let size = match format {
+        8 => 1,
+        16 => std::mem::size_of::<libc::c_short>(),
+        _ => std::mem::size_of::<libc::c_long>(),
+    };
+    let bytes = std::slice::from_raw_parts(data, items as usize * size).to_vec();
+    XFree(data as *mut libc::c_void);
+    (actual_type != 0).then_some((format, bytes))
+}
+
+/// The windows managed by the window manager, from `_NET_CLIENT_LIST`, that are visible.
+unsafe fn client_windows(display: *mut Display, root: Window) -> Vec<WindowInfo> {
+    let atom = |name: &std::ffi::CStr| XInternAtom(display, name.as_ptr(), 0);
+    let Some((32, clients)) = window_property(display, root, atom(c"_NET_CLIENT_LIST")) else {
+        return vec![];
+    };
+    let text = |window: Window, property: Atom| {
+        window_property(display, window, property)
+            .filter(|(format, _)| *format == 8)
+            .map(|(_, bytes)| String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let mut windows = vec![];
+    for client in clients.chunks_exact(std::mem::size_of::<Window>()) {
+        let window = Window::from_ne_bytes(client.try_into().unwrap());
+        let mut attributes = XWindowAttributes::default();
+        let (mut x, mut y, mut child) = (0, 0, 0);
+        let visible = XGetWindowAttributes(display, window, &mut attributes) != 0
+            && attributes.map_state == 2 // IsViewable
+            && XTranslateCoordinates(display, window, root, 0, 0, &mut x, &mut y, &mut child)
+                != 0;
+        if !visible {
+            continue;
+        }
+        let title = text(window, atom(c"_NET_WM_NAME"))
+            .or_else(|| text(window, atom(c"WM_NAME")))
+            .unwrap_or_default();
+        // The instance and class name, both terminated by a null.
+        let class = text(window, atom(c"WM_CLASS"))
+            .and_then(|v| v.split('\0').nth(1).map(str::to_owned))
+            .unwrap_or_default();
+        windows.push(WindowInfo {
+            title,
+            class,
+            x,
+            y,
+            width: attributes.width as u32,
+            height: attributes.height as u32,
+        });
+    }
+    windows
+}
+
+/// Retrieve the monitors through XRandR, it is loaded at runtime such that it is optional.
+unsafe fn monitors(display: *mut Display, window: Window) -> Vec<DisplayInfo> {
+    let library = libc::dlopen(
+        c"libXrandr.so.2".as_ptr(),
+        libc::RTLD_NOW | libc::RTLD_LOCAL,
+    );
+    if library.is_null() {
+        return vec![];
+    }
+    let get_monitors = libc::dlsym(library, c"XRRGetMonitors".as_ptr());
+    let free_monitors = libc::dlsym(library, c"XRRFreeMonitors".as_ptr());
+    let mut result = vec![];
+    if !get_monitors.is_null() && !free_monitors.is_null() {
+        let get_monitors = std::mem::transmute::<*mut libc::c_void, XRRGetMonitors>(get_monitors);
+        let free_monitors =
+            std::mem::transmute::<*mut libc::c_void, XRRFreeMonitors>(free_monitors);
+        let mut count: i32 = 0;
+        let monitors = get_monitors(display, window, 1, &mut count);
+        if !monitors.is_null() {
+            for (i, monitor) in std::slice::from_raw_parts(monitors, count as usize)
+                .iter()
+                .enumerate()
+            {
+                let name_ptr = XGetAtomName(display, monitor.name);
+                let name = if name_ptr.is_null() {
+                    String::new()
+                } else {
+                    let name = std::ffi::CStr::from_ptr(name_ptr)
+                        .to_string_lossy()
+                        .into_owned();
+                    XFree(name_ptr as *mut libc::c_void);
+                    name
+                };
+                result.push(DisplayInfo {
+                    index: i as u32 + 1,
+                    name,
+                    x: monitor.x,
+                    y: monitor.y,
+                    width: monitor.width as u32,
+                    height: monitor.height as u32,
+                    scale: scale_from_size(monitor.width, monitor.mwidth),
+                    primary: monitor.primary != 0,
+                    combined: false,
+                });
+            }
+            free_monitors(monitors);
+        }
+    }
+    libc::dlclose(library);
+    result
+}
+
+/// X11 has no scale factor, estimate it from the physical width in millimetres relative to the
+/// 96 DPI of a scale of one, rounded to quarters.
+fn scale_from_size(width: i32, width_mm: i32) -> f32 {
+    if width <= 0 || width_mm <= 0 {
+        return 0.0;
+    }
+    let dpi = width as f32 / (width_mm as f32 / 25.4);
+    ((dpi / 96.0 * 4.0).round() / 4.0).max(1.0)
+}
+
+/// The detail of an error, with the errno of the io error.
+fn errno_detail(message: String, e: &std::io::Error) -> error::ErrorDetail {
+    match e.raw_os_error() {
+        Some(errno) => error::ErrorDetail::with_os_error(message, error::OsError::Errno(errno)),
+        None => message.into(),
+    }
+}
+
+/// The tid of the calling thread.
+pub fn current_thread_id() -> u64 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u64 }
+}
+
+/// Set the niceness of the calling thread according to the priority.
+pub fn set_thread_priority(priority: crate::ThreadPriority) -> Result<(), ScreenCaptureError> {
+    use crate::ThreadPriority::*;
+    let nice = match priority {
+        Lowest => 10,
+        BelowNormal => 5,
+        Normal => 0,
+        AboveNormal => -5,
+        Highest => -10,
+    };
+    let res = unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, nice)
+    };
+    if res != 0 {
+        let e = std::io::Error::last_os_error();
+        let detail = errno_detail(format!("setting thread priority failed: {e}"), &e);
+        return Err(if e.kind() == std::io::ErrorKind::PermissionDenied {
+            ScreenCaptureError::PermissionDenied(detail)
+        } else {
+            ScreenCaptureError::Other(detail)
+        });
+    }
+    Ok(())
+}
+
+/// Restrict the calling thread to the provided cores.
+pub fn set_thread_affinity(cores: &[usize]) -> Result<(), ScreenCaptureError> {
+    // CPU_SET panics for cores beyond the fixed size set.
+    let limit = libc::CPU_SETSIZE as usize;
+    if let Some(core) = cores.iter().find(|c| **c >= limit) {
+        return Err(ScreenCaptureError::Other(
+            format!("core {core} of the thread affinity is not below {limit}").into(),
+        ));
+    }
+    let res = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for core in cores {
+            libc::CPU_SET(*core, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if res != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(ScreenCaptureError::Other(errno_detail(
+            format!("setting thread affinity to {cores:?} failed: {e}"),
+            &e,
+        )));
+    }
+    Ok(())
+}
+
+/// A named POSIX shared memory mapping, used by [`crate::shm`]. The creator removes the name
+/// when dropped.
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+pub struct SharedMemory {
+    ptr: *mut u8,
+    len: usize,
+    /// The name to remove when dropped, only for the creator.
+    owned: Option<std::ffi::CString>,
+}
+
+// The mapping is plain memory, its users synchronise the access to it.
+unsafe impl Send for SharedMemory {}
+
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+impl SharedMemory {
+    fn path(name: &str) -> std::io::Result<std::ffi::CString> {
+        std::ffi::CString::new(format!("/{name}")).map_err(std::io::Error::other)
+    }
+
+    /// Map the file descriptor, resizing it first if writable. The descriptor is closed.
+    unsafe fn map(fd: i32, len: usize, writable: bool) -> std::io::Result<*mut u8> {
+        let result = (|| {
+            if writable && libc::ftruncate(fd, len as libc::off_t) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let prot = if writable {
+                libc::PROT_READ | libc::PROT_WRITE
+            } else {
+                libc::PROT_READ
+            };
+            let ptr = libc::mmap(std::ptr::null_mut(), len, prot, libc::MAP_SHARED, fd, 0);
+            if ptr == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(ptr as *mut u8)
+        })();
+        libc::close(fd);
+        result
+    }
+
+    /// Create the mapping of `len` zeroed bytes, fails with [`std::io::ErrorKind::AlreadyExists`]
+    /// if the name is in use.
+    pub fn create(name: &str, len: usize) -> std::io::Result<SharedMemory> {
+        let path = Self::path(name)?;
+        unsafe {
+            let fd = libc::shm_open(
+                path.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600,
             );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            match Self::map(fd, len, true) {
+                Ok(ptr) => Ok(SharedMemory {
+                    ptr,
+                    len,
+                    owned: Some(path),
+                }),
+                Err(e) => {
+                    libc::shm_unlink(path.as_ptr());
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    /// Remove the name of a mapping that was left behind, mappings that are open stay valid.
+    pub fn remove(name: &str) -> std::io::Result<()> {
+        let path = Self::path(name)?;
+        if unsafe { libc::shm_unlink(path.as_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Map an existing mapping for reading.
+    pub fn open(name: &str) -> std::io::Result<SharedMemory> {
+        let path = Self::path(name)?;
+        unsafe {
+            let fd = libc::shm_open(path.as_ptr(), libc::O_RDONLY, 0);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut stat: libc::stat = std::mem::zeroed();
+            if libc::fstat(fd, &mut stat) != 0 {
+                let e = std::io::Error::last_os_error();
+                libc::close(fd);
+                return Err(e);
+            }
+            let len = stat.st_size as usize;
+            if len == 0 {
+                libc::close(fd);
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let ptr = Self::map(fd, len, false)?;
+            Ok(SharedMemory {
+                ptr,
+                len,
+                owned: None,
+            })
         }
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
 
-        Resolution { width, height }
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            if let Some(path) = &self.owned {
+                libc::shm_unlink(path.as_ptr());
+            }
+        }
     }
+}
+
+/// Whether the process with the id still exists.
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal zero only checks the process exists, it may belong to another user.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// An error event reported by the X server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct X11Error {
+    /// The address of the display connection the error occurred on.
+    display: usize,
+    serial: u64,
+    error_code: u8,
+    request_code: u8,
+    minor_code: u8,
+}
 
-    fn prepare_capture(&mut self, _display: u32, x: u32, y: u32, width: u32, height: u32) -> bool {
-        CaptureX11::prepare(self, x, y, width, height)
+impl X11Error {
+    /// Translate the error, `shm_error_base` is the first error code of the shared memory extension.
+    fn to_capture_error(self, shm_error_base: u8) -> ScreenCaptureError {
+        let detail = error::ErrorDetail::with_os_error(
+            format!(
+                "X11 request {}.{} (serial {}) failed",
+                self.request_code, self.minor_code, self.serial
+            ),
+            error::OsError::X11(self.error_code),
+        );
+        match self.error_code {
+            code if code == BadAccess => ScreenCaptureError::PermissionDenied(detail),
+            // The shared memory segment is gone, it has to be prepared again.
+            code if shm_error_base != 0 && code == shm_error_base + BadShmSeg => {
+                ScreenCaptureError::LostCapture(detail)
+            }
+            _ => ScreenCaptureError::Transient(detail),
+        }
     }
 }
 
+/// The maximum number of errors retained, the oldest are dropped beyond this.
+const X11_ERRORS_LIMIT: usize = 64;
+
+/// The error events that have not been taken yet, for all display connections.
+static X11_ERRORS: std::sync::Mutex<Vec<X11Error>> = std::sync::Mutex::new(vec![]);
+
+/// Remove and return the errors that occurred on the display connection.
+fn take_x11_errors(display: *mut Display) -> Vec<X11Error> {
+    let mut errors = X11_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    let (taken, kept) = errors
+        .drain(..)
+        .partition(|e| e.display == display as usize);
+    *errors = kept;
+    taken
+}
+
 unsafe extern "C" fn error_handler(_display: *mut Display, event: *mut XErrorEvent) -> i32 {
-    println!("Error: {:?}", event);
+    log::warn!("X11 error: {:?}", *event);
+    let event = &*event;
+    let mut errors = X11_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    if errors.len() >= X11_ERRORS_LIMIT {
+        errors.remove(0);
+    }
+    errors.push(X11Error {
+        display: event.display as usize,
+        serial: event.serial,
+        error_code: event.error_code,
+        request_code: event.request_code,
+        minor_code: event.minor_code,
+    });
     0
 }
 
-pub fn capture() -> Box<dyn Capture> {
+/// The display connections that were lost, by address.
+static X11_LOST: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(vec![]);
+
+unsafe extern "C" fn io_error_handler(display: *mut Display) -> i32 {
+    log::error!("X11 connection lost");
+    io_error_exit_handler(display, std::ptr::null_mut());
+    0
+}
+
+unsafe extern "C" fn io_error_exit_handler(display: *mut Display, _user_data: *mut libc::c_void) {
+    let mut lost = X11_LOST.lock().unwrap_or_else(|e| e.into_inner());
+    if !lost.contains(&(display as usize)) {
+        lost.push(display as usize);
+    }
+}
+
+/// Whether the peer closed the socket, without consuming any data from it.
+fn connection_closed(fd: i32) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut poll_fd, 1, 0) } <= 0 {
+        return false;
+    }
+    if poll_fd.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+        return true;
+    }
+    // Readable, peek to distinguish pending events from the end of the stream.
+    let mut byte = 0u8;
+    let res = unsafe {
+        libc::recv(
+            fd,
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    res == 0
+}
+
+/// Query the lock state of this session from logind.
+pub fn session_locked() -> Option<bool> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "self".to_owned());
+    let output = std::process::Command::new("loginctl")
+        .args(["show-session", &session, "--property=LockedHint", "--value"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// The region of the window on the screen, see [`CaptureSpecification::for_window`].
+#[cfg(feature = "raw-window-handle")]
+pub fn window_region(
+    window: raw_window_handle::RawWindowHandle,
+) -> Result<(i32, i32, u32, u32), ScreenCaptureError> {
+    use raw_window_handle::RawWindowHandle;
+    let window = match window {
+        RawWindowHandle::Xlib(handle) => handle.window as Window,
+        RawWindowHandle::Xcb(handle) => handle.window.get() as Window,
+        other => {
+            return Err(ScreenCaptureError::Other(
+                format!("capturing the window {other:?} isn't supported on X11").into(),
+            ))
+        }
+    };
+    unsafe {
+        // Without these an unknown window terminates the process.
+        XSetErrorHandler(error_handler);
+        XSetIOErrorHandler(io_error_handler);
+        let display = XOpenDisplay(std::ptr::null::<libc::c_char>());
+        if display.is_null() {
+            return Err(ScreenCaptureError::BackendUnavailable {
+                backend: "X11".into(),
+            });
+        }
+        let mut attributes = XWindowAttributes::default();
+        let (mut x, mut y, mut child) = (0, 0, 0);
+        let found = XGetWindowAttributes(display, window, &mut attributes) != 0
+            && XTranslateCoordinates(
+                display,
+                window,
+                attributes.root,
+                0,
+                0,
+                &mut x,
+                &mut y,
+                &mut child,
+            ) != 0;
+        take_x11_errors(display);
+        XCloseDisplay(display);
+        if !found {
+            return Err(ScreenCaptureError::Other(
+                format!("the window {window:#x} doesn't exist").into(),
+            ));
+        }
+        Ok((x, y, attributes.width as u32, attributes.height as u32))
+    }
+}
+
+/// A display connection moved to the thread serving the clipboard, which is its only user.
+#[cfg(feature = "png")]
+struct ClipboardOwner {
+    display: *mut Display,
+    window: Window,
+}
+
+#[cfg(feature = "png")]
+unsafe impl Send for ClipboardOwner {}
+
+/// A png that is sent in chunks with the INCR protocol, as it doesn't fit in a single request.
+/// The requestor deletes the property after reading each chunk.
+#[cfg(feature = "png")]
+struct IncrTransfer {
+    requestor: Window,
+    property: Atom,
+    /// The number of bytes sent so far.
+    sent: usize,
+}
+
+#[cfg(feature = "png")]
+impl ClipboardOwner {
+    /// Answer requests for the clipboard until another client takes it over.
+    unsafe fn serve(self, png: Vec<u8>) {
+        let atom = |name: &std::ffi::CStr| XInternAtom(self.display, name.as_ptr(), 0);
+        let (clipboard, targets, image_png, incr) = (
+            atom(c"CLIPBOARD"),
+            atom(c"TARGETS"),
+            atom(c"image/png"),
+            atom(c"INCR"),
+        );
+        // The maximum request size is in units of 4 bytes, leave room for the request itself.
+        let chunk = (XMaxRequestSize(self.display) as usize * 4).saturating_sub(64);
+        let mut transfers: Vec<IncrTransfer> = vec![];
+        let mut event = XEvent::default();
+        loop {
+            XNextEvent(self.display, &mut event);
+            if event.type_ == SelectionClear {
+                break;
+            }
+            if event.type_ == PropertyNotify {
+                self.continue_transfer(&mut transfers, event.property, &png, chunk, image_png);
+                continue;
+            }
+            if event.type_ != SelectionRequest {
+                continue;
+            }
+            let request = event.selection_request;
+            // Obsolete clients don't set the property to store the data in.
+            let property = match request.property {
+                0 => request.target,
+                v => v,
+            };
+            let stored = if request.selection != clipboard {
+                false
+            } else if request.target == targets {
+                let supported = [targets, image_png];
+                XChangeProperty(
+                    self.display,
+                    request.requestor,
+                    property,
+                    XA_ATOM,
+                    32,
+                    PropModeReplace,
+                    supported.as_ptr() as *const u8,
+                    supported.len() as i32,
+                );
+                true
+            } else if request.target == image_png && png.len() > chunk {
+                // Announce the size, the chunks follow as the requestor deletes the property.
+                XSelectInput(self.display, request.requestor, PropertyChangeMask);
+                let size = [png.len() as libc::c_long];
+                XChangeProperty(
+                    self.display,
+                    request.requestor,
+                    property,
+                    incr,
+                    32,
+                    PropModeReplace,
+                    size.as_ptr() as *const u8,
+                    1,
+                );
+                transfers.retain(|t| t.requestor != request.requestor || t.property != property);
+                transfers.push(IncrTransfer {
+                    requestor: request.requestor,
+                    property,
+                    sent: 0,
+                });
+                true
+            } else if request.target == image_png {
+                XChangeProperty(
+                    self.display,
+                    request.requestor,
+                    property,
+                    image_png,
+                    8,
+                    PropModeReplace,
+                    png.as_ptr(),
+                    png.len() as i32,
+                );
+                true
+            } else {
+                false
+            };
+            let mut reply = XEvent {
+                selection: XSelectionEvent {
+                    type_: SelectionNotify,
+                    serial: 0,
+                    send_event: 1,
+                    display: self.display,
+                    requestor: request.requestor,
+                    selection: request.selection,
+                    target: request.target,
+                    property: if stored { property } else { 0 },
+                    time: request.time,
+                },
+            };
+            XSendEvent(self.display, request.requestor, 0, 0, &mut reply);
+            XFlush(self.display);
+            take_x11_errors(self.display);
+        }
+        // Transfers in progress are abandoned, the requestors see no further chunks.
+        for transfer in transfers {
+            XSelectInput(self.display, transfer.requestor, NoEventMask);
+        }
+        XDestroyWindow(self.display, self.window);
+        take_x11_errors(self.display);
+        XCloseDisplay(self.display);
+    }
+
+    /// Send the next chunk of the transfer whose property was deleted, an empty chunk after the
+    /// last one ends the transfer.
+    unsafe fn continue_transfer(
+        &self,
+        transfers: &mut Vec<IncrTransfer>,
+        notify: XPropertyEvent,
+        png: &[u8],
+        chunk: usize,
+        image_png: Atom,
+    ) {
+        if notify.state != PropertyDelete {
+            return;
+        }
+        let Some(index) = transfers
+            .iter()
+            .position(|t| t.requestor == notify.window && t.property == notify.atom)
+        else {
+            return;
+        };
+        let transfer = &mut transfers[index];
+        let end = (transfer.sent + chunk).min(png.len());
+        XChangeProperty(
+            self.display,
+            transfer.requestor,
+            transfer.property,
+            image_png,
+            8,
+            PropModeReplace,
+            png[transfer.sent..end].as_ptr(),
+            (end - transfer.sent) as i32,
+        );
+        if transfer.sent == png.len() {
+            XSelectInput(self.display, transfer.requestor, NoEventMask);
+            transfers.remove(index);
+        } else {
+            transfer.sent = end;
+        }
+        XFlush(self.display);
+        take_x11_errors(self.display);
+    }
+}
+
+/// Own the clipboard with the image as png, see [`crate::util::copy_to_clipboard`].
+#[cfg(feature = "png")]
+pub fn copy_to_clipboard(img: &dyn ImageBGR) -> Result<(), ScreenCaptureError> {
+    let mut png = vec![];
+    crate::util::write_png_to(img, &mut png)
+        .map_err(|e| ScreenCaptureError::Other(format!("encoding the png failed: {e}").into()))?;
+    unsafe {
+        XSetErrorHandler(error_handler);
+        XSetIOErrorHandler(io_error_handler);
+        let display = XOpenDisplay(std::ptr::null::<libc::c_char>());
+        if display.is_null() {
+            return Err(ScreenCaptureError::BackendUnavailable {
+                backend: "X11".into(),
+            });
+        }
+        let root = XRootWindow(display, XDefaultScreen(display));
+        let window = XCreateSimpleWindow(display, root, 0, 0, 1, 1, 0, 0, 0);
+        let clipboard = XInternAtom(display, c"CLIPBOARD".as_ptr(), 0);
+        XSetSelectionOwner(display, clipboard, window, CurrentTime);
+        if XGetSelectionOwner(display, clipboard) != window {
+            XDestroyWindow(display, window);
+            take_x11_errors(display);
+            XCloseDisplay(display);
+            return Err(ScreenCaptureError::Other(
+                "taking ownership of the clipboard failed".into(),
+            ));
+        }
+        let owner = ClipboardOwner { display, window };
+        std::thread::Builder::new()
+            .name("clipboard".into())
+            .spawn(move || owner.serve(png))
+            .map_err(|e| {
+                ScreenCaptureError::Other(
+                    format!("spawning the clipboard thread failed: {e}").into(),
+                )
+            })?;
+    }
+    Ok(())
+}
+
+pub fn capture(options: &BackendOptions) -> Result<Box<dyn Capture + Send>, ScreenCaptureError> {
     unsafe {
         XSetErrorHandler(error_handler);
+        XSetIOErrorHandler(io_error_handler);
+    }
+    let mut z = Box::<CaptureX11>::new(CaptureX11::new()?);
+    z.strict_images = options.strict_images;
+    z.prepare(Default::default())?;
+    Ok(z)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_affinity_limit() {
+        let result = std::thread::spawn(|| set_thread_affinity(&[0, libc::CPU_SETSIZE as usize]))
+            .join()
+            .unwrap();
+        assert!(matches!(result, Err(ScreenCaptureError::Other(_))));
+    }
+
+    #[test]
+    fn test_capture_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CaptureX11>();
+    }
+
+    #[test]
+    fn test_stale_image() {
+        // The image is never dereferenced once it is stale.
+        let mut image = ImageX11 {
+            image: unsafe { XImageRef::new(std::ptr::null_mut()) },
+            poisoned: Arc::new(true.into()),
+            strict: false,
+        };
+        assert!(image.is_stale());
+        assert_eq!((image.width(), image.height()), (0, 0));
+        assert!(image.data().is_empty());
+        assert!(matches!(
+            image.try_data(),
+            Err(ScreenCaptureError::Transient(_))
+        ));
+        #[cfg(feature = "image")]
+        assert_eq!(image.to_rgba().dimensions(), (0, 0));
+
+        image.strict = true;
+        assert!(image.is_stale());
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| image.width()));
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_drop_poisons_image() {
+        // A lost connection without an image is never used by drop.
+        let capture = CaptureX11 {
+            display: std::ptr::NonNull::dangling().as_ptr(),
+            window: 0,
+            image: None,
+            image_poison: Arc::new(false.into()),
+            shminfo: Default::default(),
+            pos_x: 0,
+            pos_y: 0,
+            shm_error_base: 0,
+            strict_images: false,
+            prepared: Default::default(),
+            lost: true,
+        };
+        let image = ImageX11 {
+            image: unsafe { XImageRef::new(std::ptr::null_mut()) },
+            poisoned: Arc::clone(&capture.image_poison),
+            strict: false,
+        };
+        assert!(!image.is_stale());
+        drop(capture);
+        assert!(image.is_stale());
+        assert!(matches!(
+            image.try_data(),
+            Err(ScreenCaptureError::Transient(_))
+        ));
+    }
+
+    #[test]
+    fn test_ximage_ref() {
+        // Two rows of two pixels, padded to three pixels.
+        let mut memory: Vec<BGR> = (0..6u8).map(|v| BGR { r: v, g: 0, b: 0 }).collect();
+        // Only the fields that are read are initialised.
+        let mut ximage = std::mem::MaybeUninit::<XImage>::zeroed();
+        let ptr = ximage.as_mut_ptr();
+        unsafe {
+            (*ptr).width = 2;
+            (*ptr).height = 2;
+            (*ptr).bytes_per_line = 12;
+            (*ptr).bits_per_pixel = 32;
+            (*ptr).data = memory.as_mut_ptr() as *mut libc::c_char;
+        }
+        let image = unsafe { XImageRef::new(ptr) };
+        assert_eq!((image.width(), image.height(), image.stride()), (2, 2, 12));
+        let pixels = image.pixels().unwrap();
+        assert_eq!(pixels.row(1), &memory[3..5]);
+        assert_eq!(pixels.pixel(1, 1).r, 4);
+        assert!(pixels.contiguous().is_none());
+
+        unsafe { (*ptr).bits_per_pixel = 24 };
+        assert!(matches!(
+            image.pixels(),
+            Err(ScreenCaptureError::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn test_connection_closed() {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+        let (mut ours, theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(!connection_closed(theirs.as_raw_fd()));
+        // Pending data is not a closed connection and is left in place.
+        ours.write_all(&[1]).unwrap();
+        assert!(!connection_closed(theirs.as_raw_fd()));
+        assert!(!connection_closed(theirs.as_raw_fd()));
+        drop(ours);
+        assert!(connection_closed(theirs.as_raw_fd()));
+    }
+
+    #[test]
+    fn test_x11_errors() {
+        let error = |display: usize, error_code: u8| X11Error {
+            display,
+            serial: 1,
+            error_code,
+            request_code: 130,
+            minor_code: 4,
+        };
+        X11_ERRORS.lock().unwrap().extend([
+            error(0x1000, BadAccess),
+            error(0x2000, 128),
+            error(0x1000, 8),
+        ]);
+
+        let first = take_x11_errors(0x1000 as *mut Display);
+        assert_eq!(first, vec![error(0x1000, BadAccess), error(0x1000, 8)]);
+        assert!(take_x11_errors(0x1000 as *mut Display).is_empty());
+        let second = take_x11_errors(0x2000 as *mut Display);
+        assert_eq!(second.len(), 1);
+
+        assert!(matches!(
+            first[0].to_capture_error(128),
+            ScreenCaptureError::PermissionDenied(_)
+        ));
+        let e = second[0].to_capture_error(128);
+        assert!(matches!(e, ScreenCaptureError::LostCapture(_)));
+        assert_eq!(e.os_error(), Some(error::OsError::X11(128)));
+        assert!(matches!(
+            first[1].to_capture_error(128),
+            ScreenCaptureError::Transient(_)
+        ));
     }
-    let mut z = Box::<CaptureX11>::new(CaptureX11::new());
-    z.prepare(0, 0, 0, 0);
-    z
 }
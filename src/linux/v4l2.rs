@@ -0,0 +1,52 @@
+#![allow(non_camel_case_types)]
+// From linux/videodev2.h, only what is needed to set the format of an output device.
+
+pub const V4L2_BUF_TYPE_VIDEO_OUTPUT: u32 = 2;
+pub const V4L2_FIELD_NONE: u32 = 1;
+/// BT.601, as used by [`crate::ImageBGR::to_i420`].
+pub const V4L2_COLORSPACE_SMPTE170M: u32 = 1;
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+pub const V4L2_PIX_FMT_YUYV: u32 = fourcc(b"YUYV");
+pub const V4L2_PIX_FMT_NV12: u32 = fourcc(b"NV12");
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct v4l2_pix_format {
+    pub width: u32,
+    pub height: u32,
+    pub pixelformat: u32,
+    pub field: u32,
+    pub bytesperline: u32,
+    pub sizeimage: u32,
+    pub colorspace: u32,
+    pub priv_: u32,
+    pub flags: u32,
+    pub ycbcr_enc: u32,
+    pub quantization: u32,
+    pub xfer_func: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union v4l2_format_fmt {
+    pub pix: v4l2_pix_format,
+    pub raw_data: [u8; 200],
+    // Some members of the union hold pointers, which determine its alignment.
+    _align: [*mut libc::c_void; 0],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct v4l2_format {
+    pub type_: u32,
+    pub fmt: v4l2_format_fmt,
+}
+
+/// The `_IOWR` ioctl request number, from asm-generic/ioctl.h.
+const fn iowr(kind: u8, number: u8, size: usize) -> libc::c_ulong {
+    3 << 30 | (size as libc::c_ulong) << 16 | (kind as libc::c_ulong) << 8 | number as libc::c_ulong
+}
+pub const VIDIOC_S_FMT: libc::c_ulong = iowr(b'V', 5, std::mem::size_of::<v4l2_format>());
@@ -0,0 +1,231 @@
+//! Sending frames over the network with [NDI](https://ndi.video), such that a captured region can
+//! be picked as source in video production tools like OBS or vMix.
+//!
+//! The NDI runtime is not linked, it is loaded when the sender is created. It is searched in the
+//! directory given by the `NDI_RUNTIME_DIR_V6` environment variable, which its installer sets on
+//! Windows, and then in the default library paths.
+#![allow(non_camel_case_types)]
+use crate::{CallbackHandle, ImageBGR, ThreadedCapturer, BGR};
+use std::ffi::{c_char, c_void, CString};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// From Processing.NDI.Send.h and Processing.NDI.structs.h, only what is needed to send video.
+
+#[repr(C)]
+struct NDIlib_send_create_t {
+    p_ndi_name: *const c_char,
+    p_groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+#[repr(C)]
+struct NDIlib_video_frame_v2_t {
+    xres: i32,
+    yres: i32,
+    fourcc: u32,
+    frame_rate_n: i32,
+    frame_rate_d: i32,
+    picture_aspect_ratio: f32,
+    frame_format_type: i32,
+    timecode: i64,
+    p_data: *const u8,
+    line_stride_in_bytes: i32,
+    p_metadata: *const c_char,
+    timestamp: i64,
+}
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24
+}
+/// Four bytes per pixel with the last one ignored, which is the layout of [`BGR`].
+const NDILIB_FOURCC_VIDEO_TYPE_BGRX: u32 = fourcc(b"BGRX");
+const NDILIB_FRAME_FORMAT_TYPE_PROGRESSIVE: i32 = 1;
+
+type NDIlib_send_instance_t = *mut c_void;
+
+#[cfg(target_os = "windows")]
+const LIBRARY: &str = "Processing.NDI.Lib.x64.dll";
+#[cfg(target_os = "macos")]
+const LIBRARY: &str = "libndi.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY: &str = "libndi.so.6";
+
+/// The functions of the runtime, valid while the library is loaded.
+struct Runtime {
+    destroy: unsafe extern "C" fn(),
+    send_create: unsafe extern "C" fn(*const NDIlib_send_create_t) -> NDIlib_send_instance_t,
+    send_destroy: unsafe extern "C" fn(NDIlib_send_instance_t),
+    send_send_video_v2:
+        unsafe extern "C" fn(NDIlib_send_instance_t, *const NDIlib_video_frame_v2_t),
+    _library: libloading::Library,
+}
+
+impl Runtime {
+    fn load() -> std::io::Result<Runtime> {
+        let not_found = |e: libloading::Error| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("the NDI runtime {LIBRARY} can't be loaded: {e}"),
+            )
+        };
+        let library = unsafe {
+            match std::env::var_os("NDI_RUNTIME_DIR_V6") {
+                Some(dir) => libloading::Library::new(std::path::Path::new(&dir).join(LIBRARY))
+                    .or_else(|_| libloading::Library::new(LIBRARY)),
+                None => libloading::Library::new(LIBRARY),
+            }
+        }
+        .map_err(not_found)?;
+        unsafe {
+            let initialize = *library
+                .get::<unsafe extern "C" fn() -> bool>(b"NDIlib_initialize\0")
+                .map_err(not_found)?;
+            let runtime = Runtime {
+                destroy: *library.get(b"NDIlib_destroy\0").map_err(not_found)?,
+                send_create: *library.get(b"NDIlib_send_create\0").map_err(not_found)?,
+                send_destroy: *library.get(b"NDIlib_send_destroy\0").map_err(not_found)?,
+                send_send_video_v2: *library
+                    .get(b"NDIlib_send_send_video_v2\0")
+                    .map_err(not_found)?,
+                _library: library,
+            };
+            if !initialize() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "NDI is not supported on this cpu",
+                ));
+            }
+            Ok(runtime)
+        }
+    }
+}
+
+/// The NDI timecode, in units of 100 nanoseconds since the unix epoch.
+fn timecode(time: SystemTime) -> i64 {
+    let since = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    (since.as_nanos() / 100) as i64
+}
+
+/// An NDI source on the local network, see the [module](self) documentation.
+pub struct NdiSender {
+    runtime: Runtime,
+    instance: NDIlib_send_instance_t,
+    frame_rate: (i32, i32),
+}
+
+// The NDI send functions may be called from any thread, though not concurrently.
+unsafe impl Send for NdiSender {}
+
+impl NdiSender {
+    /// Create a source with the name, which is shown in the receivers together with the name of
+    /// this machine.
+    pub fn new(name: &str) -> std::io::Result<NdiSender> {
+        let runtime = Runtime::load()?;
+        let name = CString::new(name)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let settings = NDIlib_send_create_t {
+            p_ndi_name: name.as_ptr(),
+            p_groups: std::ptr::null(),
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = unsafe { (runtime.send_create)(&settings) };
+        if instance.is_null() {
+            unsafe { (runtime.destroy)() };
+            return Err(std::io::Error::other("creating the NDI sender failed"));
+        }
+        Ok(NdiSender {
+            runtime,
+            instance,
+            frame_rate: (30, 1),
+        })
+    }
+
+    /// Set the frame rate the receivers are told about, frames are sent as they are provided.
+    pub fn set_frame_rate(&mut self, rate: f32) {
+        if rate > 0.0 {
+            self.frame_rate = ((rate * 1000.0).round() as i32, 1000);
+        }
+    }
+
+    /// Send a frame captured at the provided time.
+    pub fn send_frame(&mut self, img: &dyn ImageBGR, time: SystemTime) {
+        let frame = video_frame(img, time, self.frame_rate);
+        // This is the synchronous variant, the data is no longer used once it returns.
+        unsafe { (self.runtime.send_send_video_v2)(self.instance, &frame) };
+    }
+
+    /// Send the frames of the capturer from its post callback, until that is removed. The frame
+    /// rate is taken from the configuration of the capturer.
+    pub fn attach(mut self, capturer: &ThreadedCapturer) -> CallbackHandle {
+        self.set_frame_rate(capturer.config().rate);
+        let sender = Mutex::new(self);
+        capturer.add_post_callback(Arc::new(move |info| {
+            let Ok(frame) = info.result else {
+                return;
+            };
+            sender
+                .lock()
+                .unwrap()
+                .send_frame(&frame.to_bgr(), info.time);
+        }))
+    }
+}
+
+impl Drop for NdiSender {
+    fn drop(&mut self) {
+        unsafe {
+            (self.runtime.send_destroy)(self.instance);
+            (self.runtime.destroy)();
+        }
+    }
+}
+
+/// The description of the image as NDI frame, it borrows the data of the image.
+fn video_frame(
+    img: &dyn ImageBGR,
+    time: SystemTime,
+    frame_rate: (i32, i32),
+) -> NDIlib_video_frame_v2_t {
+    NDIlib_video_frame_v2_t {
+        xres: img.width() as i32,
+        yres: img.height() as i32,
+        fourcc: NDILIB_FOURCC_VIDEO_TYPE_BGRX,
+        frame_rate_n: frame_rate.0,
+        frame_rate_d: frame_rate.1,
+        picture_aspect_ratio: 0.0,
+        frame_format_type: NDILIB_FRAME_FORMAT_TYPE_PROGRESSIVE,
+        timecode: timecode(time),
+        p_data: img.data().as_ptr() as *const u8,
+        line_stride_in_bytes: (img.width() as usize * std::mem::size_of::<BGR>()) as i32,
+        p_metadata: std::ptr::null(),
+        timestamp: 0,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::raster_image::RasterImageBGR;
+    use std::time::Duration;
+
+    #[test]
+    fn test_video_frame() {
+        let img = RasterImageBGR::filled(3, 2, BGR { r: 1, g: 2, b: 3 });
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1500);
+        let frame = video_frame(&img, time, (60000, 1000));
+        assert_eq!(
+            (frame.xres, frame.yres, frame.line_stride_in_bytes),
+            (3, 2, 12)
+        );
+        assert_eq!(frame.timecode, 15_000_000);
+        assert_eq!(frame.fourcc.to_le_bytes(), *b"BGRX");
+        // Only the first three bytes of a pixel are initialised.
+        let pixel = unsafe { std::slice::from_raw_parts(frame.p_data, 3) };
+        assert_eq!(pixel, &[3, 2, 1]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Safe access to the pixels of an image in memory owned by a backend, like a shared memory
+//! segment or a mapped texture, such that the image implementations need no pointer arithmetic.
+use crate::{ScreenCaptureError, BGR};
+
+/// Rows of 4 byte BGR pixels, each row may be followed by padding.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct RawPixels<'a> {
+    /// All rows, the last one without its padding.
+    pixels: &'a [BGR],
+    width: u32,
+    height: u32,
+    /// The number of pixels from the start of one row to the next.
+    stride: usize,
+}
+
+impl<'a> RawPixels<'a> {
+    /// Wrap the memory at `data`, with rows that are `stride` bytes apart.
+    ///
+    /// Fails if the pointer is null or not aligned to 4 bytes, or if the stride is shorter than a
+    /// row or not a multiple of 4 bytes.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads of `stride * height` bytes and must not be written
+    /// to for the lifetime `'a`.
+    pub(crate) unsafe fn from_raw(
+        data: *const u8,
+        width: u32,
+        height: u32,
+        stride: usize,
+    ) -> Result<RawPixels<'a>, ScreenCaptureError> {
+        let pixel_size = std::mem::size_of::<BGR>();
+        let invalid =
+            |what: &str| ScreenCaptureError::Other(format!("invalid image memory: {what}").into());
+        if width == 0 || height == 0 {
+            return Ok(RawPixels {
+                pixels: &[],
+                width,
+                height,
+                stride: width as usize,
+            });
+        }
+        if data.is_null() {
+            return Err(invalid("null pointer"));
+        }
+        if !(data as usize).is_multiple_of(std::mem::align_of::<BGR>()) {
+            return Err(invalid("misaligned pointer"));
+        }
+        if stride < width as usize * pixel_size || !stride.is_multiple_of(pixel_size) {
+            return Err(invalid("stride doesn't fit the width"));
+        }
+        let stride = stride / pixel_size;
+        let len = (height as usize - 1) * stride + width as usize;
+        // The checks above and the contract of this function make this slice valid.
+        let pixels = std::slice::from_raw_parts(data as *const BGR, len);
+        Ok(RawPixels {
+            pixels,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    /// The pixels of the row, without the padding.
+    pub(crate) fn row(&self, y: u32) -> &'a [BGR] {
+        let start = y as usize * self.stride;
+        &self.pixels[start..start + self.width as usize]
+    }
+
+    pub(crate) fn pixel(&self, x: u32, y: u32) -> BGR {
+        if x >= self.width || y >= self.height {
+            panic!("Retrieved out of bounds ({}, {})", x, y);
+        }
+        self.row(y)[x as usize]
+    }
+
+    /// All pixels as one slice, `None` if the rows are padded.
+    pub(crate) fn contiguous(&self) -> Option<&'a [BGR]> {
+        (self.stride == self.width as usize).then_some(self.pixels)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_pixels() {
+        // Three rows of two pixels, padded to three pixels.
+        let memory: Vec<BGR> = (0..9u8).map(|v| BGR { r: v, g: 0, b: 0 }).collect();
+        let data = memory.as_ptr() as *const u8;
+        let pixels = unsafe { RawPixels::from_raw(data, 2, 3, 12) }.unwrap();
+        assert_eq!(pixels.row(1), &memory[3..5]);
+        assert_eq!(pixels.pixel(1, 2).r, 7);
+        assert!(pixels.contiguous().is_none());
+
+        let pixels = unsafe { RawPixels::from_raw(data, 3, 3, 12) }.unwrap();
+        assert_eq!(pixels.contiguous(), Some(&memory[..]));
+
+        let empty = unsafe { RawPixels::from_raw(std::ptr::null(), 0, 0, 0) }.unwrap();
+        assert_eq!(empty.contiguous(), Some(&[][..]));
+
+        assert!(unsafe { RawPixels::from_raw(std::ptr::null(), 2, 3, 12) }.is_err());
+        assert!(unsafe { RawPixels::from_raw(data, 4, 3, 12) }.is_err());
+        assert!(unsafe { RawPixels::from_raw(data, 2, 3, 10) }.is_err());
+        assert!(unsafe { RawPixels::from_raw(data.wrapping_add(1), 1, 1, 4) }.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_raw_pixels_bounds() {
+        let memory = [BGR::default(); 4];
+        let pixels = unsafe { RawPixels::from_raw(memory.as_ptr() as *const u8, 2, 2, 8) }.unwrap();
+        pixels.pixel(2, 0);
+    }
+}
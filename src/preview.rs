@@ -0,0 +1,232 @@
+//! Live previews of the captured frames in egui and iced, updated from the latest
+//! [`CaptureInfo`] of a [`ThreadedCapturer`](crate::ThreadedCapturer) each time the user
+//! interface is drawn.
+//!
+//! Only the region that changed since the previous frame is converted, see
+//! [`OwnedFrame::damage`]. The egui texture is updated in place with just that region, iced
+//! images can't be updated partially, so a new image is made only if anything changed.
+use crate::capturer::CaptureInfo;
+use crate::{OwnedFrame, Rect};
+use std::sync::Arc;
+
+/// The frame shown, to determine what changed in the next one.
+#[derive(Default)]
+struct Shown {
+    counter: Option<usize>,
+    frame: Option<Arc<OwnedFrame>>,
+}
+
+impl Shown {
+    /// The new frame and the region of it that changed, `None` if the capture failed, was shown
+    /// already or nothing changed. The region is the entire frame if its size changed.
+    fn update(&mut self, info: &CaptureInfo) -> Option<(Arc<OwnedFrame>, Rect)> {
+        let frame = info.result.as_ref().ok()?;
+        if self.counter.replace(info.counter) == Some(info.counter) {
+            return None;
+        }
+        let damage = match self.frame.replace(frame.clone()) {
+            Some(previous) => frame.damage(&previous)?,
+            None => Rect {
+                x: 0,
+                y: 0,
+                width: frame.width(),
+                height: frame.height(),
+            },
+        };
+        Some((frame.clone(), damage))
+    }
+}
+
+/// The rgba pixels of a region of the frame.
+fn rgba(frame: &OwnedFrame, region: Rect) -> image::RgbaImage {
+    let Rect {
+        x,
+        y,
+        width,
+        height,
+    } = region;
+    match frame.as_rgba() {
+        Some(rgba) => image::imageops::crop_imm(rgba, x, y, width, height).to_image(),
+        None => image::imageops::crop_imm(&frame.to_rgba(), x, y, width, height).to_image(),
+    }
+}
+
+/// A preview in an egui texture, see the [module](self) documentation.
+#[cfg(feature = "egui")]
+pub struct EguiPreview {
+    name: String,
+    options: egui::TextureOptions,
+    texture: Option<egui::TextureHandle>,
+    shown: Shown,
+}
+
+#[cfg(feature = "egui")]
+impl EguiPreview {
+    /// Create a preview, the texture is named for debugging and created with the first frame.
+    pub fn new(name: impl Into<String>) -> Self {
+        EguiPreview {
+            name: name.into(),
+            options: egui::TextureOptions::LINEAR,
+            texture: None,
+            shown: Default::default(),
+        }
+    }
+
+    /// Set the sampling options of the texture.
+    pub fn with_options(mut self, options: egui::TextureOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Update the texture with the capture, returns the texture, `None` until a frame was
+    /// captured.
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        info: &CaptureInfo,
+    ) -> Option<&egui::TextureHandle> {
+        if let Some((frame, damage)) = self.shown.update(info) {
+            let size = [frame.width() as usize, frame.height() as usize];
+            let pixels = rgba(&frame, damage);
+            let image = egui::ColorImage::from_rgba_unmultiplied(
+                [pixels.width() as usize, pixels.height() as usize],
+                pixels.as_raw(),
+            );
+            match self.texture.as_mut() {
+                Some(texture) if texture.size() == size => {
+                    texture.set_partial([damage.x as usize, damage.y as usize], image, self.options)
+                }
+                _ => self.texture = Some(ctx.load_texture(&self.name, image, self.options)),
+            }
+        }
+        self.texture.as_ref()
+    }
+
+    /// The texture, `None` until a frame was captured.
+    pub fn texture(&self) -> Option<&egui::TextureHandle> {
+        self.texture.as_ref()
+    }
+}
+
+/// A preview as an iced image handle, see the [module](self) documentation.
+#[cfg(feature = "iced")]
+#[derive(Default)]
+pub struct IcedPreview {
+    handle: Option<iced_core::image::Handle>,
+    shown: Shown,
+}
+
+#[cfg(feature = "iced")]
+impl IcedPreview {
+    /// Create a preview, the image is made with the first frame.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Update the image with the capture, returns the image, `None` until a frame was captured.
+    /// The handle stays the same if nothing changed, such that iced doesn't upload it again.
+    pub fn update(&mut self, info: &CaptureInfo) -> Option<&iced_core::image::Handle> {
+        if let Some((frame, _)) = self.shown.update(info) {
+            let pixels = frame.to_rgba();
+            self.handle = Some(iced_core::image::Handle::from_rgba(
+                pixels.width(),
+                pixels.height(),
+                pixels.into_raw(),
+            ));
+        }
+        self.handle.as_ref()
+    }
+
+    /// The image, `None` until a frame was captured.
+    pub fn handle(&self) -> Option<&iced_core::image::Handle> {
+        self.handle.as_ref()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::raster_image::RasterImageBGR;
+    use crate::{OutputFormat, BGR};
+
+    fn capture(counter: usize, frame: &OwnedFrame) -> CaptureInfo {
+        CaptureInfo {
+            result: Ok(Arc::new(frame.clone())),
+            counter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_shown() {
+        let img = RasterImageBGR::filled(4, 3, BGR { r: 1, g: 2, b: 3 });
+        let mut frame = OwnedFrame::from_image(&img, OutputFormat::Rgb);
+        let mut shown = Shown::default();
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 3,
+        };
+        assert_eq!(shown.update(&capture(1, &frame)).unwrap().1, whole);
+        // The same capture again, or another one without changes.
+        assert!(shown.update(&capture(1, &frame)).is_none());
+        assert!(shown.update(&capture(2, &frame)).is_none());
+        assert!(shown.update(&CaptureInfo::default()).is_none());
+
+        let changed = Rect {
+            x: 1,
+            y: 2,
+            width: 2,
+            height: 1,
+        };
+        frame.fill_black(changed);
+        assert_eq!(shown.update(&capture(3, &frame)).unwrap().1, changed);
+        let region = rgba(&frame, changed);
+        assert_eq!((region.width(), region.height()), (2, 1));
+        assert_eq!(region.get_pixel(0, 0), &image::Rgba([0, 0, 0, 255]));
+    }
+
+    #[cfg(feature = "egui")]
+    #[test]
+    fn test_egui_preview() {
+        let ctx = egui::Context::default();
+        let img = RasterImageBGR::filled(4, 3, BGR { r: 1, g: 2, b: 3 });
+        let mut frame = OwnedFrame::from_image(&img, OutputFormat::Bgr);
+        let mut preview = EguiPreview::new("preview");
+        let texture = preview.update(&ctx, &capture(1, &frame)).unwrap();
+        assert_eq!(texture.size(), [4, 3]);
+        let id = texture.id();
+
+        frame.fill_black(Rect {
+            x: 2,
+            y: 1,
+            width: 1,
+            height: 1,
+        });
+        preview.update(&ctx, &capture(2, &frame));
+        let mut delta = ctx.tex_manager().write().take_delta();
+        let last = delta.set[&id].last().unwrap();
+        assert_eq!(last.pos, Some([2, 1]));
+        assert_eq!(last.image.size(), [1, 1]);
+        delta.clear();
+    }
+
+    #[cfg(feature = "iced")]
+    #[test]
+    fn test_iced_preview() {
+        let img = RasterImageBGR::filled(4, 3, BGR { r: 1, g: 2, b: 3 });
+        let frame = OwnedFrame::from_image(&img, OutputFormat::Rgba);
+        let mut preview = IcedPreview::new();
+        let id = preview.update(&capture(1, &frame)).unwrap().id();
+        assert_eq!(preview.update(&capture(2, &frame)).unwrap().id(), id);
+        let mut frame = frame.clone();
+        frame.fill_black(Rect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        });
+        assert_ne!(preview.update(&capture(3, &frame)).unwrap().id(), id);
+    }
+}
@@ -0,0 +1,246 @@
+//! The `screen_capture` Python module, with the frames as numpy arrays.
+//!
+//! Frames are arrays of `height` by `width` by channels of `uint8`, in the order of the format;
+//! `"rgba"`, `"rgb"` or `"bgr"`. The rgba and rgb frames of the threaded capturer are shared
+//! with the capturer instead of copied, these arrays are read only.
+//!
+//! Build the module with `maturin build --release`, which uses the configuration in
+//! `pyproject.toml`.
+use crate::{CaptureConfig, CaptureSpecification, ConfigError, ImageBGR, OutputFormat, OwnedFrame};
+use numpy::ndarray::ArrayView3;
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Arc;
+
+pyo3::create_exception!(
+    screen_capture,
+    CaptureError,
+    PyException,
+    "A capture failed, the message describes the ScreenCaptureError."
+);
+
+fn capture_error(error: crate::ScreenCaptureError) -> PyErr {
+    CaptureError::new_err(error.to_string())
+}
+
+fn config_error(error: ConfigError) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+/// Parse the name of an output format.
+fn parse_format(format: &str) -> PyResult<OutputFormat> {
+    match format {
+        "rgba" => Ok(OutputFormat::Rgba),
+        "rgb" => Ok(OutputFormat::Rgb),
+        "bgr" => Ok(OutputFormat::Bgr),
+        _ => Err(PyValueError::new_err(format!(
+            "unknown format {format:?}, expected \"rgba\", \"rgb\" or \"bgr\""
+        ))),
+    }
+}
+
+/// Create the configuration from the arguments of the constructors, the arguments override the
+/// configuration file if one is given.
+fn make_config(
+    config: Option<&str>,
+    rate: Option<f32>,
+    display: u32,
+    region: Option<(i32, i32, u32, u32)>,
+    format: &str,
+) -> PyResult<CaptureConfig> {
+    let mut result = match config {
+        Some(path) => CaptureConfig::load(path).map_err(config_error)?,
+        None => CaptureConfig::default(),
+    };
+    if let Some(rate) = rate {
+        result.rate = rate;
+    }
+    if let Some((x, y, width, height)) = region {
+        result.capture = vec![CaptureSpecification {
+            display,
+            x,
+            y,
+            width,
+            height,
+            ..Default::default()
+        }];
+    } else if config.is_none() {
+        result.capture = vec![CaptureSpecification {
+            display,
+            ..Default::default()
+        }];
+    }
+    result.output_format = parse_format(format)?;
+    Ok(result)
+}
+
+/// The number of channels of a format.
+fn channels(format: OutputFormat) -> usize {
+    match format {
+        OutputFormat::Rgba => 4,
+        OutputFormat::Rgb | OutputFormat::Bgr => 3,
+    }
+}
+
+/// Move the frame into an array, only the bgr frames are copied.
+fn owned_array(py: Python<'_>, frame: OwnedFrame) -> PyResult<Bound<'_, PyArray3<u8>>> {
+    let shape = [
+        frame.height() as usize,
+        frame.width() as usize,
+        channels(frame.format()),
+    ];
+    let data = match frame {
+        OwnedFrame::Rgba(v) => v.into_raw(),
+        OwnedFrame::Rgb(v) => v.into_raw(),
+        OwnedFrame::Bgr(v) => v.data().iter().flat_map(|p| [p.b, p.g, p.r]).collect(),
+    };
+    PyArray1::from_vec(py, data).reshape(shape)
+}
+
+/// Keeps a frame shared with the capturer alive for the arrays that borrow it.
+#[pyclass(frozen)]
+struct SharedFrame {
+    _frame: Arc<OwnedFrame>,
+}
+
+/// An array of the frame, shared with the capturer if possible.
+fn shared_array(py: Python<'_>, frame: Arc<OwnedFrame>) -> PyResult<Bound<'_, PyArray3<u8>>> {
+    let raw = match &*frame {
+        OwnedFrame::Rgba(v) => v.as_raw().as_slice(),
+        OwnedFrame::Rgb(v) => v.as_raw().as_slice(),
+        OwnedFrame::Bgr(_) => return owned_array(py, (*frame).clone()),
+    };
+    let shape = (
+        frame.height() as usize,
+        frame.width() as usize,
+        channels(frame.format()),
+    );
+    let view =
+        ArrayView3::from_shape(shape, raw).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let container = Bound::new(
+        py,
+        SharedFrame {
+            _frame: frame.clone(),
+        },
+    )?;
+    // The container owns the frame, which is never modified while it is shared.
+    let array = unsafe { PyArray3::borrow_from_array(&view, container.into_any()) };
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("write", false)?;
+    array.call_method("setflags", (), Some(&kwargs))?;
+    Ok(array)
+}
+
+/// Captures a region of a display on demand, see [`crate::Capturer`].
+#[pyclass(unsendable, name = "Capturer")]
+struct PyCapturer {
+    capturer: crate::Capturer,
+}
+
+#[pymethods]
+impl PyCapturer {
+    #[new]
+    #[pyo3(signature = (*, config=None, display=0, region=None, format="rgba"))]
+    fn new(
+        config: Option<&str>,
+        display: u32,
+        region: Option<(i32, i32, u32, u32)>,
+        format: &str,
+    ) -> PyResult<Self> {
+        let config = make_config(config, None, display, region, format)?;
+        let capturer = crate::Capturer::new(config).map_err(capture_error)?;
+        Ok(PyCapturer { capturer })
+    }
+
+    /// Capture a frame, in the format of the configuration.
+    fn capture<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let format = self.capturer.output_format();
+        let image = self.capturer.capture().map_err(capture_error)?;
+        owned_array(py, OwnedFrame::from_image(&*image, format))
+    }
+}
+
+/// Captures in a background thread at a fixed rate, see [`crate::ThreadedCapturer`].
+#[pyclass(unsendable, name = "ThreadedCapturer")]
+struct PyThreadedCapturer {
+    capturer: Option<crate::ThreadedCapturer>,
+}
+
+impl PyThreadedCapturer {
+    fn capturer(&self) -> PyResult<&crate::ThreadedCapturer> {
+        self.capturer
+            .as_ref()
+            .ok_or_else(|| CaptureError::new_err("the capturer is stopped"))
+    }
+}
+
+#[pymethods]
+impl PyThreadedCapturer {
+    #[new]
+    #[pyo3(signature = (*, config=None, rate=None, display=0, region=None, format="rgba"))]
+    fn new(
+        config: Option<&str>,
+        rate: Option<f32>,
+        display: u32,
+        region: Option<(i32, i32, u32, u32)>,
+        format: &str,
+    ) -> PyResult<Self> {
+        let config = make_config(config, rate, display, region, format)?;
+        Ok(PyThreadedCapturer {
+            capturer: Some(crate::ThreadedCapturer::new(config)),
+        })
+    }
+
+    /// The latest frame and its counter, as `(frame, counter)`.
+    fn latest<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyArray3<u8>>, usize)> {
+        let latest = self.capturer()?.latest();
+        let frame = latest.result.map_err(capture_error)?;
+        Ok((shared_array(py, frame)?, latest.counter))
+    }
+
+    /// Set the capture rate in Hz.
+    fn set_rate(&self, rate: f32) -> PyResult<()> {
+        let capturer = self.capturer()?;
+        let mut config = capturer.config();
+        config.rate = rate;
+        capturer.set_config(config);
+        Ok(())
+    }
+
+    /// Stop the capture thread, the capturer can't be used afterwards.
+    fn stop(&mut self, py: Python<'_>) -> PyResult<()> {
+        if let Some(capturer) = self.capturer.take() {
+            py.detach(|| capturer.stop())
+                .map_err(|e| CaptureError::new_err(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[pymodule]
+fn screen_capture(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyCapturer>()?;
+    m.add_class::<PyThreadedCapturer>()?;
+    m.add("CaptureError", m.py().get_type::<CaptureError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_config() {
+        let config = make_config(None, Some(5.0), 1, Some((-100, 0, 100, 50)), "bgr").unwrap();
+        assert_eq!(config.rate, 5.0);
+        assert_eq!(config.output_format, OutputFormat::Bgr);
+        assert_eq!(config.capture.len(), 1);
+        assert_eq!(config.capture[0].display, 1);
+        assert_eq!(config.capture[0].x, -100);
+        assert_eq!(config.capture[0].height, 50);
+        assert_eq!(channels(config.output_format), 3);
+        assert!(parse_format("yuv").is_err());
+    }
+}
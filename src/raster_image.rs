@@ -2,7 +2,7 @@
 use crate::*;
 
 /// Raster image, an image owning all pixels that are in it.
-#[derive(Default)]
+#[derive(Default, Clone, PartialEq)]
 pub struct RasterImageBGR {
     width: u32,
     height: u32,
@@ -29,11 +29,21 @@ impl RasterImageBGR {
 
         // The fastest copy ever.
 
-        return RasterImageBGR {
+        RasterImageBGR {
             width,
             height,
             data: img.data().to_vec(),
-        };
+        }
+    }
+
+    /// Create a raster image from the pixels, row by row.
+    pub fn from_data(width: u32, height: u32, data: Vec<BGR>) -> RasterImageBGR {
+        assert_eq!(data.len(), width as usize * height as usize);
+        RasterImageBGR {
+            width,
+            height,
+            data,
+        }
     }
 
     /// Create a new raster image of specified width and height, filled with the provided color.
@@ -64,7 +74,7 @@ impl RasterImageBGR {
     pub fn from_2d_vec(data: &[Vec<BGR>]) -> RasterImageBGR {
         let height = data.len() as u32;
         let width = data
-            .get(0)
+            .first()
             .expect("image should have at least one row")
             .len() as u32;
         let mut res: RasterImageBGR = RasterImageBGR {
@@ -142,6 +152,12 @@ impl ImageBGR for RasterImageBGR {
     }
 }
 
+impl std::fmt::Debug for RasterImageBGR {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "RasterImageBGR<{}x{}>", self.width, self.height)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -0,0 +1,1617 @@
+//! Recording of raw frames to disk and reading them back, for example to reproduce a capture
+//! problem with the [`ReplayCapture`](crate::synthetic::ReplayCapture) backend.
+//!
+//! A recording consists of two files, the data file holds the frames as raw BGRA pixels, one after
+//! the other. The index file next to it, with `.index` appended to the name, holds a line for
+//! each frame with its offset in the data file, its width, its height and its capture time in
+//! microseconds since the unix epoch.
+//!
+//! The [`FfmpegRecorder`] instead records to a video file by passing the frames to ffmpeg, and the
+//! [`FrameHistory`] keeps the most recent frames in memory to export them as an animated GIF. The
+//! [`SequenceRecorder`] writes frames as separate image files, and the [`TimeLapse`] passes
+//! frames captured at a low rate to either recorder for a sped-up result. The [`Spool`] keeps the
+//! most recent frames on disk, to save them as a recording on request.
+use crate::capturer::{CaptureInfo, PostCallback};
+use crate::hooks::{HookContext, HookEvent, Hooks};
+use crate::raster_image::RasterImageBGR;
+use crate::{ImageBGR, OutputFormat, OwnedFrame, Resolution, BGR};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The first line of an index file.
+const INDEX_HEADER: &str = "# screen_capture recording v1: offset width height time_us";
+
+/// The path of the index file that belongs to the data file.
+fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".index");
+    name.into()
+}
+
+/// A frame in a recording, see [`FrameReader::frames`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecordedFrame {
+    /// The offset of the frame in the data file, in bytes.
+    pub offset: u64,
+    /// The size of the frame.
+    pub resolution: Resolution,
+    /// The time at which the frame was captured.
+    pub time: SystemTime,
+}
+
+/// Appends frames to a recording.
+pub struct FrameRecorder {
+    data: std::io::BufWriter<std::fs::File>,
+    index: std::io::BufWriter<std::fs::File>,
+    offset: u64,
+}
+
+impl FrameRecorder {
+    /// Create a new recording at the path, an existing recording there is overwritten.
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<FrameRecorder> {
+        let path = path.as_ref();
+        let data = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut index = std::io::BufWriter::new(std::fs::File::create(index_path(path))?);
+        writeln!(index, "{INDEX_HEADER}")?;
+        Ok(FrameRecorder {
+            data,
+            index,
+            offset: 0,
+        })
+    }
+
+    /// Append a frame that was captured at the provided time.
+    pub fn append(&mut self, img: &dyn ImageBGR, time: SystemTime) -> std::io::Result<()> {
+        let bytes: Vec<u8> = img.data().iter().flat_map(|p| [p.b, p.g, p.r, 0]).collect();
+        self.data.write_all(&bytes)?;
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(
+            self.index,
+            "{} {} {} {}",
+            self.offset,
+            img.width(),
+            img.height(),
+            time.as_micros()
+        )?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Flush the recording to disk, dropping the recorder flushes as well but ignores errors.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.data.flush()?;
+        self.index.flush()
+    }
+}
+
+/// Reads the frames of a recording made with [`FrameRecorder`].
+pub struct FrameReader {
+    data: std::fs::File,
+    frames: Vec<RecordedFrame>,
+}
+
+impl FrameReader {
+    /// Open the recording at the path, this reads the index.
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<FrameReader> {
+        let path = path.as_ref();
+        let data = std::fs::File::open(path)?;
+        let index = std::io::BufReader::new(std::fs::File::open(index_path(path))?);
+        let invalid = |line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid index line \"{line}\""),
+            )
+        };
+        let mut frames = vec![];
+        for line in index.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let values: Vec<u64> = line
+                .split_ascii_whitespace()
+                .map(str::parse::<u64>)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(&line))?;
+            let [offset, width, height, time] = values[..] else {
+                return Err(invalid(&line));
+            };
+            frames.push(RecordedFrame {
+                offset,
+                resolution: Resolution {
+                    width: width as u32,
+                    height: height as u32,
+                },
+                time: UNIX_EPOCH + Duration::from_micros(time),
+            });
+        }
+        Ok(FrameReader { data, frames })
+    }
+
+    /// The frames in the recording, in the order they were recorded.
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    /// Read the frame at the index.
+    pub fn read_frame(&mut self, index: usize) -> std::io::Result<RasterImageBGR> {
+        let frame = self.frames.get(index).copied().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no frame {index} in the recording"),
+            )
+        })?;
+        let Resolution { width, height } = frame.resolution;
+        let mut bytes = vec![0u8; width as usize * height as usize * 4];
+        self.data.seek(std::io::SeekFrom::Start(frame.offset))?;
+        self.data.read_exact(&mut bytes)?;
+        let pixels = bytes
+            .chunks_exact(4)
+            .map(|c| BGR {
+                b: c[0],
+                g: c[1],
+                r: c[2],
+            })
+            .collect();
+        Ok(RasterImageBGR::from_data(width, height, pixels))
+    }
+}
+
+/// Settings of the [`FfmpegRecorder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FfmpegConfig {
+    /// The ffmpeg binary to run.
+    pub binary: PathBuf,
+    /// The frame rate of the video, frames are repeated or dropped to match their capture times.
+    pub rate: f32,
+    /// Arguments placed between the input and the output file, like the codec to use.
+    pub output_args: Vec<String>,
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        FfmpegConfig {
+            binary: "ffmpeg".into(),
+            rate: 30.0,
+            output_args: [
+                "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+            ]
+            .iter()
+            .map(|v| v.to_string())
+            .collect(),
+        }
+    }
+}
+
+/// A running ffmpeg process and the frames passed to it.
+struct FfmpegSession {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    start: SystemTime,
+    format: OutputFormat,
+    resolution: Resolution,
+    written: u64,
+}
+
+/// Records frames to a video file by passing them to ffmpeg as raw video over its stdin.
+///
+/// Pass the frames of a [`ThreadedCapturer`](crate::ThreadedCapturer) from its post callback with
+/// [`FfmpegRecorder::write_frame`]. Frames are repeated or dropped such that the video plays back
+/// at the speed they were captured.
+pub struct FfmpegRecorder {
+    config: FfmpegConfig,
+    path: Option<PathBuf>,
+    session: Option<FfmpegSession>,
+    hooks: Option<Arc<Hooks>>,
+}
+
+impl FfmpegRecorder {
+    /// Create a recorder, it doesn't record until [`FfmpegRecorder::start`] is called.
+    pub fn new(config: FfmpegConfig) -> Self {
+        FfmpegRecorder {
+            config,
+            path: None,
+            session: None,
+            hooks: None,
+        }
+    }
+
+    /// Run the [`HookEvent::SegmentCompleted`] hooks with the path of each finished file.
+    pub fn with_hooks(mut self, hooks: Arc<Hooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Start recording to the file at the path, stopping the current recording if any. The
+    /// process is spawned on the first frame, as that determines the size of the video.
+    pub fn start<P: Into<PathBuf>>(&mut self, path: P) -> std::io::Result<()> {
+        self.stop()?;
+        self.path = Some(path.into());
+        Ok(())
+    }
+
+    /// Finish the current file and continue recording in a new one.
+    pub fn segment<P: Into<PathBuf>>(&mut self, path: P) -> std::io::Result<()> {
+        self.start(path)
+    }
+
+    /// Whether a recording was started.
+    pub fn is_recording(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// The file being recorded to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Stop recording and wait for ffmpeg to finish the file, fails if ffmpeg failed.
+    pub fn stop(&mut self) -> std::io::Result<()> {
+        let path = self.path.take();
+        let Some(session) = self.session.take() else {
+            return Ok(());
+        };
+        drop(session.stdin);
+        let mut child = session.child;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!("ffmpeg failed: {status}")));
+        }
+        if let (Some(hooks), Some(path)) = (&self.hooks, path) {
+            let context = HookContext::new(HookEvent::SegmentCompleted, SystemTime::now());
+            let _ = hooks.run(&context.with_path(path));
+        }
+        Ok(())
+    }
+
+    /// Pass a frame captured at the provided time to ffmpeg, this is ignored if not recording.
+    /// All frames of a file must have the same size and format.
+    pub fn write_frame(&mut self, frame: &OwnedFrame, time: SystemTime) -> std::io::Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        let resolution = Resolution {
+            width: frame.width(),
+            height: frame.height(),
+        };
+        if self.session.is_none() {
+            self.session = Some(self.spawn(path, frame.format(), resolution, time)?);
+        }
+        let session = self.session.as_mut().unwrap();
+        if (session.format, session.resolution) != (frame.format(), resolution) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the frame differs in size or format from the previous ones",
+            ));
+        }
+        let elapsed = time.duration_since(session.start).unwrap_or_default();
+        let copies = copies(elapsed, self.config.rate, session.written);
+        let bgr: Vec<u8>;
+        let bytes: &[u8] = match frame {
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgba(v) => v.as_raw(),
+            #[cfg(feature = "image")]
+            OwnedFrame::Rgb(v) => v.as_raw(),
+            OwnedFrame::Bgr(v) => {
+                // Sent as bgr0, the padding byte of the pixels is uninitialised.
+                bgr = v.data().iter().flat_map(|p| [p.b, p.g, p.r, 0]).collect();
+                &bgr
+            }
+        };
+        for _ in 0..copies {
+            session.stdin.write_all(bytes)?;
+        }
+        session.written += copies;
+        Ok(())
+    }
+
+    fn spawn(
+        &self,
+        path: &Path,
+        format: OutputFormat,
+        resolution: Resolution,
+        start: SystemTime,
+    ) -> std::io::Result<FfmpegSession> {
+        let pixel_format = match format {
+            #[cfg(feature = "image")]
+            OutputFormat::Rgba => "rgba",
+            #[cfg(feature = "image")]
+            OutputFormat::Rgb => "rgb24",
+            OutputFormat::Bgr => "bgr0",
+        };
+        let mut child = std::process::Command::new(&self.config.binary)
+            .args([
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                pixel_format,
+            ])
+            .arg("-video_size")
+            .arg(format!("{}x{}", resolution.width, resolution.height))
+            .arg("-framerate")
+            .arg(self.config.rate.to_string())
+            .args(["-i", "-"])
+            .args(&self.config.output_args)
+            .arg("-y")
+            .arg(path)
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok(FfmpegSession {
+            child,
+            stdin,
+            start,
+            format,
+            resolution,
+            written: 0,
+        })
+    }
+}
+
+impl Drop for FfmpegRecorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// The number of times to write a frame captured after the elapsed time, such that the frames
+/// written match the elapsed time at the rate.
+fn copies(elapsed: Duration, rate: f32, written: u64) -> u64 {
+    let due = (elapsed.as_secs_f64() * rate as f64).floor() as u64 + 1;
+    due.saturating_sub(written)
+}
+
+/// Keeps the frames captured in the most recent period in memory.
+///
+/// Push the frames of a [`ThreadedCapturer`](crate::ThreadedCapturer) from its post callback and
+/// call [`FrameHistory::export_gif`] to save the last few seconds as an animation.
+///
+/// Frames can be kept compressed with QOI, which is lossless and shrinks typical desktop content
+/// many times over, they are decompressed when accessed.
+pub struct FrameHistory {
+    duration: Duration,
+    compressed: bool,
+    frames: VecDeque<(SystemTime, StoredFrame)>,
+}
+
+/// A frame in the [`FrameHistory`], as stored.
+enum StoredFrame {
+    Raw(RasterImageBGR),
+    Qoi(Vec<u8>),
+}
+
+impl StoredFrame {
+    /// The image, decompressed if necessary.
+    fn image(&self) -> std::borrow::Cow<'_, RasterImageBGR> {
+        match self {
+            StoredFrame::Raw(img) => std::borrow::Cow::Borrowed(img),
+            StoredFrame::Qoi(data) => std::borrow::Cow::Owned(
+                crate::util::read_qoi_from(&mut data.as_slice())
+                    .expect("compressed frames are valid"),
+            ),
+        }
+    }
+
+    /// The number of bytes used by the pixels.
+    fn size(&self) -> usize {
+        match self {
+            StoredFrame::Raw(img) => std::mem::size_of_val(img.data()),
+            StoredFrame::Qoi(data) => data.len(),
+        }
+    }
+}
+
+impl FrameHistory {
+    /// Create a history that keeps the frames of the provided duration.
+    pub fn new(duration: Duration) -> Self {
+        FrameHistory {
+            duration,
+            compressed: false,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Keep frames that are pushed from now on compressed.
+    pub fn with_compression(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    /// Add a frame captured at the provided time, frames older than the duration are dropped.
+    pub fn push(&mut self, img: &dyn ImageBGR, time: SystemTime) {
+        let frame = if self.compressed {
+            let mut data = vec![];
+            crate::util::write_qoi_to(img, &mut data).expect("writing to memory can't fail");
+            StoredFrame::Qoi(data)
+        } else {
+            StoredFrame::Raw(RasterImageBGR::new(img))
+        };
+        self.frames.push_back((time, frame));
+        while let Some((oldest, _)) = self.frames.front() {
+            match time.duration_since(*oldest) {
+                Ok(age) if age > self.duration => self.frames.pop_front(),
+                _ => break,
+            };
+        }
+    }
+
+    /// The number of frames in the history.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether the history holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The number of bytes used by the pixels of the frames.
+    pub fn memory_usage(&self) -> usize {
+        self.frames.iter().map(|(_, frame)| frame.size()).sum()
+    }
+
+    /// The frames in the history with their capture times, oldest first. Compressed frames are
+    /// decompressed as the iterator advances.
+    pub fn frames(
+        &self,
+    ) -> impl Iterator<Item = (SystemTime, std::borrow::Cow<'_, RasterImageBGR>)> {
+        self.frames.iter().map(|(t, frame)| (*t, frame.image()))
+    }
+
+    /// Remove all frames.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Export the frames of the most recent range as an endlessly looping GIF at the frame rate,
+    /// downscaled such that neither side exceeds max_size, zero to keep the size.
+    pub fn export_gif<P: AsRef<Path>>(
+        &self,
+        path: P,
+        range: Duration,
+        fps: f32,
+        max_size: u32,
+    ) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.export_gif_to(&mut file, range, fps, max_size)?;
+        file.flush()
+    }
+
+    /// Export the frames of the most recent range as a GIF to a writer, see [`FrameHistory::export_gif`].
+    pub fn export_gif_to<W: Write>(
+        &self,
+        writer: &mut W,
+        range: Duration,
+        fps: f32,
+        max_size: u32,
+    ) -> std::io::Result<()> {
+        let Some((newest, _)) = self.frames.back() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the history holds no frames",
+            ));
+        };
+        if fps <= 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the frame rate must be positive",
+            ));
+        }
+        let start = newest.checked_sub(range).unwrap_or(UNIX_EPOCH);
+        let frames: Vec<_> = self.frames.iter().filter(|(t, _)| *t >= start).collect();
+        let first = frames[0].0;
+
+        // Sample the frames at the rate, holding a frame longer if it was shown for multiple samples.
+        let mut selected: Vec<(usize, u16)> = vec![];
+        let mut sample = 0u64;
+        let mut current = 0;
+        loop {
+            let offset = Duration::from_secs_f64(sample as f64 / fps as f64);
+            let Some(time) = first.checked_add(offset).filter(|t| t <= newest) else {
+                break;
+            };
+            while current + 1 < frames.len() && frames[current + 1].0 <= time {
+                current += 1;
+            }
+            let centiseconds = |k: u64| (k as f64 * 100.0 / fps as f64).round() as u64;
+            let delay = (centiseconds(sample + 1) - centiseconds(sample)) as u16;
+            match selected.last_mut() {
+                Some((index, total)) if *index == current => *total = total.saturating_add(delay),
+                _ => selected.push((current, delay)),
+            }
+            sample += 1;
+        }
+
+        // The sides of a GIF are 16 bit, larger frames are downscaled regardless of max_size.
+        let limit = match max_size {
+            0 => u16::MAX as u32,
+            size => size.min(u16::MAX as u32),
+        };
+        let img = frames[0].1.image();
+        let factor = img.width().max(img.height()).div_ceil(limit).max(1);
+        let width = (img.width() / factor).max(1);
+        let height = (img.height() / factor).max(1);
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        // Global color table of 256 entries with 8 bits per channel.
+        writer.write_all(&[0xf7, 0, 0])?;
+        let mut palette = [0u8; 256 * 3];
+        for index in 0..GIF_PALETTE_SIZE {
+            let (r, g, b) = (index / 42, index / 6 % 7, index % 6);
+            palette[index * 3] = (r * 255 / 5) as u8;
+            palette[index * 3 + 1] = (g * 255 / 6) as u8;
+            palette[index * 3 + 2] = (b * 255 / 5) as u8;
+        }
+        writer.write_all(&palette)?;
+        // Application extension to loop endlessly.
+        writer.write_all(&[0x21, 0xff, 0x0b])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        for (index, delay) in selected {
+            let indices = gif_quantize(&*frames[index].1.image(), width, height);
+            writer.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+            writer.write_all(&delay.to_le_bytes())?;
+            writer.write_all(&[0x00, 0x00])?;
+            writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+            writer.write_all(&(width as u16).to_le_bytes())?;
+            writer.write_all(&(height as u16).to_le_bytes())?;
+            writer.write_all(&[0x00, 0x08])?;
+            for block in gif_lzw(&indices).chunks(255) {
+                writer.write_all(&[block.len() as u8])?;
+                writer.write_all(block)?;
+            }
+            writer.write_all(&[0x00])?;
+        }
+        writer.write_all(&[0x3b])
+    }
+}
+
+/// The number of colors in the fixed GIF palette, 6 levels of red and blue and 7 of green.
+const GIF_PALETTE_SIZE: usize = 6 * 7 * 6;
+
+/// Scale the image to the size by averaging the block of pixels that covers each output pixel and
+/// map each pixel to the nearest color in the fixed palette. Frames of a different size than the
+/// first one are scaled to its size, an empty image is black.
+fn gif_quantize(img: &dyn ImageBGR, width: u32, height: u32) -> Vec<u8> {
+    // The range of source pixels covered by an output pixel, at least one if there are any.
+    let block = |i: u32, out: u32, len: u32| {
+        let start = (i as u64 * len as u64 / out as u64) as u32;
+        let end = ((i as u64 + 1) * len as u64 / out as u64) as u32;
+        start.min(len)..end.max(start + 1).min(len)
+    };
+    let mut indices = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut r, mut g, mut b, mut count) = (0u32, 0u32, 0u32, 0u32);
+            for sy in block(y, height, img.height()) {
+                for sx in block(x, width, img.width()) {
+                    let p = img.pixel(sx, sy);
+                    (r, g, b) = (r + p.r as u32, g + p.g as u32, b + p.b as u32);
+                    count += 1;
+                }
+            }
+            if count == 0 {
+                indices.push(0);
+                continue;
+            }
+            let level = |v: u32, levels: u32| (v * (levels - 1) + count * 127) / (count * 255);
+            indices.push((level(r, 6) * 42 + level(g, 7) * 6 + level(b, 6)) as u8);
+        }
+    }
+    indices
+}
+
+/// Compress the color indices with the variable length LZW of GIF, using 8 bit codes.
+fn gif_lzw(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    let mut out = vec![];
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut size = 9;
+    let mut emit = |code: u16, size: u32| {
+        bits |= (code as u32) << bit_count;
+        bit_count += size;
+        while bit_count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END + 1;
+    emit(CLEAR, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        emit(END, size);
+        emit(0, 7);
+        return out;
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        emit(prefix, size);
+        if next >= 1 << size && size < 12 {
+            size += 1;
+        }
+        if next >= 4095 {
+            emit(CLEAR, size);
+            table.clear();
+            next = END + 1;
+            size = 9;
+        } else {
+            table.insert((prefix, index), next);
+            next += 1;
+        }
+        prefix = index as u16;
+    }
+    emit(prefix, size);
+    if next >= 1 << size && size < 12 {
+        size += 1;
+    }
+    emit(END, size);
+    // Pad the last byte.
+    emit(0, 7);
+    out
+}
+
+/// The image format of the files written by the [`SequenceRecorder`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SequenceFormat {
+    #[default]
+    Bmp,
+    Ppm,
+    Qoi,
+    #[cfg(feature = "png")]
+    Png,
+}
+
+impl SequenceFormat {
+    /// The extension of files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SequenceFormat::Bmp => "bmp",
+            SequenceFormat::Ppm => "ppm",
+            SequenceFormat::Qoi => "qoi",
+            #[cfg(feature = "png")]
+            SequenceFormat::Png => "png",
+        }
+    }
+}
+
+/// Settings of the [`SequenceRecorder`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceConfig {
+    /// The directory in which the segment directories are created.
+    pub directory: PathBuf,
+    /// Only write every nth frame, one writes all frames.
+    pub every: usize,
+    /// The format of the image files.
+    pub format: SequenceFormat,
+    /// Start a new segment once the current one holds this many bytes.
+    pub segment_bytes: Option<u64>,
+    /// Delete the oldest segments such that no more than this many remain.
+    pub retain_segments: Option<usize>,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        SequenceConfig {
+            directory: "frames".into(),
+            every: 1,
+            format: Default::default(),
+            segment_bytes: None,
+            retain_segments: None,
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch, zero padded such that names sort by time.
+fn timestamp(time: SystemTime) -> String {
+    format!(
+        "{:013}",
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    )
+}
+
+/// Writes frames to image files named by their capture time.
+///
+/// The files are written to segment directories named `segment_<unix ms>` in the configured
+/// directory, a new segment is started when the current one is full and the oldest segments are
+/// deleted to stay within the retention limit. Segments left by a previous recorder in the same
+/// directory count towards that limit.
+pub struct SequenceRecorder {
+    config: SequenceConfig,
+    seen: usize,
+    segment: Option<(PathBuf, u64)>,
+    segments: VecDeque<PathBuf>,
+    last_error: Option<std::io::Error>,
+    hooks: Option<Arc<Hooks>>,
+}
+
+impl SequenceRecorder {
+    /// Create a recorder, creating the directory if it doesn't exist.
+    pub fn new(config: SequenceConfig) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&config.directory)?;
+        let mut segments = vec![];
+        for entry in std::fs::read_dir(&config.directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir()
+                && entry.file_name().to_string_lossy().starts_with("segment_")
+            {
+                segments.push(entry.path());
+            }
+        }
+        segments.sort();
+        Ok(SequenceRecorder {
+            config,
+            seen: 0,
+            segment: None,
+            segments: segments.into(),
+            last_error: None,
+            hooks: None,
+        })
+    }
+
+    /// Run the [`HookEvent::FrameSaved`] hooks for each file written and the
+    /// [`HookEvent::SegmentCompleted`] hooks when a new segment is started.
+    pub fn with_hooks(mut self, hooks: Arc<Hooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// A post callback that records the frames of a [`ThreadedCapturer`](crate::ThreadedCapturer)
+    /// with the shared recorder, failures are available through [`SequenceRecorder::take_error`].
+    pub fn post_callback(recorder: std::sync::Arc<std::sync::Mutex<Self>>) -> PostCallback {
+        std::sync::Arc::new(move |info: CaptureInfo| {
+            let mut recorder = recorder.lock().unwrap();
+            if let Err(e) = recorder.record(&info) {
+                recorder.last_error = Some(e);
+            }
+        })
+    }
+
+    /// Record the frame of a capture, failed captures are skipped.
+    pub fn record(&mut self, info: &CaptureInfo) -> std::io::Result<Option<PathBuf>> {
+        match &info.result {
+            Ok(frame) => self.write_frame(frame, info.time),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Write the frame if it is the nth one, returning the path of the file written if any.
+    pub fn write_frame(
+        &mut self,
+        frame: &OwnedFrame,
+        time: SystemTime,
+    ) -> std::io::Result<Option<PathBuf>> {
+        let seen = self.seen;
+        self.seen += 1;
+        if !seen.is_multiple_of(self.config.every.max(1)) {
+            return Ok(None);
+        }
+
+        let full = match (&self.segment, self.config.segment_bytes) {
+            (None, _) => true,
+            (Some((_, size)), Some(limit)) => *size >= limit,
+            _ => false,
+        };
+        if full {
+            let dir = self
+                .config
+                .directory
+                .join(format!("segment_{}", timestamp(time)));
+            std::fs::create_dir_all(&dir)?;
+            if self.segments.back() != Some(&dir) {
+                self.segments.push_back(dir.clone());
+            }
+            if let (Some(hooks), Some((completed, _))) = (&self.hooks, &self.segment) {
+                if *completed != dir {
+                    let context = HookContext::new(HookEvent::SegmentCompleted, time);
+                    let _ = hooks.run(&context.with_path(completed));
+                }
+            }
+            self.segment = Some((dir, 0));
+            if let Some(retain) = self.config.retain_segments {
+                while self.segments.len() > retain.max(1) {
+                    let oldest = self.segments.pop_front().unwrap();
+                    std::fs::remove_dir_all(oldest)?;
+                }
+            }
+        }
+
+        let (dir, size) = self.segment.as_mut().unwrap();
+        let path = dir.join(format!(
+            "frame_{}_{seen:06}.{}",
+            timestamp(time),
+            self.config.format.extension()
+        ));
+        let img = frame.to_bgr();
+        let filename = path.to_string_lossy();
+        match self.config.format {
+            SequenceFormat::Bmp => crate::util::write_bmp(&img, &filename)?,
+            SequenceFormat::Ppm => crate::util::write_ppm_binary(&img, &filename)?,
+            SequenceFormat::Qoi => crate::util::write_qoi(&img, &filename)?,
+            #[cfg(feature = "png")]
+            SequenceFormat::Png => crate::util::write_png(&img, &filename)?,
+        }
+        *size += std::fs::metadata(&path)?.len();
+        if let Some(hooks) = &self.hooks {
+            let context = HookContext::new(HookEvent::FrameSaved, time);
+            let _ = hooks.run(&context.with_path(&path));
+        }
+        Ok(Some(path))
+    }
+
+    /// The segment directories that currently exist, oldest first.
+    pub fn segments(&self) -> impl Iterator<Item = &Path> {
+        self.segments.iter().map(|p| p.as_path())
+    }
+
+    /// Take the most recent failure of the post callback.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.last_error.take()
+    }
+}
+
+/// Settings of the [`TimeLapse`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimeLapseConfig {
+    /// The interval between frames of the time-lapse.
+    pub interval: Duration,
+    /// The frame rate at which the time-lapse plays back.
+    pub output_rate: f32,
+}
+
+impl Default for TimeLapseConfig {
+    fn default() -> Self {
+        TimeLapseConfig {
+            interval: Duration::from_secs(5),
+            output_rate: 30.0,
+        }
+    }
+}
+
+/// Where the [`TimeLapse`] writes its frames.
+pub enum TimeLapseSink {
+    /// Write image files, named by the sped-up timestamps of the frames.
+    Sequence(SequenceRecorder),
+    /// Write a video, its rate should equal the output rate of the time-lapse.
+    Video(FfmpegRecorder),
+}
+
+/// Passes frames captured at a low rate on to a recorder, with timestamps that are sped up such
+/// that each frame lasts a single frame at the output rate.
+///
+/// Frames that arrive before the interval has passed are dropped, so the capturer may run at a
+/// higher rate than the time-lapse. After a gap, like the system being suspended, the time-lapse
+/// continues with the next frame instead of repeating the last one to fill the gap.
+pub struct TimeLapse {
+    config: TimeLapseConfig,
+    sink: TimeLapseSink,
+    origin: Option<SystemTime>,
+    next_due: Option<SystemTime>,
+    frames: u64,
+    gaps: u64,
+    last_error: Option<std::io::Error>,
+}
+
+impl TimeLapse {
+    /// Create a time-lapse that writes to the sink.
+    pub fn new(config: TimeLapseConfig, sink: TimeLapseSink) -> Self {
+        TimeLapse {
+            config,
+            sink,
+            origin: None,
+            next_due: None,
+            frames: 0,
+            gaps: 0,
+            last_error: None,
+        }
+    }
+
+    /// Set the rate of the capture configuration to the interval, with captures aligned to the
+    /// wall clock.
+    pub fn configure(&self, config: &mut crate::CaptureConfig) {
+        config.rate = 1.0 / self.config.interval.as_secs_f32();
+        config.schedule = crate::ScheduleMode::FixedCadence;
+    }
+
+    /// A post callback that passes the frames of a [`ThreadedCapturer`](crate::ThreadedCapturer)
+    /// to the shared time-lapse, failures are available through [`TimeLapse::take_error`].
+    pub fn post_callback(timelapse: std::sync::Arc<std::sync::Mutex<Self>>) -> PostCallback {
+        std::sync::Arc::new(move |info: CaptureInfo| {
+            let mut timelapse = timelapse.lock().unwrap();
+            if let Err(e) = timelapse.record(&info) {
+                timelapse.last_error = Some(e);
+            }
+        })
+    }
+
+    /// Record the frame of a capture, failed captures are skipped.
+    pub fn record(&mut self, info: &CaptureInfo) -> std::io::Result<Option<SystemTime>> {
+        match &info.result {
+            Ok(frame) => self.write_frame(frame, info.time),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Pass the frame on if it is due, returning its sped-up timestamp if it was written.
+    pub fn write_frame(
+        &mut self,
+        frame: &OwnedFrame,
+        time: SystemTime,
+    ) -> std::io::Result<Option<SystemTime>> {
+        // Allow some jitter in the capture times, without it half of the frames could be dropped.
+        let tolerance = self.config.interval / 10;
+        if let Some(due) = self.next_due {
+            if time + tolerance < due {
+                return Ok(None);
+            }
+            if time > due + self.config.interval {
+                self.gaps += 1;
+            }
+        }
+        let origin = *self.origin.get_or_insert(time);
+        let playback =
+            origin + Duration::from_secs_f64(self.frames as f64 / self.config.output_rate as f64);
+        match &mut self.sink {
+            TimeLapseSink::Sequence(recorder) => {
+                recorder.write_frame(frame, playback)?;
+            }
+            TimeLapseSink::Video(recorder) => recorder.write_frame(frame, playback)?,
+        }
+        self.frames += 1;
+        self.next_due = Some(time + self.config.interval);
+        Ok(Some(playback))
+    }
+
+    /// The number of frames written.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The number of times more than an interval was missed, like after a suspend.
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+
+    /// Take the most recent failure of the post callback.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.last_error.take()
+    }
+
+    /// Stop the time-lapse, returning the sink to finish the recording.
+    pub fn into_sink(self) -> TimeLapseSink {
+        self.sink
+    }
+}
+
+/// Settings of the [`Spool`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpoolConfig {
+    /// The directory holding the spooled frames, the default is a directory of this process in
+    /// the temporary directory. Spools only remove the frames they wrote.
+    pub directory: PathBuf,
+    /// The period of frames to keep.
+    pub duration: Duration,
+    /// Drop the oldest frames while the frames take more than this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            directory: std::env::temp_dir()
+                .join(format!("screen_capture_spool_{}", std::process::id())),
+            duration: Duration::from_secs(30),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Keeps the frames of the most recent period on disk as QOI images, such that the last 30
+/// seconds can be saved whenever requested without holding them in memory.
+pub struct Spool {
+    config: SpoolConfig,
+    /// The prefix of the frame files, unique to this spool.
+    prefix: String,
+    /// Whether the directory was created by this spool, it is removed if empty when dropped.
+    created_directory: bool,
+    counter: u64,
+    bytes: u64,
+    frames: VecDeque<(SystemTime, PathBuf, u64)>,
+    last_error: Option<std::io::Error>,
+}
+
+impl Spool {
+    /// Create the spool, creating the directory if it doesn't exist.
+    pub fn new(config: SpoolConfig) -> std::io::Result<Self> {
+        static SPOOLS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let created_directory = !config.directory.exists();
+        std::fs::create_dir_all(&config.directory)?;
+        let id = SPOOLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(Spool {
+            config,
+            prefix: format!("spool_{}_{id}", std::process::id()),
+            created_directory,
+            counter: 0,
+            bytes: 0,
+            frames: VecDeque::new(),
+            last_error: None,
+        })
+    }
+
+    /// A post callback that spools the frames of a [`ThreadedCapturer`](crate::ThreadedCapturer)
+    /// to the shared spool, failures are available through [`Spool::take_error`].
+    pub fn post_callback(spool: std::sync::Arc<std::sync::Mutex<Self>>) -> PostCallback {
+        std::sync::Arc::new(move |info: CaptureInfo| {
+            let mut spool = spool.lock().unwrap();
+            if let Ok(frame) = &info.result {
+                if let Err(e) = spool.push(&frame.to_bgr(), info.time) {
+                    spool.last_error = Some(e);
+                }
+            }
+        })
+    }
+
+    /// Add a frame captured at the provided time, dropping the frames that fall out of the
+    /// duration or exceed the size limit.
+    pub fn push(&mut self, img: &dyn ImageBGR, time: SystemTime) -> std::io::Result<()> {
+        let path = self
+            .config
+            .directory
+            .join(format!("{}_{:010}.qoi", self.prefix, self.counter));
+        self.counter += 1;
+        crate::util::write_qoi(img, &path.to_string_lossy())?;
+        let size = std::fs::metadata(&path)?.len();
+        self.bytes += size;
+        self.frames.push_back((time, path, size));
+
+        while let Some((oldest, _, _)) = self.frames.front() {
+            let expired = time
+                .duration_since(*oldest)
+                .is_ok_and(|age| age > self.config.duration);
+            let oversized = self.config.max_bytes.is_some_and(|max| self.bytes > max);
+            if !(expired || oversized) || self.frames.len() == 1 {
+                break;
+            }
+            let (_, path, size) = self.frames.pop_front().unwrap();
+            self.bytes -= size;
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// The number of frames in the spool.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether the spool holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The number of bytes used by the spooled frames.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Save the frames in the spool as a recording at the path, see [`FrameRecorder`]. The
+    /// spool is left as is and keeps going.
+    pub fn flush_to<P: AsRef<Path>>(&self, path: P) -> std::io::Result<usize> {
+        let mut recorder = FrameRecorder::create(path)?;
+        for (time, frame, _) in &self.frames {
+            let img = crate::util::read_qoi(&frame.to_string_lossy())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+            recorder.append(&img, *time)?;
+        }
+        recorder.finish()?;
+        Ok(self.frames.len())
+    }
+
+    /// Take the most recent failure of the post callback.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.last_error.take()
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        for (_, path, _) in &self.frames {
+            let _ = std::fs::remove_file(path);
+        }
+        if self.created_directory {
+            // Only succeeds if nothing else was put there.
+            let _ = std::fs::remove_dir(&self.config.directory);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::tests::TestDir;
+
+    #[test]
+    fn test_record_and_read() {
+        let dir = TestDir::new("record_and_read");
+        let path = dir.join("recording.bgra");
+        let first = RasterImageBGR::filled(3, 2, BGR { r: 1, g: 2, b: 3 });
+        let mut second = RasterImageBGR::filled(4, 4, BGR::default());
+        second.set_pixel(3, 3, BGR { r: 9, g: 8, b: 7 });
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut recorder = FrameRecorder::create(&path).unwrap();
+        recorder.append(&first, start).unwrap();
+        recorder
+            .append(&second, start + Duration::from_millis(20))
+            .unwrap();
+        recorder.finish().unwrap();
+
+        let mut reader = FrameReader::open(&path).unwrap();
+        assert_eq!(reader.frames().len(), 2);
+        assert_eq!(reader.frames()[1].offset, 3 * 2 * 4);
+        assert_eq!(reader.frames()[1].time, start + Duration::from_millis(20));
+        assert!(reader.read_frame(1).unwrap() == second);
+        assert!(reader.read_frame(0).unwrap() == first);
+        assert!(reader.read_frame(2).is_err());
+
+        use crate::synthetic::ReplayCapture;
+        use crate::Capture;
+        let mut replay = ReplayCapture::new(reader);
+        replay.prepare_capture(0, 1, 0, 0, 1).unwrap();
+        assert_eq!(
+            replay.resolution().unwrap(),
+            Resolution {
+                width: 3,
+                height: 2
+            }
+        );
+        replay.capture_image().unwrap();
+        let img = replay.image().unwrap();
+        assert_eq!((img.width(), img.height()), (2, 1));
+        replay.capture_image().unwrap();
+        assert_eq!(replay.image().unwrap().pixel(2, 0), BGR::default());
+        assert!(replay.capture_image().is_err());
+        replay.set_looping(true);
+        assert!(replay.capture_image().is_ok());
+    }
+
+    #[test]
+    fn test_replay_empty() {
+        let dir = TestDir::new("replay_empty");
+        let path = dir.join("empty.bgra");
+        FrameRecorder::create(&path).unwrap().finish().unwrap();
+        let mut replay = crate::synthetic::ReplayCapture::new(FrameReader::open(&path).unwrap());
+        use crate::Capture;
+        assert!(replay.resolution().is_err());
+        #[allow(deprecated)]
+        let resolution = replay.resolution_or_default();
+        assert_eq!(resolution, Resolution::default());
+
+        // The capturer reports the failure instead of preparing for an empty desktop.
+        let mut capturer = crate::Capturer::with_backend(Box::new(replay), Default::default());
+        assert!(capturer.capture().is_err());
+        assert!(capturer.selection().is_none());
+    }
+
+    #[test]
+    fn test_ffmpeg_copies() {
+        let ms = Duration::from_millis;
+        assert_eq!(copies(ms(0), 10.0, 0), 1);
+        assert_eq!(copies(ms(50), 10.0, 1), 0);
+        assert_eq!(copies(ms(100), 10.0, 1), 1);
+        // A stall of three intervals repeats the frame to catch up.
+        assert_eq!(copies(ms(420), 10.0, 2), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ffmpeg_recorder() {
+        use std::os::unix::fs::PermissionsExt;
+        // Stand in for ffmpeg that writes its input to the output file, the last argument.
+        let dir = TestDir::new("ffmpeg_recorder");
+        let binary = dir.join("fake_ffmpeg.sh");
+        std::fs::write(
+            &binary,
+            "#!/bin/sh\nfor a; do out=$a; done\ncat > \"$out\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut recorder = FfmpegRecorder::new(FfmpegConfig {
+            binary,
+            rate: 10.0,
+            output_args: vec![],
+        });
+        let frame = OwnedFrame::from_image(
+            &RasterImageBGR::filled(2, 2, BGR { r: 1, g: 2, b: 3 }),
+            OutputFormat::Bgr,
+        );
+        let start = SystemTime::now();
+        // Not recording yet, this is ignored.
+        recorder.write_frame(&frame, start).unwrap();
+
+        let first = dir.join("first.raw");
+        let second = dir.join("second.raw");
+        recorder.start(&first).unwrap();
+        recorder.write_frame(&frame, start).unwrap();
+        recorder
+            .write_frame(&frame, start + Duration::from_millis(250))
+            .unwrap();
+        recorder.segment(&second).unwrap();
+        recorder.write_frame(&frame, start).unwrap();
+        recorder.stop().unwrap();
+        assert!(!recorder.is_recording());
+
+        let frame_size = 2 * 2 * 4;
+        assert_eq!(std::fs::read(&first).unwrap().len(), 3 * frame_size);
+        let data = std::fs::read(&second).unwrap();
+        assert_eq!(data, [3, 2, 1, 0].repeat(4));
+    }
+
+    /// Decode the LZW data of a GIF frame with 8 bit codes.
+    fn gif_lzw_decode(data: &[u8]) -> Vec<u8> {
+        let (mut bits, mut bit_count, mut pos) = (0u32, 0u32, 0);
+        let mut size = 9;
+        let mut table: Vec<Vec<u8>> = (0..=257u16).map(|v| vec![v as u8]).collect();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = vec![];
+        loop {
+            while bit_count < size {
+                bits |= (data[pos] as u32) << bit_count;
+                pos += 1;
+                bit_count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            bit_count -= size;
+            match code {
+                256 => {
+                    table.truncate(258);
+                    size = 9;
+                    previous = None;
+                    continue;
+                }
+                257 => return out,
+                _ => {}
+            }
+            let entry = match (&previous, table.get(code)) {
+                (_, Some(entry)) => entry.clone(),
+                (Some(p), None) => [p.clone(), vec![p[0]]].concat(),
+                (None, None) => panic!("invalid code {code}"),
+            };
+            if let Some(p) = previous {
+                table.push([p, vec![entry[0]]].concat());
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            out.extend(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_gif_lzw() {
+        let mut state = 1u32;
+        let indices: Vec<u8> = (0..50000)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if i % 3 == 0 {
+                    (state >> 24) as u8
+                } else {
+                    (i / 100) as u8
+                }
+            })
+            .collect();
+        assert_eq!(gif_lzw_decode(&gif_lzw(&indices)), indices);
+        assert_eq!(gif_lzw_decode(&gif_lzw(&[])), Vec::<u8>::new());
+        assert_eq!(gif_lzw_decode(&gif_lzw(&[7])), vec![7]);
+    }
+
+    #[test]
+    fn test_export_gif() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ms = Duration::from_millis;
+        let mut history = FrameHistory::new(Duration::from_secs(1));
+        assert!(history
+            .export_gif_to(&mut vec![], ms(500), 10.0, 0)
+            .is_err());
+        let colors = [
+            BGR { r: 0, g: 0, b: 0 },
+            BGR { r: 255, g: 0, b: 0 },
+            BGR { r: 0, g: 255, b: 0 },
+            BGR { r: 0, g: 0, b: 255 },
+        ];
+        let mut img = RasterImageBGR::filled(8, 4, colors[0]);
+        history.push(&img, start);
+        for (i, t) in [(1, 1000), (2, 1100), (3, 1300)] {
+            img.fill_rectangle(0, 4, 0, 2, colors[i]);
+            history.push(&img, start + ms(t));
+        }
+        // The first frame is older than the duration.
+        assert_eq!(history.len(), 3);
+
+        let mut data = vec![];
+        history.export_gif_to(&mut data, ms(500), 10.0, 4).unwrap();
+        assert_eq!(&data[0..6], b"GIF89a");
+        assert_eq!(&data[6..10], &[4, 0, 2, 0]);
+
+        // Skip the screen descriptor, the palette and the loop extension.
+        let mut pos = 13 + 768 + 19;
+        let mut frames = vec![];
+        while data[pos] == 0x21 {
+            let delay = u16::from_le_bytes([data[pos + 4], data[pos + 5]]);
+            pos += 8;
+            assert_eq!(data[pos], 0x2c);
+            pos += 11;
+            let mut lzw = vec![];
+            while data[pos] != 0 {
+                let len = data[pos] as usize;
+                lzw.extend(&data[pos + 1..pos + 1 + len]);
+                pos += 1 + len;
+            }
+            pos += 1;
+            frames.push((delay, gif_lzw_decode(&lzw)));
+        }
+        assert_eq!(&data[pos..], &[0x3b]);
+
+        // Samples at 0, 100, 200 and 300 ms, the second frame shows for two of them.
+        let delays: Vec<u16> = frames.iter().map(|(d, _)| *d).collect();
+        assert_eq!(delays, vec![10, 20, 10]);
+        let green = (6 * 6) as u8;
+        let black = 0;
+        assert_eq!(
+            frames[1].1,
+            vec![green, green, black, black, black, black, black, black]
+        );
+
+        // A frame of another size is scaled to the size of the first.
+        history.push(&RasterImageBGR::filled(2, 1, colors[1]), start + ms(1400));
+        let mut data = vec![];
+        history.export_gif_to(&mut data, ms(500), 10.0, 0).unwrap();
+        assert_eq!(&data[6..10], &[8, 0, 4, 0]);
+        let red = (5 * 42) as u8;
+        assert_eq!(
+            gif_quantize(&RasterImageBGR::filled(2, 1, colors[1]), 8, 4),
+            vec![red; 32]
+        );
+        assert_eq!(
+            gif_quantize(&RasterImageBGR::filled(0, 0, colors[1]), 2, 1),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn test_gif_size_limit() {
+        let mut history = FrameHistory::new(Duration::from_secs(1));
+        history.push(
+            &RasterImageBGR::filled(70000, 1, Default::default()),
+            UNIX_EPOCH + Duration::from_secs(1),
+        );
+        let mut data = vec![];
+        history
+            .export_gif_to(&mut data, Duration::from_secs(1), 10.0, 0)
+            .unwrap();
+        // Halved, as the width doesn't fit 16 bits.
+        assert_eq!(
+            &data[6..10],
+            &[35000u16.to_le_bytes(), 1u16.to_le_bytes()].concat()[..]
+        );
+    }
+
+    #[test]
+    fn test_sequence_recorder() {
+        let dir = TestDir::new("sequence_recorder");
+        let directory = dir.join("sequence");
+        let config = SequenceConfig {
+            directory: directory.clone(),
+            every: 2,
+            format: SequenceFormat::Ppm,
+            segment_bytes: Some(30),
+            retain_segments: Some(2),
+        };
+        let mut recorder = SequenceRecorder::new(config.clone()).unwrap();
+        let img = RasterImageBGR::filled(3, 3, BGR { r: 1, g: 2, b: 3 });
+        let frame = OwnedFrame::from_image(&img, OutputFormat::Bgr);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let first = recorder.write_frame(&frame, start).unwrap().unwrap();
+        assert!(first.ends_with("segment_1700000000000/frame_1700000000000_000000.ppm"));
+        let back = crate::util::read_ppm(&first.to_string_lossy()).unwrap();
+        assert!(back.data().iter().all(|p| *p == BGR { r: 1, g: 2, b: 3 }));
+
+        let mut written = vec![];
+        for i in 1..8 {
+            let time = start + Duration::from_secs(i);
+            written.push(recorder.write_frame(&frame, time).unwrap());
+        }
+        assert!(written[0].is_none());
+
+        // Each frame fills a segment, only the last two segments are kept.
+        let segments: Vec<_> = recorder.segments().map(|p| p.to_owned()).collect();
+        assert_eq!(segments.len(), 2);
+        assert!(!first.exists());
+        assert!(written[5].as_ref().unwrap().exists());
+
+        // A new recorder picks up the existing segments.
+        let recorder = SequenceRecorder::new(config).unwrap();
+        assert_eq!(recorder.segments().count(), 2);
+    }
+
+    #[test]
+    fn test_compressed_history() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut raw = FrameHistory::new(Duration::from_secs(10));
+        let mut compressed = FrameHistory::new(Duration::from_secs(10)).with_compression(true);
+        let mut img = RasterImageBGR::filled(
+            64,
+            32,
+            BGR {
+                r: 20,
+                g: 30,
+                b: 40,
+            },
+        );
+        for i in 0..4 {
+            img.fill_rectangle(0, 8 * (i + 1), 0, 4, BGR { r: 200, g: 0, b: 0 });
+            let time = start + Duration::from_millis(100 * i as u64);
+            raw.push(&img, time);
+            compressed.push(&img, time);
+        }
+        assert_eq!(raw.memory_usage(), 4 * 64 * 32 * 4);
+        assert!(compressed.memory_usage() * 20 < raw.memory_usage());
+        for ((t0, a), (t1, b)) in raw.frames().zip(compressed.frames()) {
+            assert_eq!(t0, t1);
+            assert!(*a == *b);
+        }
+
+        let (mut a, mut b) = (vec![], vec![]);
+        let range = Duration::from_secs(1);
+        raw.export_gif_to(&mut a, range, 10.0, 0).unwrap();
+        compressed.export_gif_to(&mut b, range, 10.0, 0).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_time_lapse() {
+        let dir = TestDir::new("time_lapse");
+        let directory = dir.join("time_lapse");
+        let recorder = SequenceRecorder::new(SequenceConfig {
+            directory: directory.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let config = TimeLapseConfig {
+            interval: Duration::from_secs(5),
+            output_rate: 10.0,
+        };
+        let mut timelapse = TimeLapse::new(config, TimeLapseSink::Sequence(recorder));
+        let mut capture_config = crate::CaptureConfig::default();
+        timelapse.configure(&mut capture_config);
+        assert_eq!(capture_config.rate, 0.2);
+
+        let frame = OwnedFrame::from_image(
+            &RasterImageBGR::filled(2, 2, BGR::default()),
+            OutputFormat::Bgr,
+        );
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut write = |ms| timelapse.write_frame(&frame, at(ms)).unwrap();
+        assert_eq!(write(0), Some(at(0)));
+        // Too early, the capturer runs faster than the time-lapse.
+        assert_eq!(write(1000), None);
+        // Slightly early is within the jitter.
+        assert_eq!(write(4900), Some(at(100)));
+        assert_eq!(write(10000), Some(at(200)));
+        // Suspended for an hour, this continues with the next frame.
+        assert_eq!(write(3_610_000), Some(at(300)));
+        assert_eq!(timelapse.frames(), 4);
+        assert_eq!(timelapse.gaps(), 1);
+
+        let TimeLapseSink::Sequence(recorder) = timelapse.into_sink() else {
+            unreachable!();
+        };
+        let segment = recorder.segments().next().unwrap().to_owned();
+        let mut names: Vec<String> = std::fs::read_dir(segment)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        // The files are named by the sped-up timestamps, not the capture times.
+        assert_eq!(
+            names,
+            [
+                "frame_1700000000000_000000.bmp",
+                "frame_1700000000100_000001.bmp",
+                "frame_1700000000200_000002.bmp",
+                "frame_1700000000300_000003.bmp",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spool() {
+        let dir = TestDir::new("spool");
+        let directory = dir.join("spool");
+        let mut spool = Spool::new(SpoolConfig {
+            directory: directory.clone(),
+            duration: Duration::from_secs(2),
+            max_bytes: None,
+        })
+        .unwrap();
+        let spooled = || {
+            std::fs::read_dir(&directory)
+                .unwrap()
+                .filter(|e| {
+                    e.as_ref()
+                        .unwrap()
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with("spool_")
+                })
+                .count()
+        };
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut img = RasterImageBGR::filled(4, 4, BGR::default());
+        for i in 0..5u8 {
+            img.set_pixel(0, 0, BGR { r: i, g: 0, b: 0 });
+            spool
+                .push(&img, start + Duration::from_secs(i as u64))
+                .unwrap();
+        }
+        // Frames at 2, 3 and 4 seconds remain.
+        assert_eq!(spool.len(), 3);
+        assert_eq!(spooled(), 3);
+
+        let path = directory.join("saved.bgra");
+        assert_eq!(spool.flush_to(&path).unwrap(), 3);
+        let mut reader = FrameReader::open(&path).unwrap();
+        assert_eq!(reader.frames()[0].time, start + Duration::from_secs(2));
+        assert_eq!(reader.read_frame(2).unwrap().pixel(0, 0).r, 4);
+        drop(spool);
+        assert_eq!(spooled(), 0);
+
+        // The size limit keeps the most recent frame at least.
+        let mut spool = Spool::new(SpoolConfig {
+            directory: directory.clone(),
+            duration: Duration::from_secs(2),
+            max_bytes: Some(1),
+        })
+        .unwrap();
+        spool.push(&img, start).unwrap();
+        spool.push(&img, start).unwrap();
+        assert_eq!(spool.len(), 1);
+        // Frames of other spools in the directory are left alone.
+        let other = directory.join("spool_0000000000.qoi");
+        std::fs::write(&other, b"").unwrap();
+        let mut spool_other = Spool::new(SpoolConfig {
+            directory: directory.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        spool_other.push(&img, start).unwrap();
+        // The left over file and a frame of each spool.
+        assert_eq!(spooled(), 3);
+        drop(spool_other);
+        assert_eq!(spooled(), 2);
+        assert!(spool.flush_to(directory.join("saved.bgra")).is_ok());
+        drop(spool);
+        assert!(other.exists());
+    }
+}
@@ -0,0 +1,206 @@
+//! An adapter mirroring the API of the [scrap](https://crates.io/crates/scrap) crate, such that
+//! code written against it can switch to the backends of this crate by changing the import.
+//!
+//! ```no_run
+//! use screen_capture::scrap::{Capturer, Display};
+//! let display = Display::primary().unwrap();
+//! let mut capturer = Capturer::new(display).unwrap();
+//! let height = capturer.height();
+//! loop {
+//!     match capturer.frame() {
+//!         Ok(frame) => {
+//!             // Four bytes per pixel, in the order blue, green, red and zero.
+//!             let stride = frame.len() / height;
+//!             println!("{} {} {}", frame[2], frame[1], frame[0]);
+//!             break;
+//!         }
+//!         Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+//!         Err(e) => panic!("{e}"),
+//!     }
+//! }
+//! ```
+//!
+//! To capture on a thread instead, pass [`Display::specification`] to a
+//! [`ThreadedCapturer`](crate::ThreadedCapturer).
+use crate::capturer::CaptureSpecification;
+use crate::{Capture, DisplayInfo, ScreenCaptureError};
+use std::io;
+
+/// Convert the error, transient failures and timeouts become [`io::ErrorKind::WouldBlock`] like
+/// scrap returns when no new frame is available.
+fn io_error(e: ScreenCaptureError) -> io::Error {
+    let kind = match e {
+        ScreenCaptureError::Transient(_) | ScreenCaptureError::Timeout => io::ErrorKind::WouldBlock,
+        ScreenCaptureError::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+        ScreenCaptureError::BackendUnavailable { .. } => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
+
+/// A display that can be captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Display {
+    info: DisplayInfo,
+}
+
+impl Display {
+    /// The primary display, the first one if none is marked as primary.
+    pub fn primary() -> io::Result<Display> {
+        let mut displays = Display::all()?;
+        let index = displays.iter().position(|d| d.info.primary).unwrap_or(0);
+        Ok(displays.swap_remove(index))
+    }
+
+    /// All displays, displays that span the others, like the entire X11 screen, are omitted.
+    pub fn all() -> io::Result<Vec<Display>> {
+        let mut capture = crate::capture().map_err(io_error)?;
+        displays(capture.as_mut())
+    }
+
+    pub fn width(&self) -> usize {
+        self.info.width as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.info.height as usize
+    }
+
+    /// The information about the display as reported by the backend.
+    pub fn info(&self) -> &DisplayInfo {
+        &self.info
+    }
+
+    /// A specification capturing the entire display, for use with a
+    /// [`ThreadedCapturer`](crate::ThreadedCapturer).
+    pub fn specification(&self) -> CaptureSpecification {
+        CaptureSpecification {
+            display: self.info.index,
+            ..Default::default()
+        }
+    }
+}
+
+/// The displays of the backend, the entire desktop if it can't enumerate them.
+fn displays(capture: &mut dyn Capture) -> io::Result<Vec<Display>> {
+    let mut displays: Vec<DisplayInfo> = capture.displays();
+    if displays.iter().any(|d| !d.combined) {
+        displays.retain(|d| !d.combined);
+    }
+    if displays.is_empty() {
+        let resolution = capture.resolution().map_err(io_error)?;
+        displays.push(DisplayInfo {
+            width: resolution.width,
+            height: resolution.height,
+            primary: true,
+            ..Default::default()
+        });
+    }
+    Ok(displays.into_iter().map(|info| Display { info }).collect())
+}
+
+/// The pixels of a captured frame, four bytes per pixel in the order blue, green, red and zero,
+/// without padding between the rows.
+pub struct Frame<'a>(&'a [u8]);
+
+impl std::ops::Deref for Frame<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.0
+    }
+}
+
+/// Captures frames of a display.
+pub struct Capturer {
+    capture: Box<dyn Capture + Send>,
+    display: Display,
+    /// The bytes of the most recent frame.
+    buffer: Vec<u8>,
+}
+
+impl Capturer {
+    /// Create a capturer for the display, with the backend of this platform.
+    pub fn new(display: Display) -> io::Result<Capturer> {
+        Capturer::with_backend(crate::capture().map_err(io_error)?, display)
+    }
+
+    /// Create a capturer for the display with the provided backend.
+    pub fn with_backend(
+        mut capture: Box<dyn Capture + Send>,
+        display: Display,
+    ) -> io::Result<Capturer> {
+        capture
+            .prepare_capture(
+                display.info.index,
+                0,
+                0,
+                display.info.width,
+                display.info.height,
+            )
+            .map_err(io_error)?;
+        Ok(Capturer {
+            capture,
+            display,
+            buffer: vec![],
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.display.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.display.height()
+    }
+
+    /// Capture a frame, an [`io::ErrorKind::WouldBlock`] error if none is available yet.
+    pub fn frame(&mut self) -> io::Result<Frame<'_>> {
+        self.capture.capture_image().map_err(io_error)?;
+        let image = self.capture.image().map_err(io_error)?;
+        // The padding byte of the pixels is uninitialised, it is zero in the frame.
+        self.buffer.clear();
+        self.buffer
+            .extend(image.data().iter().flat_map(|p| [p.b, p.g, p.r, 0]));
+        Ok(Frame(&self.buffer))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::synthetic::TestPatternCapture;
+    use crate::Resolution;
+
+    #[test]
+    fn test_scrap_capturer() {
+        let mut backend = TestPatternCapture::new(Resolution {
+            width: 6,
+            height: 4,
+        });
+        let displays = displays(&mut backend).unwrap();
+        assert_eq!(displays.len(), 1);
+        assert_eq!((displays[0].width(), displays[0].height()), (6, 4));
+        assert_eq!(displays[0].specification().display, 0);
+
+        let mut capturer = Capturer::with_backend(Box::new(backend), displays[0].clone()).unwrap();
+        let frame = capturer.frame().unwrap();
+        assert_eq!(frame.len(), 6 * 4 * 4);
+        let stride = frame.len() / 4;
+        // The pixel at (5, 2) of the first frame.
+        assert_eq!(&frame[2 * stride + 5 * 4..][..4], &[1, 2, 5, 0]);
+        let frame = capturer.frame().unwrap();
+        assert_eq!(frame[0], 2);
+    }
+
+    #[test]
+    fn test_scrap_would_block() {
+        assert_eq!(
+            io_error(ScreenCaptureError::Timeout).kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(
+            io_error(ScreenCaptureError::Disconnected).kind(),
+            io::ErrorKind::Other
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Publishing frames to other processes on the same machine through a ring in shared memory.
+//!
+//! The [`Publisher`] creates a named shared memory mapping, a POSIX shared memory object on
+//! Linux and a file mapping on Windows, which any number of [`Subscriber`]s open for reading.
+//! Frames are copied into the ring as they are, without serialization. The layout, in native
+//! endianness, is:
+//!
+//!  - A header of 64 bytes: magic `SCFR` as `u32`, version `u32`, number of slots `u32`, the
+//!    process id of the publisher `u32`, the data size of a slot `u64` and the sequence of the
+//!    latest frame `u64`, zero if none was published yet.
+//!  - The slots, frame `n` is in slot `n % slots`. Each slot has a header of 64 bytes; the
+//!    sequence `u64`, the timestamp in nanoseconds since the unix epoch `u64`, width `u32`,
+//!    height `u32`, stride in bytes `u32`, format `u32` (0 rgba, 1 rgb, 2 bgr with four bytes per
+//!    pixel) and the data length `u64`, followed by the data, padded to a multiple of 64 bytes.
+//!
+//! The sequence of a slot is zero while it is written, readers check it is unchanged after
+//! copying the data.
+use crate::backend::{process_alive, SharedMemory};
+use crate::raster_image::RasterImageBGR;
+use crate::{ImageBGR, OwnedFrame, BGR};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAGIC: u32 = u32::from_ne_bytes(*b"SCFR");
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+const SLOT_HEADER_SIZE: usize = 64;
+
+#[repr(C)]
+struct RingHeader {
+    magic: u32,
+    version: u32,
+    slots: u32,
+    owner: u32,
+    slot_size: u64,
+    latest: AtomicU64,
+}
+
+#[repr(C)]
+struct SlotHeader {
+    sequence: AtomicU64,
+    timestamp: u64,
+    width: u32,
+    height: u32,
+    stride: u32,
+    format: u32,
+    len: u64,
+}
+
+/// The layout of a ring, shared by the publisher and subscriber.
+struct Ring {
+    memory: SharedMemory,
+    slots: u32,
+    slot_size: usize,
+}
+
+impl Ring {
+    /// The distance between the slots.
+    fn slot_stride(slot_size: usize) -> usize {
+        SLOT_HEADER_SIZE + slot_size.div_ceil(64) * 64
+    }
+
+    fn size(slots: u32, slot_size: usize) -> Option<usize> {
+        Self::slot_stride(slot_size)
+            .checked_mul(slots as usize)?
+            .checked_add(HEADER_SIZE)
+    }
+
+    fn header(&self) -> &RingHeader {
+        // The mapping is page aligned and at least the size of the header.
+        unsafe { &*(self.memory.as_ptr() as *const RingHeader) }
+    }
+
+    /// The header and data of the slot of the sequence.
+    fn slot(&self, sequence: u64) -> (*mut SlotHeader, *mut u8) {
+        let index = (sequence % self.slots as u64) as usize;
+        let offset = HEADER_SIZE + index * Self::slot_stride(self.slot_size);
+        // The size of the mapping was checked to hold all slots.
+        unsafe {
+            let slot = self.memory.as_ptr().add(offset);
+            (slot as *mut SlotHeader, slot.add(SLOT_HEADER_SIZE))
+        }
+    }
+}
+
+fn format_code(frame: &OwnedFrame) -> u32 {
+    match frame {
+        OwnedFrame::Rgba(_) => 0,
+        OwnedFrame::Rgb(_) => 1,
+        OwnedFrame::Bgr(_) => 2,
+    }
+}
+
+/// A frame read by a [`Subscriber`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedFrame {
+    /// The sequence of the frame, starting at one and increasing by one for each frame published.
+    pub sequence: u64,
+    /// The time passed to [`Publisher::publish`].
+    pub time: SystemTime,
+    pub frame: OwnedFrame,
+}
+
+/// Writes frames into a ring in shared memory, the name is removed when this is dropped.
+pub struct Publisher {
+    ring: Ring,
+    sequence: u64,
+}
+
+impl Publisher {
+    /// Create the ring with the name, of `slots` frames of at most `slot_size` bytes, like
+    /// `4 * width * height` for rgba frames. Subscribers can read a frame until `slots - 1` newer
+    /// frames are published. Fails with [`ErrorKind::AlreadyExists`] if the name is in use, rings
+    /// left behind by a publisher that exited are replaced.
+    pub fn create(name: &str, slots: u32, slot_size: usize) -> std::io::Result<Publisher> {
+        if slots == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "the ring needs a slot"));
+        }
+        let size = Ring::size(slots, slot_size)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "the ring is too large"))?;
+        let memory = match SharedMemory::create(name, size) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists && Self::stale(name) => {
+                SharedMemory::remove(name)?;
+                SharedMemory::create(name, size)?
+            }
+            result => result?,
+        };
+        // The memory is zeroed, so latest is zero until the first frame is published.
+        unsafe {
+            let header = memory.as_ptr() as *mut RingHeader;
+            (*header).magic = MAGIC;
+            (*header).version = VERSION;
+            (*header).slots = slots;
+            (*header).owner = std::process::id();
+            (*header).slot_size = slot_size as u64;
+        }
+        Ok(Publisher {
+            ring: Ring {
+                memory,
+                slots,
+                slot_size,
+            },
+            sequence: 0,
+        })
+    }
+
+    /// Whether the name is a ring of a publisher that no longer exists, other mappings are
+    /// never considered stale.
+    fn stale(name: &str) -> bool {
+        let Ok(memory) = SharedMemory::open(name) else {
+            return false;
+        };
+        if memory.size() < HEADER_SIZE {
+            return false;
+        }
+        let (magic, owner) = unsafe {
+            let header = memory.as_ptr() as *const RingHeader;
+            ((*header).magic, (*header).owner)
+        };
+        magic == MAGIC && owner != 0 && !process_alive(owner)
+    }
+
+    /// The sequence of the last frame published, zero if none was.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Write the frame into the next slot and return its sequence, fails if the frame is larger
+    /// than a slot.
+    pub fn publish(&mut self, frame: &OwnedFrame, time: SystemTime) -> std::io::Result<u64> {
+        let bgr;
+        let (data, pixel_size): (&[u8], u32) = match frame {
+            OwnedFrame::Rgba(v) => (v.as_raw(), 4),
+            OwnedFrame::Rgb(v) => (v.as_raw(), 3),
+            OwnedFrame::Bgr(v) => {
+                bgr = v
+                    .data()
+                    .iter()
+                    .flat_map(|p| [p.b, p.g, p.r, 0])
+                    .collect::<Vec<u8>>();
+                (&bgr, 4)
+            }
+        };
+        if data.len() > self.ring.slot_size {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "frame of {} bytes doesn't fit a slot of {} bytes",
+                    data.len(),
+                    self.ring.slot_size
+                ),
+            ));
+        }
+        let sequence = self.sequence + 1;
+        let (slot, slot_data) = self.ring.slot(sequence);
+        let timestamp = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        unsafe {
+            (*slot).sequence.store(0, Ordering::Relaxed);
+            fence(Ordering::Release);
+            (*slot).timestamp = timestamp;
+            (*slot).width = frame.width();
+            (*slot).height = frame.height();
+            (*slot).stride = frame.width() * pixel_size;
+            (*slot).format = format_code(frame);
+            (*slot).len = data.len() as u64;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), slot_data, data.len());
+            (*slot).sequence.store(sequence, Ordering::Release);
+        }
+        self.ring.header().latest.store(sequence, Ordering::Release);
+        self.sequence = sequence;
+        Ok(sequence)
+    }
+}
+
+/// Reads the frames of a [`Publisher`], possibly in another process.
+pub struct Subscriber {
+    ring: Ring,
+    last: u64,
+}
+
+impl Subscriber {
+    /// The number of attempts to read a frame while the publisher overwrites it.
+    const ATTEMPTS: usize = 16;
+
+    /// Open the ring with the name, fails if it doesn't exist or isn't a ring of this version.
+    pub fn open(name: &str) -> std::io::Result<Subscriber> {
+        let memory = SharedMemory::open(name)?;
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, what.to_owned());
+        if memory.size() < HEADER_SIZE {
+            return Err(invalid("the mapping is too small for a ring"));
+        }
+        let (magic, version, slots, slot_size) = unsafe {
+            let header = memory.as_ptr() as *const RingHeader;
+            (
+                (*header).magic,
+                (*header).version,
+                (*header).slots,
+                (*header).slot_size,
+            )
+        };
+        if magic != MAGIC || version != VERSION {
+            return Err(invalid("the mapping is not a ring of this version"));
+        }
+        let slot_size = usize::try_from(slot_size).map_err(|_| invalid("slots too large"))?;
+        match Ring::size(slots, slot_size) {
+            Some(size) if slots > 0 && size <= memory.size() => {}
+            _ => return Err(invalid("the mapping is too small for its slots")),
+        }
+        Ok(Subscriber {
+            ring: Ring {
+                memory,
+                slots,
+                slot_size,
+            },
+            last: 0,
+        })
+    }
+
+    /// Copy the frame of the sequence, `None` if it was overwritten.
+    fn read(&self, sequence: u64) -> std::io::Result<Option<PublishedFrame>> {
+        let (slot, slot_data) = self.ring.slot(sequence);
+        let slot = unsafe { &*slot };
+        if slot.sequence.load(Ordering::Acquire) != sequence {
+            return Ok(None);
+        }
+        // The fields may be overwritten while they are read, they are only used if the sequence
+        // is unchanged afterwards.
+        let (timestamp, width, height, format, len) = unsafe {
+            (
+                std::ptr::read_volatile(&slot.timestamp),
+                std::ptr::read_volatile(&slot.width),
+                std::ptr::read_volatile(&slot.height),
+                std::ptr::read_volatile(&slot.format),
+                std::ptr::read_volatile(&slot.len) as usize,
+            )
+        };
+        let len = len.min(self.ring.slot_size);
+        let mut data = vec![0u8; len];
+        unsafe { std::ptr::copy_nonoverlapping(slot_data, data.as_mut_ptr(), len) };
+        fence(Ordering::Acquire);
+        if slot.sequence.load(Ordering::Relaxed) != sequence {
+            return Ok(None);
+        }
+
+        let invalid = || Error::new(ErrorKind::InvalidData, "the frame doesn't match its size");
+        let frame = match format {
+            0 => OwnedFrame::Rgba(
+                image::RgbaImage::from_raw(width, height, data).ok_or_else(invalid)?,
+            ),
+            1 => {
+                OwnedFrame::Rgb(image::RgbImage::from_raw(width, height, data).ok_or_else(invalid)?)
+            }
+            2 => {
+                if len != width as usize * height as usize * 4 {
+                    return Err(invalid());
+                }
+                let pixels = data
+                    .chunks_exact(4)
+                    .map(|p| BGR {
+                        b: p[0],
+                        g: p[1],
+                        r: p[2],
+                    })
+                    .collect();
+                OwnedFrame::Bgr(RasterImageBGR::from_data(width, height, pixels))
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown frame format")),
+        };
+        Ok(Some(PublishedFrame {
+            sequence,
+            time: UNIX_EPOCH + Duration::from_nanos(timestamp),
+            frame,
+        }))
+    }
+
+    /// Copy the latest frame, `None` if no frame was published yet.
+    pub fn latest(&self) -> std::io::Result<Option<PublishedFrame>> {
+        for _ in 0..Self::ATTEMPTS {
+            let sequence = self.ring.header().latest.load(Ordering::Acquire);
+            if sequence == 0 {
+                return Ok(None);
+            }
+            if let Some(frame) = self.read(sequence)? {
+                return Ok(Some(frame));
+            }
+        }
+        Err(Error::new(
+            ErrorKind::WouldBlock,
+            "the publisher overwrote the frames while they were read",
+        ))
+    }
+
+    /// Copy the latest frame if it is newer than the one returned by the previous call.
+    pub fn poll(&mut self) -> std::io::Result<Option<PublishedFrame>> {
+        if self.ring.header().latest.load(Ordering::Acquire) == self.last {
+            return Ok(None);
+        }
+        let frame = self.latest()?;
+        if let Some(frame) = &frame {
+            self.last = frame.sequence;
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_subscribe() {
+        let name = format!("screen_capture_test_{}", std::process::id());
+        let mut publisher = Publisher::create(&name, 3, 4 * 4 * 4).unwrap();
+        let mut subscriber = Subscriber::open(&name).unwrap();
+        assert_eq!(subscriber.latest().unwrap(), None);
+
+        let mut image = RasterImageBGR::filled(4, 2, BGR { r: 1, g: 2, b: 3 });
+        image.set_pixel(3, 1, BGR { r: 4, g: 5, b: 6 });
+        let time = UNIX_EPOCH + Duration::from_millis(1234);
+        for format in [
+            crate::OutputFormat::Rgba,
+            crate::OutputFormat::Rgb,
+            crate::OutputFormat::Bgr,
+        ] {
+            let frame = OwnedFrame::from_image(&image, format);
+            let sequence = publisher.publish(&frame, time).unwrap();
+            let published = subscriber.poll().unwrap().unwrap();
+            assert_eq!(published.sequence, sequence);
+            assert_eq!(published.time, time);
+            assert_eq!(published.frame, frame);
+            assert_eq!(subscriber.poll().unwrap(), None);
+        }
+
+        // Frames that were overwritten are gone, the latest is still there.
+        for _ in 0..4 {
+            publisher
+                .publish(&OwnedFrame::Bgr(image.clone()), time)
+                .unwrap();
+        }
+        assert_eq!(subscriber.read(4).unwrap(), None);
+        assert_eq!(subscriber.poll().unwrap().unwrap().sequence, 7);
+
+        let large = RasterImageBGR::filled(8, 8, BGR::default());
+        assert!(publisher.publish(&OwnedFrame::Bgr(large), time).is_err());
+
+        // Another subscriber sees the same ring, until the publisher removes it.
+        assert_eq!(
+            Subscriber::open(&name)
+                .unwrap()
+                .latest()
+                .unwrap()
+                .unwrap()
+                .sequence,
+            7
+        );
+        drop(publisher);
+        assert!(Subscriber::open(&name).is_err());
+        assert_eq!(subscriber.latest().unwrap().unwrap().sequence, 7);
+    }
+
+    #[test]
+    fn test_publisher_exists() {
+        let name = format!("screen_capture_test_exists_{}", std::process::id());
+        let publisher = Publisher::create(&name, 1, 64).unwrap();
+        let e = Publisher::create(&name, 1, 64).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+
+        // The ring of a publisher that exited is replaced.
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        unsafe { (*(publisher.ring.memory.as_ptr() as *mut RingHeader)).owner = dead };
+        // Dropping it would remove the name of the replacement.
+        std::mem::forget(publisher);
+        let publisher = Publisher::create(&name, 1, 64).unwrap();
+        assert_eq!(publisher.ring.header().owner, std::process::id());
+        drop(publisher);
+        assert!(Subscriber::open(&name).is_err());
+    }
+}
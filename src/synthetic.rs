@@ -0,0 +1,355 @@
+//! Synthetic capture backends that render a test pattern, replay a recording or inject faults,
+//! useful for testing.
+use crate::raster_image::RasterImageBGR;
+use crate::recording::FrameReader;
+use crate::{Capture, ImageBGR, Rect, Resolution, ScreenCaptureError, BGR};
+
+/// Capture backend producing a deterministic test pattern instead of capturing the screen.
+///
+/// The pixel at desktop coordinate `(x, y)` of frame `n` has `r = x % 256`, `g = y % 256` and
+/// `b = n % 256`, where `n` counts the calls to [`Capture::capture_image`], starting at one.
+pub struct TestPatternCapture {
+    resolution: Resolution,
+    region: Option<(u32, u32, u32, u32)>,
+    frame: u64,
+    image: Option<RasterImageBGR>,
+}
+
+impl TestPatternCapture {
+    /// Create a new test pattern backend with the provided desktop resolution.
+    pub fn new(resolution: Resolution) -> Self {
+        TestPatternCapture {
+            resolution,
+            region: None,
+            frame: 0,
+            image: None,
+        }
+    }
+
+    /// Change the desktop resolution, the capture needs to be prepared again afterwards.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        self.region = None;
+    }
+
+    /// The number of frames captured so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The value of the test pattern at the desktop coordinate for the provided frame.
+    pub fn pattern(x: u32, y: u32, frame: u64) -> BGR {
+        BGR {
+            r: (x % 256) as u8,
+            g: (y % 256) as u8,
+            b: (frame % 256) as u8,
+        }
+    }
+}
+
+impl Capture for TestPatternCapture {
+    fn name(&self) -> &str {
+        "test pattern"
+    }
+
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+        let (x, y, width, height) = self.region.ok_or_else(|| {
+            ScreenCaptureError::Initialisation("no region prepared, call prepare_capture".into())
+        })?;
+        self.frame += 1;
+        let mut image = RasterImageBGR::filled(width, height, Default::default());
+        for iy in 0..height {
+            for ix in 0..width {
+                image.set_pixel(ix, iy, Self::pattern(x + ix, y + iy, self.frame));
+            }
+        }
+        self.image = Some(image);
+        Ok(())
+    }
+
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        self.image
+            .as_ref()
+            .map(|v| Box::new(v.clone()) as Box<dyn ImageBGR>)
+            .ok_or_else(|| ScreenCaptureError::Initialisation("no image captured yet".into()))
+    }
+
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+        Ok(self.resolution)
+    }
+
+    fn prepare_capture(
+        &mut self,
+        _display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
+        // Clamp the region to the desktop, like the X11 backend does.
+        let region = Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+        .clamped(self.resolution)?;
+        self.region = Some((region.x, region.y, region.width, region.height));
+        self.image = None;
+        Ok(())
+    }
+}
+
+/// Capture backend that replays a recording made with
+/// [`FrameRecorder`](crate::recording::FrameRecorder), each capture returns the next frame.
+pub struct ReplayCapture {
+    reader: FrameReader,
+    next: usize,
+    looping: bool,
+    region: Option<(u32, u32, u32, u32)>,
+    image: Option<RasterImageBGR>,
+}
+
+impl ReplayCapture {
+    /// Create a backend replaying the recording from the start.
+    pub fn new(reader: FrameReader) -> Self {
+        ReplayCapture {
+            reader,
+            next: 0,
+            looping: false,
+            region: None,
+            image: None,
+        }
+    }
+
+    /// Whether to start from the first frame again after the last one, otherwise capturing fails
+    /// at the end of the recording.
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+}
+
+impl Capture for ReplayCapture {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+        if self.next >= self.reader.frames().len() {
+            if !self.looping || self.reader.frames().is_empty() {
+                return Err(ScreenCaptureError::Other("end of the recording".into()));
+            }
+            self.next = 0;
+        }
+        let frame = self.reader.read_frame(self.next).map_err(|e| {
+            ScreenCaptureError::Other(format!("reading the recording failed: {e}").into())
+        })?;
+        self.next += 1;
+        let (x, y, width, height) = self.region.unwrap_or((0, 0, 0, 0));
+        let x = x.min(frame.width());
+        let y = y.min(frame.height());
+        let width = if width == 0 { u32::MAX } else { width }.min(frame.width() - x);
+        let height = if height == 0 { u32::MAX } else { height }.min(frame.height() - y);
+        let mut image = RasterImageBGR::filled(width, height, Default::default());
+        for iy in 0..height {
+            for ix in 0..width {
+                image.set_pixel(ix, iy, frame.pixel(x + ix, y + iy));
+            }
+        }
+        self.image = Some(image);
+        Ok(())
+    }
+
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        self.image
+            .as_ref()
+            .map(|v| Box::new(v.clone()) as Box<dyn ImageBGR>)
+            .ok_or_else(|| ScreenCaptureError::Initialisation("no image captured yet".into()))
+    }
+
+    /// The resolution of the frame the next capture returns.
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+        let frames = self.reader.frames();
+        let next = if self.looping && self.next >= frames.len() {
+            0
+        } else {
+            self.next
+        };
+        frames
+            .get(next)
+            .or(frames.last())
+            .map(|f| f.resolution)
+            .ok_or_else(|| ScreenCaptureError::Other("the recording has no frames".into()))
+    }
+
+    fn prepare_capture(
+        &mut self,
+        _display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
+        // The resolution may change between frames, so the region is applied to each frame.
+        self.region = Some((x, y, width, height));
+        self.image = None;
+        Ok(())
+    }
+}
+
+/// Handle to schedule the errors of the [`FaultInjector`] backends it created, and to observe
+/// them while they are owned by a capturer.
+#[cfg(feature = "fault-injection")]
+#[derive(Clone, Default)]
+pub struct FaultHandle {
+    state: std::sync::Arc<std::sync::Mutex<FaultState>>,
+}
+
+#[cfg(feature = "fault-injection")]
+#[derive(Default)]
+struct FaultState {
+    /// The errors to return, by the count of the capture they replace.
+    faults: std::collections::BTreeMap<u64, ScreenCaptureError>,
+    captures: u64,
+    resets: u64,
+}
+
+#[cfg(feature = "fault-injection")]
+impl FaultHandle {
+    /// Create a handle without any faults scheduled.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Wrap the backend, its captures fail as scheduled through this handle.
+    pub fn wrap(&self, inner: Box<dyn Capture>) -> FaultInjector {
+        FaultInjector {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Fail the capture with this count with the error, counting the calls to
+    /// [`Capture::capture_image`] of all wrapped backends, starting at one.
+    pub fn inject(&self, capture: u64, error: ScreenCaptureError) {
+        self.state.lock().unwrap().faults.insert(capture, error);
+    }
+
+    /// Fail all captures in the range with the error, see [`FaultHandle::inject`].
+    pub fn inject_range(&self, captures: std::ops::Range<u64>, error: ScreenCaptureError) {
+        let mut state = self.state.lock().unwrap();
+        for capture in captures {
+            state.faults.insert(capture, error.clone());
+        }
+    }
+
+    /// Remove all faults that didn't happen yet.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// The number of captures so far, including the failed ones.
+    pub fn captures(&self) -> u64 {
+        self.state.lock().unwrap().captures
+    }
+
+    /// The number of calls to [`Capture::reset`] so far.
+    pub fn resets(&self) -> u64 {
+        self.state.lock().unwrap().resets
+    }
+}
+
+/// Capture backend that wraps another backend and replaces its captures with errors, to test the
+/// recovery of the capturers deterministically. Created through [`FaultHandle::wrap`].
+#[cfg(feature = "fault-injection")]
+pub struct FaultInjector {
+    inner: Box<dyn Capture>,
+    state: std::sync::Arc<std::sync::Mutex<FaultState>>,
+}
+
+#[cfg(feature = "fault-injection")]
+impl Capture for FaultInjector {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+        let fault = {
+            let mut state = self.state.lock().unwrap();
+            state.captures += 1;
+            let capture = state.captures;
+            state.faults.remove(&capture)
+        };
+        match fault {
+            Some(e) => Err(e),
+            None => self.inner.capture_image(),
+        }
+    }
+
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        self.inner.image()
+    }
+
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+        self.inner.resolution()
+    }
+
+    fn prepare_capture(
+        &mut self,
+        display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
+        self.inner.prepare_capture(display, x, y, width, height)
+    }
+
+    fn displays(&mut self) -> Vec<crate::DisplayInfo> {
+        self.inner.displays()
+    }
+
+    fn windows(&mut self) -> Vec<crate::WindowInfo> {
+        self.inner.windows()
+    }
+
+    fn reset(&mut self) -> Result<(), ScreenCaptureError> {
+        self.state.lock().unwrap().resets += 1;
+        self.inner.reset()
+    }
+
+    fn debug_messages(&mut self) -> Vec<String> {
+        self.inner.debug_messages()
+    }
+
+    fn shared_texture(&mut self) -> Result<crate::SharedTexture, ScreenCaptureError> {
+        self.inner.shared_texture()
+    }
+
+    #[cfg(feature = "wgpu")]
+    fn frame_as_wgpu_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<wgpu::Texture, ScreenCaptureError> {
+        self.inner.frame_as_wgpu_texture(device, queue)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Backend factory for a test pattern of the provided resolution.
+    pub fn test_pattern(
+        width: u32,
+        height: u32,
+    ) -> impl Fn() -> Result<Box<dyn Capture>, ScreenCaptureError> + Send + Sync + 'static {
+        move || {
+            Ok(Box::new(TestPatternCapture::new(Resolution {
+                width,
+                height,
+            })))
+        }
+    }
+}
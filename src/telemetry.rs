@@ -0,0 +1,130 @@
+//! Metrics and tracing spans of the capture work, these are no-ops without the `metrics`
+//! feature.
+//!
+//! The following metrics are emitted, all labelled with the `display`:
+//!
+//!  - `screen_capture_frames_total`: counter of the frames captured.
+//!  - `screen_capture_failures_total`: counter of the failed captures, also labelled with `kind`.
+//!  - `screen_capture_capture_seconds`: histogram of the time spent capturing a frame.
+//!  - `screen_capture_conversion_seconds`: histogram of the time spent converting and processing
+//!    a frame.
+use crate::ScreenCaptureError;
+use std::time::Duration;
+
+/// The kind of the error, used as label.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) fn error_kind(error: &ScreenCaptureError) -> &'static str {
+    match error {
+        ScreenCaptureError::Initialisation(_) => "initialisation",
+        ScreenCaptureError::Transient(_) => "transient",
+        ScreenCaptureError::LostCapture(_) => "lost_capture",
+        ScreenCaptureError::PermissionDenied(_) => "permission_denied",
+        ScreenCaptureError::Timeout => "timeout",
+        ScreenCaptureError::UnsupportedFormat { .. } => "unsupported_format",
+        ScreenCaptureError::BackendUnavailable { .. } => "backend_unavailable",
+        ScreenCaptureError::Disconnected => "disconnected",
+        ScreenCaptureError::Other(_) => "other",
+    }
+}
+
+/// Record a frame that was captured and converted.
+pub(crate) fn frame_captured(display: u32, capture: Duration, conversion: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let display = display.to_string();
+        metrics::counter!("screen_capture_frames_total", "display" => display.clone()).increment(1);
+        metrics::histogram!("screen_capture_capture_seconds", "display" => display.clone())
+            .record(capture.as_secs_f64());
+        metrics::histogram!("screen_capture_conversion_seconds", "display" => display)
+            .record(conversion.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (display, capture, conversion);
+}
+
+/// Record a capture that failed.
+pub(crate) fn capture_failed(display: u32, error: &ScreenCaptureError) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(
+        "screen_capture_failures_total",
+        "display" => display.to_string(),
+        "kind" => error_kind(error)
+    )
+    .increment(1);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (display, error);
+}
+
+/// The span of the work of a frame, it is exited when dropped.
+#[cfg(feature = "metrics")]
+pub(crate) type Span = tracing::span::EnteredSpan;
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Span;
+
+/// Enter the span of the work of all displays for the frame.
+pub(crate) fn frame_span(counter: usize) -> Span {
+    #[cfg(feature = "metrics")]
+    return tracing::debug_span!("screen_capture_frame", counter).entered();
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = counter;
+        Span
+    }
+}
+
+/// Enter the span of capturing and converting the display at the index.
+pub(crate) fn display_span(index: usize) -> Span {
+    #[cfg(feature = "metrics")]
+    return tracing::debug_span!("screen_capture_display", index).entered();
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = index;
+        Span
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind() {
+        assert_eq!(
+            error_kind(&ScreenCaptureError::Transient("x".into())),
+            "transient"
+        );
+        assert_eq!(error_kind(&ScreenCaptureError::Timeout), "timeout");
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn test_metrics() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            frame_captured(1, Duration::from_millis(5), Duration::from_millis(2));
+            frame_captured(1, Duration::from_millis(7), Duration::from_millis(2));
+            capture_failed(1, &ScreenCaptureError::Disconnected);
+        });
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(k, _, _, _)| k.key().name() == name)
+                .map(|(_, _, _, v)| v)
+                .unwrap()
+        };
+        assert_eq!(
+            value("screen_capture_frames_total"),
+            &DebugValue::Counter(2)
+        );
+        assert_eq!(
+            value("screen_capture_failures_total"),
+            &DebugValue::Counter(1)
+        );
+        assert!(
+            matches!(value("screen_capture_capture_seconds"), DebugValue::Histogram(v) if v.len() == 2)
+        );
+    }
+}
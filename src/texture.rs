@@ -0,0 +1,107 @@
+//! Uploading images and frames to [`wgpu`] textures, to display them without reading them back.
+//!
+//! The textures are in the [`wgpu::TextureFormat::Rgba8Unorm`] format with an opaque alpha, and
+//! can be sampled, copied from and written to.
+use crate::{ImageBGR, OwnedFrame, ScreenCaptureError};
+
+/// The usage of the created textures.
+pub const TEXTURE_USAGE: wgpu::TextureUsages = wgpu::TextureUsages::TEXTURE_BINDING
+    .union(wgpu::TextureUsages::COPY_SRC)
+    .union(wgpu::TextureUsages::COPY_DST);
+
+/// Upload rgba data of the size to a new texture.
+fn upload(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+) -> Result<wgpu::Texture, ScreenCaptureError> {
+    if width == 0 || height == 0 {
+        return Err(ScreenCaptureError::Other(
+            "can't create a texture of an empty image".into(),
+        ));
+    }
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("screen_capture frame"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: TEXTURE_USAGE,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        texture.as_image_copy(),
+        rgba,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(width * 4),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+    Ok(texture)
+}
+
+/// Upload the image to a new texture, the write is submitted with the next submission on the
+/// queue.
+pub fn image_to_texture(
+    image: &dyn ImageBGR,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<wgpu::Texture, ScreenCaptureError> {
+    let rgba = image.to_rgba();
+    upload(device, queue, rgba.width(), rgba.height(), rgba.as_raw())
+}
+
+/// Upload the frame to a new texture, this converts the frame unless it is rgba.
+pub fn frame_to_texture(
+    frame: &OwnedFrame,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<wgpu::Texture, ScreenCaptureError> {
+    match frame.as_rgba() {
+        Some(rgba) => upload(device, queue, rgba.width(), rgba.height(), rgba.as_raw()),
+        None => {
+            let rgba = frame.to_rgba();
+            upload(device, queue, rgba.width(), rgba.height(), rgba.as_raw())
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::synthetic::TestPatternCapture;
+    use crate::{Capture, OutputFormat, Resolution};
+
+    #[test]
+    fn test_texture() {
+        let (device, queue) = wgpu::Device::noop(&Default::default());
+        let mut capture = TestPatternCapture::new(Resolution {
+            width: 8,
+            height: 8,
+        });
+        capture.prepare_capture(0, 2, 2, 4, 3).unwrap();
+        capture.capture_image().unwrap();
+        let texture = capture.frame_as_wgpu_texture(&device, &queue).unwrap();
+        assert_eq!((texture.width(), texture.height()), (4, 3));
+        assert_eq!(texture.format(), wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(texture.usage(), TEXTURE_USAGE);
+
+        let image = capture.image().unwrap();
+        let frame = OwnedFrame::from_image(&*image, OutputFormat::Rgb);
+        let texture = frame_to_texture(&frame, &device, &queue).unwrap();
+        assert_eq!((texture.width(), texture.height()), (4, 3));
+
+        let empty = crate::raster_image::RasterImageBGR::from_data(0, 0, vec![]);
+        assert!(image_to_texture(&empty, &device, &queue).is_err());
+    }
+}
@@ -1,85 +1,122 @@
 //! Various io helpers that don't rely on [`image`].
+//!
+//! Each format can be written to and read from a path, or any [`Write`] and [`Read`] through the
+//! functions ending in `_to` and `_from`. Images can be compared with [`compare`].
 use crate::raster_image;
 use crate::{ImageBGR, BGR};
+use std::io::{Read, Write};
 
-/// Reads a ppm image from disk. (or rather ppms written by [`write_ppm`]).
+/// Create the file and write to it through a buffer.
+fn write_file<F: FnOnce(&mut std::io::BufWriter<std::fs::File>) -> std::io::Result<()>>(
+    filename: &str,
+    f: F,
+) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(filename)?);
+    f(&mut file)?;
+    file.flush()
+}
+
+/// Reads a ppm image from disk, both the ascii (P3) and binary (P6) variants with a maximum value
+/// of 255 are supported, as written by [`write_ppm`] and [`write_ppm_binary`].
 pub fn read_ppm(filename: &str) -> Result<Box<dyn ImageBGR>, Box<dyn std::error::Error>> {
-    use std::fs::File;
-    let file = File::open(filename)?;
-    use std::io::{BufRead, BufReader};
-    let br = BufReader::new(file);
-    let mut lines = br.lines();
-    let width: u32;
-    let height: u32;
+    read_ppm_from(&mut std::fs::File::open(filename)?)
+}
+
+/// Reads a ppm image from the reader, see [`read_ppm`].
+pub fn read_ppm_from(
+    reader: &mut impl Read,
+) -> Result<Box<dyn ImageBGR>, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
     fn make_error(v: &str) -> Box<dyn std::error::Error> {
-        Box::new(std::io::Error::new(std::io::ErrorKind::Other, v))
+        Box::new(std::io::Error::other(v))
     }
 
-    // First, read the type, this must be P3
-    let l = lines
-        .next()
-        .ok_or_else(|| make_error("Not enough lines"))??;
-    if l != "P3" {
+    // The header consists of four whitespace separated tokens, comments start with '#'.
+    let mut pos = 0;
+    let next_token = |pos: &mut usize| -> Result<String, Box<dyn std::error::Error>> {
+        loop {
+            match data.get(*pos) {
+                Some(b'#') => {
+                    while data.get(*pos).is_some_and(|c| *c != b'\n') {
+                        *pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => *pos += 1,
+                Some(_) => break,
+                None => return Err(make_error("Not enough data")),
+            }
+        }
+        let start = *pos;
+        while data.get(*pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            *pos += 1;
+        }
+        Ok(String::from_utf8_lossy(&data[start..*pos]).into_owned())
+    };
+
+    let magic = next_token(&mut pos)?;
+    if magic != "P3" && magic != "P6" {
         return Err(make_error("Input format not supported."));
     }
-
-    // This is where we get the resolution.
-    let l = lines
-        .next()
-        .ok_or_else(|| make_error("Not enough lines"))??;
-    let mut values = l.trim().split(' ').map(|x| str::parse::<u32>(x));
-    width = values
-        .next()
-        .ok_or_else(|| make_error("Could not parse width."))??;
-    height = values
-        .next()
-        .ok_or_else(|| make_error("Could not parse height."))??;
-
-    // And check the scaling.
-    let l = lines
-        .next()
-        .ok_or_else(|| make_error("Not enough lines"))??;
-    if l != "255" {
+    let width = next_token(&mut pos)?
+        .parse::<u32>()
+        .map_err(|_| make_error("Could not parse width."))?;
+    let height = next_token(&mut pos)?
+        .parse::<u32>()
+        .map_err(|_| make_error("Could not parse height."))?;
+    if next_token(&mut pos)? != "255" {
         return Err(make_error("Scaling not supported, only 255 supported"));
     }
+    // The size is untrusted, it is checked against the data before allocating the image.
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(3))
+        .ok_or_else(|| make_error("Image too large."))?;
 
-    let mut img: Vec<Vec<BGR>> = Default::default();
-    img.resize(height as usize, vec![]);
-
-    // Now, we iterate over the remaining lines, each holds a row for the image.
-    for (li, l) in lines.enumerate() {
-        let l = l?;
-        // Allocate this row.
-        img[li].resize(width as usize, Default::default());
-        // Finally, parse the row.
-        // https://doc.rust-lang.org/rust-by-example/error/iter_result.html
-        let split = l.trim().split(' ').map(|x| str::parse::<u32>(x));
-        let numbers: Result<Vec<_>, _> = split.collect();
+    let rgb: Vec<u8> = if magic == "P6" {
+        // Exactly one whitespace character separates the header from the pixels.
+        let pixels = data
+            .get(pos + 1..)
+            .and_then(|v| v.get(..size))
+            .ok_or_else(|| make_error("Not enough pixel data"))?;
+        pixels.to_vec()
+    } else {
+        let numbers: Result<Vec<u8>, _> = String::from_utf8_lossy(&data[pos..])
+            .split_ascii_whitespace()
+            .map(str::parse::<u8>)
+            .collect();
         let numbers = numbers?;
-        // Cool, now we have a bunch of numbers, verify the width.
-        if numbers.len() / 3 != width as usize {
+        if numbers.len() != size {
             return Err(make_error(
-                format!("Width is incorrect, got {}", numbers.len() / 3).as_str(),
+                format!("Expected {} values, got {}", size, numbers.len()).as_str(),
             ));
         }
+        numbers
+    };
 
-        // Finally, we can convert the bytes.
-        for i in 0..width as usize {
-            let r = u8::try_from(numbers[i * 3])?;
-            let g = u8::try_from(numbers[i * 3 + 1])?;
-            let b = u8::try_from(numbers[i * 3 + 2])?;
-            img[li][i] = BGR { r, g, b };
-        }
+    let mut img = raster_image::RasterImageBGR::filled(width, height, Default::default());
+    for (i, c) in rgb.chunks_exact(3).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        img.set_pixel(
+            x,
+            y,
+            BGR {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+            },
+        );
     }
-
-    Ok(Box::new(raster_image::RasterImageBGR::from_2d_vec(&img)))
+    Ok(Box::new(img))
 }
 
 /// Dump a ppm file to disk.
 pub fn write_ppm(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
-    use std::fs::File;
-    use std::io::prelude::*;
-    let mut file = File::create(filename)?;
+    write_file(filename, |file| write_ppm_to(img, file))
+}
+
+/// Write a ppm image to the writer, see [`write_ppm`].
+pub fn write_ppm_to(img: &dyn ImageBGR, file: &mut impl Write) -> std::io::Result<()> {
     file.write_all(b"P3\n")?;
     let width = img.width();
     let height = img.height();
@@ -99,12 +136,120 @@ pub fn write_ppm(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Dump a binary ppm (P6) file to disk, this is much smaller and faster than [`write_ppm`].
+pub fn write_ppm_binary(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
+    write_file(filename, |file| write_ppm_binary_to(img, file))
+}
+
+/// Write a binary ppm (P6) image to the writer, see [`write_ppm_binary`].
+pub fn write_ppm_binary_to(img: &dyn ImageBGR, file: &mut impl Write) -> std::io::Result<()> {
+    let width = img.width();
+    let height = img.height();
+    file.write_all(format!("P6\n{} {}\n255\n", width, height).as_ref())?;
+    let mut row: Vec<u8> = vec![0; width as usize * 3];
+    for y in 0..height {
+        for x in 0..width {
+            let color = img.pixel(x, y);
+            row[(x * 3) as usize..(x * 3 + 3) as usize]
+                .copy_from_slice(&[color.r, color.g, color.b]);
+        }
+        file.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Write a pam (P7) file with alpha to disk, unlike the other formats this keeps the alpha.
+#[cfg(feature = "image")]
+pub fn write_pam(img: &image::RgbaImage, filename: &str) -> std::io::Result<()> {
+    write_file(filename, |file| write_pam_to(img, file))
+}
+
+/// Write a pam image to the writer, see [`write_pam`].
+#[cfg(feature = "image")]
+pub fn write_pam_to(img: &image::RgbaImage, file: &mut impl Write) -> std::io::Result<()> {
+    write!(
+        file,
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+        img.width(),
+        img.height()
+    )?;
+    file.write_all(img.as_raw())
+}
+
+/// Read a pam (P7) file from disk, with the `RGB_ALPHA` or `RGB` tuple types and a maximum value of
+/// 255. Images without alpha are opaque.
+#[cfg(feature = "image")]
+pub fn read_pam(filename: &str) -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+    read_pam_from(&mut std::fs::File::open(filename)?)
+}
+
+/// Read a pam image from the reader, see [`read_pam`].
+#[cfg(feature = "image")]
+pub fn read_pam_from(
+    reader: &mut impl Read,
+) -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut lines = data.split(|&c| c == b'\n');
+    let mut pos = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        pos += line.len() + 1;
+        Some(String::from_utf8_lossy(line).trim().to_owned())
+    };
+    if next_line().as_deref() != Some("P7") {
+        return Err("not a pam image".into());
+    }
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    loop {
+        let line = next_line().ok_or("pam header lacks ENDHDR")?;
+        let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+        let value = value.trim();
+        match key {
+            "ENDHDR" => break,
+            "WIDTH" => width = Some(value.parse::<u32>()?),
+            "HEIGHT" => height = Some(value.parse::<u32>()?),
+            "DEPTH" => depth = Some(value.parse::<usize>()?),
+            "MAXVAL" => maxval = Some(value.parse::<u32>()?),
+            "TUPLTYPE" if value != "RGB_ALPHA" && value != "RGB" => {
+                return Err(format!("tuple type {value} not supported").into());
+            }
+            _ => {}
+        }
+    }
+    let (Some(width), Some(height), Some(depth)) = (width, height, depth) else {
+        return Err("pam header lacks the size".into());
+    };
+    if maxval != Some(255) || !(depth == 3 || depth == 4) {
+        return Err("only 8 bit RGB and RGB_ALPHA are supported".into());
+    }
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|v| v.checked_mul(depth))
+        .ok_or("pam image too large")?;
+    let pixels = data
+        .get(pos..)
+        .and_then(|v| v.get(..size))
+        .ok_or("not enough pixel data")?;
+    let rgba = if depth == 4 {
+        pixels.to_vec()
+    } else {
+        pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect()
+    };
+    Ok(image::RgbaImage::from_raw(width, height, rgba).expect("size was checked"))
+}
+
 /// Dump a bmp file to disk, mostly because windows can't open ppm.
 pub fn write_bmp(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
+    write_file(filename, |file| write_bmp_to(img, file))
+}
+
+/// Write a bmp image to the writer, see [`write_bmp`].
+pub fn write_bmp_to(img: &dyn ImageBGR, file: &mut impl Write) -> std::io::Result<()> {
     // Adopted from https://stackoverflow.com/a/62946358
-    use std::fs::File;
-    use std::io::prelude::*;
-    let mut file = File::create(filename)?;
     let width = img.width();
     let height = img.height();
     let pad = (((width as i32) * -3) & 3) as u32;
@@ -144,24 +289,1693 @@ pub fn write_bmp(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Place the image on the system clipboard, to paste it into other applications.
+///
+/// On Windows it is placed as `CF_DIBV5` bitmap. On X11 it is offered as `image/png` by a thread
+/// that owns the clipboard until another application takes it over, so it is lost when the
+/// process exits unless a clipboard manager keeps a copy, images that don't fit in a single request
+/// are sent incrementally. This requires the `png` feature there.
+#[cfg(any(target_os = "windows", feature = "png"))]
+pub fn copy_to_clipboard(img: &dyn ImageBGR) -> Result<(), crate::ScreenCaptureError> {
+    crate::backend::copy_to_clipboard(img)
+}
+
+/// Write a png file to disk, the image is converted to RGB as the alpha channel of the captures
+/// carries no information.
+#[cfg(feature = "png")]
+pub fn write_png(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
+    write_file(filename, |file| write_png_to(img, file))
+}
+
+/// Write a png image to the writer, see [`write_png`].
+#[cfg(feature = "png")]
+pub fn write_png_to(img: &dyn ImageBGR, file: &mut impl Write) -> std::io::Result<()> {
+    write_rgb_png_to(&img.to_rgb(), file)
+}
+
+/// Write an rgb image as png to the writer.
+#[cfg(feature = "png")]
+pub(crate) fn write_rgb_png_to(
+    rgb: &image::RgbImage,
+    file: &mut impl Write,
+) -> std::io::Result<()> {
+    use image::ImageEncoder;
+    image::codecs::png::PngEncoder::new(file)
+        .write_image(
+            rgb,
+            rgb.width(),
+            rgb.height(),
+            image::ExtendedColorType::Rgb8,
+        )
+        .map_err(std::io::Error::other)
+}
+
+/// Descriptive metadata to store with saved images, see [`write_png_with_metadata`] and
+/// [`write_bmp_with_metadata`]. The version of this crate is always stored.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImageMetadata {
+    /// The time at which the image was captured.
+    pub time: Option<std::time::SystemTime>,
+    /// The name of the display that was captured.
+    pub display: Option<String>,
+    /// The region of the display that was captured.
+    pub region: Option<crate::Rect>,
+}
+
+impl ImageMetadata {
+    /// The metadata as key value pairs, the keys follow the png text chunk keywords.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![(
+            "Software",
+            format!("screen_capture {}", env!("CARGO_PKG_VERSION")),
+        )];
+        if let Some(time) = self.time {
+            entries.push(("Creation Time", rfc3339(time)));
+        }
+        if let Some(display) = &self.display {
+            entries.push(("Display", display.clone()));
+        }
+        if let Some(r) = &self.region {
+            let region = format!("{},{},{},{}", r.x, r.y, r.width, r.height);
+            entries.push(("Region", region));
+        }
+        entries
+    }
+}
+
+/// Format the time as RFC 3339 in UTC with milliseconds, like `2023-11-14T22:13:20.000Z`.
+fn rfc3339(time: std::time::SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    // Convert the days to a civil date, following Howard Hinnant's days_from_civil inverse.
+    let z = (seconds / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let t = seconds % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t / 3600,
+        t / 60 % 60,
+        t % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The crc32 used by png chunks.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Write a png file to disk with the metadata stored in text chunks.
+#[cfg(feature = "png")]
+pub fn write_png_with_metadata(
+    img: &dyn ImageBGR,
+    filename: &str,
+    metadata: &ImageMetadata,
+) -> std::io::Result<()> {
+    write_file(filename, |file| {
+        write_png_with_metadata_to(img, file, metadata)
+    })
+}
+
+/// Write a png image with metadata to the writer, see [`write_png_with_metadata`].
+#[cfg(feature = "png")]
+pub fn write_png_with_metadata_to(
+    img: &dyn ImageBGR,
+    file: &mut impl Write,
+    metadata: &ImageMetadata,
+) -> std::io::Result<()> {
+    let mut png = vec![];
+    write_png_to(img, &mut png)?;
+    // The signature and the header chunk, the text chunks follow the header.
+    const HEADER_END: usize = 8 + 4 + 4 + 13 + 4;
+    file.write_all(&png[..HEADER_END])?;
+    for (key, value) in metadata.entries() {
+        let mut chunk = b"tEXt".to_vec();
+        chunk.extend(key.as_bytes());
+        chunk.push(0);
+        // Text chunks are latin-1, replace anything outside of it.
+        chunk.extend(value.chars().map(|c| u8::try_from(c).unwrap_or(b'?')));
+        file.write_all(&(chunk.len() as u32 - 4).to_be_bytes())?;
+        file.write_all(&chunk)?;
+        file.write_all(&crc32(&chunk).to_be_bytes())?;
+    }
+    file.write_all(&png[HEADER_END..])
+}
+
+/// Read the text chunks of a png image, as keyword and text pairs.
+pub fn read_png_text_from(reader: &mut impl Read) -> std::io::Result<Vec<(String, String)>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let invalid = |v: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, v.to_owned());
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(invalid("not a png image"));
+    }
+    let mut texts = vec![];
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + 8 + length + 4;
+        if end > data.len() {
+            return Err(invalid("truncated chunk"));
+        }
+        let chunk = &data[pos + 4..pos + 8 + length];
+        if crc32(chunk).to_be_bytes() != data[end - 4..end] {
+            return Err(invalid("chunk checksum mismatch"));
+        }
+        if chunk.starts_with(b"tEXt") {
+            let body = &chunk[4..];
+            let split = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+            let latin1 = |v: &[u8]| v.iter().map(|&b| b as char).collect::<String>();
+            texts.push((
+                latin1(&body[..split]),
+                latin1(body.get(split + 1..).unwrap_or(&[])),
+            ));
+        }
+        pos = end;
+    }
+    Ok(texts)
+}
+
+/// Write a bmp file to disk, with the metadata in a sidecar file that has `.txt` appended to the
+/// filename, holding a `key: value` line for each entry.
+pub fn write_bmp_with_metadata(
+    img: &dyn ImageBGR,
+    filename: &str,
+    metadata: &ImageMetadata,
+) -> std::io::Result<()> {
+    write_bmp(img, filename)?;
+    let sidecar: String = metadata
+        .entries()
+        .iter()
+        .map(|(key, value)| format!("{key}: {value}\n"))
+        .collect();
+    std::fs::write(format!("{filename}.txt"), sidecar)
+}
+
+/// Write a jpeg file to disk with the provided quality, from 1 to 100.
+#[cfg(feature = "jpeg")]
+pub fn write_jpeg(img: &dyn ImageBGR, filename: &str, quality: u8) -> std::io::Result<()> {
+    write_file(filename, |file| write_jpeg_to(img, file, quality))
+}
+
+/// Write a jpeg image to the writer, see [`write_jpeg`].
+#[cfg(feature = "jpeg")]
+pub fn write_jpeg_to(
+    img: &dyn ImageBGR,
+    file: &mut impl Write,
+    quality: u8,
+) -> std::io::Result<()> {
+    use image::ImageEncoder;
+    let rgb = img.to_rgb();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(file, quality.clamp(1, 100))
+        .write_image(
+            &rgb,
+            rgb.width(),
+            rgb.height(),
+            image::ExtendedColorType::Rgb8,
+        )
+        .map_err(std::io::Error::other)
+}
+
+/// Reads an uncompressed 24 or 32 bit bmp image from disk, like the ones written by [`write_bmp`].
+pub fn read_bmp(
+    filename: &str,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    read_bmp_from(&mut std::fs::File::open(filename)?)
+}
+
+/// Reads a bmp image from the reader, see [`read_bmp`].
+pub fn read_bmp_from(
+    reader: &mut impl Read,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    fn make_error(v: &str) -> Box<dyn std::error::Error> {
+        Box::new(std::io::Error::other(v))
+    }
+    let u32_at = |offset: usize| -> Result<u32, Box<dyn std::error::Error>> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| make_error("Not enough data"))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    };
+    if !data.starts_with(b"BM") {
+        return Err(make_error("Input format not supported."));
+    }
+    let pixel_offset = u32_at(10)? as usize;
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bits = u32_at(26)? >> 16;
+    let compression = u32_at(30)?;
+    // Compression 3 are bitfields, which 32 bit images use to declare the usual BGRA layout.
+    if (bits != 24 && bits != 32) || !(compression == 0 || (bits == 32 && compression == 3)) {
+        return Err(make_error(
+            format!("Only uncompressed 24 or 32 bit supported, got {bits} bit").as_str(),
+        ));
+    }
+    if width <= 0 || height == 0 {
+        return Err(make_error("Invalid dimensions."));
+    }
+    // Rows are stored bottom up, unless the height is negative.
+    let (width, bottom_up) = (width as u32, height > 0);
+    let height = height.unsigned_abs();
+    let bytes_per_pixel = (bits / 8) as usize;
+    let stride = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+    // Check all rows are present before allocating the image, the size is untrusted.
+    let end = (height as usize - 1)
+        .checked_mul(stride)
+        .and_then(|v| v.checked_add(pixel_offset))
+        .and_then(|v| v.checked_add(width as usize * bytes_per_pixel));
+    if end.is_none_or(|end| end > data.len()) {
+        return Err(make_error("Not enough pixel data"));
+    }
+
+    let mut img = raster_image::RasterImageBGR::filled(width, height, Default::default());
+    for y in 0..height {
+        let start = pixel_offset + y as usize * stride;
+        let row = data
+            .get(start..start + width as usize * bytes_per_pixel)
+            .ok_or_else(|| make_error("Not enough pixel data"))?;
+        let y = if bottom_up { height - y - 1 } else { y };
+        for (x, c) in row.chunks_exact(bytes_per_pixel).enumerate() {
+            img.set_pixel(
+                x as u32,
+                y,
+                BGR {
+                    b: c[0],
+                    g: c[1],
+                    r: c[2],
+                },
+            );
+        }
+    }
+    Ok(img)
+}
+
+/// The hash of a pixel into the index of previously seen pixels of the QOI format.
+fn qoi_hash(c: [u8; 4]) -> usize {
+    (c[0] as usize * 3 + c[1] as usize * 5 + c[2] as usize * 7 + c[3] as usize * 11) % 64
+}
+
+/// Dump a qoi file to disk, see <https://qoiformat.org>. This compresses desktop content well
+/// and is much faster than png.
+pub fn write_qoi(img: &dyn ImageBGR, filename: &str) -> std::io::Result<()> {
+    write_file(filename, |file| write_qoi_to(img, file))
+}
+
+/// Write a qoi image to the writer, see [`write_qoi`].
+pub fn write_qoi_to(img: &dyn ImageBGR, file: &mut impl Write) -> std::io::Result<()> {
+    let width = img.width();
+    let height = img.height();
+    let mut out: Vec<u8> = Vec::with_capacity(14 + (width * height) as usize + 8);
+    out.extend_from_slice(b"qoif");
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&height.to_be_bytes());
+    // Three channels, sRGB with linear alpha.
+    out.extend_from_slice(&[3, 0]);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut prev = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+    let total = width as usize * height as usize;
+    for (i, color) in (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| img.pixel(x, y))
+        .enumerate()
+    {
+        let px = [color.r, color.g, color.b, 255];
+        if px == prev {
+            run += 1;
+            if run == 62 || i + 1 == total {
+                out.push(0xc0 | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(0xc0 | (run - 1));
+            run = 0;
+        }
+        let hash = qoi_hash(px);
+        if index[hash] == px {
+            out.push(hash as u8);
+        } else {
+            index[hash] = px;
+            let dr = px[0].wrapping_sub(prev[0]) as i8;
+            let dg = px[1].wrapping_sub(prev[1]) as i8;
+            let db = px[2].wrapping_sub(prev[2]) as i8;
+            let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+            if (-2..=1).contains(&dr) && (-2..=1).contains(&dg) && (-2..=1).contains(&db) {
+                out.push(0x40 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+            } else if (-32..=31).contains(&dg)
+                && (-8..=7).contains(&dr_dg)
+                && (-8..=7).contains(&db_dg)
+            {
+                out.push(0x80 | (dg + 32) as u8);
+                out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                out.extend_from_slice(&[0xfe, px[0], px[1], px[2]]);
+            }
+        }
+        prev = px;
+    }
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    file.write_all(&out)
+}
+
+/// Reads a qoi image from disk, the alpha channel is discarded.
+pub fn read_qoi(
+    filename: &str,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    read_qoi_from(&mut std::fs::File::open(filename)?)
+}
+
+/// Reads a qoi image from the reader, see [`read_qoi`].
+pub fn read_qoi_from(
+    reader: &mut impl Read,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    fn make_error(v: &str) -> Box<dyn std::error::Error> {
+        Box::new(std::io::Error::other(v))
+    }
+    if data.len() < 14 || !data.starts_with(b"qoif") {
+        return Err(make_error("Input format not supported."));
+    }
+    let width = u32::from_be_bytes(data[4..8].try_into()?);
+    let height = u32::from_be_bytes(data[8..12].try_into()?);
+    // A single byte encodes at most a run of 62 pixels, reject sizes the data can't fill before
+    // allocating the image.
+    let capacity = (data.len() - 14).saturating_mul(62) as u64;
+    if width as u64 * height as u64 > capacity {
+        return Err(make_error("Not enough pixel data"));
+    }
+
+    let mut img = raster_image::RasterImageBGR::filled(width, height, Default::default());
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0u8, 0, 0, 255];
+    let mut run = 0u8;
+    let mut pos = 14;
+    let mut byte = || -> Result<u8, Box<dyn std::error::Error>> {
+        let v = *data
+            .get(pos)
+            .ok_or_else(|| make_error("Not enough pixel data"))?;
+        pos += 1;
+        Ok(v)
+    };
+    for y in 0..height {
+        for x in 0..width {
+            if run > 0 {
+                run -= 1;
+            } else {
+                let op = byte()?;
+                match op {
+                    0xfe => px = [byte()?, byte()?, byte()?, px[3]],
+                    0xff => px = [byte()?, byte()?, byte()?, byte()?],
+                    _ => match op >> 6 {
+                        0 => px = index[op as usize],
+                        1 => {
+                            px[0] = px[0].wrapping_add((op >> 4) & 3).wrapping_sub(2);
+                            px[1] = px[1].wrapping_add((op >> 2) & 3).wrapping_sub(2);
+                            px[2] = px[2].wrapping_add(op & 3).wrapping_sub(2);
+                        }
+                        2 => {
+                            let next = byte()?;
+                            let dg = (op & 0x3f).wrapping_sub(32);
+                            px[0] = px[0].wrapping_add(dg.wrapping_sub(8).wrapping_add(next >> 4));
+                            px[1] = px[1].wrapping_add(dg);
+                            px[2] = px[2].wrapping_add(dg.wrapping_sub(8).wrapping_add(next & 0xf));
+                        }
+                        _ => run = op & 0x3f,
+                    },
+                }
+                index[qoi_hash(px)] = px;
+            }
+            img.set_pixel(
+                x,
+                y,
+                BGR {
+                    r: px[0],
+                    g: px[1],
+                    b: px[2],
+                },
+            );
+        }
+    }
+    Ok(img)
+}
+
+/// The first line of the manifest of a tiled image.
+const TILES_HEADER: &str = "# screen_capture tiles v1: x y width height file";
+
+/// Write a large image as a grid of qoi tiles in the directory, with a `manifest.txt` that
+/// describes their placement. The directory is created if it doesn't exist.
+pub fn write_tiled(
+    img: &dyn ImageBGR,
+    directory: &str,
+    columns: u32,
+    rows: u32,
+) -> std::io::Result<()> {
+    let directory = std::path::Path::new(directory);
+    std::fs::create_dir_all(directory)?;
+    let (columns, rows) = (
+        columns.clamp(1, img.width().max(1)),
+        rows.clamp(1, img.height().max(1)),
+    );
+    let mut manifest = format!("{TILES_HEADER}\n{} {}\n", img.width(), img.height());
+    for row in 0..rows {
+        let (y0, y1) = (row * img.height() / rows, (row + 1) * img.height() / rows);
+        for column in 0..columns {
+            let (x0, x1) = (
+                column * img.width() / columns,
+                (column + 1) * img.width() / columns,
+            );
+            let mut data = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+            for y in y0..y1 {
+                let start = (y * img.width()) as usize;
+                data.extend_from_slice(&img.data()[start + x0 as usize..start + x1 as usize]);
+            }
+            let tile = raster_image::RasterImageBGR::from_data(x1 - x0, y1 - y0, data);
+            let name = format!("tile_{row}_{column}.qoi");
+            write_qoi(&tile, &directory.join(&name).to_string_lossy())?;
+            manifest += &format!("{x0} {y0} {} {} {name}\n", x1 - x0, y1 - y0);
+        }
+    }
+    std::fs::write(directory.join("manifest.txt"), manifest)
+}
+
+/// Read an image written by [`write_tiled`] from the directory, reassembling the tiles.
+pub fn read_tiled(
+    directory: &str,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    let directory = std::path::Path::new(directory);
+    let manifest = std::fs::read_to_string(directory.join("manifest.txt"))?;
+    let mut lines = manifest.lines();
+    if lines.next() != Some(TILES_HEADER) {
+        return Err("not a tiled image manifest".into());
+    }
+    let size: Vec<u32> = lines
+        .next()
+        .ok_or("manifest lacks the image size")?
+        .split_whitespace()
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()?;
+    let [width, height] = size[..] else {
+        return Err("malformed image size in manifest".into());
+    };
+    // The size is untrusted, the image is only allocated once the tiles are read and fill it.
+    let mut tiles = vec![];
+    let mut area = 0u64;
+    for line in lines {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [x, y, w, h, name] = parts[..] else {
+            return Err(format!("malformed tile line: {line}").into());
+        };
+        let (x, y, w, h): (u32, u32, u32, u32) = (x.parse()?, y.parse()?, w.parse()?, h.parse()?);
+        let tile = read_qoi(&directory.join(name).to_string_lossy())?;
+        let outside =
+            |start: u32, size: u32, limit: u32| start.checked_add(size).is_none_or(|v| v > limit);
+        if (tile.width(), tile.height()) != (w, h) || outside(x, w, width) || outside(y, h, height)
+        {
+            return Err(format!("tile {name} doesn't match the manifest").into());
+        }
+        area += w as u64 * h as u64;
+        tiles.push((x, y, tile));
+    }
+    if area != width as u64 * height as u64 {
+        return Err("the tiles don't cover the image size in the manifest".into());
+    }
+    let mut img = raster_image::RasterImageBGR::filled(width, height, BGR::default());
+    for (x, y, tile) in tiles {
+        for ty in 0..tile.height() {
+            for tx in 0..tile.width() {
+                img.set_pixel(x + tx, y + ty, tile.pixel(tx, ty));
+            }
+        }
+    }
+    Ok(img)
+}
+
+/// Writes successive frames as a y4m video stream, which tools like ffmpeg can read.
+///
+/// The frames are converted with [`ImageBGR::to_i420`], all frames must have the same size.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    rate: (u32, u32),
+    header_written: bool,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Create a writer for frames of the provided size, at a frame rate of `rate.0 / rate.1`.
+    pub fn new(writer: W, width: u32, height: u32, rate: (u32, u32)) -> Self {
+        Y4mWriter {
+            writer,
+            width,
+            height,
+            rate,
+            header_written: false,
+        }
+    }
+
+    /// Append a frame to the stream.
+    pub fn write_frame(&mut self, img: &dyn ImageBGR) -> std::io::Result<()> {
+        if (img.width(), img.height()) != (self.width, self.height) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{}, the stream is {}x{}",
+                    img.width(),
+                    img.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+        if !self.header_written {
+            writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg",
+                self.width, self.height, self.rate.0, self.rate.1
+            )?;
+            self.header_written = true;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&img.to_i420())
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A running server started by [`mjpeg_server`].
+#[cfg(feature = "mjpeg")]
+pub struct MjpegServer {
+    local_addr: std::net::SocketAddr,
+    running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(feature = "mjpeg")]
+impl MjpegServer {
+    /// The address the server listens on, useful if it was started on port zero.
+    pub fn local_addr(&self) -> std::net::SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections, streams to connected clients end after their next frame.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        use std::sync::atomic::Ordering::Relaxed;
+        self.running.store(false, Relaxed);
+        // Wake up the listener, it is blocked on accepting a connection.
+        let _ = std::net::TcpStream::connect(self.local_addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(feature = "mjpeg")]
+impl Drop for MjpegServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Serve the frames of the capturer as a multipart jpeg stream over http, such that a browser
+/// pointed at the address shows what is being captured. Every request receives the stream,
+/// each client is served from its own thread.
+#[cfg(feature = "mjpeg")]
+pub fn mjpeg_server<A: std::net::ToSocketAddrs>(
+    capturer: crate::ThreadedCapturer,
+    addr: A,
+) -> std::io::Result<MjpegServer> {
+    use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+    use std::sync::Arc;
+    let listener = std::net::TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let running = Arc::new(AtomicBool::new(true));
+    let capturer = Arc::new(capturer);
+
+    // Stream the frames to a client until it disconnects or the server stops.
+    fn serve(
+        mut stream: std::net::TcpStream,
+        capturer: &crate::ThreadedCapturer,
+        running: &AtomicBool,
+    ) -> std::io::Result<()> {
+        // The request itself doesn't matter, read until the end of its headers.
+        let mut reader = std::io::BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        while std::io::BufRead::read_line(&mut reader, &mut line)? > 2 {
+            line.clear();
+        }
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+              Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )?;
+        let mut last = 0;
+        while running.load(Relaxed) {
+            let info = capturer.latest();
+            let frame = match info.result {
+                Ok(frame) if info.counter != last => frame,
+                _ => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    continue;
+                }
+            };
+            last = info.counter;
+            let rgb = image::DynamicImage::ImageRgba8(frame.to_rgba()).to_rgb8();
+            let mut jpeg = vec![];
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 80)
+                .encode_image(&rgb)
+                .map_err(std::io::Error::other)?;
+            write!(
+                stream,
+                "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                jpeg.len()
+            )?;
+            stream.write_all(&jpeg)?;
+            stream.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    let thread = {
+        let running = running.clone();
+        std::thread::Builder::new()
+            .name("mjpeg server".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if !running.load(Relaxed) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let (capturer, running) = (capturer.clone(), running.clone());
+                    std::thread::spawn(move || {
+                        // Clients disconnecting is expected, nothing to report.
+                        let _ = serve(stream, &capturer, &running);
+                    });
+                }
+            })?
+    };
+    Ok(MjpegServer {
+        local_addr,
+        running,
+        thread: Some(thread),
+    })
+}
+
+/// Glyphs of 3 by 5 pixels for the digits, `:` and `.`, each row is three bits, left first.
+const GLYPHS: [(char, [u8; 5]); 12] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+];
+
+/// Scale of the glyphs in the labels of the contact sheet.
+const LABEL_SCALE: u32 = 2;
+
+/// Height of the label below each thumbnail in the contact sheet.
+const LABEL_HEIGHT: u32 = 7 * LABEL_SCALE;
+
+/// Draw the text at the position, characters without a glyph are left blank.
+fn draw_text(img: &mut raster_image::RasterImageBGR, x: u32, y: u32, text: &str, color: BGR) {
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(g, _)| *g == c) else {
+            continue;
+        };
+        let left = x + i as u32 * 4 * LABEL_SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                let px = left + column * LABEL_SCALE;
+                let py = y + row as u32 * LABEL_SCALE;
+                for dy in 0..LABEL_SCALE {
+                    for dx in 0..LABEL_SCALE {
+                        if px + dx < img.width() && py + dy < img.height() {
+                            img.set_pixel(px + dx, py + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Downscale the image such that neither side exceeds max_size, averaging the covered pixels.
+fn thumbnail(img: &dyn ImageBGR, max_size: u32) -> raster_image::RasterImageBGR {
+    let (width, height) = (img.width().max(1), img.height().max(1));
+    let scale = (max_size as f64 / width.max(height) as f64).min(1.0);
+    let w = ((width as f64 * scale).round() as u32).max(1);
+    let h = ((height as f64 * scale).round() as u32).max(1);
+    let mut data = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        let (y0, y1) = (
+            y * height / h,
+            ((y + 1) * height / h).max(y * height / h + 1),
+        );
+        for x in 0..w {
+            let (x0, x1) = (x * width / w, ((x + 1) * width / w).max(x * width / w + 1));
+            let (mut r, mut g, mut b, mut n) = (0u32, 0u32, 0u32, 0u32);
+            for sy in y0..y1.min(img.height()) {
+                for sx in x0..x1.min(img.width()) {
+                    let p = img.pixel(sx, sy);
+                    (r, g, b, n) = (r + p.r as u32, g + p.g as u32, b + p.b as u32, n + 1);
+                }
+            }
+            let n = n.max(1);
+            data.push(BGR {
+                r: (r / n) as u8,
+                g: (g / n) as u8,
+                b: (b / n) as u8,
+            });
+        }
+    }
+    raster_image::RasterImageBGR::from_data(w, h, data)
+}
+
+/// Create a single image with a grid of thumbnails of the frames, each labelled with the time of
+/// day in UTC at which it was captured. Thumbnails are at most thumb_size on either side.
+pub fn contact_sheet(
+    frames: &[(std::time::SystemTime, &dyn ImageBGR)],
+    columns: u32,
+    thumb_size: u32,
+) -> raster_image::RasterImageBGR {
+    const SPACING: u32 = 4;
+    const BACKGROUND: BGR = BGR {
+        r: 32,
+        g: 32,
+        b: 32,
+    };
+    const TEXT: BGR = BGR {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    let columns = columns.max(1).min(frames.len().max(1) as u32);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let cell_width = thumb_size + SPACING;
+    let cell_height = thumb_size + LABEL_HEIGHT + SPACING;
+    let mut sheet = raster_image::RasterImageBGR::filled(
+        columns * cell_width + SPACING,
+        rows * cell_height + SPACING,
+        BACKGROUND,
+    );
+    for (i, (time, img)) in frames.iter().enumerate() {
+        let left = SPACING + (i as u32 % columns) * cell_width;
+        let top = SPACING + (i as u32 / columns) * cell_height;
+        let thumb = thumbnail(*img, thumb_size);
+        for y in 0..thumb.height() {
+            for x in 0..thumb.width() {
+                sheet.set_pixel(left + x, top + y, thumb.pixel(x, y));
+            }
+        }
+        let since_epoch = time
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let seconds = since_epoch.as_secs() % 86400;
+        let label = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            since_epoch.subsec_millis()
+        );
+        let mut label_image =
+            raster_image::RasterImageBGR::filled(thumb_size, LABEL_HEIGHT, BACKGROUND);
+        draw_text(&mut label_image, 0, LABEL_SCALE, &label, TEXT);
+        for y in 0..LABEL_HEIGHT {
+            for x in 0..thumb_size {
+                sheet.set_pixel(left + x, top + thumb_size + y, label_image.pixel(x, y));
+            }
+        }
+    }
+    sheet
+}
+
+/// Incremental SHA-256 digest.
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Sha256 {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+
+    pub(crate) fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() == 64 {
+                let block: [u8; 64] = self.buffer[..].try_into().unwrap();
+                self.compress(&block);
+                self.buffer.clear();
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.buffer.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut out = [0u8; 32];
+        for (chunk, v) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&v.to_be_bytes());
+        }
+        out
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, wi) in Self::K.iter().zip(w) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(wi);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+/// Encode the data as standard base64, with padding.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let v = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |v, (i, &b)| v | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(v >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Read an image in any of the supported formats from disk, the format is determined from the
+/// contents. Png is supported with the `png` feature.
+pub fn read_image(
+    filename: &str,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    read_image_from(&mut std::fs::File::open(filename)?)
+}
+
+/// Read an image in any of the supported formats from the reader, see [`read_image`].
+pub fn read_image_from(
+    reader: &mut impl Read,
+) -> Result<raster_image::RasterImageBGR, Box<dyn std::error::Error>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    match data.get(..4).unwrap_or_default() {
+        [b'P', b'3' | b'6', ..] => {
+            let img = read_ppm_from(&mut data.as_slice())?;
+            Ok(raster_image::RasterImageBGR::new(&*img))
+        }
+        [b'B', b'M', ..] => read_bmp_from(&mut data.as_slice()),
+        b"qoif" => read_qoi_from(&mut data.as_slice()),
+        #[cfg(feature = "image")]
+        [b'P', b'7', ..] => {
+            let rgba = read_pam_from(&mut data.as_slice())?;
+            Ok(crate::OwnedFrame::Rgba(rgba).to_bgr())
+        }
+        #[cfg(feature = "png")]
+        [0x89, b'P', b'N', b'G'] => {
+            let rgb = image::load_from_memory_with_format(&data, image::ImageFormat::Png)?;
+            Ok(crate::OwnedFrame::Rgb(rgb.to_rgb8()).to_bgr())
+        }
+        _ => Err("unsupported image format".into()),
+    }
+}
+
+/// The path of a file in the assets directory of this crate, for tests against golden images.
+#[cfg(test)]
+pub(crate) fn test_asset(name: &str) -> String {
+    format!("{}/assets/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Compare the image against the image in the file, failing if it differs in size or any channel
+/// differs by more than the tolerance.
+pub fn compare_to_file(
+    img: &dyn ImageBGR,
+    filename: &str,
+    tolerance: u8,
+) -> Result<Comparison, Box<dyn std::error::Error>> {
+    let expected = read_image(filename)?;
+    if (img.width(), img.height()) != (expected.width(), expected.height()) {
+        return Err(format!(
+            "size {}x{} differs from {}x{} of {filename}",
+            img.width(),
+            img.height(),
+            expected.width(),
+            expected.height()
+        )
+        .into());
+    }
+    let comparison = compare(img, &expected);
+    if !comparison.within(tolerance) {
+        return Err(format!(
+            "{} pixels differ from {filename}, up to {:?}, psnr {:.2} dB",
+            comparison.differing, comparison.max_error, comparison.psnr
+        )
+        .into());
+    }
+    Ok(comparison)
+}
+
+/// The result of comparing two images of the same size, see [`compare`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Peak signal to noise ratio over all channels in dB, infinite if the images are equal.
+    pub psnr: f64,
+    /// Structural similarity of the luma, averaged over blocks of 8 by 8 pixels, one if equal.
+    pub ssim: f64,
+    /// The largest absolute difference of each channel.
+    pub max_error: BGR,
+    /// The number of pixels that differ.
+    pub differing: usize,
+    /// The absolute difference of each pixel, only set by [`compare_with_diff`].
+    pub diff: Option<raster_image::RasterImageBGR>,
+}
+
+impl Comparison {
+    /// Whether no channel differs by more than the tolerance.
+    pub fn within(&self, tolerance: u8) -> bool {
+        let e = self.max_error;
+        e.r <= tolerance && e.g <= tolerance && e.b <= tolerance
+    }
+}
+
+/// Compare two images, the images must be of the same size.
+pub fn compare(a: &dyn ImageBGR, b: &dyn ImageBGR) -> Comparison {
+    assert_eq!(
+        (a.width(), a.height()),
+        (b.width(), b.height()),
+        "images must be of the same size"
+    );
+    let mut squared = 0u64;
+    let mut max_error = BGR::default();
+    let mut differing = 0;
+    for (p, q) in a.data().iter().zip(b.data()) {
+        let (b, g, r) = (p.b.abs_diff(q.b), p.g.abs_diff(q.g), p.r.abs_diff(q.r));
+        squared += [b, g, r].iter().map(|&v| v as u64 * v as u64).sum::<u64>();
+        max_error.b = max_error.b.max(b);
+        max_error.g = max_error.g.max(g);
+        max_error.r = max_error.r.max(r);
+        differing += usize::from(b != 0 || g != 0 || r != 0);
+    }
+    let mse = squared as f64 / (a.data().len() * 3).max(1) as f64;
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    };
+    Comparison {
+        psnr,
+        ssim: ssim(a, b),
+        max_error,
+        differing,
+        diff: None,
+    }
+}
+
+/// Compare two images like [`compare`], also creating the diff image.
+pub fn compare_with_diff(a: &dyn ImageBGR, b: &dyn ImageBGR) -> Comparison {
+    let mut comparison = compare(a, b);
+    let data = a
+        .data()
+        .iter()
+        .zip(b.data())
+        .map(|(p, q)| BGR {
+            b: p.b.abs_diff(q.b),
+            g: p.g.abs_diff(q.g),
+            r: p.r.abs_diff(q.r),
+        })
+        .collect();
+    comparison.diff = Some(raster_image::RasterImageBGR::from_data(
+        a.width(),
+        a.height(),
+        data,
+    ));
+    comparison
+}
+
+/// The mean structural similarity of the luma of two images of the same size.
+fn ssim(a: &dyn ImageBGR, b: &dyn ImageBGR) -> f64 {
+    const BLOCK: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |p: BGR| 0.299 * p.r as f64 + 0.587 * p.g as f64 + 0.114 * p.b as f64;
+    let (mut total, mut blocks) = (0.0, 0);
+    for y0 in (0..a.height()).step_by(BLOCK as usize) {
+        for x0 in (0..a.width()).step_by(BLOCK as usize) {
+            let mut values = vec![];
+            for y in y0..(y0 + BLOCK).min(a.height()) {
+                for x in x0..(x0 + BLOCK).min(a.width()) {
+                    values.push((luma(a.pixel(x, y)), luma(b.pixel(x, y))));
+                }
+            }
+            let n = values.len() as f64;
+            let mean_a = values.iter().map(|v| v.0).sum::<f64>() / n;
+            let mean_b = values.iter().map(|v| v.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covar) = (0.0, 0.0, 0.0);
+            for (va, vb) in values {
+                var_a += (va - mean_a) * (va - mean_a) / n;
+                var_b += (vb - mean_b) * (vb - mean_b) / n;
+                covar += (va - mean_a) * (vb - mean_b) / n;
+            }
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            blocks += 1;
+        }
+    }
+    if blocks == 0 {
+        1.0
+    } else {
+        total / blocks as f64
+    }
+}
+
 pub trait WriteSupport {
     fn write_ppm(&self, filename: &str) -> std::io::Result<()>;
+    fn write_ppm_binary(&self, filename: &str) -> std::io::Result<()>;
     fn write_bmp(&self, filename: &str) -> std::io::Result<()>;
+    fn write_qoi(&self, filename: &str) -> std::io::Result<()>;
+    #[cfg(feature = "png")]
+    fn write_png(&self, filename: &str) -> std::io::Result<()>;
+    #[cfg(feature = "jpeg")]
+    fn write_jpeg(&self, filename: &str, quality: u8) -> std::io::Result<()>;
 }
 impl WriteSupport for dyn ImageBGR {
     fn write_ppm(&self, filename: &str) -> std::io::Result<()> {
         write_ppm(self, filename)
     }
+    fn write_ppm_binary(&self, filename: &str) -> std::io::Result<()> {
+        write_ppm_binary(self, filename)
+    }
     fn write_bmp(&self, filename: &str) -> std::io::Result<()> {
         write_bmp(self, filename)
     }
+    fn write_qoi(&self, filename: &str) -> std::io::Result<()> {
+        write_qoi(self, filename)
+    }
+    #[cfg(feature = "png")]
+    fn write_png(&self, filename: &str) -> std::io::Result<()> {
+        write_png(self, filename)
+    }
+    #[cfg(feature = "jpeg")]
+    fn write_jpeg(&self, filename: &str, quality: u8) -> std::io::Result<()> {
+        write_jpeg(self, filename, quality)
+    }
 }
 
 impl WriteSupport for crate::raster_image::RasterImageBGR {
     fn write_ppm(&self, filename: &str) -> std::io::Result<()> {
         write_ppm(self, filename)
     }
+    fn write_ppm_binary(&self, filename: &str) -> std::io::Result<()> {
+        write_ppm_binary(self, filename)
+    }
     fn write_bmp(&self, filename: &str) -> std::io::Result<()> {
         write_bmp(self, filename)
     }
+    fn write_qoi(&self, filename: &str) -> std::io::Result<()> {
+        write_qoi(self, filename)
+    }
+    #[cfg(feature = "png")]
+    fn write_png(&self, filename: &str) -> std::io::Result<()> {
+        write_png(self, filename)
+    }
+    #[cfg(feature = "jpeg")]
+    fn write_jpeg(&self, filename: &str, quality: u8) -> std::io::Result<()> {
+        write_jpeg(self, filename, quality)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::raster_image::RasterImageBGR;
+    use crate::tests::TestDir;
+
+    #[test]
+    fn test_bmp_roundtrip() {
+        // An odd width, such that the rows are padded.
+        let img = RasterImageBGR::from_2d_vec(&[
+            vec![
+                BGR { r: 1, g: 2, b: 3 },
+                BGR { r: 4, g: 5, b: 6 },
+                BGR { r: 7, g: 8, b: 9 },
+            ],
+            vec![
+                BGR { r: 0, g: 128, b: 9 },
+                BGR { r: 7, g: 8, b: 13 },
+                BGR::default(),
+            ],
+        ]);
+        let dir = TestDir::new("bmp_roundtrip");
+        let path = dir.join("roundtrip.bmp");
+        img.write_bmp(path.to_str().unwrap()).unwrap();
+        let read = read_bmp(path.to_str().unwrap()).unwrap();
+        assert!(read == img);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_write_png() {
+        let img = RasterImageBGR::filled(3, 2, BGR { r: 1, g: 2, b: 3 });
+        let dir = TestDir::new("write_png");
+        let path = dir.join("write.png");
+        img.write_png(path.to_str().unwrap()).unwrap();
+        let read = image::open(&path).unwrap().to_rgb8();
+        assert_eq!(read.dimensions(), (3, 2));
+        assert_eq!(read.get_pixel(2, 1), &image::Rgb([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_qoi_roundtrip() {
+        // Cover runs, small and larger differences, index hits and full colours.
+        let mut rows = vec![];
+        for y in 0..8u32 {
+            rows.push(
+                (0..70u32)
+                    .map(|x| match (x / 10, y) {
+                        (0, _) => BGR::default(),
+                        (1, _) => BGR {
+                            r: x as u8,
+                            g: 1,
+                            b: 200,
+                        },
+                        (2, _) => BGR {
+                            r: (x * 5) as u8,
+                            g: (x * 6) as u8,
+                            b: (x * 7) as u8,
+                        },
+                        (3, y) => BGR {
+                            r: (x * 37 + y) as u8,
+                            g: (x * 91) as u8,
+                            b: 3,
+                        },
+                        _ if x % 2 == 0 => BGR {
+                            r: 10,
+                            g: 20,
+                            b: 30,
+                        },
+                        _ => BGR {
+                            r: 250,
+                            g: 0,
+                            b: 99,
+                        },
+                    })
+                    .collect(),
+            );
+        }
+        let img = RasterImageBGR::from_2d_vec(&rows);
+        let dir = TestDir::new("qoi_roundtrip");
+        let path = dir.join("roundtrip.qoi");
+        img.write_qoi(path.to_str().unwrap()).unwrap();
+        let read = read_qoi(path.to_str().unwrap()).unwrap();
+        assert!(read == img);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let img = RasterImageBGR::filled(5, 3, BGR { r: 9, g: 8, b: 7 });
+        let mut ppm = vec![];
+        write_ppm_binary_to(&img, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n5 3\n255\n"));
+        let read = read_ppm_from(&mut ppm.as_slice()).unwrap();
+        assert!(RasterImageBGR::new(read.as_ref()) == img);
+
+        let mut bmp = vec![];
+        write_bmp_to(&img, &mut bmp).unwrap();
+        assert!(read_bmp_from(&mut bmp.as_slice()).unwrap() == img);
+
+        let mut qoi = vec![];
+        write_qoi_to(&img, &mut qoi).unwrap();
+        assert!(read_qoi_from(&mut std::io::Cursor::new(qoi)).unwrap() == img);
+    }
+
+    #[test]
+    fn test_y4m_writer() {
+        let white = RasterImageBGR::filled(
+            3,
+            3,
+            BGR {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        );
+        let mut writer = Y4mWriter::new(vec![], 3, 3, (30, 1));
+        writer.write_frame(&white).unwrap();
+        writer
+            .write_frame(&RasterImageBGR::filled(3, 3, BGR::default()))
+            .unwrap();
+        assert!(writer
+            .write_frame(&RasterImageBGR::filled(2, 3, BGR::default()))
+            .is_err());
+        let stream = writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W3 H3 F30:1 Ip A1:1 C420jpeg\n";
+        // Nine luma samples and four of each chroma plane.
+        let frame_len = b"FRAME\n".len() + 9 + 2 * 4;
+        assert!(stream.starts_with(header));
+        assert_eq!(stream.len(), header.len() + 2 * frame_len);
+        let first = &stream[header.len() + 6..header.len() + frame_len];
+        assert_eq!(&first[..9], &[235; 9]);
+        assert_eq!(&first[9..], &[128; 8]);
+        let second = &stream[header.len() + frame_len + 6..];
+        assert_eq!(&second[..9], &[16; 9]);
+    }
+
+    #[cfg(feature = "mjpeg")]
+    #[test]
+    fn test_mjpeg_server() {
+        use crate::synthetic::tests::test_pattern;
+        use crate::ThreadedCapturer;
+        let capturer = ThreadedCapturer::builder()
+            .backend(test_pattern(16, 16))
+            .rate(50.0)
+            .build();
+        let server = mjpeg_server(capturer, "127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut received = vec![];
+        let mut buffer = [0u8; 1024];
+        while !received.windows(4).any(|w| w == [0xff, 0xd8, 0xff, 0xe0]) {
+            let n = stream.read(&mut buffer).unwrap();
+            assert!(n > 0);
+            received.extend_from_slice(&buffer[..n]);
+        }
+        let text = String::from_utf8_lossy(&received);
+        assert!(text.starts_with("HTTP/1.1 200 OK"));
+        assert!(text.contains("--frame\r\nContent-Type: image/jpeg"));
+        server.stop();
+    }
+
+    #[test]
+    fn test_sha256() {
+        let hex = |v: [u8; 32]| v.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let digest = |data: &[u8]| {
+            let mut d = Sha256::new();
+            d.update(data);
+            hex(d.finish())
+        };
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        // Feeding the data in pieces gives the same digest.
+        let mut d = Sha256::new();
+        for piece in [
+            &b"abcdbcdecdefdefgefghfghighij"[..],
+            b"hijkijkljklmklmnlmnomnopnopq",
+        ] {
+            d.update(piece);
+        }
+        assert_eq!(
+            hex(d.finish()),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_golden() {
+        let mut img = RasterImageBGR::filled(4, 3, BGR::default());
+        img.set_gradient(0, 4, 0, 3);
+        let golden = test_asset("gradient_4x3.ppm");
+        assert!(compare_to_file(&img, &golden, 0)
+            .unwrap()
+            .psnr
+            .is_infinite());
+
+        // Every format reads back through read_image.
+        let dir = TestDir::new("golden");
+        let path = dir.join("golden");
+        let path = path.to_string_lossy();
+        write_ppm_binary(&img, &path).unwrap();
+        assert!(read_image(&path).unwrap() == img);
+        write_bmp(&img, &path).unwrap();
+        assert!(read_image(&path).unwrap() == img);
+        write_qoi(&img, &path).unwrap();
+        assert!(read_image(&path).unwrap() == img);
+        #[cfg(feature = "png")]
+        {
+            write_png(&img, &path).unwrap();
+            assert!(read_image(&path).unwrap() == img);
+        }
+
+        // Small deviations pass within the tolerance.
+        let p = img.pixel(1, 1);
+        img.set_pixel(1, 1, BGR { g: p.g + 2, ..p });
+        assert!(compare_to_file(&img, &golden, 0).is_err());
+        assert_eq!(compare_to_file(&img, &golden, 2).unwrap().differing, 1);
+        let small = RasterImageBGR::filled(2, 2, BGR::default());
+        assert!(compare_to_file(&small, &golden, 255).is_err());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_pam_roundtrip() {
+        let mut img = image::RgbaImage::new(3, 2);
+        for (i, p) in img.pixels_mut().enumerate() {
+            *p = image::Rgba([i as u8, 10 * i as u8, 255 - i as u8, 40 * i as u8]);
+        }
+        let mut data = vec![];
+        write_pam_to(&img, &mut data).unwrap();
+        assert_eq!(read_pam_from(&mut data.as_slice()).unwrap(), img);
+        let bgr = read_image_from(&mut data.as_slice()).unwrap();
+        assert_eq!(
+            bgr.pixel(2, 1),
+            BGR {
+                r: 5,
+                g: 50,
+                b: 250
+            }
+        );
+
+        let rgb = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n\x01\x02\x03";
+        let back = read_pam_from(&mut rgb.as_slice()).unwrap();
+        assert_eq!(back.get_pixel(0, 0).0, [1, 2, 3, 255]);
+        let truncated = &data[..data.len() - 1];
+        assert!(read_pam_from(&mut &truncated[..]).is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn test_tiled_roundtrip() {
+        let mut img = RasterImageBGR::filled(13, 7, BGR::default());
+        img.set_gradient(0, 13, 0, 7);
+        let dir = TestDir::new("tiled_roundtrip");
+        let directory = dir.join("tiles");
+        let directory = directory.to_string_lossy();
+        write_tiled(&img, &directory, 3, 2).unwrap();
+        let manifest = std::fs::read_to_string(format!("{directory}/manifest.txt")).unwrap();
+        assert_eq!(manifest.lines().count(), 2 + 6);
+        assert!(manifest.contains("4 3 4 4 tile_1_1.qoi"));
+        let back = read_tiled(&directory).unwrap();
+        assert!(back == img);
+
+        // A size the tiles don't fill is rejected before allocating it.
+        let max = u32::MAX;
+        let huge = manifest.replacen("13 7", &format!("{max} {max}"), 1);
+        std::fs::write(format!("{directory}/manifest.txt"), huge).unwrap();
+        assert!(read_tiled(&directory).is_err());
+        let partial: String = manifest
+            .lines()
+            .take(2 + 5)
+            .map(|l| l.to_owned() + "\n")
+            .collect();
+        std::fs::write(format!("{directory}/manifest.txt"), partial).unwrap();
+        assert!(read_tiled(&directory).is_err());
+    }
+
+    #[test]
+    fn test_contact_sheet() {
+        let red = RasterImageBGR::filled(400, 200, BGR { r: 255, g: 0, b: 0 });
+        let mut blue = RasterImageBGR::filled(100, 300, BGR { r: 0, g: 0, b: 255 });
+        blue.fill_rectangle(0, 100, 0, 150, BGR::default());
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(3_723_004);
+        let frames: [(std::time::SystemTime, &dyn ImageBGR); 3] =
+            [(time, &red), (time, &blue), (time, &red)];
+        let sheet = contact_sheet(&frames, 2, 100);
+        // Two columns and two rows of 100 pixel cells, with the labels and spacing.
+        assert_eq!(sheet.width(), 4 + 2 * 104);
+        assert_eq!(sheet.height(), 4 + 2 * (100 + LABEL_HEIGHT + 4));
+        assert_eq!(sheet.pixel(4, 4), BGR { r: 255, g: 0, b: 0 });
+        assert_eq!(sheet.pixel(4 + 99, 4 + 49), BGR { r: 255, g: 0, b: 0 });
+        // The red thumbnail is 100x50, the rest of the cell is background.
+        assert_eq!(
+            sheet.pixel(4, 4 + 60),
+            BGR {
+                r: 32,
+                g: 32,
+                b: 32
+            }
+        );
+        // The blue thumbnail is 33x100, its top half black.
+        assert_eq!(sheet.pixel(108, 4 + 10), BGR::default());
+        assert_eq!(sheet.pixel(108, 4 + 90), BGR { r: 0, g: 0, b: 255 });
+        // The label starts with a 0 of 01:02:03.004, whose top left pixel is set.
+        let white = BGR {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        assert_eq!(sheet.pixel(4, 4 + 100 + LABEL_SCALE), white);
+        // The last cell is empty.
+        assert_eq!(
+            sheet.pixel(108, 4 + 120),
+            BGR {
+                r: 32,
+                g: 32,
+                b: 32
+            }
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123);
+        assert_eq!(rfc3339(time), "2023-11-14T22:13:20.123Z");
+        assert_eq!(rfc3339(std::time::UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+
+        let metadata = ImageMetadata {
+            time: Some(time),
+            display: Some("DP-2".to_owned()),
+            region: Some(crate::Rect {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+            }),
+        };
+        let img = RasterImageBGR::filled(2, 2, BGR { r: 1, g: 2, b: 3 });
+        let dir = TestDir::new("metadata");
+        let path = dir.join("metadata.bmp");
+        let path = path.to_string_lossy();
+        write_bmp_with_metadata(&img, &path, &metadata).unwrap();
+        let sidecar = std::fs::read_to_string(format!("{path}.txt")).unwrap();
+        assert!(sidecar.starts_with("Software: screen_capture "));
+        assert!(sidecar
+            .contains("Creation Time: 2023-11-14T22:13:20.123Z\nDisplay: DP-2\nRegion: 1,2,3,4\n"));
+
+        #[cfg(feature = "png")]
+        {
+            let mut png = vec![];
+            write_png_with_metadata_to(&img, &mut png, &metadata).unwrap();
+            let texts = read_png_text_from(&mut png.as_slice()).unwrap();
+            assert_eq!(texts.len(), 4);
+            assert_eq!(texts[2], ("Display".to_owned(), "DP-2".to_owned()));
+            let back = image::load_from_memory(&png).unwrap().to_rgb8();
+            assert_eq!(back.get_pixel(1, 1).0, [1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut a = RasterImageBGR::filled(
+            10,
+            10,
+            BGR {
+                r: 10,
+                g: 20,
+                b: 30,
+            },
+        );
+        a.set_gradient(0, 10, 0, 10);
+        let same = compare(&a, &a);
+        assert!(same.psnr.is_infinite());
+        assert_eq!(same.ssim, 1.0);
+        assert_eq!(same.max_error, BGR::default());
+        assert_eq!(same.differing, 0);
+        assert!(same.diff.is_none());
+
+        let mut b = a.clone();
+        let p = a.pixel(3, 4);
+        b.set_pixel(
+            3,
+            4,
+            BGR {
+                r: p.r.wrapping_add(16),
+                ..p
+            },
+        );
+        let changed = compare_with_diff(&a, &b);
+        assert_eq!(changed.max_error, BGR { r: 16, g: 0, b: 0 });
+        assert_eq!(changed.differing, 1);
+        // One channel of one of the 300 values is off by 16.
+        let expected = 10.0 * (255.0f64 * 255.0 / (256.0 / 300.0)).log10();
+        assert!((changed.psnr - expected).abs() < 1e-9);
+        assert!(changed.ssim < 1.0 && changed.ssim > 0.9);
+        let diff = changed.diff.unwrap();
+        assert_eq!(diff.pixel(3, 4), BGR { r: 16, g: 0, b: 0 });
+        assert_eq!(diff.pixel(0, 0), BGR::default());
+    }
+
+    #[test]
+    fn test_malformed() {
+        // Headers declaring huge images with little data fail without panicking or allocating.
+        let max = u32::MAX;
+        assert!(read_ppm_from(&mut format!("P6 {max} {max} 255 abc").as_bytes()).is_err());
+        assert!(read_ppm_from(&mut format!("P3 {max} {max} 255 1 2 3").as_bytes()).is_err());
+        #[cfg(feature = "image")]
+        assert!(read_pam_from(
+            &mut format!("P7\nWIDTH {max}\nHEIGHT {max}\nDEPTH 4\nMAXVAL 255\nENDHDR\n").as_bytes()
+        )
+        .is_err());
+        let mut bmp = vec![0u8; 54];
+        bmp[..2].copy_from_slice(b"BM");
+        bmp[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        bmp[18..22].copy_from_slice(&i32::MAX.to_le_bytes());
+        bmp[22..26].copy_from_slice(&i32::MIN.to_le_bytes());
+        bmp[26..30].copy_from_slice(&(24u32 << 16).to_le_bytes());
+        assert!(read_bmp_from(&mut bmp.as_slice()).is_err());
+        let mut qoi = b"qoif".to_vec();
+        qoi.extend_from_slice(&max.to_be_bytes());
+        qoi.extend_from_slice(&max.to_be_bytes());
+        qoi.extend_from_slice(&[3, 0, 0xfd]);
+        assert!(read_qoi_from(&mut qoi.as_slice()).is_err());
+        assert!(read_image_from(&mut &b"P6"[..]).is_err());
+    }
+
+    #[test]
+    fn test_ppm_roundtrip() {
+        let img = RasterImageBGR::from_2d_vec(&[
+            vec![
+                BGR { r: 1, g: 2, b: 3 },
+                BGR {
+                    r: 10,
+                    g: 32,
+                    b: 255,
+                },
+            ],
+            vec![BGR { r: 0, g: 128, b: 9 }, BGR { r: 7, g: 8, b: 13 }],
+        ]);
+        let dir = TestDir::new("ppm_roundtrip");
+        let ascii = dir.join("ascii.ppm");
+        let binary = dir.join("binary.ppm");
+        img.write_ppm(ascii.to_str().unwrap()).unwrap();
+        img.write_ppm_binary(binary.to_str().unwrap()).unwrap();
+        for path in [ascii, binary] {
+            let read = read_ppm(path.to_str().unwrap()).unwrap();
+            assert!(RasterImageBGR::new(read.as_ref()) == img, "{path:?}");
+        }
+    }
 }
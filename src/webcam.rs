@@ -0,0 +1,159 @@
+//! A virtual webcam on Linux, writing frames to an output device of
+//! [v4l2loopback](https://github.com/umlaeute/v4l2loopback), such that a captured region can be
+//! picked as camera in video conferencing applications. The device is created with:
+//!
+//! ```text
+//! sudo modprobe v4l2loopback video_nr=10 exclusive_caps=1 card_label="Screen"
+//! ```
+use crate::backend::v4l2::*;
+use crate::{CallbackHandle, ImageBGR, ThreadedCapturer};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+/// The pixel format written to the device.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum WebcamFormat {
+    /// Packed, see [`ImageBGR::to_yuyv`], supported by nearly all applications.
+    #[default]
+    Yuyv,
+    /// Planar, see [`ImageBGR::to_nv12`], this requires an even width and height.
+    Nv12,
+}
+
+impl WebcamFormat {
+    /// The width, bytes per line and bytes per frame of the device for frames of the size.
+    fn layout(&self, width: u32, height: u32) -> (u32, u32, u32) {
+        match self {
+            WebcamFormat::Yuyv => {
+                let width = width.div_ceil(2) * 2;
+                (width, width * 2, width * 2 * height)
+            }
+            WebcamFormat::Nv12 => (width, width, width * height * 3 / 2),
+        }
+    }
+}
+
+/// An output device of v4l2loopback, see the [module](self) documentation.
+pub struct VirtualWebcam {
+    device: std::fs::File,
+    width: u32,
+    height: u32,
+    format: WebcamFormat,
+}
+
+impl VirtualWebcam {
+    /// Open the device, like `/dev/video10`, and set its format for frames of the size.
+    pub fn open(
+        path: impl AsRef<std::path::Path>,
+        width: u32,
+        height: u32,
+        format: WebcamFormat,
+    ) -> std::io::Result<VirtualWebcam> {
+        let odd = !width.is_multiple_of(2) || !height.is_multiple_of(2);
+        if width == 0 || height == 0 || (format == WebcamFormat::Nv12 && odd) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{format:?} can't hold frames of {width}x{height}"),
+            ));
+        }
+        let device = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let (device_width, bytesperline, sizeimage) = format.layout(width, height);
+        let mut request = v4l2_format {
+            type_: V4L2_BUF_TYPE_VIDEO_OUTPUT,
+            fmt: v4l2_format_fmt { raw_data: [0; 200] },
+        };
+        request.fmt.pix = v4l2_pix_format {
+            width: device_width,
+            height,
+            pixelformat: match format {
+                WebcamFormat::Yuyv => V4L2_PIX_FMT_YUYV,
+                WebcamFormat::Nv12 => V4L2_PIX_FMT_NV12,
+            },
+            field: V4L2_FIELD_NONE,
+            bytesperline,
+            sizeimage,
+            colorspace: V4L2_COLORSPACE_SMPTE170M,
+            ..Default::default()
+        };
+        if unsafe { libc::ioctl(device.as_raw_fd(), VIDIOC_S_FMT as _, &mut request) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(VirtualWebcam {
+            device,
+            width,
+            height,
+            format,
+        })
+    }
+
+    /// Write a frame, it must have the size the webcam was opened with.
+    pub fn write_frame(&mut self, img: &dyn ImageBGR) -> std::io::Result<()> {
+        if (img.width(), img.height()) != (self.width, self.height) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{}, the webcam is {}x{}",
+                    img.width(),
+                    img.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+        let data = match self.format {
+            WebcamFormat::Yuyv => img.to_yuyv(),
+            WebcamFormat::Nv12 => img.to_nv12(),
+        };
+        self.device.write_all(&data)
+    }
+
+    /// Write the frames of the capturer from its post callback, until that is removed. Frames
+    /// that can't be written are dropped with a warning.
+    pub fn attach(self, capturer: &ThreadedCapturer) -> CallbackHandle {
+        let webcam = Mutex::new(self);
+        capturer.add_post_callback(Arc::new(move |info| {
+            let Ok(frame) = info.result else {
+                return;
+            };
+            if let Err(e) = webcam.lock().unwrap().write_frame(&frame.to_bgr()) {
+                log::warn!("Writing to the virtual webcam failed: {e}");
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::raster_image::RasterImageBGR;
+
+    #[test]
+    fn test_virtual_webcam() {
+        // Not a video device, or an odd size for NV12.
+        assert!(VirtualWebcam::open("/dev/null", 4, 2, WebcamFormat::Yuyv).is_err());
+        let odd = VirtualWebcam::open("/dev/null", 3, 2, WebcamFormat::Nv12);
+        assert_eq!(odd.err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(WebcamFormat::Yuyv.layout(3, 2), (4, 8, 16));
+        assert_eq!(WebcamFormat::Nv12.layout(4, 2), (4, 4, 12));
+
+        let mut webcam = VirtualWebcam {
+            device: std::fs::File::options()
+                .write(true)
+                .open("/dev/null")
+                .unwrap(),
+            width: 3,
+            height: 2,
+            format: WebcamFormat::Yuyv,
+        };
+        webcam
+            .write_frame(&RasterImageBGR::filled(3, 2, Default::default()))
+            .unwrap();
+        assert!(webcam
+            .write_frame(&RasterImageBGR::filled(4, 2, Default::default()))
+            .is_err());
+    }
+}
@@ -10,119 +10,209 @@
 //! Whenever failure happens, we try to reinstantiate the duplicator, this can happen when the
 //! resolution changes, or when we don't have permissions to do a screen capture.
 
+use crate::pixels::RawPixels;
 use crate::*;
-use windows;
 
 use windows::{
     core::Result, core::*, Win32::Graphics::Direct3D11::*, Win32::Graphics::Dxgi::Common::*,
     Win32::Graphics::Dxgi::*,
 };
 
-struct ImageWin {
-    _image: ID3D11Texture2D,
-    mapped: windows::Win32::Graphics::Direct3D11::D3D11_MAPPED_SUBRESOURCE,
+/// A staging texture mapped for reading by the cpu, it is unmapped when dropped.
+struct MappedTexture {
+    texture: ID3D11Texture2D,
+    context: ID3D11DeviceContext,
+    mapped: D3D11_MAPPED_SUBRESOURCE,
     width: u32,
     height: u32,
 }
 
-impl ImageWin {
-    fn new(texture: ID3D11Texture2D) -> Self {
-        // Need to map the texture here to ensure we can read from it later.
-
-        let mut desc: windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC =
-            Default::default();
+impl MappedTexture {
+    /// Map the texture, which must be a staging texture with cpu read access.
+    fn new(texture: ID3D11Texture2D) -> Result<Self> {
+        let mut desc: D3D11_TEXTURE2D_DESC = Default::default();
         unsafe { texture.GetDesc(&mut desc) };
 
-        let width = desc.Width;
-        let height = desc.Height;
-
         // Map the texture, retrieval of device and context and mapping from.
         // https://github.com/Microsoft/graphics-driver-samples/blob/master/render-only-sample/rostest/util.cpp
         // Get the device, get the context, then map the texture.
-        let mapped;
         unsafe {
             let mut device: Option<ID3D11Device> = None;
             texture.GetDevice(&mut device);
-            let device = device.expect("Should have a device associated to it.");
+            let device = device.ok_or_else(|| missing("texture device"))?;
 
             let mut context: Option<ID3D11DeviceContext> = None;
             device.GetImmediateContext(&mut context);
-            let context = context.expect("Should have a context associated to it.");
+            let context = context.ok_or_else(|| missing("immediate context"))?;
 
             // Now that we have the context, we can perform the mapping.
-            mapped = context
-                .Map(
-                    &texture,
-                    0, // subresource
-                    D3D11_MAP_READ,
-                    0,
-                )
-                .expect("Mapping should succeed"); // MapFlags
+            let mapped = context.Map(
+                &texture,
+                0, // subresource
+                D3D11_MAP_READ,
+                0, // MapFlags
+            )?;
+            Ok(MappedTexture {
+                texture,
+                context,
+                mapped,
+                width: desc.Width,
+                height: desc.Height,
+            })
         }
-        ImageWin {
-            width,
-            height,
-            _image: texture,
-            mapped,
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The number of bytes from the start of one row to the next.
+    fn stride(&self) -> usize {
+        self.mapped.RowPitch as usize
+    }
+
+    fn pixels(&self) -> std::result::Result<RawPixels<'_>, ScreenCaptureError> {
+        // The mapping stays valid until this is dropped, and nothing copies into this texture
+        // after it is mapped.
+        unsafe {
+            RawPixels::from_raw(
+                self.mapped.pData as *const u8,
+                self.width,
+                self.height,
+                self.stride(),
+            )
         }
     }
 }
 
+impl Drop for MappedTexture {
+    fn drop(&mut self) {
+        unsafe { self.context.Unmap(&self.texture, 0) };
+    }
+}
+
+struct ImageWin {
+    texture: MappedTexture,
+    /// The pixels without the padding at the end of each row, only if the rows are padded.
+    packed: Option<Vec<BGR>>,
+}
+
+impl ImageWin {
+    fn new(texture: MappedTexture) -> std::result::Result<Self, ScreenCaptureError> {
+        let pixels = texture.pixels()?;
+        let packed = match pixels.contiguous() {
+            Some(_) => None,
+            None => Some(
+                (0..texture.height())
+                    .flat_map(|y| pixels.row(y))
+                    .copied()
+                    .collect(),
+            ),
+        };
+        Ok(ImageWin { texture, packed })
+    }
+
+    fn pixels(&self) -> RawPixels<'_> {
+        self.texture
+            .pixels()
+            .expect("pixels were checked on creation")
+    }
+}
+
 impl ImageBGR for ImageWin {
     fn width(&self) -> u32 {
-        self.width
+        self.texture.width()
     }
     fn height(&self) -> u32 {
-        self.height
+        self.texture.height()
     }
 
     fn pixel(&self, x: u32, y: u32) -> BGR {
-        if x > self.width || y > self.height {
-            panic!("Retrieved out of bounds ({}, {})", x, y);
+        self.pixels().pixel(x, y)
+    }
+
+    fn data(&self) -> &[BGR] {
+        match &self.packed {
+            Some(packed) => packed,
+            None => self
+                .pixels()
+                .contiguous()
+                .expect("rows were checked on creation"),
         }
-        // Finally, we can now do the whole casting dance on the mappe data, and calculate what to retrieve.
-        // const uint8_t* data = reinterpret_cast<const uint8_t*>(mapped_.pData);
-        // const uint8_t stride = (mapped_.RowPitch / getWidth());
-        // return (*reinterpret_cast<const uint32_t*>(data + y * mapped_.RowPitch + x * stride)) & 0x00FFFFFF;
+    }
+}
+
+/// A texture shared with other processes through an NT handle, guarded by a keyed mutex, the
+/// handle is closed when dropped.
+struct SharedTextureWin {
+    texture: ID3D11Texture2D,
+    mutex: IDXGIKeyedMutex,
+    handle: windows::Win32::Foundation::HANDLE,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+}
+
+impl SharedTextureWin {
+    /// Create a shared texture of the size and format of the image on its device.
+    fn new(device: &ID3D11Device, image: &D3D11_TEXTURE2D_DESC) -> Result<Self> {
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: image.Width,
+            Height: image.Height,
+            Format: image.Format,
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET,
+            MiscFlags: D3D11_RESOURCE_MISC_SHARED_NTHANDLE | D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX,
+            ..Default::default()
+        };
         unsafe {
-            // println!("Image: {:?}", self.image.unwrap());
-            // Do some pointer magic and reach into the data, do a few casts and we're golden.
-            let data =
-                std::mem::transmute::<*const core::ffi::c_void, *const u8>(self.mapped.pData);
-            let stride = (self.mapped.RowPitch / self.width) as u32;
-            // println!("rowpitch {}", self.mapped.RowPitch); 7680 for 1920
-            // println!("stride {}", stride); 4
-            let as_integer = *std::mem::transmute::<*const u8, *const u32>(
-                data.offset((y * self.mapped.RowPitch + x * stride) as isize)
-                    .try_into()
-                    .unwrap(),
-            );
-            let masked = as_integer & 0x00FFFFFF;
-            // println!("as integer: {}", as_integer);
-            return BGR {
-                r: ((masked >> 16) & 0xFF) as u8,
-                g: ((masked >> 8) & 0xFF) as u8,
-                b: (masked & 0xFF) as u8,
-            };
+            let texture = device.CreateTexture2D(&desc, std::ptr::null())?;
+            let mutex: IDXGIKeyedMutex = texture.cast()?;
+            let resource: IDXGIResource1 = texture.cast()?;
+            let handle = resource.CreateSharedHandle(
+                std::ptr::null(),
+                DXGI_SHARED_RESOURCE_READ | DXGI_SHARED_RESOURCE_WRITE,
+                windows::Win32::Foundation::PWSTR::default(),
+            )?;
+            Ok(SharedTextureWin {
+                texture,
+                mutex,
+                handle,
+                width: desc.Width,
+                height: desc.Height,
+                format: desc.Format,
+            })
         }
     }
 
-    fn data(&self) -> &[BGR] {
-        // Should always have an image.
+    /// Copy the source into this texture while holding the keyed mutex.
+    fn copy_from(&self, context: &ID3D11DeviceContext, source: &ID3D11Texture2D) -> Result<()> {
         unsafe {
-            let data =
-                std::mem::transmute::<*const core::ffi::c_void, *const BGR>(self.mapped.pData);
-            let width = self.width as usize;
-            let height = self.height as usize;
-            let stride = (self.mapped.RowPitch / self.width) as u32;
-            assert!(stride == 4);
-            assert!(self.mapped.RowPitch / stride == self.width);
-            let len = width * height;
-            std::slice::from_raw_parts(data, len)
+            // Consumers hold the mutex only briefly, the watchdog of the capturer reports a
+            // consumer that never releases it.
+            self.mutex.AcquireSync(SharedTexture::KEY, u32::MAX)?;
+            context.CopyResource(&self.texture, source);
+            self.mutex.ReleaseSync(SharedTexture::KEY)
         }
     }
 }
 
+impl Drop for SharedTextureWin {
+    fn drop(&mut self) {
+        unsafe { windows::Win32::Foundation::CloseHandle(self.handle) };
+    }
+}
+
 // For d3d12 we could follow  https://github.com/microsoft/windows-samples-rs/blob/5d67b33e7115ec1dd4f8448301bf6ce794c93b5f/direct3d12/src/main.rs#L204-L234.
 
 #[derive(Default)]
@@ -133,9 +223,21 @@ struct CaptureWin {
     output: Option<IDXGIOutput>,
     duplicator: Option<IDXGIOutputDuplication>,
 
+    /// The latest frame, on the graphics device.
     image: Option<ID3D11Texture2D>,
+    /// The texture shared by [`Capture::shared_texture`], created on first use.
+    shared: Option<SharedTextureWin>,
+
+    /// The options the device was created with, used by [`Capture::reset`].
+    options: BackendOptions,
+    /// The display and region of the last [`CaptureWin::prepare`], used by [`Capture::reset`].
+    prepared: Option<(u32, Rect)>,
 }
 
+// The d3d11 device is free threaded, the immediate context and the duplication are not, but
+// these are only used through this struct, so it can be moved between threads.
+unsafe impl Send for CaptureWin {}
+
 impl Drop for CaptureWin {
     fn drop(&mut self) {}
 }
@@ -150,12 +252,16 @@ fn from_wide(arr: &[u16]) -> OsString {
 }
 
 impl CaptureWin {
-    fn init_adaptor(&mut self) -> Result<()> {
+    fn init_adaptor(&mut self, debug_layer: bool) -> Result<()> {
         // let (factory, device) = create_device().expect("Must have a device.");
         // let adaptor = hardware_adapter(&factory).expect("Must have an adaptor.");
         // self.adaptor = Some(adaptor);
 
-        let dxgi_factory_flags = DXGI_CREATE_FACTORY_DEBUG;
+        let dxgi_factory_flags = if debug_layer {
+            DXGI_CREATE_FACTORY_DEBUG
+        } else {
+            0
+        };
         let factory: IDXGIFactory4 = unsafe { CreateDXGIFactory2(dxgi_factory_flags) }?;
 
         for i in 0.. {
@@ -171,7 +277,7 @@ impl CaptureWin {
             }
 
             // Print some info about the adapter.
-            println!(
+            log::info!(
                 "Adapter {} -> {:#?} with {} memory",
                 i,
                 from_wide(&desc.Description),
@@ -180,9 +286,11 @@ impl CaptureWin {
 
             // Instantiate the d3d11 device now.
             let sdk_version = windows::Win32::Graphics::Direct3D11::D3D11_SDK_VERSION;
-            let create_flags =
-                windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_BGRA_SUPPORT
-                    | windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_DEBUG;
+            let mut create_flags =
+                windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_BGRA_SUPPORT;
+            if debug_layer {
+                create_flags |= windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_DEBUG;
+            }
             let mut level_used = windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_9_3;
             let feature_levels = [
                 windows::Win32::Graphics::Direct3D::D3D_FEATURE_LEVEL_11_0,
@@ -218,19 +326,13 @@ impl CaptureWin {
     fn init_output(&mut self, desired: u32) -> Result<()> {
         // Obtain the video outputs used by this adaptor.
         // Is the primary screen always the zeroth index??
-        let adaptor = self
-            .adaptor
-            .as_ref()
-            .expect("Must be called with an adaptor");
+        let adaptor = self.adaptor.as_ref().ok_or_else(|| missing("adaptor"))?;
         let mut output_index: u32 = 0;
         unsafe {
-            let mut res = adaptor.EnumOutputs(output_index);
-            while res.is_ok() {
-                // println!("idxgiouptut:");
-                let output = res.unwrap();
+            while let Ok(output) = adaptor.EnumOutputs(output_index) {
                 let desc = output.GetDesc()?;
                 if desired == output_index {
-                    println!(
+                    log::debug!(
                         "Found desired output: {}, name: {}, monitor: {}",
                         output_index,
                         OsString::from_wide(&desc.DeviceName)
@@ -241,15 +343,14 @@ impl CaptureWin {
                     self.output = Some(output);
                     return Ok(());
                 }
-                output_index = output_index + 1;
-                res = adaptor.EnumOutputs(output_index);
+                output_index += 1;
             }
         }
         Err(windows::core::Error::OK) // Just to make an error without failure information.
     }
 
     fn init_duplicator(&mut self) -> Result<()> {
-        let output = self.output.as_ref().expect("Must have an output");
+        let output = self.output.as_ref().ok_or_else(|| missing("output"))?;
         self.duplicator = None;
 
         unsafe {
@@ -261,19 +362,13 @@ impl CaptureWin {
             // desc.Monitor
             // );
 
-            let output1: Result<IDXGIOutput1> = output.cast();
-            let output1 = output1.expect("Should have succeeded.");
+            let output1: IDXGIOutput1 = output.cast()?;
             // let output1 = output.GetParent::<&IDXGIOutput1>().expect("Yes");
             // From C++, the following can fail with:
             //  E_ACCESSDENIED, when on fullscreen uac prompt
             //  DXGI_ERROR_SESSION_DISCONNECTED, somehow.
-            self.duplicator =
-                Some(output1.DuplicateOutput(self.device.as_ref().expect("Must have a device"))?);
-
-            let duplicator = self
-                .duplicator
-                .as_ref()
-                .expect("Must have a duplicator now");
+            let device = self.device.as_ref().ok_or_else(|| missing("device"))?;
+            let duplicator = self.duplicator.insert(output1.DuplicateOutput(device)?);
             let mut desc: DXGI_OUTDUPL_DESC = DXGI_OUTDUPL_DESC {
                 ModeDesc: DXGI_MODE_DESC {
                     Width: 0,
@@ -290,7 +385,7 @@ impl CaptureWin {
                 DesktopImageInSystemMemory: windows::Win32::Foundation::BOOL(0),
             };
             duplicator.GetDesc(&mut desc);
-            println!(
+            log::info!(
                 "Duplicator initialised: {}x{} @ {}/{}, in memory: {}",
                 desc.ModeDesc.Width,
                 desc.ModeDesc.Height,
@@ -302,19 +397,40 @@ impl CaptureWin {
         Ok(())
     }
 
-    pub fn new() -> CaptureWin {
-        let mut n: CaptureWin = Default::default();
-        n.init_adaptor()
-            .expect("Should have an adaptor and d3d11 device now.");
-        n
+    pub fn new(options: &BackendOptions) -> std::result::Result<CaptureWin, ScreenCaptureError> {
+        let mut n = CaptureWin::default();
+        n.options = options.clone();
+        n.init_adaptor(options.debug_layer).map_err(|e| {
+            ScreenCaptureError::Initialisation(ErrorDetail::with_os_error(
+                format!("failed to obtain an adaptor and d3d11 device: {:?}", e),
+                OsError::Hresult(e.code().0),
+            ))
+        })?;
+        Ok(n)
     }
 
-    pub fn prepare(&mut self, display: u32, _x: u32, _y: u32, _width: u32, _height: u32) -> bool {
-        self.init_output(display)
-            .expect("Should be able to setup the output.");
-        self.init_duplicator()
-            .expect("Should be able to get the duplicator.");
-        true
+    /// Prepare the output, the entire output is captured so the region is only validated.
+    pub fn prepare(
+        &mut self,
+        display: u32,
+        region: Rect,
+    ) -> std::result::Result<(), ScreenCaptureError> {
+        self.prepared = Some((display, region));
+        self.init_output(display).map_err(|e| {
+            ScreenCaptureError::Initialisation(ErrorDetail::with_os_error(
+                format!("failed to set up output {}: {:?}", display, e),
+                OsError::Hresult(e.code().0),
+            ))
+        })?;
+        let desc = unsafe { self.output.as_ref().map(|o| o.GetDesc()) };
+        if let Some(Ok(desc)) = desc {
+            let rect = desc.DesktopCoordinates;
+            region.clamped(Resolution {
+                width: (rect.right - rect.left) as u32,
+                height: (rect.bottom - rect.top) as u32,
+            })?;
+        }
+        self.init_duplicator().map_err(to_capture_error)
     }
 
     pub fn capture(&mut self) -> Result<()> {
@@ -329,11 +445,12 @@ impl CaptureWin {
         let mut frame_info: windows::Win32::Graphics::Dxgi::DXGI_OUTDUPL_FRAME_INFO =
             Default::default();
         let mut pp_desktop_resource: Option<IDXGIResource> = None;
+        let duplicator = self
+            .duplicator
+            .as_ref()
+            .ok_or_else(|| missing("duplicator"))?;
         let res = unsafe {
-            self.duplicator
-                .as_ref()
-                .expect("Must have duplicator")
-                .AcquireNextFrame(timeout_in_ms, &mut frame_info, &mut pp_desktop_resource)
+            duplicator.AcquireNextFrame(timeout_in_ms, &mut frame_info, &mut pp_desktop_resource)
         };
 
         if let Err(ref r) = res {
@@ -351,28 +468,21 @@ impl CaptureWin {
                     return Ok(()); // likely no draw events since last frame, return ok since we have a frame to show.
                 }
                 // Well, we timed out, and we don't have any image... bummer.
-                return Err(windows::core::Error::OK); // Just to make an error without failure information.
+                return Err(r.clone());
             } else {
-                println!("Unhandled error!: {:?}", r);
+                log::warn!("Unhandled error acquiring a frame: {:?}", r);
                 unsafe {
-                    self.duplicator
-                        .as_ref()
-                        .expect("Should have a duplicator.")
-                        .ReleaseFrame()?;
+                    duplicator.ReleaseFrame()?;
                 }
                 return Err(windows::core::Error::OK); // Just to make an error without failure information.
             }
         }
 
-        // Well, we got here, res must be ok.
-        let _ok = res.expect("Should be ok.");
-
         // Now, we can do something with textures and all that.
-        let texture: Result<ID3D11Texture2D> = pp_desktop_resource
+        let frame: ID3D11Texture2D = pp_desktop_resource
             .as_ref()
-            .expect("Should be resource")
-            .cast();
-        let frame = texture.expect("Must be a texture.");
+            .ok_or_else(|| missing("desktop resource"))?
+            .cast()?;
         let mut tex_desc: windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC =
             Default::default();
         unsafe { frame.GetDesc(&mut tex_desc) };
@@ -383,55 +493,101 @@ impl CaptureWin {
             unsafe { img.GetDesc(&mut img_desc) };
         }
 
-        // Here, we create an texture that will be mapped.
+        // Here, we create the texture that holds the frame, it is copied from by image() and
+        // shared_texture().
         if self.image.is_none()
             || img_desc.Width != tex_desc.Width
             || img_desc.Height != tex_desc.Height
         {
             // No mapped image to use yet, or size is different. Create a new image using the device.
-            let mut new_img: windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC =
-                Default::default();
-            new_img.Width = tex_desc.Width;
-            new_img.Height = tex_desc.Height;
-            new_img.Format = tex_desc.Format;
-            new_img.MipLevels = 1; // from C++ side.
-            new_img.ArraySize = 1; // from C++ side.
-            new_img.SampleDesc.Count = 1; // from C++ side.
-            new_img.Usage = windows::Win32::Graphics::Direct3D11::D3D11_USAGE_STAGING;
-            new_img.CPUAccessFlags = windows::Win32::Graphics::Direct3D11::D3D11_CPU_ACCESS_READ;
+            let new_img = D3D11_TEXTURE2D_DESC {
+                Width: tex_desc.Width,
+                Height: tex_desc.Height,
+                Format: tex_desc.Format,
+                MipLevels: 1, // from C++ side.
+                ArraySize: 1, // from C++ side.
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1, // from C++ side.
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_DEFAULT,
+                ..Default::default()
+            };
 
             self.image = Some(unsafe {
                 self.device
                     .as_ref()
-                    .expect("Must have device")
-                    .CreateTexture2D(
-                        &new_img,
-                        0 as *const windows::Win32::Graphics::Direct3D11::D3D11_SUBRESOURCE_DATA,
-                    )?
+                    .ok_or_else(|| missing("device"))?
+                    .CreateTexture2D(&new_img, std::ptr::null())?
             });
         }
 
         // Finally, we are at the end of all of this and we can actually copy the resource.
+        let context = self
+            .device_context
+            .as_ref()
+            .ok_or_else(|| missing("device context"))?;
+        let image = self.image.as_ref().ok_or_else(|| missing("image"))?;
+        let duplicator = self
+            .duplicator
+            .as_ref()
+            .ok_or_else(|| missing("duplicator"))?;
         unsafe {
-            self.device_context
-                .as_ref()
-                .expect("Should have a device context.")
-                .CopyResource(self.image.as_ref().unwrap(), frame);
-            self.duplicator
-                .as_ref()
-                .expect("Should have a duplicator.")
-                .ReleaseFrame()?;
+            context.CopyResource(image, &frame);
+            duplicator.ReleaseFrame()?;
         }
         Ok(())
     }
 
-    fn image(&mut self) -> Result<ImageWin> {
+    /// Retrieve and clear the messages stored by the debug layer, empty if it isn't enabled.
+    fn get_debug_messages(&self) -> Vec<String> {
+        let mut messages = vec![];
+        let queue: ID3D11InfoQueue = match self.device.as_ref().map(|d| d.cast()) {
+            Some(Ok(queue)) => queue,
+            _ => return messages,
+        };
+        unsafe {
+            for i in 0..queue.GetNumStoredMessages() {
+                // First retrieve the length, then the message itself.
+                let mut length: usize = 0;
+                if queue
+                    .GetMessage(i, std::ptr::null_mut(), &mut length)
+                    .is_err()
+                {
+                    continue;
+                }
+                // Use u64 storage to ensure the message struct is aligned.
+                let mut storage = vec![0u64; length.div_ceil(8)];
+                let message = storage.as_mut_ptr() as *mut D3D11_MESSAGE;
+                if queue.GetMessage(i, message, &mut length).is_err() {
+                    continue;
+                }
+                let description = std::slice::from_raw_parts(
+                    (*message).pDescription as *const u8,
+                    (*message).DescriptionByteLength,
+                );
+                messages.push(
+                    String::from_utf8_lossy(description)
+                        .trim_end_matches('\0')
+                        .to_owned(),
+                );
+            }
+            queue.ClearStoredMessages();
+        }
+        messages
+    }
+
+    /// Log the messages stored by the debug layer, used when an error occurs.
+    fn dump_debug_messages(&self) {
+        for message in self.get_debug_messages() {
+            log::warn!("D3D11: {}", message);
+        }
+    }
+
+    fn image(&mut self) -> Result<MappedTexture> {
         // Need to make a new image here now, because we can't copy into mapped images, so we need to ensure we hand off a
         // fresh image.
-        let image = self
-            .image
-            .as_ref()
-            .expect("Must have an image, can't retrieve one without.");
+        let image = self.image.as_ref().ok_or_else(|| missing("image"))?;
 
         let mut tex_desc: windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC =
             Default::default();
@@ -439,59 +595,620 @@ impl CaptureWin {
             image.GetDesc(&mut tex_desc);
         }
 
-        let mut new_img: windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC =
-            Default::default();
-        new_img.Width = tex_desc.Width;
-        new_img.Height = tex_desc.Height;
-        new_img.Format = tex_desc.Format;
-        new_img.MipLevels = 1; // from C++ side.
-        new_img.ArraySize = 1; // from C++ side.
-        new_img.SampleDesc.Count = 1; // from C++ side.
-        new_img.Usage = windows::Win32::Graphics::Direct3D11::D3D11_USAGE_STAGING;
-        new_img.CPUAccessFlags = windows::Win32::Graphics::Direct3D11::D3D11_CPU_ACCESS_READ;
-        let device = self.device.as_ref().expect("Must have a device");
+        let new_img = D3D11_TEXTURE2D_DESC {
+            Width: tex_desc.Width,
+            Height: tex_desc.Height,
+            Format: tex_desc.Format,
+            MipLevels: 1, // from C++ side.
+            ArraySize: 1, // from C++ side.
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1, // from C++ side.
+                Quality: 0,
+            },
+            Usage: D3D11_USAGE_STAGING,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+            ..Default::default()
+        };
+        let device = self.device.as_ref().ok_or_else(|| missing("device"))?;
         let new_texture = unsafe {
             // Need to wrap this into a releasing thing.
-            device.CreateTexture2D(
-                &new_img,
-                0 as *const windows::Win32::Graphics::Direct3D11::D3D11_SUBRESOURCE_DATA,
-            )?
+            device.CreateTexture2D(&new_img, std::ptr::null())?
         };
         unsafe {
             self.device_context
                 .as_ref()
-                .expect("Should have a device context.")
+                .ok_or_else(|| missing("device context"))?
                 .CopyResource(&new_texture, image);
         }
 
-        Ok(ImageWin::new(new_texture))
+        MappedTexture::new(new_texture)
+    }
+
+    /// Copy the latest frame into the shared texture, recreating it if the size or format changed.
+    fn share(&mut self) -> Result<&SharedTextureWin> {
+        let image = self.image.as_ref().ok_or_else(|| missing("image"))?;
+        let mut desc: D3D11_TEXTURE2D_DESC = Default::default();
+        unsafe { image.GetDesc(&mut desc) };
+        let stale = match &self.shared {
+            Some(shared) => {
+                (shared.width, shared.height, shared.format)
+                    != (desc.Width, desc.Height, desc.Format)
+            }
+            None => true,
+        };
+        if stale {
+            self.shared = None;
+            let device = self.device.as_ref().ok_or_else(|| missing("device"))?;
+            self.shared = Some(SharedTextureWin::new(device, &desc)?);
+        }
+        let context = self
+            .device_context
+            .as_ref()
+            .ok_or_else(|| missing("device context"))?;
+        let shared = self
+            .shared
+            .as_ref()
+            .ok_or_else(|| missing("shared texture"))?;
+        shared.copy_from(context, image)?;
+        Ok(shared)
+    }
+}
+
+/// Error for a d3d11 or dxgi object that should be present but is not, this maps to a transient error.
+fn missing(what: &str) -> windows::core::Error {
+    log::debug!("No {} available", what);
+    windows::core::Error::OK // Just to make an error without failure information.
+}
+
+/// Convert the windows error into the crate's error type.
+fn to_capture_error(e: windows::core::Error) -> ScreenCaptureError {
+    let code = e.code();
+    let message = ErrorDetail::with_os_error(format!("{:?}", e), OsError::Hresult(code.0));
+    if code == windows::Win32::Graphics::Dxgi::DXGI_ERROR_ACCESS_LOST {
+        ScreenCaptureError::LostCapture(message)
+    } else if code == windows::Win32::Foundation::E_ACCESSDENIED {
+        ScreenCaptureError::PermissionDenied(message)
+    } else if code == windows::Win32::Graphics::Dxgi::DXGI_ERROR_WAIT_TIMEOUT {
+        ScreenCaptureError::Timeout
+    } else if code == windows::Win32::Graphics::Dxgi::DXGI_ERROR_DEVICE_REMOVED
+        || code == windows::Win32::Graphics::Dxgi::DXGI_ERROR_DEVICE_RESET
+    {
+        ScreenCaptureError::Disconnected
+    } else if code == windows::Win32::Graphics::Dxgi::DXGI_ERROR_UNSUPPORTED {
+        ScreenCaptureError::BackendUnavailable {
+            backend: "Desktop Duplication".into(),
+        }
+    } else if code == windows::Win32::Foundation::S_OK {
+        // The errors made without failure information signal no frame was available.
+        ScreenCaptureError::Transient(message)
+    } else {
+        ScreenCaptureError::Other(message)
     }
 }
 
 impl Capture for CaptureWin {
-    fn capture_image(&mut self) -> bool {
-        let res = CaptureWin::capture(self);
-        return res.is_ok();
+    fn capture_image(&mut self) -> std::result::Result<(), ScreenCaptureError> {
+        CaptureWin::capture(self).map_err(|e| {
+            self.dump_debug_messages();
+            to_capture_error(e)
+        })
     }
-    fn image(&mut self) -> std::result::Result<Box<dyn ImageBGR>, ()> {
-        Ok(Box::<ImageWin>::new(
-            CaptureWin::image(self).map_err(|_| ())?,
-        ))
+    fn image(&mut self) -> std::result::Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        let texture = CaptureWin::image(self).map_err(to_capture_error)?;
+        Ok(Box::new(ImageWin::new(texture)?))
     }
 
-    fn resolution(&mut self) -> Resolution {
-        Resolution {
-            width: 0,
-            height: 0,
+    /// The bounding box of the outputs attached to the adaptor.
+    fn resolution(&mut self) -> std::result::Result<Resolution, ScreenCaptureError> {
+        let adaptor = self.adaptor.as_ref().ok_or_else(|| {
+            ScreenCaptureError::Initialisation("no adaptor, cannot retrieve the resolution".into())
+        })?;
+        let (mut left, mut top, mut right, mut bottom) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        let mut output_index: u32 = 0;
+        unsafe {
+            while let Ok(output) = adaptor.EnumOutputs(output_index) {
+                let rect = output
+                    .GetDesc()
+                    .map_err(to_capture_error)?
+                    .DesktopCoordinates;
+                left = left.min(rect.left);
+                top = top.min(rect.top);
+                right = right.max(rect.right);
+                bottom = bottom.max(rect.bottom);
+                output_index += 1;
+            }
         }
+        if output_index == 0 {
+            return Err(ScreenCaptureError::Disconnected);
+        }
+        Ok(Resolution {
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        })
+    }
+
+    fn prepare_capture(
+        &mut self,
+        display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> std::result::Result<(), ScreenCaptureError> {
+        let region = Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        CaptureWin::prepare(self, display, region)
+    }
+
+    /// Recreate the device, like after it was removed, and prepare the last prepared output again.
+    fn reset(&mut self) -> std::result::Result<(), ScreenCaptureError> {
+        let prepared = self.prepared;
+        *self = CaptureWin::new(&self.options)?;
+        match prepared {
+            Some((display, region)) => self.prepare(display, region),
+            None => Ok(()),
+        }
+    }
+
+    fn debug_messages(&mut self) -> Vec<String> {
+        self.get_debug_messages()
+    }
+
+    fn shared_texture(&mut self) -> std::result::Result<SharedTexture, ScreenCaptureError> {
+        let shared = self.share().map_err(to_capture_error)?;
+        Ok(SharedTexture {
+            handle: shared.handle.0,
+            width: shared.width,
+            height: shared.height,
+            format: shared.format,
+        })
+    }
+
+    fn name(&self) -> &str {
+        "Desktop Duplication"
+    }
+
+    fn displays(&mut self) -> Vec<DisplayInfo> {
+        let mut displays = vec![];
+        let adaptor = match self.adaptor.as_ref() {
+            Some(adaptor) => adaptor,
+            None => return displays,
+        };
+        let mut output_index: u32 = 0;
+        unsafe {
+            while let Ok(output) = adaptor.EnumOutputs(output_index) {
+                if let Ok(desc) = output.GetDesc() {
+                    let rect = desc.DesktopCoordinates;
+                    // The effective DPI is only reported if the process is DPI aware, it is 96
+                    // otherwise.
+                    let (mut dpi_x, mut dpi_y) = (0u32, 0u32);
+                    let scale = windows::Win32::UI::HiDpi::GetDpiForMonitor(
+                        desc.Monitor,
+                        windows::Win32::UI::HiDpi::MDT_EFFECTIVE_DPI,
+                        &mut dpi_x,
+                        &mut dpi_y,
+                    )
+                    .map(|_| dpi_x as f32 / 96.0)
+                    .unwrap_or_default();
+                    displays.push(DisplayInfo {
+                        index: output_index,
+                        name: from_wide(&desc.DeviceName).to_string_lossy().into_owned(),
+                        x: rect.left,
+                        y: rect.top,
+                        width: (rect.right - rect.left) as u32,
+                        height: (rect.bottom - rect.top) as u32,
+                        scale,
+                        // The primary display is always at the origin of the desktop.
+                        primary: rect.left == 0 && rect.top == 0,
+                        combined: false,
+                    });
+                }
+                output_index += 1;
+            }
+        }
+        displays
+    }
+
+    /// The visible top-level windows with a title, through `EnumWindows`.
+    fn windows(&mut self) -> Vec<WindowInfo> {
+        top_level_windows()
+    }
+}
+
+/// The visible top-level windows with a title, with the region of their client area.
+fn top_level_windows() -> Vec<WindowInfo> {
+    use windows::Win32::Foundation::{BOOL, HWND, LPARAM, POINT, PWSTR, RECT};
+    use windows::Win32::Graphics::Gdi::ClientToScreen;
+    use windows::Win32::UI::WindowsAndMessaging::*;
+    unsafe extern "system" fn visit(hwnd: HWND, param: LPARAM) -> BOOL {
+        let windows = &mut *(param as *mut Vec<WindowInfo>);
+        let mut title = [0u16; 512];
+        let mut class = [0u16; 256];
+        let mut rect = RECT::default();
+        let mut origin = POINT::default();
+        let found = IsWindowVisible(hwnd).as_bool()
+            && GetWindowTextW(hwnd, PWSTR(title.as_mut_ptr()), title.len() as i32) > 0
+            && GetClassNameW(hwnd, PWSTR(class.as_mut_ptr()), class.len() as i32) > 0
+            && GetClientRect(hwnd, &mut rect).as_bool()
+            && ClientToScreen(hwnd, &mut origin).as_bool();
+        if found {
+            windows.push(WindowInfo {
+                title: from_wide(&title).to_string_lossy().into_owned(),
+                class: from_wide(&class).to_string_lossy().into_owned(),
+                x: origin.x,
+                y: origin.y,
+                width: (rect.right - rect.left) as u32,
+                height: (rect.bottom - rect.top) as u32,
+            });
+        }
+        // Continue the enumeration.
+        BOOL(1)
+    }
+    let mut windows: Vec<WindowInfo> = vec![];
+    unsafe {
+        EnumWindows(Some(visit), &mut windows as *mut Vec<WindowInfo> as LPARAM);
+    }
+    windows
+}
+
+/// Set the priority of the calling thread.
+pub fn set_thread_priority(
+    priority: crate::ThreadPriority,
+) -> std::result::Result<(), ScreenCaptureError> {
+    use windows::Win32::System::Threading::*;
+    let value = match priority {
+        crate::ThreadPriority::Lowest => THREAD_PRIORITY_LOWEST,
+        crate::ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+        crate::ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+        crate::ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+        crate::ThreadPriority::Highest => THREAD_PRIORITY_HIGHEST,
+    };
+    if !unsafe { SetThreadPriority(GetCurrentThread(), value) }.as_bool() {
+        let e = windows::core::Error::from_win32();
+        return Err(ScreenCaptureError::Other(ErrorDetail::with_os_error(
+            format!("setting thread priority failed: {:?}", e),
+            OsError::Hresult(e.code().0),
+        )));
+    }
+    Ok(())
+}
+
+/// The identifier of the calling thread.
+pub fn current_thread_id() -> u64 {
+    unsafe { windows::Win32::System::Threading::GetCurrentThreadId() as u64 }
+}
+
+/// Restrict the calling thread to the provided cores.
+pub fn set_thread_affinity(cores: &[usize]) -> std::result::Result<(), ScreenCaptureError> {
+    use windows::Win32::System::Threading::*;
+    let limit = usize::BITS as usize;
+    if let Some(core) = cores.iter().find(|c| **c >= limit) {
+        return Err(ScreenCaptureError::Other(
+            format!("core {core} of the thread affinity is not below {limit}").into(),
+        ));
+    }
+    let mask = cores.iter().fold(0usize, |mask, core| mask | (1 << core));
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+        let e = windows::core::Error::from_win32();
+        return Err(ScreenCaptureError::Other(ErrorDetail::with_os_error(
+            format!("setting thread affinity to {:?} failed: {:?}", cores, e),
+            OsError::Hresult(e.code().0),
+        )));
+    }
+    Ok(())
+}
+
+/// A named file mapping backed by the paging file, used by [`crate::shm`]. The name is removed
+/// when the last handle to it is closed.
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+pub struct SharedMemory {
+    handle: windows::Win32::Foundation::HANDLE,
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is plain memory, its users synchronise the access to it.
+unsafe impl Send for SharedMemory {}
+
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+impl SharedMemory {
+    /// Map the view of the mapping, closing the handle on failure.
+    unsafe fn map(
+        handle: windows::Win32::Foundation::HANDLE,
+        access: windows::Win32::System::Memory::FILE_MAP,
+        len: usize,
+    ) -> std::io::Result<*mut u8> {
+        use windows::Win32::Foundation::CloseHandle;
+        if handle.is_invalid() || handle.0 == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ptr = windows::Win32::System::Memory::MapViewOfFile(handle, access, 0, 0, len);
+        if ptr.is_null() {
+            let e = std::io::Error::last_os_error();
+            CloseHandle(handle);
+            return Err(e);
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    /// Create the mapping of `len` zeroed bytes, fails with [`std::io::ErrorKind::AlreadyExists`]
+    /// if the name is in use.
+    pub fn create(name: &str, len: usize) -> std::io::Result<SharedMemory> {
+        use windows::Win32::Foundation::*;
+        use windows::Win32::System::Memory::*;
+        unsafe {
+            let handle = CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                std::ptr::null(),
+                PAGE_READWRITE,
+                (len as u64 >> 32) as u32,
+                len as u32,
+                name,
+            );
+            // The handle then refers to the mapping of someone else.
+            if handle.0 != 0 && GetLastError() == ERROR_ALREADY_EXISTS {
+                CloseHandle(handle);
+                return Err(std::io::ErrorKind::AlreadyExists.into());
+            }
+            let ptr = Self::map(handle, FILE_MAP_ALL_ACCESS, len)?;
+            Ok(SharedMemory { handle, ptr, len })
+        }
+    }
+
+    /// The name is removed with the last handle to the mapping, so none are left behind.
+    pub fn remove(_name: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Map an existing mapping for reading.
+    pub fn open(name: &str) -> std::io::Result<SharedMemory> {
+        use windows::Win32::System::Memory::*;
+        unsafe {
+            let handle = OpenFileMappingW(FILE_MAP_READ, false, name);
+            let ptr = Self::map(handle, FILE_MAP_READ, 0)?;
+            let mut info: MEMORY_BASIC_INFORMATION = Default::default();
+            VirtualQuery(
+                ptr as *const std::ffi::c_void,
+                &mut info,
+                std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+            );
+            Ok(SharedMemory {
+                handle,
+                ptr,
+                len: info.RegionSize,
+            })
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
     }
 
-    fn prepare_capture(&mut self, display: u32, x: u32, y: u32, width: u32, height: u32) -> bool {
-        return CaptureWin::prepare(self, display, x, y, width, height);
+    pub fn size(&self) -> usize {
+        self.len
     }
 }
 
-pub fn capture() -> Box<dyn Capture> {
-    let z = Box::<CaptureWin>::new(CaptureWin::new());
-    z
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            windows::Win32::System::Memory::UnmapViewOfFile(self.ptr as *const std::ffi::c_void);
+            windows::Win32::Foundation::CloseHandle(self.handle);
+        }
+    }
+}
+
+/// Whether the process with the id still exists.
+#[cfg_attr(not(feature = "shm"), allow(dead_code))]
+pub fn process_alive(pid: u32) -> bool {
+    use windows::Win32::Foundation::*;
+    use windows::Win32::System::Threading::*;
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid);
+        if process.0 == 0 {
+            // Processes of other users can't be opened, but they exist.
+            return GetLastError() == ERROR_ACCESS_DENIED;
+        }
+        let mut code = 0u32;
+        let queried = GetExitCodeProcess(process, &mut code).as_bool();
+        CloseHandle(process);
+        !queried || code == STILL_ACTIVE.0 as u32
+    }
+}
+
+/// The session is considered locked if the input desktop can't be opened.
+pub fn session_locked() -> Option<bool> {
+    use windows::Win32::System::StationsAndDesktops::*;
+    // DESKTOP_SWITCHDESKTOP, fails while the secure desktop of the lock screen is active.
+    let desktop = unsafe { OpenInputDesktop(0, false, 0x0100) };
+    if desktop == 0 {
+        return Some(true);
+    }
+    unsafe { CloseDesktop(desktop) };
+    Some(false)
+}
+
+/// The region of the client area of the window on the desktop, see
+/// [`CaptureSpecification::for_window`].
+#[cfg(feature = "raw-window-handle")]
+pub fn window_region(
+    window: raw_window_handle::RawWindowHandle,
+) -> std::result::Result<(i32, i32, u32, u32), ScreenCaptureError> {
+    use windows::Win32::Foundation::{HWND, POINT, RECT};
+    let raw_window_handle::RawWindowHandle::Win32(handle) = window else {
+        return Err(ScreenCaptureError::Other(
+            format!("capturing the window {window:?} isn't supported on Windows").into(),
+        ));
+    };
+    let hwnd: HWND = handle.hwnd.get();
+    let mut rect = RECT::default();
+    let mut origin = POINT::default();
+    let found = unsafe {
+        windows::Win32::UI::WindowsAndMessaging::GetClientRect(hwnd, &mut rect).as_bool()
+            && windows::Win32::Graphics::Gdi::ClientToScreen(hwnd, &mut origin).as_bool()
+    };
+    if !found {
+        return Err(ScreenCaptureError::Other(
+            format!("the window {:#x} doesn't exist", hwnd).into(),
+        ));
+    }
+    Ok((
+        origin.x,
+        origin.y,
+        (rect.right - rect.left) as u32,
+        (rect.bottom - rect.top) as u32,
+    ))
+}
+
+/// Place the image on the clipboard as bitmap, see [`crate::util::copy_to_clipboard`].
+pub fn copy_to_clipboard(img: &dyn ImageBGR) -> std::result::Result<(), ScreenCaptureError> {
+    use windows::Win32::Foundation::{HANDLE, HWND};
+    use windows::Win32::Graphics::Gdi::BITMAPV5HEADER;
+    use windows::Win32::System::DataExchange::*;
+    use windows::Win32::System::Memory::*;
+    // From WinUser.h and wingdi.h.
+    const CF_DIBV5: u32 = 17;
+    const BI_BITFIELDS: u32 = 3;
+    const LCS_SRGB: u32 = 0x7352_4742;
+    let failed = |what: &str| {
+        let e = windows::core::Error::from_win32();
+        ScreenCaptureError::Other(ErrorDetail::with_os_error(
+            format!("{what} failed: {:?}", e),
+            OsError::Hresult(e.code().0),
+        ))
+    };
+
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let header = BITMAPV5HEADER {
+        bV5Size: std::mem::size_of::<BITMAPV5HEADER>() as u32,
+        bV5Width: width as i32,
+        // Positive, the rows are stored bottom up as most applications expect.
+        bV5Height: height as i32,
+        bV5Planes: 1,
+        bV5BitCount: 32,
+        bV5Compression: BI_BITFIELDS,
+        bV5SizeImage: (width * height * 4) as u32,
+        bV5RedMask: 0x00ff_0000,
+        bV5GreenMask: 0x0000_ff00,
+        bV5BlueMask: 0x0000_00ff,
+        bV5CSType: LCS_SRGB,
+        ..Default::default()
+    };
+    let offset = header.bV5Size as usize;
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, offset + width * height * 4);
+        if memory == 0 {
+            return Err(failed("allocating the clipboard data"));
+        }
+        let data = GlobalLock(memory) as *mut u8;
+        std::ptr::copy_nonoverlapping(&header as *const _ as *const u8, data, offset);
+        let pixels = data.add(offset) as *mut BGR;
+        for (y, row) in img.data().chunks_exact(width.max(1)).enumerate() {
+            std::ptr::copy_nonoverlapping(
+                row.as_ptr(),
+                pixels.add((height - 1 - y) * width),
+                width,
+            );
+        }
+        GlobalUnlock(memory);
+
+        if !OpenClipboard(HWND::default()).as_bool() {
+            let e = failed("opening the clipboard");
+            GlobalFree(memory);
+            return Err(e);
+        }
+        EmptyClipboard();
+        // The clipboard owns the memory once this succeeds.
+        let result = if SetClipboardData(CF_DIBV5, HANDLE(memory)).0 == 0 {
+            let e = failed("setting the clipboard data");
+            GlobalFree(memory);
+            Err(e)
+        } else {
+            Ok(())
+        };
+        CloseClipboard();
+        result
+    }
+}
+
+/// Create an instance of the named pipe, like `\\.\pipe\screen_capture`, and wait for a client to
+/// connect to it, see [`crate::daemon`].
+#[cfg(feature = "daemon")]
+pub fn accept_pipe(name: &str) -> std::io::Result<std::fs::File> {
+    use windows::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+    use windows::Win32::System::Pipes::*;
+    // From winerror.h, the client connected between creating the pipe and waiting for it.
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+    unsafe {
+        let handle = CreateNamedPipeW(
+            name,
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            4096,
+            4096,
+            0,
+            std::ptr::null(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+        if !ConnectNamedPipe(handle, std::ptr::null_mut()).as_bool() {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED) {
+                CloseHandle(handle);
+                return Err(e);
+            }
+        }
+        Ok(std::fs::File::from_raw_handle(handle.0 as RawHandle))
+    }
+}
+
+pub fn capture(
+    options: &BackendOptions,
+) -> std::result::Result<Box<dyn Capture + Send>, ScreenCaptureError> {
+    Ok(Box::<CaptureWin>::new(CaptureWin::new(options)?))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CaptureWin>();
+    }
+
+    #[test]
+    fn test_missing_objects_are_transient() {
+        // Without adaptor, device or duplicator nothing may panic, all paths must return an error.
+        let mut capture: CaptureWin = Default::default();
+        assert!(matches!(
+            capture.prepare(0, Default::default()),
+            Err(ScreenCaptureError::Initialisation(_))
+        ));
+        assert!(matches!(
+            Capture::capture_image(&mut capture),
+            Err(ScreenCaptureError::Transient(_))
+        ));
+        assert!(matches!(
+            Capture::image(&mut capture),
+            Err(ScreenCaptureError::Transient(_))
+        ));
+        assert!(matches!(
+            capture.shared_texture(),
+            Err(ScreenCaptureError::Transient(_))
+        ));
+        assert!(capture.debug_messages().is_empty());
+        assert!(matches!(
+            Capture::resolution(&mut capture),
+            Err(ScreenCaptureError::Initialisation(_))
+        ));
+    }
 }
@@ -0,0 +1,235 @@
+//! End to end tests of the capture pipeline with the synthetic test pattern backend.
+use screen_capture::capturer::CaptureInfo;
+use screen_capture::synthetic::TestPatternCapture;
+use screen_capture::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Test pattern backend whose desktop resolution can be changed while a capturer owns it.
+struct Desktop {
+    inner: TestPatternCapture,
+    current: Resolution,
+    resolution: Arc<Mutex<Resolution>>,
+}
+
+impl Desktop {
+    fn boxed(resolution: &Arc<Mutex<Resolution>>) -> Box<dyn Capture> {
+        let current = *resolution.lock().unwrap();
+        Box::new(Desktop {
+            inner: TestPatternCapture::new(current),
+            current,
+            resolution: resolution.clone(),
+        })
+    }
+}
+
+impl Capture for Desktop {
+    fn capture_image(&mut self) -> Result<(), ScreenCaptureError> {
+        self.inner.capture_image()
+    }
+
+    fn image(&mut self) -> Result<Box<dyn ImageBGR>, ScreenCaptureError> {
+        self.inner.image()
+    }
+
+    fn resolution(&mut self) -> Result<Resolution, ScreenCaptureError> {
+        let resolution = *self.resolution.lock().unwrap();
+        if resolution != self.current {
+            self.current = resolution;
+            self.inner.set_resolution(resolution);
+        }
+        Ok(resolution)
+    }
+
+    fn prepare_capture(
+        &mut self,
+        display: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), ScreenCaptureError> {
+        self.inner.prepare_capture(display, x, y, width, height)
+    }
+}
+
+fn resolution(width: u32, height: u32) -> Resolution {
+    Resolution { width, height }
+}
+
+/// Captures the region at `(2, 3)` of `4` by `2` pixels on an `8` by `8` desktop, everything
+/// otherwise.
+fn config() -> CaptureConfig {
+    CaptureConfig {
+        capture: vec![
+            CaptureSpecification {
+                match_width: Some(8),
+                match_height: Some(8),
+                x: 2,
+                y: 3,
+                width: 4,
+                height: 2,
+                ..Default::default()
+            },
+            CaptureSpecification::default(),
+        ],
+        rate: 200.0,
+        ..Default::default()
+    }
+}
+
+/// Check the frame shows the test pattern of the region at the offset.
+fn assert_pattern(frame: &OwnedFrame, x: u32, y: u32, width: u32, height: u32) {
+    let img = frame.to_bgr();
+    assert_eq!((img.width(), img.height()), (width, height));
+    let blue = img.pixel(0, 0).b;
+    for iy in 0..height {
+        for ix in 0..width {
+            let p = img.pixel(ix, iy);
+            assert_eq!((p.r, p.g, p.b), ((x + ix) as u8, (y + iy) as u8, blue));
+        }
+    }
+}
+
+/// Wait until the condition holds, failing after a few seconds.
+fn wait_until<F: FnMut() -> bool>(mut condition: F) {
+    let start = std::time::Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "condition not met in time"
+        );
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Start a threaded capturer on the desktop, collecting all frames it produces.
+fn threaded(
+    desktop: &Arc<Mutex<Resolution>>,
+    backend: impl Fn(Box<dyn Capture>) -> Box<dyn Capture> + Send + Sync + 'static,
+) -> (ThreadedCapturer, Arc<Mutex<Vec<CaptureInfo>>>) {
+    let frames = Arc::new(Mutex::new(vec![]));
+    let desktop = desktop.clone();
+    let capturer = {
+        let frames = frames.clone();
+        ThreadedCapturer::builder()
+            .config(config())
+            .backend(move || Ok(backend(Desktop::boxed(&desktop))))
+            .on_frame(move |info| frames.lock().unwrap().push(info))
+            .build()
+    };
+    (capturer, frames)
+}
+
+#[test]
+fn test_capturer_resolution_change() {
+    let desktop = Arc::new(Mutex::new(resolution(8, 8)));
+    let mut capturer = Capturer::with_backend(Desktop::boxed(&desktop), config());
+    let frame = OwnedFrame::from_image(&*capturer.capture().unwrap(), OutputFormat::Bgr);
+    assert_pattern(&frame, 2, 3, 4, 2);
+    let change = capturer.take_specification_change().unwrap();
+    assert_eq!(change.previous, None);
+    assert_eq!(change.current.x, 2);
+
+    *desktop.lock().unwrap() = resolution(16, 4);
+    let frame = OwnedFrame::from_image(&*capturer.capture().unwrap(), OutputFormat::Bgr);
+    assert_pattern(&frame, 0, 0, 16, 4);
+    let change = capturer.take_specification_change().unwrap();
+    assert_eq!(change.previous.unwrap().x, 2);
+    assert_eq!(capturer.take_specification_change(), None);
+
+    // Back to the original resolution, the same frames as before.
+    *desktop.lock().unwrap() = resolution(8, 8);
+    let frame = OwnedFrame::from_image(&*capturer.capture().unwrap(), OutputFormat::Bgr);
+    assert_pattern(&frame, 2, 3, 4, 2);
+}
+
+#[test]
+fn test_threaded_stream() {
+    let desktop = Arc::new(Mutex::new(resolution(8, 8)));
+    let (capturer, frames) = threaded(&desktop, |b| b);
+    let size = |info: &CaptureInfo| {
+        info.result
+            .as_ref()
+            .map(|f| (f.width(), f.height()))
+            .unwrap()
+    };
+    wait_until(|| frames.lock().unwrap().len() >= 3);
+
+    *desktop.lock().unwrap() = resolution(12, 6);
+    wait_until(|| frames.lock().unwrap().last().map(size) == Some((12, 6)));
+
+    // Swapping the config applies to the next frames.
+    capturer.set_config(CaptureConfig {
+        capture: vec![CaptureSpecification {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 3,
+            ..Default::default()
+        }],
+        ..config()
+    });
+    wait_until(|| frames.lock().unwrap().last().map(size) == Some((3, 3)));
+    capturer.stop().unwrap();
+
+    let frames = frames.lock().unwrap();
+    for pair in frames.windows(2) {
+        assert!(pair[0].counter < pair[1].counter);
+        assert!(pair[0].time <= pair[1].time);
+    }
+    let mut sizes: Vec<(u32, u32)> = frames.iter().map(size).collect();
+    sizes.dedup();
+    assert_eq!(sizes, vec![(4, 2), (12, 6), (3, 3)]);
+    for info in frames.iter() {
+        let frame = info.result.as_ref().unwrap();
+        match size(info) {
+            (4, 2) => assert_pattern(frame, 2, 3, 4, 2),
+            (12, 6) => assert_pattern(frame, 0, 0, 12, 6),
+            _ => assert_pattern(frame, 1, 1, 3, 3),
+        }
+        assert_eq!(info.displays.len(), 1);
+        assert!(info.displays[0].fresh);
+    }
+}
+
+#[test]
+#[cfg(feature = "fault-injection")]
+fn test_threaded_faults() {
+    use screen_capture::synthetic::FaultHandle;
+    let desktop = Arc::new(Mutex::new(resolution(8, 8)));
+    let faults = FaultHandle::new();
+    faults.inject(2, ScreenCaptureError::Timeout);
+    faults.inject_range(4..6, ScreenCaptureError::LostCapture("lost".into()));
+    let (capturer, frames) = {
+        let faults = faults.clone();
+        threaded(&desktop, move |b| Box::new(faults.wrap(b)))
+    };
+    wait_until(|| frames.lock().unwrap().len() >= 8);
+    capturer.stop().unwrap();
+
+    // Every capture produced a frame, the injected failures in their place.
+    let frames = frames.lock().unwrap();
+    let results: Vec<Option<ScreenCaptureError>> = frames
+        .iter()
+        .take(7)
+        .map(|info| info.result.as_ref().err().cloned())
+        .collect();
+    let lost = || Some(ScreenCaptureError::LostCapture("lost".into()));
+    assert_eq!(
+        results,
+        vec![
+            None,
+            Some(ScreenCaptureError::Timeout),
+            None,
+            lost(),
+            lost(),
+            None,
+            None
+        ]
+    );
+    for info in frames.iter().filter(|info| info.result.is_ok()) {
+        assert_pattern(info.result.as_ref().unwrap(), 2, 3, 4, 2);
+    }
+    assert_eq!(faults.resets(), 0);
+}